@@ -0,0 +1,295 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomically;
+use crate::config::Config;
+use crate::error::BackupError;
+use crate::paths::{backup_location, is_gz, resolve_safe_os_path, stays_inside};
+
+// ---------- Tar archives ----------
+
+/// Where `archive` bundles `file`: "notes.txt" -> "notes.txt.tar" (".tar.gz" when
+/// compressed), beside the file or in the backup directory like its backups.
+pub(crate) fn archive_path_for(
+    cfg: &Config,
+    base: &Path,
+    file: &Path,
+    compress: bool,
+) -> PathBuf {
+    let (dir, name) = backup_location(cfg, base, file);
+    let ext = if compress { "tar.gz" } else { "tar" };
+    dir.join(format!("{}.{}", name, ext))
+}
+
+/// Write `files` (absolute, all under `base`) into a tar archive at `dest`, each stored
+/// under its path relative to `base`. Gzip-compressed at `level` if `dest` ends in ".gz".
+pub(crate) fn write_tar(
+    dest: &Path,
+    base: &Path,
+    files: &[PathBuf],
+    level: u32,
+) -> io::Result<()> {
+    write_atomically(dest, |out| {
+        let out = io::BufWriter::new(&mut *out);
+        if is_gz(dest) {
+            let encoder = flate2::write::GzEncoder::new(out, flate2::Compression::new(level));
+            append_files(encoder, base, files)?.finish()?.flush()
+        } else {
+            append_files(out, base, files)?.flush()
+        }
+    })
+}
+
+/// Append `files` (absolute, all under `base`) to the plain tar archive at `dest`, after
+/// its last entry, so earlier entries are kept and a name stored twice extracts as its
+/// later copy. If writing fails the archive is cut back to its old entries.
+pub(crate) fn append_tar(dest: &Path, base: &Path, files: &[PathBuf]) -> io::Result<()> {
+    let end = entries_end(dest)?;
+    let mut archive = fs::OpenOptions::new().read(true).write(true).open(dest)?;
+    archive.seek(io::SeekFrom::Start(end))?;
+    let appended = append_files(io::BufWriter::new(&mut archive), base, files)
+        .and_then(|mut out| out.flush())
+        .and_then(|()| archive.sync_all());
+    if let Err(e) = appended {
+        // Two zero blocks end a tar archive.
+        archive.set_len(end)?;
+        archive.seek(io::SeekFrom::Start(end))?;
+        archive.write_all(&[0; 1024])?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// The offset right after the data of an archive's last entry, where its end-of-archive
+/// blocks start.
+fn entries_end(archive: &Path) -> io::Result<u64> {
+    let mut tar = open_tar(archive)?;
+    let mut end = 0;
+    for entry in tar.entries()? {
+        let entry = entry?;
+        end = entry.raw_file_position() + entry.size().div_ceil(512) * 512;
+    }
+    Ok(end)
+}
+
+fn append_files<W: Write>(out: W, base: &Path, files: &[PathBuf]) -> io::Result<W> {
+    let mut builder = tar::Builder::new(out);
+    builder.mode(tar::HeaderMode::Complete);
+    for file in files {
+        builder.append_path_with_name(file, file.strip_prefix(base).unwrap_or(file))?;
+    }
+    builder.into_inner()
+}
+
+/// A regular file stored in an archive, under the name it was stored with.
+pub(crate) struct TarMember {
+    pub(crate) name: PathBuf,
+    pub(crate) size: u64,
+    /// Modification time in seconds since the epoch, as tar records it.
+    pub(crate) mtime: u64,
+}
+
+/// The regular files of an archive in the order they were stored; a name appended more
+/// than once appears once per copy.
+pub(crate) fn list_tar(archive: &Path) -> io::Result<Vec<TarMember>> {
+    let mut tar = open_tar(archive)?;
+    let mut members = Vec::new();
+    for entry in tar.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type() == tar::EntryType::Regular {
+            let name = entry.path()?.into_owned();
+            members.push(TarMember { name, size: entry.size(), mtime: entry.header().mtime()? });
+        }
+    }
+    Ok(members)
+}
+
+fn open_tar(archive: &Path) -> io::Result<tar::Archive<Box<dyn io::Read>>> {
+    let file = io::BufReader::new(fs::File::open(archive)?);
+    let reader: Box<dyn io::Read> = if is_gz(archive) {
+        Box::new(flate2::read::GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// Where an archive entry named `name` lands under `base`. The name goes through
+/// `resolve_safe_os_path` exactly like user input (no absolute paths, no "..", ...), and
+/// the nearest directory of the target that already exists must really be inside `base`,
+/// so a symlinked directory cannot carry the entry out of it.
+fn entry_target(base: &Path, name: &OsStr) -> Result<PathBuf, BackupError> {
+    let rejected = |msg: &str| {
+        BackupError::PathRejected(format!("Archive entry '{}': {}", name.to_string_lossy(), msg))
+    };
+    let target = resolve_safe_os_path(base, name).map_err(|e| match e {
+        BackupError::PathRejected(msg) => rejected(&msg),
+        other => other,
+    })?;
+    if !stays_inside(base, &target)? {
+        return Err(rejected("leads out of the destination through a symlink"));
+    }
+    Ok(target)
+}
+
+/// Where each regular file of an archive would land under `base`, refusing the
+/// whole archive if any entry would escape it ("tar slip", see `entry_target`); links
+/// and special files are refused too.
+pub(crate) fn read_tar_entries(
+    archive: &Path,
+    base: &Path,
+) -> Result<Vec<PathBuf>, BackupError> {
+    let mut tar = open_tar(archive)?;
+    let mut entries = Vec::new();
+    for entry in tar.entries()? {
+        let entry = entry?;
+        let target = entry_target(base, entry.path()?.as_os_str())?;
+        match entry.header().entry_type() {
+            tar::EntryType::Regular => entries.push(target),
+            tar::EntryType::Directory => {}
+            _ => {
+                return Err(BackupError::PathRejected(format!(
+                    "Archive entry '{}' is a link or special file",
+                    entry.path()?.display()
+                )))
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Extract the regular files of an archive whose `read_tar_entries` target is accepted by
+/// `wanted`, each written atomically and given its recorded modification time.
+/// Only call this on an archive `read_tar_entries` has accepted; each target is checked
+/// again right before it is written, and the extraction stops at the first one that
+/// would escape `base`.
+pub(crate) fn extract_tar(
+    archive: &Path,
+    base: &Path,
+    wanted: impl Fn(&Path) -> bool,
+) -> Result<(), BackupError> {
+    let mut tar = open_tar(archive)?;
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+        let target = entry_target(base, entry.path()?.as_os_str())?;
+        if !wanted(&target) {
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mtime = entry.header().mtime()?;
+        write_atomically(&target, |out| {
+            io::copy(&mut entry, out)?;
+            let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime);
+            out.set_modified(modified)
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::ops::{BackupManager, ExtractOptions};
+
+    /// A tar entry written as is: `tar::Builder` itself refuses the names these tests need.
+    enum Entry<'a> {
+        File(&'a str),
+        Symlink(&'a str, &'a str),
+    }
+
+    fn write_raw_tar(path: &Path, entries: &[Entry]) {
+        let mut tar = tar::Builder::new(fs::File::create(path).unwrap());
+        for entry in entries {
+            let mut header = tar::Header::new_gnu();
+            let (name, data): (&str, &[u8]) = match entry {
+                Entry::File(name) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    (name, b"escaped")
+                }
+                Entry::Symlink(name, target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_link_name(target).unwrap();
+                    (name, b"")
+                }
+            };
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append(&header, data).unwrap();
+        }
+        tar.finish().unwrap();
+    }
+
+    fn names(dir: &Path) -> BTreeSet<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect()
+    }
+
+    /// Extract an archive of `entries` under "<tmp>/base", which must fail, and check that
+    /// no file was written: nothing beside the base or in "<tmp>/outside", nothing new in
+    /// the base.
+    fn assert_refused(entries: &[Entry], setup: impl FnOnce(&Path, &Path)) {
+        let root = tempfile::tempdir().unwrap();
+        let (base, outside) = (root.path().join("base"), root.path().join("outside"));
+        fs::create_dir_all(&base).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        setup(&base, &outside);
+        write_raw_tar(&base.join("evil.tar"), entries);
+        let before = names(&base);
+
+        let config = Config { log_path: base.join("logfile.txt"), ..Config::default() };
+        let mgr = BackupManager::new(&base, config);
+        let result = mgr.extract("evil.tar", ExtractOptions::default());
+        assert!(matches!(result, Err(BackupError::PathRejected(_))), "{:?}", result);
+
+        assert_eq!(names(root.path()), BTreeSet::from(["base".into(), "outside".into()]));
+        assert!(names(&outside).is_empty());
+        let mut after = names(&base);
+        after.remove("logfile.txt");
+        after.remove("logfile.txt.lock");
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn parent_traversal_is_refused() {
+        assert_refused(&[Entry::File("ok.txt"), Entry::File("../x")], |_, _| {});
+        assert_refused(&[Entry::File("ok.txt"), Entry::File("sub/../../x")], |_, _| {});
+    }
+
+    #[test]
+    fn absolute_entries_are_refused() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("abs.txt");
+        let name = target.to_str().unwrap();
+        assert_refused(&[Entry::File("ok.txt"), Entry::File(name)], |_, _| {});
+        assert!(!target.exists());
+        assert_refused(&[Entry::File("/abs")], |_, _| {});
+    }
+
+    #[test]
+    fn symlink_entries_are_refused() {
+        let entries = [Entry::Symlink("link", "../outside"), Entry::File("link/x")];
+        assert_refused(&entries, |_, _| {});
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn entries_under_a_symlinked_directory_are_refused() {
+        let entries = [Entry::File("ok.txt"), Entry::File("out/x")];
+        assert_refused(&entries, |base, outside| {
+            std::os::unix::fs::symlink(outside, base.join("out")).unwrap();
+        });
+    }
+}
@@ -0,0 +1,231 @@
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+use crate::cancel::Writing;
+use crate::checksum::{sha256_file, sha256_of};
+use crate::error::BackupError;
+use crate::paths::file_name_of;
+use crate::progress::{ProgressFn, ProgressReader};
+use crate::sparse::copy_sparse;
+
+// ---------- Atomic writes ----------
+
+/// Produce `dest` through a temporary file in the same directory ("<name>.XXXXXX.tmp"),
+/// flushed to disk and renamed over `dest` only after `write` has finished. `dest` is
+/// therefore always either its old content or the complete new one; on error the temporary
+/// file is removed, also when the copy is cancelled (see `cancel`).
+pub(crate) fn write_atomically<T>(
+    dest: &Path,
+    write: impl FnOnce(&mut fs::File) -> io::Result<T>,
+) -> io::Result<T> {
+    let _writing = Writing::start();
+    let dir = match dest.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let mut tmp = tempfile::Builder::new()
+        .prefix(&format!("{}.", file_name_of(dest)))
+        .suffix(".tmp")
+        .tempfile_in(dir)?;
+    let result = write(tmp.as_file_mut())?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(dest).map_err(|e| e.error)?;
+    Ok(result)
+}
+
+/// Make `dest` a hardlink to `src`: linked under a temporary name in `dest`'s directory and
+/// renamed over `dest`, like `write_atomically`. Fails where the filesystem has no
+/// hardlinks or `src` is on another device; callers copy instead.
+pub(crate) fn link_atomically(src: &Path, dest: &Path) -> io::Result<()> {
+    let dir = match dest.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let tmp = tempfile::Builder::new()
+        .prefix(&format!("{}.", file_name_of(dest)))
+        .suffix(".tmp")
+        .make_in(dir, |tmp| fs::hard_link(src, tmp))?;
+    tmp.persist(dest).map_err(|e| e.error)
+}
+
+/// Flush `path`'s content and metadata to disk. Read access is enough on Unix; Windows
+/// only flushes a handle that can write.
+pub(crate) fn sync_file(path: &Path) -> io::Result<()> {
+    let file = if cfg!(windows) {
+        fs::File::options().write(true).open(path)?
+    } else {
+        fs::File::open(path)?
+    };
+    file.sync_all()
+}
+
+/// Flush the entries of directory `dir` to disk, so files just created or renamed into it
+/// survive a crash under their new names. Windows cannot open a directory for this and
+/// commits renames itself, so there it does nothing.
+pub(crate) fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Copy all of `input` to `out` through a buffer of `buffer_size` bytes, then flush `out`.
+/// Returns the bytes copied.
+pub(crate) fn copy_buffered(
+    input: &mut impl Read,
+    out: &mut impl Write,
+    buffer_size: u64,
+) -> io::Result<u64> {
+    let mut buf = vec![0u8; buffer_size.max(1) as usize];
+    let mut copied = 0;
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        out.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    out.flush()?;
+    Ok(copied)
+}
+
+/// Atomic replacement for `fs::copy`: same content and permissions, via `write_atomically`,
+/// copied `buffer_size` bytes at a time. `progress` is told how far along the copy is.
+pub(crate) fn copy_atomically(
+    src: &Path,
+    dest: &Path,
+    buffer_size: u64,
+    progress: Option<&ProgressFn>,
+) -> io::Result<u64> {
+    write_atomically(dest, |out| {
+        let input = fs::File::open(src)?;
+        let meta = input.metadata()?;
+        let mut input = ProgressReader::new(input, meta.len(), progress);
+        let copied = copy_buffered(&mut input, out, buffer_size)?;
+        out.set_permissions(meta.permissions())?;
+        Ok(copied)
+    })
+}
+
+/// `copy_atomically` that also reads the staged copy back and compares its SHA-256 with
+/// `src` before it replaces `dest`. A copy that does not match is discarded and `dest`
+/// keeps its previous content. With `sparse`, holes and blocks of zeros are not written
+/// (see `copy_sparse`). Returns the bytes copied and the digest.
+pub(crate) fn copy_verified(
+    src: &Path,
+    dest: &Path,
+    buffer_size: u64,
+    sparse: bool,
+    progress: Option<&ProgressFn>,
+) -> Result<(u64, String), BackupError> {
+    let mut mismatched = false;
+    let copied = write_atomically(dest, |out| {
+        let mut input = fs::File::open(src)?;
+        let meta = input.metadata()?;
+        let copied = if sparse {
+            copy_sparse(&mut input, out, buffer_size, progress)?
+        } else {
+            let mut input = ProgressReader::new(input, meta.len(), progress);
+            copy_buffered(&mut input, out, buffer_size)?
+        };
+        out.set_permissions(meta.permissions())?;
+        out.sync_all()?;
+        out.rewind()?;
+        let digest = sha256_of(&mut *out)?;
+        if digest != sha256_file(src)? {
+            mismatched = true;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "staged copy mismatch"));
+        }
+        Ok((copied, digest))
+    });
+    match copied {
+        Err(_) if mismatched => Err(BackupError::Mismatch(dest.to_path_buf())),
+        other => Ok(other?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A source whose read fails once `limit` bytes were read, like a disk or a network
+    /// share going away in the middle of a copy.
+    struct FailingReader {
+        read: usize,
+        limit: usize,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.read >= self.limit {
+                return Err(io::Error::other("device went away"));
+            }
+            let n = buf.len().min(self.limit - self.read);
+            buf[..n].fill(b'n');
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn failed_copy_leaves_the_previous_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup = dir.path().join("notes.txt.bak");
+        fs::write(&backup, "previous backup").unwrap();
+
+        let mut input = FailingReader { read: 0, limit: 10_000 };
+        let result = write_atomically(&backup, |out| copy_buffered(&mut input, out, 1024));
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "previous backup");
+        // The partial copy is gone too.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    /// The default `copy_buffer_size`.
+    const BUFFER: usize = 64 * 1024;
+
+    /// `len` bytes that do not repeat with any buffer size, with a run of zeros in the
+    /// middle for the sparse copy to leave as a hole.
+    fn sample(len: usize) -> Vec<u8> {
+        let mut state: u32 = 0x2545_f491;
+        let mut data: Vec<u8> = (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+        if len > 3 * BUFFER {
+            data[BUFFER..2 * BUFFER].fill(0);
+        }
+        data
+    }
+
+    #[test]
+    fn copies_are_byte_identical() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("source");
+        let sizes = [0, 1, BUFFER - 1, BUFFER, BUFFER + 1, 3 * BUFFER + 7, 5 * 1024 * 1024 + 3];
+        for len in sizes {
+            let data = sample(len);
+            fs::write(&src, &data).unwrap();
+
+            let dest = dir.path().join("atomic");
+            assert_eq!(copy_atomically(&src, &dest, BUFFER as u64, None).unwrap(), len as u64);
+            assert!(fs::read(&dest).unwrap() == data, "copy of {} bytes differs", len);
+
+            for sparse in [false, true] {
+                let dest = dir.path().join(format!("verified-{}", sparse));
+                let (copied, digest) =
+                    copy_verified(&src, &dest, BUFFER as u64, sparse, None).unwrap();
+                assert_eq!(copied, len as u64);
+                assert_eq!(digest, sha256_of(&data[..]).unwrap());
+                assert!(fs::read(&dest).unwrap() == data, "copy of {} bytes differs", len);
+            }
+        }
+    }
+}
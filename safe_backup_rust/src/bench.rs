@@ -0,0 +1,96 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::atomic::{copy_atomically, sync_file};
+
+// ---------- Copy benchmark ----------
+
+/// Buffer sizes `BackupManager::bench` tries when none are given: from 4K up to 8M.
+pub const BENCH_BUFFER_SIZES: [u64; 5] = [4 << 10, 64 << 10, 256 << 10, 1 << 20, 8 << 20];
+
+/// Each strategy copies the file this many times; the fastest run counts, so one slowed
+/// down by another process does not decide.
+const BENCH_ROUNDS: usize = 3;
+
+/// How fast one way of copying went on the benchmark file.
+#[derive(Debug, Clone)]
+pub struct CopyBench {
+    /// `fs::copy` when `None`; otherwise the buffered copy backups use
+    /// (`copy_buffer_size`), with this buffer.
+    pub buffer_size: Option<u64>,
+    pub bytes: u64,
+    /// The fastest round, including the flush to disk.
+    pub elapsed: Duration,
+}
+
+impl CopyBench {
+    pub fn bytes_per_sec(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.bytes as f64 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Fill `path` with `size` bytes that do not compress, so a filesystem that compresses or
+/// deduplicates cannot skip the work.
+fn write_bench_file(path: &Path, size: u64) -> io::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut chunk = vec![0u8; 1 << 20];
+    let mut left = size;
+    while left > 0 {
+        for word in chunk.chunks_mut(8) {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            word.copy_from_slice(&state.to_le_bytes()[..word.len()]);
+        }
+        let n = left.min(chunk.len() as u64) as usize;
+        out.write_all(&chunk[..n])?;
+        left -= n as u64;
+    }
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+/// Time `fs::copy` and the buffered copy with each of `buffers` on a generated file of
+/// `size` bytes in `dir`, calling `each` as each result comes in. The `fs::copy` runs are
+/// flushed to disk like the buffered ones, which always are. Only files in a temporary
+/// directory under `dir` are written, and it is removed again whatever happens.
+pub(crate) fn bench_copies(
+    dir: &Path,
+    size: u64,
+    buffers: &[u64],
+    mut each: impl FnMut(&CopyBench),
+) -> io::Result<Vec<CopyBench>> {
+    let tmp = tempfile::Builder::new().prefix(".safe_backup-bench.").tempdir_in(dir)?;
+    let src = tmp.path().join("source.dat");
+    let dest = tmp.path().join("copy.dat");
+    write_bench_file(&src, size)?;
+    let strategies = std::iter::once(None).chain(buffers.iter().copied().map(Some));
+    let mut results = Vec::new();
+    for buffer_size in strategies {
+        let mut fastest = Duration::MAX;
+        for _ in 0..BENCH_ROUNDS {
+            let started = Instant::now();
+            match buffer_size {
+                None => {
+                    fs::copy(&src, &dest)?;
+                    sync_file(&dest)?;
+                }
+                Some(buffer) => {
+                    copy_atomically(&src, &dest, buffer, None)?;
+                }
+            }
+            fastest = fastest.min(started.elapsed());
+            fs::remove_file(&dest)?;
+        }
+        let result = CopyBench { buffer_size, bytes: size, elapsed: fastest };
+        each(&result);
+        results.push(result);
+    }
+    Ok(results)
+}
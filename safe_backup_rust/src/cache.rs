@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::atomic::write_atomically;
+use crate::checksum::{digest_file, sha256_backup_content, HashAlgorithm};
+use crate::paths::{is_delta, is_ref};
+use crate::trash::TRASH_DIR;
+
+// ---------- Checksum cache ----------
+
+/// Where digests are remembered between runs, inside the trash like the undo record so
+/// that it is never backed up or listed.
+const CACHE_FILE: &str = "checksums.json";
+
+/// A file modified this recently may still change within its current mtime tick without
+/// its size or time showing it, so its digest is not remembered yet.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// A digest of a file as it was; reused only while the file keeps its size and
+/// modification time.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedDigest {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+    digest: String,
+}
+
+/// Digests computed before, keyed by what was hashed and the file's absolute path
+/// ("blake3 /home/me/notes.txt.bak", "content /home/me/notes.txt.bak.gz"). Loaded on
+/// first use and written back by `save` if anything was added.
+pub(crate) struct ChecksumCache {
+    path: PathBuf,
+    enabled: bool,
+    entries: Mutex<Option<HashMap<String, CachedDigest>>>,
+    changed: AtomicBool,
+}
+
+impl ChecksumCache {
+    /// The cache kept under `base`; with `enabled` false every digest is computed afresh
+    /// and nothing is remembered.
+    pub(crate) fn new(base: &Path, enabled: bool) -> Self {
+        ChecksumCache {
+            path: base.join(TRASH_DIR).join(CACHE_FILE),
+            enabled,
+            entries: Mutex::new(None),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// `path`'s digest with `algorithm`.
+    pub(crate) fn file(&self, algorithm: HashAlgorithm, path: &Path) -> io::Result<String> {
+        self.digest(algorithm.name(), path, |path| digest_file(algorithm, path))
+    }
+
+    /// SHA-256 of a backup's content (see `sha256_backup_content`). Deltas and
+    /// deduplicated backups are always hashed through, as their content lives in other
+    /// files.
+    pub(crate) fn content(&self, backup: &Path) -> io::Result<String> {
+        if is_delta(backup) || is_ref(backup) {
+            return sha256_backup_content(backup);
+        }
+        self.digest("content", backup, sha256_backup_content)
+    }
+
+    fn digest(
+        &self,
+        kind: &str,
+        path: &Path,
+        compute: impl FnOnce(&Path) -> io::Result<String>,
+    ) -> io::Result<String> {
+        if !self.enabled {
+            return compute(path);
+        }
+        let meta = fs::metadata(path)?;
+        let since_epoch = meta.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let key = format!("{} {}", kind, std::path::absolute(path)?.display());
+        let unchanged = |cached: &CachedDigest| {
+            cached.size == meta.len()
+                && cached.modified_secs == since_epoch.as_secs()
+                && cached.modified_nanos == since_epoch.subsec_nanos()
+        };
+        {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            let entries = entries.get_or_insert_with(|| self.load());
+            if let Some(cached) = entries.get(&key).filter(|cached| unchanged(cached)) {
+                return Ok(cached.digest.clone());
+            }
+        }
+        let digest = compute(path)?;
+        let settled = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .is_ok_and(|now| now.saturating_sub(since_epoch) >= SETTLE_TIME);
+        if settled {
+            let cached = CachedDigest {
+                size: meta.len(),
+                modified_secs: since_epoch.as_secs(),
+                modified_nanos: since_epoch.subsec_nanos(),
+                digest: digest.clone(),
+            };
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            entries.get_or_insert_with(|| self.load()).insert(key, cached);
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        Ok(digest)
+    }
+
+    /// The remembered digests; a missing or damaged cache file is just an empty cache.
+    fn load(&self) -> HashMap<String, CachedDigest> {
+        let Ok(json) = fs::read(&self.path) else {
+            return HashMap::new();
+        };
+        serde_json::from_slice(&json).unwrap_or_else(|e| {
+            debug!("ignoring the damaged checksum cache {}: {}", self.path.display(), e);
+            HashMap::new()
+        })
+    }
+
+    /// Write the cache back if digests were added, dropping entries of files that are
+    /// gone.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        if !self.changed.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(entries) = entries.as_mut() else {
+            return Ok(());
+        };
+        entries.retain(|key, _| key.split_once(' ').is_some_and(|(_, p)| Path::new(p).exists()));
+        fs::create_dir_all(self.path.parent().unwrap_or(Path::new(".")))?;
+        let json = serde_json::to_vec(&*entries).map_err(io::Error::other)?;
+        write_atomically(&self.path, |out| io::Write::write_all(out, &json))?;
+        self.changed.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+}
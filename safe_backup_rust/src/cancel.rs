@@ -0,0 +1,68 @@
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// ---------- Cancellation ----------
+
+/// Set by `cancel`; copies stop at their next chunk while it is.
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// How many operations are writing something a sudden exit could leave half-done.
+static WRITING: AtomicUsize = AtomicUsize::new(0);
+
+/// Ask the copies in progress to stop at their next chunk: each one then removes its
+/// temporary file and fails with `BackupError::Cancelled`. Safe to call from a signal
+/// handler. Returns false if nothing is being written, or cancelling was already asked
+/// for; the caller can then just exit.
+pub fn cancel() -> bool {
+    if WRITING.load(Ordering::SeqCst) == 0 {
+        return false;
+    }
+    !CANCELLED.swap(true, Ordering::SeqCst)
+}
+
+/// Let operations copy again after `cancel`, e.g. before the next interactive command.
+pub fn clear_cancel() {
+    CANCELLED.store(false, Ordering::SeqCst);
+}
+
+/// Fails with a `Cancelled` error once `cancel` was called.
+pub(crate) fn check_cancelled() -> io::Result<()> {
+    if CANCELLED.load(Ordering::Relaxed) {
+        return Err(io::Error::other(Cancelled));
+    }
+    Ok(())
+}
+
+/// True if `e` came from `check_cancelled`.
+pub(crate) fn is_cancelled(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<Cancelled>())
+}
+
+/// Marks an operation as writing for as long as it is held, so `cancel` waits for it to
+/// clean up instead of letting the process exit.
+pub(crate) struct Writing;
+
+impl Writing {
+    pub(crate) fn start() -> Self {
+        WRITING.fetch_add(1, Ordering::SeqCst);
+        Writing
+    }
+}
+
+impl Drop for Writing {
+    fn drop(&mut self) {
+        WRITING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug)]
+struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
@@ -0,0 +1,187 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::delta::sha256_delta_content;
+use crate::error::BackupError;
+use crate::paths::{file_name_of, is_delta, is_enc, is_gz, is_ref};
+use crate::store::ref_blob;
+
+// ---------- Checksums ----------
+
+/// The digest a checksum sidecar records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// BLAKE3: much faster than SHA-256 on large files.
+    #[default]
+    Blake3,
+    Sha256,
+    /// SHA-1, for tools that expect it; not collision resistant.
+    Sha1,
+}
+
+impl HashAlgorithm {
+    /// Name as configured and accepted on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha1 => "sha1",
+        }
+    }
+
+    /// Name in a sidecar line, as `sha256sum --tag` and `b3sum --tag` write it.
+    fn tag(self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "BLAKE3",
+            HashAlgorithm::Sha256 => "SHA256",
+            HashAlgorithm::Sha1 => "SHA1",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<HashAlgorithm> {
+        [HashAlgorithm::Blake3, HashAlgorithm::Sha256, HashAlgorithm::Sha1]
+            .into_iter()
+            .find(|algorithm| algorithm.tag() == tag)
+    }
+}
+
+/// Feed everything read from `reader` to `update`, in chunks.
+fn read_chunks(mut reader: impl io::Read, mut update: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        update(&buf[..n]);
+    }
+}
+
+/// Hex SHA-256 of everything read from `reader`, streamed in chunks.
+pub(crate) fn sha256_of(reader: impl io::Read) -> io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    read_chunks(reader, |chunk| hasher.update(chunk))?;
+    Ok(hex_digest(&hasher.finalize()))
+}
+
+/// Hex digest of everything read from `reader` with `algorithm`.
+pub(crate) fn digest_of(algorithm: HashAlgorithm, reader: impl io::Read) -> io::Result<String> {
+    use sha1::{Digest, Sha1};
+
+    match algorithm {
+        HashAlgorithm::Sha256 => sha256_of(reader),
+        HashAlgorithm::Sha1 => {
+            let mut hasher = Sha1::new();
+            read_chunks(reader, |chunk| hasher.update(chunk))?;
+            Ok(hex_digest(&hasher.finalize()))
+        }
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            read_chunks(reader, |chunk| {
+                hasher.update(chunk);
+            })?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }
+    }
+}
+
+pub(crate) fn digest_file(algorithm: HashAlgorithm, path: &Path) -> io::Result<String> {
+    digest_of(algorithm, fs::File::open(path)?)
+}
+
+/// Lowercase hex of a finished digest.
+pub(crate) fn hex_digest(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn sha256_file(path: &Path) -> io::Result<String> {
+    sha256_of(fs::File::open(path)?)
+}
+
+/// SHA-256 of a backup's *content*: compressed backups are hashed after decompression
+/// and deltas after being rebuilt from their base, so they compare equal to their source;
+/// deduplicated backups are hashed through to their content in the store.
+/// Encrypted backups cannot be read without their passphrase and are refused.
+pub(crate) fn sha256_backup_content(backup: &Path) -> io::Result<String> {
+    if is_enc(backup) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is encrypted; its content needs the passphrase", backup.display()),
+        ));
+    }
+    if is_delta(backup) {
+        return sha256_delta_content(backup);
+    }
+    if is_ref(backup) {
+        return sha256_file(&ref_blob(backup)?);
+    }
+    let file = fs::File::open(backup)?;
+    if is_gz(backup) {
+        sha256_of(flate2::read::GzDecoder::new(io::BufReader::new(file)))
+    } else {
+        sha256_of(file)
+    }
+}
+
+/// Confirm a freshly written backup has the same content as its source.
+/// Returns the (shared) content digest.
+pub(crate) fn check_backup_matches(source: &Path, backup: &Path) -> Result<String, BackupError> {
+    let digest = sha256_file(source)?;
+    if digest != sha256_backup_content(backup)? {
+        return Err(BackupError::Mismatch(backup.to_path_buf()));
+    }
+    Ok(digest)
+}
+
+/// Checksum sidecar for a backup: "file.ext.bak" -> "file.ext.bak.sha256". The name stays
+/// the same whatever the algorithm; the sidecar says which one it used.
+pub(crate) fn sidecar_path_for(backup: &Path) -> PathBuf {
+    backup.with_file_name(format!("{}.sha256", file_name_of(backup)))
+}
+
+/// Record the backup's own digest next to it in the tagged format `sha256sum -c` and
+/// `b3sum -c` read: "BLAKE3 (<name>) = <hex>".
+pub(crate) fn write_checksum_sidecar(
+    backup: &Path,
+    algorithm: HashAlgorithm,
+) -> io::Result<PathBuf> {
+    let sidecar = sidecar_path_for(backup);
+    let digest = digest_file(algorithm, backup)?;
+    let line = format!("{} ({}) = {}\n", algorithm.tag(), file_name_of(backup), digest);
+    fs::write(&sidecar, line)?;
+    Ok(sidecar)
+}
+
+/// The algorithm and digest recorded in a backup's sidecar, or `None` if there is no
+/// sidecar. Untagged "<hex>  <name>" sidecars, written before the algorithm could be
+/// chosen, are SHA-256.
+pub(crate) fn read_checksum_sidecar(
+    backup: &Path,
+) -> io::Result<Option<(HashAlgorithm, String)>> {
+    let sidecar = sidecar_path_for(backup);
+    if !sidecar.exists() {
+        return Ok(None);
+    }
+    let invalid = |msg: &str| {
+        let msg = format!("Checksum sidecar {} {}", sidecar.display(), msg);
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    };
+    let contents = fs::read_to_string(&sidecar)?;
+    let line = contents.lines().next().unwrap_or("").trim();
+    if line.is_empty() {
+        return Err(invalid("is empty"));
+    }
+    let Some((tagged, hex)) = line.rsplit_once(" = ") else {
+        let hex = line.split_whitespace().next().unwrap_or(line);
+        return Ok(Some((HashAlgorithm::Sha256, hex.to_lowercase())));
+    };
+    let tag = tagged.split_once(" (").map_or(tagged, |(tag, _)| tag);
+    match HashAlgorithm::from_tag(tag) {
+        Some(algorithm) => Ok(Some((algorithm, hex.trim().to_lowercase()))),
+        None => Err(invalid(&format!("uses an unknown algorithm '{}'", tag))),
+    }
+}
@@ -0,0 +1,48 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::atomic::write_atomically;
+use crate::progress::{ProgressFn, ProgressReader};
+
+// ---------- Compression ----------
+
+/// Stream `src` into a gzip-compressed `dst` (written atomically) at `level` (0-9).
+/// Returns (original, compressed) sizes.
+pub(crate) fn compress_file(
+    src: &Path,
+    dst: &Path,
+    level: u32,
+    progress: Option<&ProgressFn>,
+) -> io::Result<(u64, u64)> {
+    let original = write_atomically(dst, |out| {
+        let file = fs::File::open(src)?;
+        let total = file.metadata()?.len();
+        let mut input = io::BufReader::new(ProgressReader::new(file, total, progress));
+        let level = flate2::Compression::new(level);
+        let mut encoder = flate2::write::GzEncoder::new(io::BufWriter::new(&mut *out), level);
+        let original = io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.flush()?;
+        out.set_permissions(fs::metadata(src)?.permissions())?;
+        Ok(original)
+    })?;
+    let compressed = fs::metadata(dst)?.len();
+    Ok((original, compressed))
+}
+
+/// Stream a gzip-compressed `src` back into plain `dst`. Returns the decompressed size.
+/// Progress is measured in compressed bytes read.
+pub(crate) fn decompress_file(
+    src: &Path,
+    dst: &Path,
+    progress: Option<&ProgressFn>,
+) -> io::Result<u64> {
+    let file = fs::File::open(src)?;
+    let total = file.metadata()?.len();
+    let input = io::BufReader::new(ProgressReader::new(file, total, progress));
+    let mut decoder = flate2::read::GzDecoder::new(input);
+    let mut output = io::BufWriter::new(fs::File::create(dst)?);
+    let written = io::copy(&mut decoder, &mut output)?;
+    output.flush()?;
+    Ok(written)
+}
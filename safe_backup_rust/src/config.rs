@@ -0,0 +1,438 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::checksum::HashAlgorithm;
+use crate::error::BackupError;
+use crate::log::{LogFormat, LogLevel, LOG_TIME_FORMAT};
+use crate::paths::{parse_name_template, VERSION_STAMP_FORMAT};
+use crate::progress::parse_size;
+
+// ---------- Settings ----------
+
+/// Config file looked up in the current directory when no `--config` is given.
+pub const CONFIG_FILE_NAME: &str = "safe_backup.toml";
+
+/// Copy buffers larger than this would only waste memory.
+const MAX_COPY_BUFFER_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Settings that shape how the operations behave. Loaded from `safe_backup.toml`
+/// when present; every key is optional and falls back to the built-in default.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where `log_action` appends its entries.
+    pub log_path: PathBuf,
+    /// Plain pipe-delimited lines or newline-delimited JSON.
+    pub log_format: LogFormat,
+    /// Which entries are written: "all", "results" (successes and failures), "failures",
+    /// or "off" for no log. `--no-log` and `--log-level` override it.
+    pub log_level: LogLevel,
+    /// Rotate the log once it reaches this many bytes (0 disables rotation).
+    pub log_max_size: u64,
+    /// How many rotated logs ("logfile.txt.1", ".2", ...) to keep.
+    pub log_max_files: usize,
+    /// strftime-style format of each entry's local time, e.g. "%Y-%m-%d %H:%M:%S%.3f". It
+    /// must hold the date and the time to the second, so `log --since` can read it back.
+    pub log_time_format: String,
+    /// Number the log entries "#1", "#2", ... on, across rotations, so a missing entry
+    /// shows as a gap even where timestamps are the same; `verify-log` reports gaps.
+    pub log_sequence: bool,
+    /// Appended to a file's name to form its backup name, e.g. ".bak", ".backup" or "~".
+    /// `--suffix` overrides it. Backups are only looked for under the suffix in effect:
+    /// after changing it, the ones made with the old suffix are reached with `--suffix`.
+    pub backup_suffix: String,
+    /// Keep all backups in this directory instead of beside each file. `--backup-dir` and
+    /// $SAFE_BACKUP_DIR override it.
+    pub backup_dir: Option<PathBuf>,
+    /// Mirror each file's folders under `backup_dir` ("src/main.rs" ->
+    /// "<backup_dir>/src/main.rs.bak") instead of flattening them into the backup's name
+    /// ("src%2Fmain.rs.bak"). `--preserve-structure` turns it on. Backups are only looked
+    /// for in the layout in effect.
+    pub preserve_structure: bool,
+    /// What a plain (not versioned) backup does when its ".bak" already exists with other
+    /// content: "overwrite" it, "skip" the file and keep the existing backup, fail with
+    /// "error", or keep a timestamped "version" instead, for this backup only. `--if-exists`
+    /// overrides it.
+    pub existing_backup: ExistingBackup,
+    /// Create the folders a backup goes into under `backup_dir` when they are missing, as
+    /// with `preserve_structure`; otherwise such a backup is refused. `--parents` turns it
+    /// on.
+    pub create_parents: bool,
+    /// Keep versioned backups in one folder per file under this directory:
+    /// "<versions_dir>/notes.txt/2024-06-01T14-30-00.bak".
+    pub versions_dir: Option<PathBuf>,
+    /// strftime-style format of the time in versioned backup names, e.g. "%Y%m%d-%H%M%S".
+    /// It must hold the date and the time to the second and give a file name without
+    /// '.', '/' or '\' (nor ':' and the other characters Windows refuses, on Windows).
+    /// Versions are only recognized by the format in effect.
+    pub version_stamp_format: String,
+    /// How versioned backup names are built, before the suffix, from `{name}` and `{ext}`
+    /// (the file name split at its last dot: "notes" and ".txt"), `{timestamp}`
+    /// (`version_stamp_format`) and `{counter}` ("-1", "-2", ... when two versions fall in
+    /// the same second; right after the timestamp unless placed). "{timestamp}_{name}{ext}"
+    /// sorts a folder by date. Unset: "notes.txt.<stamp>", or just "<stamp>" in the
+    /// `versions_dir`. Like the stamp format, versions are only recognized by the template
+    /// in effect.
+    pub name_template: Option<String>,
+    /// How many backups `prune` keeps when no count is given. Versioned backups also prune
+    /// down to it right after each new version (`--keep` overrides it for one run).
+    pub keep_versions: Option<usize>,
+    /// How many files a glob backup copies at once.
+    pub jobs: usize,
+    /// How many times a copy is tried when it fails with a transient error (a timeout, an
+    /// interrupted or would-block call), as happens on network mounts.
+    pub copy_attempts: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    pub retry_delay_ms: u64,
+    /// Bytes read and written at a time when copying: a byte count, or a size such as
+    /// "1M". Larger buffers can be faster on network filesystems.
+    #[serde(deserialize_with = "size_value")]
+    pub copy_buffer_size: u64,
+    /// Gzip level for compressed backups and archives, from 0 (store only, fastest) to 9
+    /// (smallest).
+    pub compress_level: u32,
+    /// Sync each log entry, and the directory and sidecars of each new backup, to disk
+    /// before reporting success, so a crash or power cut right afterwards cannot lose them.
+    /// Every sync waits for the disk (milliseconds each on spinning disks and network
+    /// mounts), so backing up many small files is noticeably slower; turn it off when speed
+    /// matters more. A backup's content is always flushed before it replaces the old one.
+    pub durable: bool,
+    /// Digest recorded in the checksum sidecar of each new backup.
+    pub hash: HashAlgorithm,
+    /// Reuse the digest of a file whose size and modification time have not changed since
+    /// it was last hashed (kept in ".trash/checksums.json"), so verify and status sweeps
+    /// over unchanged files are fast. Damage that leaves both alone goes unnoticed until
+    /// a run with `--rehash`, which hashes everything again.
+    pub checksum_cache: bool,
+    /// How to back up files by extension, overriding how the backup was asked for, e.g.
+    /// `[extensions.log]` with `compress = true`, `[extensions.jpg]` with
+    /// `compress = false`. Extensions are given without the dot and matched ignoring case;
+    /// the longest one that ends the name wins, so "tar.gz" beats "gz".
+    pub extensions: BTreeMap<String, ExtensionRule>,
+    /// Refuse to back up files larger than this: a byte count, or a size such as "1G".
+    #[serde(deserialize_with = "size_setting")]
+    pub max_size: Option<u64>,
+    /// Private key for "sftp://" locations, instead of the ssh agent and the default keys
+    /// in ~/.ssh (with those, a password is asked for if no key is accepted).
+    pub sftp_identity: Option<PathBuf>,
+    /// Let backup, restore and delete act on a symlink's target instead of refusing it.
+    pub follow_symlinks: bool,
+    /// Answers that confirm a yes/no prompt besides "y" and "yes", e.g. ["ja", "oui"];
+    /// case is ignored. Anything else, including an empty answer, still means no.
+    pub confirm_words: Vec<String>,
+    /// Deleting a file at least this big (a byte count, or a size such as "100M") asks for
+    /// its name to be typed again instead of a yes/no. `--force` still skips asking.
+    #[serde(deserialize_with = "size_setting")]
+    pub confirm_name_size: Option<u64>,
+    /// Shell command run before each backup, restore and delete, e.g. to stop the service
+    /// that writes the file; if it exits with an error the operation is not done. It finds
+    /// the operation and the file in $SAFE_BACKUP_OPERATION and $SAFE_BACKUP_FILE ($1 and
+    /// $2 with sh). `--no-hooks` skips both hooks.
+    pub pre_hook: Option<String>,
+    /// Shell command run after each backup, restore and delete, whether it worked or not
+    /// ($SAFE_BACKUP_OUTCOME says), e.g. to start the service again. Its failure is logged
+    /// but does not fail the operation, which is done by then.
+    pub post_hook: Option<String>,
+    /// Let the file operations (backup, restore, verify, ...) take an absolute path. Only
+    /// ever set from `--allow-absolute`, for one run at a time.
+    #[serde(skip)]
+    pub allow_absolute: bool,
+    /// Report what would happen without writing, copying or deleting anything.
+    #[serde(skip)]
+    pub dry_run: bool,
+    /// The config file the settings were read from, if any; set by `load`.
+    #[serde(skip)]
+    pub loaded_from: Option<PathBuf>,
+    /// Where the settings not left at their default came from, by key: `load` marks the
+    /// keys of the file, the CLI those its flags (or the environment) set.
+    #[serde(skip)]
+    pub origins: BTreeMap<String, SettingOrigin>,
+}
+
+/// What `Config::existing_backup` does with a backup that is already there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingBackup {
+    /// Replace it with the new backup.
+    #[default]
+    Overwrite,
+    /// Keep it and leave the file out, so the backup stays the first one made.
+    Skip,
+    /// Refuse the backup with `BackupError::BackupExists`.
+    Error,
+    /// Leave it alone and make a timestamped version instead.
+    Version,
+}
+
+impl ExistingBackup {
+    /// Name as configured, logged and accepted on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            ExistingBackup::Overwrite => "overwrite",
+            ExistingBackup::Skip => "skip",
+            ExistingBackup::Error => "error",
+            ExistingBackup::Version => "version",
+        }
+    }
+}
+
+/// Where the value of a setting in effect came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SettingOrigin {
+    #[default]
+    Default,
+    /// The config file.
+    Config,
+    /// An environment variable, such as $SAFE_BACKUP_DIR.
+    Env,
+    /// A command-line flag.
+    Cli,
+}
+
+impl SettingOrigin {
+    pub fn name(self) -> &'static str {
+        match self {
+            SettingOrigin::Default => "default",
+            SettingOrigin::Config => "config",
+            SettingOrigin::Env => "env",
+            SettingOrigin::Cli => "cli",
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            log_path: PathBuf::from("logfile.txt"),
+            log_format: LogFormat::Plain,
+            log_level: LogLevel::All,
+            log_max_size: 10 * 1024 * 1024,
+            log_max_files: 5,
+            log_time_format: LOG_TIME_FORMAT.to_string(),
+            log_sequence: false,
+            backup_suffix: ".bak".to_string(),
+            backup_dir: None,
+            preserve_structure: false,
+            existing_backup: ExistingBackup::Overwrite,
+            create_parents: false,
+            versions_dir: None,
+            version_stamp_format: VERSION_STAMP_FORMAT.to_string(),
+            name_template: None,
+            keep_versions: None,
+            jobs: 1,
+            copy_attempts: 3,
+            retry_delay_ms: 200,
+            copy_buffer_size: 64 * 1024,
+            compress_level: 6,
+            durable: true,
+            hash: HashAlgorithm::Blake3,
+            checksum_cache: true,
+            extensions: BTreeMap::new(),
+            max_size: None,
+            sftp_identity: None,
+            follow_symlinks: false,
+            confirm_words: Vec::new(),
+            confirm_name_size: None,
+            pre_hook: None,
+            post_hook: None,
+            allow_absolute: false,
+            dry_run: false,
+            loaded_from: None,
+            origins: BTreeMap::new(),
+        }
+    }
+}
+
+/// What `Config::extensions` says about files with one extension. Settings left out keep
+/// what the backup was asked for.
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtensionRule {
+    /// Compress the backup, or never compress it. Compression is not added to an
+    /// encrypted, incremental or deduplicated backup.
+    pub compress: Option<bool>,
+    /// Encrypt the backup (instead of compressing it), or never encrypt it.
+    pub encrypt: Option<bool>,
+    /// Skip these files, in globs and directory backups too, unless `--allow-backup`.
+    pub exclude: bool,
+}
+
+impl Config {
+    /// Load settings from `path`, or from `safe_backup.toml` in the CWD if it exists.
+    /// No config file at all means defaults; an explicitly given file must exist.
+    pub fn load(path: Option<&Path>) -> Result<Config, BackupError> {
+        let path = match path {
+            Some(p) => p.to_path_buf(),
+            None => {
+                let default = PathBuf::from(CONFIG_FILE_NAME);
+                if !default.exists() {
+                    return Ok(Config::default());
+                }
+                default
+            }
+        };
+        let text = fs::read_to_string(&path)?;
+        let invalid = |e: toml::de::Error| {
+            BackupError::Config(format!("Invalid config {}: {}", path.display(), e))
+        };
+        let mut cfg: Config = toml::from_str(&text).map_err(invalid)?;
+        cfg.validate()?;
+        let keys = toml::from_str::<toml::Table>(&text).map_err(invalid)?;
+        cfg.origins = keys.into_iter().map(|(key, _)| (key, SettingOrigin::Config)).collect();
+        cfg.loaded_from = Some(path);
+        Ok(cfg)
+    }
+
+    /// Reject settings the operations cannot work with (empty paths, odd suffixes, ...).
+    pub fn validate(&self) -> Result<(), BackupError> {
+        let invalid = |msg: &str| Err(BackupError::Config(msg.to_string()));
+        if self.log_path.as_os_str().is_empty() {
+            return invalid("log_path must not be empty");
+        }
+        let suffix = &self.backup_suffix;
+        if suffix.is_empty() || suffix == "." {
+            return invalid("backup_suffix must not be empty, e.g. \".bak\" or \"~\"");
+        }
+        if suffix.contains(['/', '\\', '\0']) {
+            return invalid("backup_suffix must not contain path separators or NUL");
+        }
+        // These are appended to backups and their sidecars, and would make names ambiguous.
+        let appended = [".gz", ".enc", ".delta", ".ref", ".sha256", ".meta", ".state"];
+        if appended.contains(&suffix.as_str()) {
+            return invalid("backup_suffix must not be an extension backups get appended");
+        }
+        if self.backup_dir.as_ref().is_some_and(|d| d.as_os_str().is_empty()) {
+            return invalid("backup_dir must not be empty");
+        }
+        let escapes = |d: &PathBuf| d.components().any(|c| c == Component::ParentDir);
+        if self.backup_dir.as_ref().is_some_and(escapes) {
+            return invalid("backup_dir must not contain '..'");
+        }
+        if self.versions_dir.as_ref().is_some_and(|d| d.as_os_str().is_empty()) {
+            return invalid("versions_dir must not be empty");
+        }
+        if self.versions_dir.as_ref().is_some_and(escapes) {
+            return invalid("versions_dir must not contain '..'");
+        }
+        check_time_format("log_time_format", &self.log_time_format, &['|', '\n', '\r'])?;
+        let unsafe_in_names: &[char] = match cfg!(windows) {
+            true => &['.', '/', '\\', '\0', ':', '*', '?', '"', '<', '>', '|'],
+            false => &['.', '/', '\\', '\0'],
+        };
+        check_time_format("version_stamp_format", &self.version_stamp_format, unsafe_in_names)?;
+        if let Some(template) = &self.name_template {
+            parse_name_template(template).map_err(|why| {
+                BackupError::Config(format!("name_template {}", why))
+            })?;
+            if let Some(c) = template.chars().find(|c| unsafe_in_names[1..].contains(c)) {
+                return invalid(&format!("name_template must not contain {:?}", c));
+            }
+        }
+        if self.keep_versions == Some(0) {
+            return invalid("keep_versions must be at least 1");
+        }
+        if self.jobs == 0 {
+            return invalid("jobs must be at least 1");
+        }
+        if self.copy_attempts == 0 {
+            return invalid("copy_attempts must be at least 1");
+        }
+        if self.copy_buffer_size == 0 || self.copy_buffer_size > MAX_COPY_BUFFER_SIZE {
+            return invalid("copy_buffer_size must be more than 0 and at most 1G");
+        }
+        if self.compress_level > 9 {
+            return invalid("compress_level must be between 0 and 9");
+        }
+        if self.confirm_words.iter().any(|w| w.trim().is_empty()) {
+            return invalid("confirm_words must not contain an empty answer");
+        }
+        for (key, hook) in [("pre_hook", &self.pre_hook), ("post_hook", &self.post_hook)] {
+            if hook.as_ref().is_some_and(|command| command.trim().is_empty()) {
+                return invalid(&format!("{} must not be empty", key));
+            }
+        }
+        if self.max_size == Some(0) {
+            return invalid("max_size must be more than 0");
+        }
+        for (extension, rule) in &self.extensions {
+            if extension.is_empty() || extension.starts_with('.') {
+                return invalid("extensions must be given without the dot, e.g. [extensions.log]");
+            }
+            if extension.contains(['/', '\\', '\0']) {
+                return invalid("extensions must not contain path separators or NUL");
+            }
+            if rule.compress == Some(true) && rule.encrypt == Some(true) {
+                return invalid("an extension rule can compress or encrypt, not both");
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the setting `key` (as in the config file) got its value.
+    pub fn origin(&self, key: &str) -> SettingOrigin {
+        self.origins.get(key).copied().unwrap_or_default()
+    }
+
+    /// The rule of `extensions` for `path`'s name, if one matches.
+    pub(crate) fn extension_rule(&self, path: &Path) -> Option<&ExtensionRule> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        self.extensions
+            .iter()
+            .filter(|(extension, _)| {
+                let extension = extension.to_lowercase();
+                name.len() > extension.len() + 1
+                    && name.ends_with(&extension)
+                    && name[..name.len() - extension.len()].ends_with('.')
+            })
+            .max_by_key(|(extension, _)| extension.len())
+            .map(|(_, rule)| rule)
+    }
+}
+
+/// Reject a strftime-style `format` setting chrono cannot use, that does not give back
+/// the time it was formatted from (to the second), or whose output contains any of
+/// `refused`.
+fn check_time_format(key: &str, format: &str, refused: &[char]) -> Result<(), BackupError> {
+    use chrono::format::{Item, StrftimeItems};
+    use chrono::TimeZone;
+
+    let invalid = |why: &str| Err(BackupError::Config(format!("{} {}", key, why)));
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return invalid("is not a valid strftime format");
+    }
+    // Entries and names are stamped with the local time, which has an offset to format.
+    let sample = chrono::FixedOffset::east_opt(0)
+        .and_then(|utc| utc.with_ymd_and_hms(2024, 11, 28, 21, 47, 35).single())
+        .expect("a valid sample time");
+    let mut text = String::new();
+    let formatted = write!(text, "{}", sample.format(format));
+    let parsed = chrono::NaiveDateTime::parse_from_str(&text, format);
+    if formatted.is_err() || parsed != Ok(sample.naive_local()) {
+        return invalid("must hold the date and the time down to the second");
+    }
+    if let Some(c) = text.chars().find(|c| refused.contains(c)) {
+        return invalid(&format!("must not produce {:?}", c));
+    }
+    Ok(())
+}
+
+/// A size setting given either as a plain byte count or as a string with a unit ("1G").
+fn size_value<'de, D: serde::Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match serde::Deserialize::deserialize(d)? {
+        Size::Bytes(bytes) => Ok(bytes),
+        Size::Text(text) => parse_size(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+/// `size_value` for settings that are off unless given.
+fn size_setting<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+    size_value(d).map(Some)
+}
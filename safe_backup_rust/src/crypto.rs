@@ -0,0 +1,102 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use zeroize::Zeroizing;
+
+use crate::atomic::write_atomically;
+use crate::error::BackupError;
+use crate::progress::{ProgressFn, ProgressReader};
+
+// ---------- Encryption ----------
+
+/// Asked for the passphrase of an encrypted backup at `path`. The flag is true when a new
+/// backup is about to be encrypted, so the prompt can ask twice to catch typos.
+pub type PassphraseFn = dyn Fn(&Path, bool) -> io::Result<String> + Send + Sync;
+
+/// Encrypted backups start with this, followed by the Argon2 salt and the AES-GCM nonce.
+pub(crate) const ENC_MAGIC: &[u8; 8] = b"SBAKENC1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Derive the AES-256 key from a passphrase with Argon2id. The key is wiped on drop.
+fn derive_key(passphrase: &str, salt: &[u8]) -> io::Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| io::Error::other(format!("Key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+/// Encrypt `src` into `dst` (written atomically) under a key derived from `passphrase`.
+/// Returns the plaintext size.
+pub(crate) fn encrypt_file(
+    src: &Path,
+    dst: &Path,
+    passphrase: &str,
+    progress: Option<&ProgressFn>,
+) -> io::Result<u64> {
+    let file = fs::File::open(src)?;
+    let total = file.metadata()?.len();
+    let mut plain = Zeroizing::new(Vec::with_capacity(total as usize));
+    ProgressReader::new(file, total, progress).read_to_end(&mut plain)?;
+
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let key = derive_key(passphrase, &salt)?;
+    let sealed = Aes256Gcm::new((&*key).into())
+        .encrypt(&nonce, plain.as_slice())
+        .map_err(|_| io::Error::other("Encryption failed"))?;
+
+    write_atomically(dst, |out| {
+        out.write_all(ENC_MAGIC)?;
+        out.write_all(&salt)?;
+        out.write_all(&nonce)?;
+        out.write_all(&sealed)?;
+        out.set_permissions(fs::metadata(src)?.permissions())?;
+        Ok(())
+    })?;
+    Ok(plain.len() as u64)
+}
+
+/// Decrypt an encrypted backup into memory (wiped on drop). A wrong passphrase or a
+/// damaged file fails the authentication check and yields `BackupError::Decrypt`.
+pub(crate) fn decrypt_to_vec(
+    src: &Path,
+    passphrase: &str,
+    progress: Option<&ProgressFn>,
+) -> Result<Zeroizing<Vec<u8>>, BackupError> {
+    let file = fs::File::open(src)?;
+    let total = file.metadata()?.len();
+    let mut data = Vec::new();
+    ProgressReader::new(file, total, progress).read_to_end(&mut data)?;
+
+    let header = data.strip_prefix(ENC_MAGIC.as_slice());
+    let Some(rest) = header.filter(|r| r.len() >= SALT_LEN + NONCE_LEN) else {
+        return Err(BackupError::Decrypt(src.to_path_buf()));
+    };
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    let key = derive_key(passphrase, salt)?;
+    let plain = Aes256Gcm::new((&*key).into())
+        .decrypt(Nonce::from_slice(nonce), sealed)
+        .map_err(|_| BackupError::Decrypt(src.to_path_buf()))?;
+    Ok(Zeroizing::new(plain))
+}
+
+/// Decrypt `src` into plain `dst` (written atomically). Nothing is written if the
+/// passphrase is wrong. Returns the plaintext size.
+pub(crate) fn decrypt_file(
+    src: &Path,
+    dst: &Path,
+    passphrase: &str,
+    progress: Option<&ProgressFn>,
+) -> Result<u64, BackupError> {
+    let plain = decrypt_to_vec(src, passphrase, progress)?;
+    write_atomically(dst, |out| out.write_all(&plain))?;
+    Ok(plain.len() as u64)
+}
@@ -0,0 +1,216 @@
+use std::fs;
+use std::io::{self, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomically;
+use crate::checksum::{hex_digest, sha256_file};
+use crate::paths::file_name_of;
+use crate::progress::ProgressFn;
+
+// ---------- Incremental (delta) backups ----------
+
+/// A delta file is this, a little-endian u32 manifest length, the JSON manifest, then the
+/// changed blocks in index order.
+pub(crate) const DELTA_MAGIC: &[u8; 8] = b"SBAKDLT1";
+
+/// Files are compared with their base in blocks of this many bytes.
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Manifests larger than this are not ours.
+const MAX_MANIFEST_LEN: u32 = 64 * 1024 * 1024;
+
+/// What a delta needs to rebuild its file from the base.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DeltaManifest {
+    /// File name of the full backup the delta applies to, in the same directory.
+    base: String,
+    /// SHA-256 of the base when the delta was written; a base overwritten since is refused.
+    base_sha256: String,
+    block_size: u64,
+    /// Length of the rebuilt file.
+    size: u64,
+    /// Indices of the blocks stored in the delta, ascending; all others come from the base.
+    changed: Vec<u64>,
+}
+
+/// How much of a file a new delta had to store.
+pub(crate) struct DeltaStats {
+    pub changed: usize,
+    pub blocks: u64,
+}
+
+/// Write a delta of `src` against the full backup `base` to `dst` (atomically): only the
+/// blocks that differ from the base are stored.
+pub(crate) fn write_delta(
+    src: &Path,
+    base: &Path,
+    dst: &Path,
+    progress: Option<&ProgressFn>,
+) -> io::Result<DeltaStats> {
+    let size = fs::metadata(src)?.len();
+    let changed = changed_blocks(src, base)?;
+    let manifest = DeltaManifest {
+        base: file_name_of(base),
+        base_sha256: sha256_file(base)?,
+        block_size: BLOCK_SIZE,
+        size,
+        changed,
+    };
+    let json = serde_json::to_vec(&manifest).map_err(io::Error::other)?;
+
+    write_atomically(dst, |out| {
+        let mut out = BufWriter::new(&mut *out);
+        out.write_all(DELTA_MAGIC)?;
+        out.write_all(&(json.len() as u32).to_le_bytes())?;
+        out.write_all(&json)?;
+        let mut input = io::BufReader::new(fs::File::open(src)?);
+        let mut buf = vec![0; BLOCK_SIZE as usize];
+        let mut wanted = manifest.changed.iter().peekable();
+        let mut done = 0;
+        for index in 0..size.div_ceil(BLOCK_SIZE) {
+            let n = read_block(&mut input, &mut buf)?;
+            if wanted.next_if_eq(&&index).is_some() {
+                out.write_all(&buf[..n])?;
+            }
+            done += n as u64;
+            if let Some(report) = progress {
+                report(done, size);
+            }
+        }
+        out.flush()
+    })?;
+    Ok(DeltaStats { changed: manifest.changed.len(), blocks: size.div_ceil(BLOCK_SIZE) })
+}
+
+/// Rebuild the file a delta describes into `dst` (atomically). Returns its size.
+pub(crate) fn restore_delta(
+    delta: &Path,
+    dst: &Path,
+    progress: Option<&ProgressFn>,
+) -> io::Result<u64> {
+    write_atomically(dst, |out| {
+        let mut out = BufWriter::new(&mut *out);
+        let size = rebuild(delta, &mut out, progress)?;
+        out.flush()?;
+        Ok(size)
+    })
+}
+
+/// SHA-256 of the file a delta describes, without writing it anywhere.
+pub(crate) fn sha256_delta_content(delta: &Path) -> io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    rebuild(delta, &mut hasher, None)?;
+    Ok(hex_digest(&hasher.finalize()))
+}
+
+/// The file a delta describes, rebuilt in memory.
+pub(crate) fn read_delta(delta: &Path) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    rebuild(delta, &mut content, None)?;
+    Ok(content)
+}
+
+/// The full backup a delta applies to.
+pub(crate) fn delta_base(delta: &Path) -> io::Result<PathBuf> {
+    let (manifest, _) = open_delta(delta)?;
+    Ok(delta.with_file_name(manifest.base))
+}
+
+/// Indices of the `src` blocks that differ from (or lie beyond the end of) `base`.
+fn changed_blocks(src: &Path, base: &Path) -> io::Result<Vec<u64>> {
+    let mut src = io::BufReader::new(fs::File::open(src)?);
+    let mut base = io::BufReader::new(fs::File::open(base)?);
+    let mut new_block = vec![0; BLOCK_SIZE as usize];
+    let mut old_block = vec![0; BLOCK_SIZE as usize];
+    let mut changed = Vec::new();
+    for index in 0.. {
+        let n = read_block(&mut src, &mut new_block)?;
+        if n == 0 {
+            break;
+        }
+        let m = read_block(&mut base, &mut old_block)?;
+        if new_block[..n] != old_block[..m] {
+            changed.push(index);
+        }
+    }
+    Ok(changed)
+}
+
+/// Fill `buf` as far as the input allows; short only at the end of the input.
+fn read_block(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// The manifest of a delta, and a reader positioned at its first stored block.
+fn open_delta(delta: &Path) -> io::Result<(DeltaManifest, io::BufReader<fs::File>)> {
+    let invalid = |msg: &str| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", delta.display(), msg))
+    };
+    let mut input = io::BufReader::new(fs::File::open(delta)?);
+    let mut header = [0u8; 12];
+    input.read_exact(&mut header).map_err(|_| invalid("not a delta backup"))?;
+    if &header[..8] != DELTA_MAGIC {
+        return Err(invalid("not a delta backup"));
+    }
+    let len = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+    if len > MAX_MANIFEST_LEN {
+        return Err(invalid("damaged delta manifest"));
+    }
+    let mut json = vec![0; len as usize];
+    input.read_exact(&mut json)?;
+    let manifest: DeltaManifest =
+        serde_json::from_slice(&json).map_err(|_| invalid("damaged delta manifest"))?;
+    if manifest.block_size == 0 {
+        return Err(invalid("damaged delta manifest"));
+    }
+    Ok((manifest, input))
+}
+
+/// Write the file a delta describes to `out`: stored blocks from the delta, the rest from
+/// its base, which must still be the one the delta was made against.
+fn rebuild(delta: &Path, out: &mut impl Write, progress: Option<&ProgressFn>) -> io::Result<u64> {
+    let (manifest, mut stored) = open_delta(delta)?;
+    let base_path = delta.with_file_name(&manifest.base);
+    let base_sha256 = sha256_file(&base_path).map_err(|e| {
+        io::Error::new(e.kind(), format!("Base backup {}: {}", base_path.display(), e))
+    })?;
+    if base_sha256 != manifest.base_sha256 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Base backup {} has changed since {} was made",
+                base_path.display(),
+                file_name_of(delta)
+            ),
+        ));
+    }
+
+    let mut base = fs::File::open(&base_path)?;
+    let block_size = manifest.block_size;
+    let mut buf = vec![0; block_size as usize];
+    let mut wanted = manifest.changed.iter().peekable();
+    for index in 0..manifest.size.div_ceil(block_size) {
+        let start = index * block_size;
+        let block = &mut buf[..(manifest.size - start).min(block_size) as usize];
+        if wanted.next_if_eq(&&index).is_some() {
+            stored.read_exact(block)?;
+        } else {
+            base.seek(io::SeekFrom::Start(start))?;
+            base.read_exact(block)?;
+        }
+        out.write_all(block)?;
+        if let Some(report) = progress {
+            report(start + block.len() as u64, manifest.size);
+        }
+    }
+    Ok(manifest.size)
+}
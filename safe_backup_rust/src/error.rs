@@ -0,0 +1,249 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::cancel::is_cancelled;
+use crate::ops::CheckStatus;
+use crate::progress::format_size;
+
+// ---------- Errors ----------
+
+/// Why an operation failed. Match on the variant rather than on the message.
+#[derive(Debug)]
+pub enum BackupError {
+    /// The filename was refused by `resolve_safe_path` (empty, absolute, "..", NUL).
+    PathRejected(String),
+    /// A path component is a reserved Windows device name such as "CON" or "com1.txt".
+    ReservedName(String),
+    /// A source file or backup the operation needs does not exist.
+    NotFound(String),
+    /// The path exists but is not a regular file.
+    NotRegularFile(PathBuf),
+    /// On a case-insensitive filesystem, the requested name only matches an existing
+    /// entry with different case ("Notes.txt" for "notes.txt").
+    CaseMismatch { requested: PathBuf, existing: PathBuf },
+    /// The path is a symlink, which could lead outside the base directory, and
+    /// `follow_symlinks` is off.
+    Symlink(PathBuf),
+    /// The request cannot be carried out as asked (unknown version, missing count, ...).
+    InvalidInput(String),
+    /// A freshly written backup does not have the same content as its source.
+    Mismatch(PathBuf),
+    /// An encrypted backup could not be decrypted: wrong passphrase, or the file is damaged.
+    Decrypt(PathBuf),
+    /// The file is over the configured `max_size` for backups.
+    TooLarge { path: PathBuf, size: u64, limit: u64 },
+    /// The backup destination's filesystem has less free space than the backup needs.
+    InsufficientSpace { path: PathBuf, needed: u64, available: u64 },
+    /// `check` found the newest backup of a file missing, stale, corrupt or too old.
+    CheckFailed { file: PathBuf, status: CheckStatus },
+    /// Some operations of a batch (e.g. a glob) failed; each was reported on its own.
+    BatchFailed { failed: usize, total: usize },
+    /// The config file could not be parsed or holds an unusable value.
+    Config(String),
+    /// `cancel` stopped the copy; what it had written was removed.
+    Cancelled,
+    /// Another process holds the base directory's lock (see `BackupManager::lock`).
+    Locked { path: PathBuf, pid: Option<u32> },
+    /// The operation went through but gave these warnings, which `--strict` counts as a
+    /// failure.
+    Strict(Vec<String>),
+    /// A plain backup already exists and `existing_backup` is "error".
+    BackupExists(PathBuf),
+    /// The `pre_hook` exited with an error (or could not be run), so `operation` was not
+    /// done.
+    HookFailed { operation: &'static str, reason: String },
+    /// Another process has the file open and does not let it be read (a sharing violation
+    /// on Windows), even after the `copy_attempts`.
+    InUse(PathBuf),
+    /// A restored file does not read back as its backup's recorded content. The restore
+    /// can be undone (`BackupManager::undo`) when `undoable`.
+    RestoreMismatch { target: PathBuf, undoable: bool },
+    /// `test-restore` read the backup back, but it or its restored content does not match
+    /// what was recorded for it.
+    TestRestoreFailed(PathBuf),
+    /// Any other I/O failure.
+    Io(io::Error),
+}
+
+impl BackupError {
+    /// Short, stable name of the variant, used to tag failures in the log.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            BackupError::PathRejected(_) => "path-rejected",
+            BackupError::ReservedName(_) => "reserved-name",
+            BackupError::NotFound(_) => "not-found",
+            BackupError::NotRegularFile(_) => "not-regular-file",
+            BackupError::CaseMismatch { .. } => "case-mismatch",
+            BackupError::Symlink(_) => "symlink-refused",
+            BackupError::InvalidInput(_) => "invalid-input",
+            BackupError::Mismatch(_) => "mismatch",
+            BackupError::Decrypt(_) => "decrypt",
+            BackupError::TooLarge { .. } => "too-large",
+            BackupError::InsufficientSpace { .. } => "insufficient-space",
+            BackupError::CheckFailed { .. } => "check-failed",
+            BackupError::BatchFailed { .. } => "batch-failed",
+            BackupError::Config(_) => "config",
+            BackupError::Cancelled => "cancelled",
+            BackupError::Locked { .. } => "locked",
+            BackupError::Strict(_) => "strict",
+            BackupError::BackupExists(_) => "backup-exists",
+            BackupError::HookFailed { .. } => "hook-failed",
+            BackupError::InUse(_) => "in-use",
+            BackupError::RestoreMismatch { .. } => "restore-mismatch",
+            BackupError::TestRestoreFailed(_) => "test-restore-failed",
+            BackupError::Io(_) => "io",
+        }
+    }
+
+    /// The variant's name, e.g. "NotFound", for machine-readable error reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BackupError::PathRejected(_) => "PathRejected",
+            BackupError::ReservedName(_) => "ReservedName",
+            BackupError::NotFound(_) => "NotFound",
+            BackupError::NotRegularFile(_) => "NotRegularFile",
+            BackupError::CaseMismatch { .. } => "CaseMismatch",
+            BackupError::Symlink(_) => "Symlink",
+            BackupError::InvalidInput(_) => "InvalidInput",
+            BackupError::Mismatch(_) => "Mismatch",
+            BackupError::Decrypt(_) => "Decrypt",
+            BackupError::TooLarge { .. } => "TooLarge",
+            BackupError::InsufficientSpace { .. } => "InsufficientSpace",
+            BackupError::CheckFailed { .. } => "CheckFailed",
+            BackupError::BatchFailed { .. } => "BatchFailed",
+            BackupError::Config(_) => "Config",
+            BackupError::Cancelled => "Cancelled",
+            BackupError::Locked { .. } => "Locked",
+            BackupError::Strict(_) => "Strict",
+            BackupError::BackupExists(_) => "BackupExists",
+            BackupError::HookFailed { .. } => "HookFailed",
+            BackupError::InUse(_) => "InUse",
+            BackupError::RestoreMismatch { .. } => "RestoreMismatch",
+            BackupError::TestRestoreFailed(_) => "TestRestoreFailed",
+            BackupError::Io(_) => "Io",
+        }
+    }
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::PathRejected(msg)
+            | BackupError::NotFound(msg)
+            | BackupError::InvalidInput(msg)
+            | BackupError::Config(msg) => f.write_str(msg),
+            BackupError::ReservedName(name) => {
+                write!(f, "'{}' is a reserved device name on Windows", name)
+            }
+            BackupError::NotRegularFile(path) => {
+                write!(f, "{} is not a regular file", path.display())
+            }
+            BackupError::CaseMismatch { requested, existing } => write!(
+                f,
+                "{} differs only in case from the existing {}; on this filesystem they are the \
+                 same file, so give the name as it is",
+                requested.display(),
+                existing.display()
+            ),
+            BackupError::Symlink(path) => write!(
+                f,
+                "{} is a symlink and may point outside the working directory; use \
+                 --follow-symlinks to act on its target",
+                path.display()
+            ),
+            BackupError::Mismatch(backup) => {
+                write!(f, "Backup {} does not match its source", backup.display())
+            }
+            BackupError::Decrypt(backup) => write!(
+                f,
+                "Cannot decrypt {}: wrong passphrase or damaged backup",
+                backup.display()
+            ),
+            BackupError::TooLarge { path, size, limit } => write!(
+                f,
+                "{} is {}, over the {} limit for backups",
+                path.display(),
+                format_size(*size),
+                format_size(*limit)
+            ),
+            BackupError::InsufficientSpace { path, needed, available } => write!(
+                f,
+                "Insufficient space for {}: it needs {}, only {} available",
+                path.display(),
+                format_size(*needed),
+                format_size(*available)
+            ),
+            BackupError::CheckFailed { file, status } => {
+                write!(f, "Backup check of {} failed: {}", file.display(), status.describe())
+            }
+            BackupError::BatchFailed { failed, total } => {
+                write!(f, "{} of {} operations failed", failed, total)
+            }
+            BackupError::Cancelled => f.write_str("Cancelled; nothing was left half-written"),
+            BackupError::Locked { path, pid } => {
+                let holder = pid.map(|pid| format!(" (process {})", pid)).unwrap_or_default();
+                write!(
+                    f,
+                    "Another safe_backup{} is working in this directory, holding {}; try again \
+                     once it is done, or use --wait",
+                    holder,
+                    path.display()
+                )
+            }
+            BackupError::Strict(warnings) => write!(
+                f,
+                "{} warning{} with --strict: {}",
+                warnings.len(),
+                if warnings.len() == 1 { "" } else { "s" },
+                warnings.join("; ")
+            ),
+            BackupError::BackupExists(backup) => write!(
+                f,
+                "The backup {} already exists and existing_backup is \"error\"; use \
+                 --if-exists to replace it, skip the file or keep a version",
+                backup.display()
+            ),
+            BackupError::HookFailed { operation, reason } => {
+                write!(f, "The pre_hook {}, so the {} was not done", reason, operation)
+            }
+            BackupError::InUse(path) => write!(
+                f,
+                "{} is in use by another process, which does not let it be read; close it \
+                 and try again, or try --shared-read",
+                path.display()
+            ),
+            BackupError::RestoreMismatch { target, undoable } => {
+                write!(f, "The restored {} does not match its backup", target.display())?;
+                match undoable {
+                    true => write!(f, "; 'undo' puts back what was there before"),
+                    false => Ok(()),
+                }
+            }
+            BackupError::TestRestoreFailed(backup) => write!(
+                f,
+                "The backup {} does not restore to what was recorded for it",
+                backup.display()
+            ),
+            BackupError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BackupError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BackupError {
+    fn from(e: io::Error) -> Self {
+        if is_cancelled(&e) {
+            return BackupError::Cancelled;
+        }
+        BackupError::Io(e)
+    }
+}
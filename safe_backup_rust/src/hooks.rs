@@ -0,0 +1,58 @@
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+// ---------- Hooks ----------
+
+/// `pre_hook` or `post_hook`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Hook {
+    Pre,
+    Post,
+}
+
+impl Hook {
+    /// The config key, which also names the hook in the log.
+    pub(crate) fn key(self) -> &'static str {
+        match self {
+            Hook::Pre => "pre_hook",
+            Hook::Post => "post_hook",
+        }
+    }
+}
+
+/// Run the hook `command` through the shell (`sh -c`, or `cmd /C` on Windows) in `base`
+/// and wait for it. It gets the operation and the file as given in SAFE_BACKUP_OPERATION
+/// and SAFE_BACKUP_FILE, the post hook also "success" or "failure" in
+/// SAFE_BACKUP_OUTCOME; `sh` passes the operation and the file as $1 and $2 too. Its
+/// output goes to stderr, so it never mixes with what goes to stdout (`cat`, `--json`),
+/// and it reads nothing: stdin may hold the names of a batch.
+pub(crate) fn run_hook(
+    command: &str,
+    base: &Path,
+    hook: Hook,
+    operation: &str,
+    file: &str,
+    outcome: Option<&str>,
+) -> io::Result<ExitStatus> {
+    let mut shell = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut sh = Command::new("sh");
+        sh.arg("-c").arg(command).args(["safe_backup", operation, file]);
+        sh
+    };
+    shell
+        .current_dir(base)
+        .env("SAFE_BACKUP_HOOK", hook.key())
+        .env("SAFE_BACKUP_OPERATION", operation)
+        .env("SAFE_BACKUP_FILE", file)
+        .stdin(Stdio::null())
+        .stdout(io::stderr());
+    if let Some(outcome) = outcome {
+        shell.env("SAFE_BACKUP_OUTCOME", outcome);
+    }
+    shell.status()
+}
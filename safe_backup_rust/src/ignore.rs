@@ -0,0 +1,85 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::BackupError;
+
+// ---------- .backupignore ----------
+
+/// File in a backed-up directory, or any directory below it, listing globs of entries its
+/// directory backup leaves out, one per line as in ".gitignore".
+pub(crate) const IGNORE_FILE: &str = ".backupignore";
+
+/// One line of a `.backupignore`.
+#[derive(Clone)]
+pub(crate) struct IgnoreRule {
+    /// Directory of the `.backupignore`, relative to the backed-up directory; the rule
+    /// only applies below it.
+    dir: PathBuf,
+    pattern: glob::Pattern,
+    /// The pattern has a '/' other than at its end, so it matches the path relative to
+    /// `dir` ("build/*.o", "/out"); otherwise it matches the name at any depth ("*.tmp").
+    anchored: bool,
+    /// "cache/" matches directories only.
+    dir_only: bool,
+    /// "!keep.log" brings back what an earlier rule left out.
+    negated: bool,
+}
+
+/// The rules of `root/rel/.backupignore`, or none if there is no such file. Blank lines
+/// and lines starting with '#' are skipped.
+pub(crate) fn read_ignore_rules(root: &Path, rel: &Path) -> Result<Vec<IgnoreRule>, BackupError> {
+    let path = root.join(rel).join(IGNORE_FILE);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut rules = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let pattern = glob::Pattern::new(line.trim_start_matches('/')).map_err(|e| {
+            BackupError::InvalidInput(format!("{} line {}: {}", path.display(), i + 1, e))
+        })?;
+        rules.push(IgnoreRule { dir: rel.to_path_buf(), pattern, anchored, dir_only, negated });
+    }
+    Ok(rules)
+}
+
+/// True if `rules` (outer `.backupignore` files first) leave out the entry at `rel`,
+/// relative to the backed-up directory. As in ".gitignore", the last rule that matches
+/// decides.
+pub(crate) fn is_ignored(rules: &[IgnoreRule], rel: &Path, is_dir: bool) -> bool {
+    let options = glob::MatchOptions { require_literal_separator: true, ..Default::default() };
+    let mut ignored = false;
+    for rule in rules {
+        let Ok(below) = rel.strip_prefix(&rule.dir) else {
+            continue;
+        };
+        if rule.dir_only && !is_dir {
+            continue;
+        }
+        let matched = if rule.anchored {
+            rule.pattern.matches_path_with(below, options)
+        } else {
+            let name = rel.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+            rule.pattern.matches_with(&name, options)
+        };
+        if matched {
+            ignored = !rule.negated;
+        }
+    }
+    ignored
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::atomic::write_atomically;
+use crate::config::Config;
+use crate::paths::BackupEntry;
+use crate::trash::TRASH_DIR;
+
+// ---------- Latest-backup index ----------
+
+/// Where the newest backup of each file is remembered, inside the trash like the checksum
+/// cache so that it is never backed up or listed.
+const INDEX_FILE: &str = "index.json";
+
+/// Held while the index is read and written back, as the files of a glob backup are
+/// recorded from several threads.
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+/// The newest backup of one file, as it was when written.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct IndexEntry {
+    /// Relative to the base.
+    backup: PathBuf,
+    size: u64,
+    modified: (u64, u32),
+    /// SHA-256 of the backup's content, unless it is encrypted and was indexed by
+    /// `reindex`.
+    sha256: Option<String>,
+    /// The settings that place backups when it was written, see `layout`.
+    layout: String,
+}
+
+/// Files relative to the base, each with its newest backup.
+type Index = BTreeMap<PathBuf, IndexEntry>;
+
+fn index_path(base: &Path) -> PathBuf {
+    base.join(TRASH_DIR).join(INDEX_FILE)
+}
+
+/// Where backups go under `cfg`: an entry written under other settings is not used.
+fn layout(cfg: &Config) -> String {
+    format!(
+        "{}|{:?}|{:?}|{}|{:?}",
+        cfg.backup_suffix,
+        cfg.backup_dir,
+        cfg.versions_dir,
+        cfg.preserve_structure,
+        cfg.name_template
+    )
+}
+
+/// The index; a missing or damaged one is just empty.
+fn read_index(base: &Path) -> Index {
+    let path = index_path(base);
+    let Ok(json) = fs::read(&path) else {
+        return Index::new();
+    };
+    serde_json::from_slice(&json).unwrap_or_else(|e| {
+        debug!("ignoring the damaged index {}: {}", path.display(), e);
+        Index::new()
+    })
+}
+
+fn write_index(base: &Path, index: &Index) -> io::Result<()> {
+    fs::create_dir_all(base.join(TRASH_DIR))?;
+    let json = serde_json::to_vec(index).map_err(io::Error::other)?;
+    write_atomically(&index_path(base), |out| io::Write::write_all(out, &json))
+}
+
+/// `time` as seconds and nanoseconds since the epoch.
+fn epoch_time(time: SystemTime) -> (u64, u32) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    (since_epoch.as_secs(), since_epoch.subsec_nanos())
+}
+
+fn index_entry(
+    cfg: &Config,
+    base: &Path,
+    backup: &BackupEntry,
+    sha256: Option<&str>,
+) -> IndexEntry {
+    IndexEntry {
+        backup: backup.path.strip_prefix(base).unwrap_or(&backup.path).to_path_buf(),
+        size: backup.size,
+        modified: epoch_time(backup.modified),
+        sha256: sha256.map(str::to_string),
+        layout: layout(cfg),
+    }
+}
+
+/// Remember `backup` as the newest backup of `file`, with the SHA-256 of its content.
+pub(crate) fn record_backup(
+    cfg: &Config,
+    base: &Path,
+    file: &Path,
+    backup: &BackupEntry,
+    sha256: &str,
+) -> io::Result<()> {
+    let _lock = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut index = read_index(base);
+    let file = file.strip_prefix(base).unwrap_or(file).to_path_buf();
+    index.insert(file, index_entry(cfg, base, backup, Some(sha256)));
+    write_index(base, &index)
+}
+
+/// Replace the whole index with `entries`: (file, its newest backup, SHA-256 of that
+/// backup's content if known).
+pub(crate) fn rebuild_index(
+    cfg: &Config,
+    base: &Path,
+    entries: &[(PathBuf, BackupEntry, Option<String>)],
+) -> io::Result<()> {
+    let _lock = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let index = entries
+        .iter()
+        .map(|(file, backup, sha256)| {
+            let file = file.strip_prefix(base).unwrap_or(file).to_path_buf();
+            (file, index_entry(cfg, base, backup, sha256.as_deref()))
+        })
+        .collect();
+    write_index(base, &index)
+}
+
+/// The newest backup of `file` as the index has it, if it was recorded under the current
+/// settings and is still on disk with the size and modification time recorded. `None`
+/// means the caller has to look for itself. Backups made by other means than `backup`
+/// (copied in by hand) are only seen after `reindex`.
+pub(crate) fn indexed_backup(cfg: &Config, base: &Path, file: &Path) -> Option<BackupEntry> {
+    current_entry(cfg, base, file).map(|(backup, _)| backup)
+}
+
+/// The SHA-256 of `backup`'s content as recorded when it was written, if it is the newest
+/// backup of `file` and the index entry is current (see `indexed_backup`).
+pub(crate) fn indexed_sha256(
+    cfg: &Config,
+    base: &Path,
+    file: &Path,
+    backup: &Path,
+) -> Option<String> {
+    current_entry(cfg, base, file)
+        .filter(|(entry, _)| entry.path == backup)
+        .and_then(|(_, sha256)| sha256)
+}
+
+fn current_entry(
+    cfg: &Config,
+    base: &Path,
+    file: &Path,
+) -> Option<(BackupEntry, Option<String>)> {
+    let mut index = {
+        let _lock = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        read_index(base)
+    };
+    let entry = index.remove(file.strip_prefix(base).unwrap_or(file))?;
+    if entry.layout != layout(cfg) {
+        return None;
+    }
+    let path = base.join(&entry.backup);
+    let meta = fs::metadata(&path).ok()?;
+    let modified = UNIX_EPOCH + Duration::new(entry.modified.0, entry.modified.1);
+    let unchanged = meta.is_file()
+        && meta.len() == entry.size
+        && meta.modified().ok() == Some(modified);
+    if !unchanged {
+        debug!("the index entry of {} is out of date", file.display());
+        return None;
+    }
+    Some((BackupEntry { path, size: entry.size, modified }, entry.sha256))
+}
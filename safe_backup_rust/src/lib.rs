@@ -0,0 +1,57 @@
+//! Back up, restore, verify and delete files safely.
+//!
+//! `BackupManager` runs the operations; the `safe_backup` binary is a thin CLI around it.
+
+mod archive;
+mod atomic;
+mod bench;
+mod cache;
+mod cancel;
+mod checksum;
+mod compress;
+mod config;
+mod crypto;
+mod delta;
+mod error;
+mod hooks;
+mod ignore;
+mod index;
+mod lock;
+mod log;
+mod metadata;
+mod ops;
+mod paths;
+mod progress;
+mod remote;
+mod share;
+mod snapshot;
+mod sparse;
+mod store;
+mod trash;
+mod undo;
+mod wipe;
+
+pub use bench::{CopyBench, BENCH_BUFFER_SIZES};
+pub use cancel::{cancel, clear_cancel};
+pub use checksum::HashAlgorithm;
+pub use config::{Config, ExistingBackup, ExtensionRule, SettingOrigin, CONFIG_FILE_NAME};
+pub use crypto::PassphraseFn;
+pub use error::BackupError;
+pub use lock::BaseLock;
+pub use log::{log_action, log_write_failed, LogCheck, LogEntry, LogFormat, LogLevel};
+pub use ops::{
+    ActionStats, ArchiveMember, ArchiveOptions, ArchiveReport, AuditEntry, AuditStatus,
+    BackupManager, BackupOptions, BackupReport, CheckReport, CheckStatus, CompareReport,
+    ConfirmLevel, Confirmation, ContentRecord, CopyOptions, CopyReport, DeleteOptions, DeleteReport,
+    DiffChanges, DiffReport, ExtractOptions, ExtractReport, GcReport, LogFilter, LogReport,
+    MigrateReport, MoveOptions, MoveReport, OrphanReport, OverwritePolicy, PruneOptions,
+    PruneReport, RestoreOptions, RestoreReport, SkippedEntry, StatusReport, SyncEntry, SyncReport,
+    SyncStatus, TestRestoreReport, UndeleteReport, UndoReport, VerifyBasis, VerifyReport,
+};
+pub use paths::{is_glob, resolve_safe_os_path, resolve_safe_path, BackupEntry, BackupFormat};
+pub use progress::{
+    format_duration, format_size, parse_size, transfer_stats, DirProgress, DirProgressFn,
+    ProgressFn,
+};
+pub use remote::SftpPasswordFn;
+pub use wipe::WipePattern;
@@ -0,0 +1,71 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+use log::debug;
+
+use crate::error::BackupError;
+
+// ---------- Instance lock ----------
+
+/// In the base directory, locked by the instance working there. It holds that instance's
+/// PID, for the message another instance refuses to start with.
+pub(crate) const LOCK_FILE: &str = ".safe_backup.lock";
+
+/// The base directory's lock, held until dropped. The OS releases it when the process
+/// ends in any way, so a crashed instance never leaves a lock that blocks the next one;
+/// only its PID is left in the file, which a clean release empties.
+#[derive(Debug)]
+pub struct BaseLock {
+    file: File,
+}
+
+impl Drop for BaseLock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
+
+/// True if `path` is the lock file of `base`, which is never backed up.
+pub(crate) fn is_lock_file(base: &Path, path: &Path) -> bool {
+    path.parent() == Some(base) && path.file_name().is_some_and(|name| name == LOCK_FILE)
+}
+
+/// Lock `base` for this process. If another process holds it, wait for it to let go with
+/// `wait`, or fail with `BackupError::Locked`. `waiting` is called once before blocking.
+pub(crate) fn lock_base(
+    base: &Path,
+    wait: bool,
+    waiting: impl FnOnce(Option<u32>),
+) -> Result<BaseLock, BackupError> {
+    let path = base.join(LOCK_FILE);
+    let mut file =
+        OpenOptions::new().create(true).truncate(false).read(true).write(true).open(&path)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            let pid = holder(&mut file);
+            if !wait {
+                return Err(BackupError::Locked { path, pid });
+            }
+            waiting(pid);
+            file.lock()?;
+        }
+        Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+    }
+    if let Some(pid) = holder(&mut file) {
+        debug!("process {} did not release {}; taking it over", pid, path.display());
+    }
+    file.set_len(0)?;
+    file.rewind()?;
+    write!(file, "{}", std::process::id())?;
+    Ok(BaseLock { file })
+}
+
+/// The PID written in the lock file, if it can be read.
+fn holder(file: &mut File) -> Option<u32> {
+    let mut pid = String::new();
+    file.rewind().and_then(|_| file.read_to_string(&mut pid)).ok()?;
+    pid.trim().parse().ok()
+}
+
@@ -0,0 +1,681 @@
+use std::fs;
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::checksum::sha256_of;
+use crate::config::Config;
+use crate::paths::file_name_of;
+
+// ---------- Logging ----------
+
+/// ISO-8601 local time stamped on every log entry, unless `log_time_format` says otherwise.
+pub(crate) const LOG_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// Held while an entry is written, so concurrent operations append whole, chained lines.
+static LOG_LOCK: Mutex<()> = Mutex::new(());
+
+/// Set once an entry could not be written, so the warning is given only once per run.
+static LOG_WRITE_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// How `log_action` formats each entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// "time | action | file | outcome | detail" lines.
+    #[default]
+    Plain,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Which entries `log_action` writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /// Every entry, dry runs, unchanged and skipped files included.
+    #[default]
+    All,
+    /// Successes and failures only.
+    Results,
+    /// Failures only: failed operations, checksum mismatches, corrupt backups, a broken log.
+    Failures,
+    /// No log at all.
+    Off,
+}
+
+impl LogLevel {
+    /// Whether an entry with this outcome is written at this level.
+    fn records(self, outcome: &str) -> bool {
+        let failed = matches!(
+            outcome,
+            "failure" | "mismatch" | "corrupt" | "error" | "broken" | "gap" | "transient-error"
+        );
+        match self {
+            LogLevel::All => true,
+            LogLevel::Results => failed || outcome == "success",
+            LogLevel::Failures => failed,
+            LogLevel::Off => false,
+        }
+    }
+}
+
+/// A log line in the JSON format.
+#[derive(serde::Serialize)]
+struct JsonLogEntry<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    timestamp: String,
+    action: &'a str,
+    filename: &'a str,
+    outcome: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+}
+
+/// True once an entry could not be written to the log in this run (see `log_action`).
+pub fn log_write_failed() -> bool {
+    LOG_WRITE_FAILED.load(Ordering::Relaxed)
+}
+
+/// Append one entry to the log: what was done (`action`), to which file, how it went,
+/// and optional free-form detail (an error message, the backup chosen, ...).
+/// The current local time is added automatically, with `log_sequence` a number one past
+/// the previous entry's, and the entry is chained to the one before it, the first entry
+/// after a rotation to the last one of "logfile.txt.1" (see `check_log_chain`). Entries
+/// below `log_level` are left out.
+///
+/// The log is a record of the operations, not part of them: if it cannot be written
+/// (a read-only directory, a full disk), a warning is given once and the operation's
+/// result stands.
+pub fn log_action(cfg: &Config, action: &str, filename: &str, outcome: &str, detail: Option<&str>) {
+    if !cfg.log_level.records(outcome) {
+        return;
+    }
+    if let Err(e) = write_entry(cfg, action, filename, outcome, detail) {
+        if !LOG_WRITE_FAILED.swap(true, Ordering::Relaxed) {
+            warn!("cannot write the log {}: {}; carrying on without it", cfg.log_path.display(), e);
+        }
+    }
+}
+
+fn write_entry(
+    cfg: &Config,
+    action: &str,
+    filename: &str,
+    outcome: &str,
+    detail: Option<&str>,
+) -> io::Result<()> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    let timestamp = chrono::Local::now().format(&cfg.log_time_format).to_string();
+
+    // Rotation, numbering, chaining and the append must not interleave with other entries:
+    // the mutex covers this process's threads, the lock file other processes (cron, a
+    // second shell).
+    let _guard = LOG_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(log_lock_path(&cfg.log_path))?;
+    lock.lock()?;
+    rotate_log_if_needed(cfg)?;
+    let last = previous_entry(cfg)?;
+    let seq = match cfg.log_sequence {
+        true => Some(next_sequence(last.as_deref())),
+        false => None,
+    };
+    let line = match cfg.log_format {
+        LogFormat::Plain => {
+            let entry = match detail {
+                Some(d) => {
+                    format!("{} | {} | {} | {} | {}", timestamp, action, filename, outcome, d)
+                }
+                None => format!("{} | {} | {} | {}", timestamp, action, filename, outcome),
+            };
+            match seq {
+                Some(n) => format!("#{} | {}", n, entry),
+                None => entry,
+            }
+        }
+        LogFormat::Json => {
+            let entry = JsonLogEntry {
+                seq,
+                timestamp,
+                action,
+                filename,
+                outcome,
+                message: detail,
+            };
+            serde_json::to_string(&entry).map_err(io::Error::other)?
+        }
+    };
+    let prev = last.as_deref().and_then(split_chained).map(|(_, hash)| hash.to_string());
+    let hash = chain_hash(prev.as_deref(), &line)?;
+    let line = match cfg.log_format {
+        LogFormat::Plain => format!("{}{}{}", line, PLAIN_HASH_SEP, hash),
+        LogFormat::Json => {
+            let open = line.strip_suffix('}').unwrap_or(&line);
+            format!("{}{}{}\"}}", open, JSON_HASH_KEY, hash)
+        }
+    };
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&cfg.log_path)?;
+    // One write per entry, so even a reader without the lock never sees half a line.
+    f.write_all(format!("{}\n", line).as_bytes())?;
+    if cfg.durable {
+        f.sync_data()?;
+    }
+    lock.unlock()?;
+    debug!("log written: {} {} {} -> {}", action, filename, outcome, cfg.log_path.display());
+    Ok(())
+}
+
+/// The line the next entry follows: the active log's last one or, in a log that was just
+/// rotated away, the last one of "logfile.txt.1".
+fn previous_entry(cfg: &Config) -> io::Result<Option<String>> {
+    match last_line(&cfg.log_path)? {
+        Some(line) => Ok(Some(line)),
+        None if cfg.log_max_files > 0 => last_line(&rotated_log_path(&cfg.log_path, 1)),
+        None => Ok(None),
+    }
+}
+
+/// The number for the entry after `last` (see `previous_entry`). After an entry without a
+/// number (written before `log_sequence` was turned on) they start again at 1.
+fn next_sequence(last: Option<&str>) -> u64 {
+    let seq = last.and_then(parse_entry).and_then(|entry| entry.seq);
+    seq.map_or(1, |n| n + 1)
+}
+
+/// True if `path` is the active log, one of its rotated predecessors or its lock file.
+pub(crate) fn is_log_file(cfg: &Config, path: &Path) -> bool {
+    let Ok(log) = std::path::absolute(&cfg.log_path) else {
+        return false;
+    };
+    path == log
+        || path == log_lock_path(&log)
+        || (1..=cfg.log_max_files).any(|n| path == rotated_log_path(&log, n))
+}
+
+/// "logfile.txt" -> "logfile.txt.lock", locked while an entry is written.
+fn log_lock_path(log: &Path) -> PathBuf {
+    log.with_file_name(format!("{}.lock", file_name_of(log)))
+}
+
+/// "logfile.txt" -> "logfile.txt.<n>"
+fn rotated_log_path(log: &Path, n: usize) -> PathBuf {
+    log.with_file_name(format!("{}.{}", file_name_of(log), n))
+}
+
+/// If the log has grown past `log_max_size`, shift "logfile.txt.N-1" -> ".N", ...,
+/// "logfile.txt" -> ".1" and let the next write start a fresh file. Files that vanish
+/// mid-rotation (another instance rotated first) are not an error.
+fn rotate_log_if_needed(cfg: &Config) -> io::Result<()> {
+    let log = &cfg.log_path;
+    if cfg.log_max_size == 0 {
+        return Ok(());
+    }
+    match fs::metadata(log) {
+        Ok(meta) if meta.len() >= cfg.log_max_size => {}
+        Ok(_) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+
+    let ignore_missing = |r: io::Result<()>| match r {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    };
+    if cfg.log_max_files == 0 {
+        return ignore_missing(fs::remove_file(log));
+    }
+    ignore_missing(fs::remove_file(rotated_log_path(log, cfg.log_max_files)))?;
+    for n in (1..cfg.log_max_files).rev() {
+        ignore_missing(fs::rename(rotated_log_path(log, n), rotated_log_path(log, n + 1)))?;
+    }
+    ignore_missing(fs::rename(log, rotated_log_path(log, 1)))
+}
+
+// ---------- Hash chain ----------
+
+/// Plain entries end in " | #<hash>"; JSON entries end in a "hash" field.
+const PLAIN_HASH_SEP: &str = " | #";
+const JSON_HASH_KEY: &str = ",\"hash\":\"";
+
+/// Chain value before the first entry of a log file.
+const CHAIN_SEED: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// SHA-256 over the previous entry's hash and this entry's content.
+fn chain_hash(prev: Option<&str>, content: &str) -> io::Result<String> {
+    let input = format!("{}{}", prev.unwrap_or(CHAIN_SEED), content);
+    sha256_of(input.as_bytes())
+}
+
+/// Split a chained log line into its content and its hash; `None` for lines written
+/// before chaining was added.
+fn split_chained(line: &str) -> Option<(String, &str)> {
+    let is_hash = |h: &str| h.len() == 64 && h.bytes().all(|b| b.is_ascii_hexdigit());
+    if let Some(rest) = line.strip_suffix("\"}") {
+        if let Some((open, hash)) = rest.rsplit_once(JSON_HASH_KEY).filter(|(_, h)| is_hash(h)) {
+            return Some((format!("{}}}", open), hash));
+        }
+    }
+    let (content, hash) = line.rsplit_once(PLAIN_HASH_SEP)?;
+    is_hash(hash).then(|| (content.to_string(), hash))
+}
+
+/// The last complete line of `log`, if it exists and has one. Only the end of the file is
+/// read.
+fn last_line(log: &Path) -> io::Result<Option<String>> {
+    let mut file = match fs::File::open(log) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let len = file.metadata()?.len();
+    let mut window = 4096;
+    loop {
+        let start = len.saturating_sub(window);
+        file.seek(io::SeekFrom::Start(start))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        let text = String::from_utf8_lossy(&tail);
+        let text = text.trim_end_matches('\n');
+        // A complete last line needs a newline before it, unless it starts the file.
+        if let Some((_, last)) = text.rsplit_once('\n').or((start == 0).then_some(("", text))) {
+            return Ok((!last.is_empty()).then(|| last.to_string()));
+        }
+        window *= 4;
+    }
+}
+
+/// Result of checking one log file's hash chain.
+#[derive(Debug)]
+pub struct LogCheck {
+    pub path: PathBuf,
+    /// Number of lines in the file.
+    pub lines: usize,
+    /// Lines at the start written before entries were chained; these cannot be checked.
+    pub unchained: usize,
+    /// The first line (1-based) whose hash does not follow from the lines before it:
+    /// that line or one before it was edited, inserted or removed.
+    pub broken_at: Option<usize>,
+    /// The first numbered line (1-based) whose number is not one past the numbered line
+    /// before it: entries in between are missing.
+    pub gap_at: Option<usize>,
+    /// The file's first entry does not follow from the last one of the file before it:
+    /// that file was removed or replaced, or the start of this one cut off.
+    pub unlinked: bool,
+    /// The hash and number of the last entry, which the next file's first entry follows.
+    last_hash: Option<String>,
+    last_seq: Option<u64>,
+}
+
+/// Where the chain of a log file starts (see `check_log_chain`).
+#[derive(Clone, Copy)]
+enum ChainStart<'a> {
+    /// The first log ever written: its first entry is chained to `CHAIN_SEED`.
+    Seed,
+    /// After the log file checked as this: its first entry follows that one's last.
+    After(&'a LogCheck),
+    /// After a file rotation has since removed, so the first entry cannot be checked.
+    Dropped,
+}
+
+/// Walk a log file and recompute each entry's hash from the previous one, the first from
+/// `start`. The log and its rotated files form one chain, so a rotated file that is
+/// removed or replaced breaks it just like an edited entry.
+fn check_log_chain(log: &Path, start: ChainStart) -> io::Result<LogCheck> {
+    let text = fs::read_to_string(log)?;
+    let mut check = LogCheck {
+        path: log.to_path_buf(),
+        lines: 0,
+        unchained: 0,
+        broken_at: None,
+        gap_at: None,
+        unlinked: false,
+        last_hash: None,
+        last_seq: None,
+    };
+    let (mut prev, mut prev_seq, mut linked) = match start {
+        ChainStart::Seed => (None, None, true),
+        ChainStart::After(before) => (before.last_hash.clone(), before.last_seq, true),
+        ChainStart::Dropped => (None, None, false),
+    };
+    for (i, line) in text.lines().enumerate() {
+        check.lines += 1;
+        if let Some(seq) = parse_entry(line).and_then(|entry| entry.seq) {
+            if prev_seq.is_some_and(|p| seq != p + 1) && check.gap_at.is_none() {
+                check.gap_at = Some(i + 1);
+            }
+            prev_seq = Some(seq);
+        }
+        if check.broken_at.is_some() {
+            continue;
+        }
+        match split_chained(line) {
+            Some((content, hash)) => {
+                // The first entry of a file whose predecessor is gone is taken as it is.
+                if linked && chain_hash(prev.as_deref(), &content)? != hash {
+                    check.broken_at = Some(i + 1);
+                    check.unlinked = i == check.unchained;
+                }
+                prev = Some(hash.to_string());
+                linked = true;
+            }
+            None if prev.is_none() => check.unchained += 1,
+            None => check.broken_at = Some(i + 1),
+        }
+    }
+    check.last_hash = prev;
+    check.last_seq = prev_seq;
+    Ok(check)
+}
+
+/// Check the chain of every log file (see `log_files`), oldest first, each one's from
+/// where the one before it ended. The oldest one starts from the seed, unless it is in
+/// the last slot "logfile.txt.<log_max_files>", whose predecessor a rotation may have
+/// removed.
+pub(crate) fn check_log_chains(cfg: &Config) -> io::Result<Vec<LogCheck>> {
+    let oldest_kept = rotated_log_path(&cfg.log_path, cfg.log_max_files);
+    let mut checks: Vec<LogCheck> = Vec::new();
+    for log in log_files(cfg) {
+        let start = match checks.last() {
+            Some(before) => ChainStart::After(before),
+            None if cfg.log_max_files > 0 && log == oldest_kept => ChainStart::Dropped,
+            None => ChainStart::Seed,
+        };
+        let check = check_log_chain(&log, start)?;
+        checks.push(check);
+    }
+    Ok(checks)
+}
+
+/// The active log and its rotated predecessors that exist, oldest first.
+pub(crate) fn log_files(cfg: &Config) -> Vec<PathBuf> {
+    let log = &cfg.log_path;
+    let mut files: Vec<_> = (1..=cfg.log_max_files)
+        .rev()
+        .map(|n| rotated_log_path(log, n))
+        .filter(|p| p.is_file())
+        .collect();
+    if log.is_file() {
+        files.push(log.clone());
+    }
+    files
+}
+
+// ---------- Reading entries ----------
+
+/// One entry of the log as `log_action` wrote it, without its chain hash.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct LogEntry {
+    /// The entry's number, if it was written with `log_sequence`.
+    #[serde(default)]
+    pub seq: Option<u64>,
+    pub timestamp: String,
+    pub action: String,
+    pub filename: String,
+    pub outcome: String,
+    /// The free-form detail ("message" in the JSON format).
+    #[serde(rename = "message")]
+    pub detail: Option<String>,
+}
+
+impl LogEntry {
+    /// When the entry was written, if its timestamp is in the format `log_action` uses
+    /// with `cfg`.
+    pub fn time(&self, cfg: &Config) -> Option<chrono::NaiveDateTime> {
+        chrono::NaiveDateTime::parse_from_str(&self.timestamp, &cfg.log_time_format).ok()
+    }
+}
+
+impl std::fmt::Display for LogEntry {
+    /// The plain log format: "#seq | time | action | file | outcome | detail".
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(seq) = self.seq {
+            write!(f, "#{} | ", seq)?;
+        }
+        write!(f, "{} | {} | {} | {}", self.timestamp, self.action, self.filename, self.outcome)?;
+        match &self.detail {
+            Some(detail) => write!(f, " | {}", detail),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse a log line in either format; `None` for lines that are neither.
+fn parse_entry(line: &str) -> Option<LogEntry> {
+    if line.starts_with('{') {
+        return serde_json::from_str(line).ok();
+    }
+    let content = split_chained(line).map_or_else(|| line.to_string(), |(content, _)| content);
+    let (seq, content) = match content.strip_prefix('#').and_then(|c| c.split_once(" | ")) {
+        Some((n, rest)) => (Some(n.parse().ok()?), rest),
+        None => (None, content.as_str()),
+    };
+    let mut fields = content.splitn(5, " | ");
+    Some(LogEntry {
+        seq,
+        timestamp: fields.next()?.to_string(),
+        action: fields.next()?.to_string(),
+        filename: fields.next()?.to_string(),
+        outcome: fields.next()?.to_string(),
+        detail: fields.next().map(str::to_string),
+    })
+}
+
+/// Every entry of the rotated logs and the active one, oldest first. Lines that cannot be
+/// parsed are skipped.
+pub(crate) fn read_entries(cfg: &Config) -> io::Result<Vec<LogEntry>> {
+    let mut entries = Vec::new();
+    for log in log_files(cfg) {
+        let text = fs::read(&log)?;
+        entries.extend(String::from_utf8_lossy(&text).lines().filter_map(parse_entry));
+    }
+    Ok(entries)
+}
+
+// ---------- Following ----------
+
+/// How often `follow_entries` looks for new lines.
+const FOLLOW_POLL: Duration = Duration::from_millis(250);
+
+/// True if `path` is no longer the file that was opened as `open`: the log was rotated
+/// (or removed) and a new one started in its place.
+fn replaced(open: &fs::Metadata, path: &fs::Metadata, read: u64) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let _ = read;
+        open.dev() != path.dev() || open.ino() != path.ino()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = open;
+        path.len() < read
+    }
+}
+
+/// Read what was appended to `file` since `read` bytes and call `each` with the entries
+/// of the complete lines; a partial last line is kept in `pending`. False once `each`
+/// returned false.
+fn read_appended(
+    file: &mut fs::File,
+    read: &mut u64,
+    pending: &mut Vec<u8>,
+    each: &mut impl FnMut(LogEntry) -> bool,
+) -> io::Result<bool> {
+    *read += file.read_to_end(pending)? as u64;
+    while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+        let line: Vec<u8> = pending.drain(..=end).collect();
+        if let Some(entry) = parse_entry(String::from_utf8_lossy(&line).trim_end()) {
+            if !each(entry) {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Call `each` with every entry appended to the active log from now on, like `tail -f`,
+/// until it returns false. When the log is rotated the rest of the old file is read and
+/// the new one followed from its start; a log that does not exist yet is waited for.
+pub(crate) fn follow_entries(
+    cfg: &Config,
+    mut each: impl FnMut(LogEntry) -> bool,
+) -> io::Result<()> {
+    let log = &cfg.log_path;
+    let open = |from_end: bool| -> io::Result<Option<(fs::File, u64)>> {
+        let mut file = match fs::File::open(log) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let read = if from_end { file.seek(io::SeekFrom::End(0))? } else { 0 };
+        Ok(Some((file, read)))
+    };
+    let mut current = open(true)?;
+    let mut pending = Vec::new();
+    loop {
+        if let Some((file, read)) = &mut current {
+            if !read_appended(file, read, &mut pending, &mut each)? {
+                return Ok(());
+            }
+        }
+        let rotated = match (&current, fs::metadata(log)) {
+            (None, Ok(_)) => true,
+            (Some((file, read)), Ok(meta)) => replaced(&file.metadata()?, &meta, *read),
+            (_, Err(e)) if e.kind() == io::ErrorKind::NotFound => false,
+            (_, Err(e)) => return Err(e),
+        };
+        if !rotated {
+            std::thread::sleep(FOLLOW_POLL);
+            continue;
+        }
+        // Entries written between the last read and the rotation are still in the old file.
+        if let Some((file, read)) = &mut current {
+            if !read_appended(file, read, &mut pending, &mut each)? {
+                return Ok(());
+            }
+        }
+        debug!("following the new log {}", log.display());
+        pending.clear();
+        current = open(false)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_entries_stay_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = Config {
+            log_path: dir.path().join("logfile.txt"),
+            log_sequence: true,
+            log_max_size: 0,
+            durable: false,
+            ..Config::default()
+        };
+        let (threads, per_thread) = (8, 50);
+        std::thread::scope(|scope| {
+            for t in 0..threads {
+                let cfg = &cfg;
+                scope.spawn(move || {
+                    for n in 0..per_thread {
+                        let file = format!("thread-{}.txt", t);
+                        log_action(cfg, "backup", &file, "success", Some(&n.to_string()));
+                    }
+                });
+            }
+        });
+
+        let text = fs::read_to_string(&cfg.log_path).unwrap();
+        let entries: Vec<_> = text.lines().map(|line| parse_entry(line).unwrap()).collect();
+        assert_eq!(entries.len(), threads * per_thread);
+        for t in 0..threads {
+            let file = format!("thread-{}.txt", t);
+            let mut details: Vec<usize> = entries
+                .iter()
+                .filter(|e| e.filename == file)
+                .map(|e| e.detail.as_deref().unwrap().parse().unwrap())
+                .collect();
+            details.sort_unstable();
+            assert_eq!(details, (0..per_thread).collect::<Vec<_>>());
+        }
+        let check = check_log_chain(&cfg.log_path, ChainStart::Seed).unwrap();
+        assert_eq!(check.broken_at, None);
+        assert_eq!(check.gap_at, None);
+    }
+
+    /// A log of `entries` entries rotated into several files.
+    fn rotated_log(dir: &Path, entries: usize) -> Config {
+        let cfg = Config {
+            log_path: dir.join("logfile.txt"),
+            log_sequence: true,
+            log_max_size: 300,
+            log_max_files: 3,
+            durable: false,
+            ..Config::default()
+        };
+        for n in 0..entries {
+            log_action(&cfg, "backup", "notes.txt", "success", Some(&n.to_string()));
+        }
+        cfg
+    }
+
+    fn intact(cfg: &Config) -> bool {
+        let checks = check_log_chains(cfg).unwrap();
+        checks.iter().all(|c| c.broken_at.is_none() && c.gap_at.is_none())
+    }
+
+    #[test]
+    fn chain_runs_across_rotated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = rotated_log(dir.path(), 10);
+        assert_eq!(log_files(&cfg).len(), 4);
+        assert!(intact(&cfg));
+
+        // Once the oldest files were dropped, the oldest kept one is still intact.
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = rotated_log(dir.path(), 30);
+        assert!(intact(&cfg));
+    }
+
+    #[test]
+    fn removed_or_replaced_rotated_files_break_the_chain() {
+        for (victim, replace) in [(2, false), (2, true), (3, false)] {
+            let dir = tempfile::tempdir().unwrap();
+            let cfg = rotated_log(dir.path(), 10);
+            let path = rotated_log_path(&cfg.log_path, victim);
+            fs::remove_file(&path).unwrap();
+            if replace {
+                let forged = Config { log_path: path, ..cfg.clone() };
+                log_action(&forged, "backup", "notes.txt", "success", Some("forged"));
+            }
+            let checks = check_log_chains(&cfg).unwrap();
+            assert!(checks.iter().any(|c| c.unlinked), "{} {}", victim, replace);
+        }
+    }
+
+    #[test]
+    fn cutting_off_the_start_of_the_log_breaks_the_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = rotated_log(dir.path(), 10);
+        let text = fs::read_to_string(&cfg.log_path).unwrap();
+        let rest: Vec<_> = text.lines().skip(1).collect();
+        fs::write(&cfg.log_path, rest.join("\n") + "\n").unwrap();
+        assert!(!intact(&cfg));
+    }
+}
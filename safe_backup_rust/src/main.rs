@@ -1,151 +1,3166 @@
-use std::fs;
-use std::io::{self, Write};
-use std::path::{Path, PathBuf, Component};
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
-/// ---------- Path helpers (simple & robust on Windows/macOS/Linux) ----------
+use chrono::NaiveDateTime;
+use owo_colors::{AnsiColors, OwoColorize, Stream};
+use rayon::prelude::*;
+use safe_backup::{
+    format_duration, format_size, is_glob, log_action, parse_size, resolve_safe_path,
+    transfer_stats, ArchiveOptions, AuditStatus, BackupEntry, BackupError, BackupManager,
+    BackupOptions, BackupReport, BaseLock, CheckStatus, Config, ConfirmLevel, Confirmation,
+    CopyBench, CopyOptions, DeleteOptions, DiffChanges, DirProgress, ExistingBackup, ExtractOptions,
+    HashAlgorithm, LogFilter, LogFormat, LogLevel, MoveOptions, OverwritePolicy, PruneOptions,
+    RestoreOptions, RestoreReport, SettingOrigin, SyncStatus, VerifyBasis, WipePattern,
+    BENCH_BUFFER_SIZES,
+};
+use zeroize::Zeroizing;
 
-/// Resolve a user-supplied filename safely under the current working directory.
-/// Rules:
-/// - reject empty names
-/// - reject absolute paths
-/// - reject any parent traversal ("..") anywhere in the input
-/// - otherwise, join under the CWD (no canonicalization needed → avoids Windows false-positives)
-fn resolve_safe_path(input: &str) -> io::Result<PathBuf> {
-    let trimmed = input.trim();
-    if trimmed.is_empty() {
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Empty filename"));
+// ---------- Output ----------
+
+/// Set by `--quiet`: only errors are printed, and the exit status tells the rest.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--verbose`: `check` prints its result, which it otherwise only exits with.
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--strict`: a command that gave warnings fails once it is done.
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// The warnings given so far with `--strict`.
+static STRICT_WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn strict_warnings() -> MutexGuard<'static, Vec<String>> {
+    STRICT_WARNINGS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// With `--strict`, fail with the warnings given, counting a log that could not be
+/// written as one.
+fn strict_result() -> Result<(), BackupError> {
+    if !STRICT.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let mut warnings = std::mem::take(&mut *strict_warnings());
+    if safe_backup::log_write_failed() {
+        warnings.push("The log could not be written".to_string());
+    }
+    match warnings.is_empty() {
+        true => Ok(()),
+        false => Err(BackupError::Strict(warnings)),
+    }
+}
+
+/// Set by `--wait`: a command finding the base directory locked waits instead of failing.
+static WAIT_FOR_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--error-format json`: failures are printed as JSON lines for scripts.
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// How a failure is printed on stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum ErrorFormat {
+    /// "Operation failed: <message>", in red on a terminal
+    #[default]
+    Human,
+    /// One line of JSON: {"error_kind": "NotFound", "message": "..."}
+    Json,
+}
+
+/// `println!`, unless `--quiet` was given. `say!(Green: "...")` colors the line.
+macro_rules! say {
+    ($color:ident: $($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            println!("{}", paint(format!($($arg)*), Stream::Stdout, AnsiColors::$color))
+        }
+    };
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// `eprintln!` in yellow for warnings, unless `--quiet` was given; with `--strict` the
+/// warning is also kept for `strict_result`. Errors always go through `print_error`.
+macro_rules! warning {
+    ($($arg:tt)*) => {{
+        let warning = format!($($arg)*);
+        if STRICT.load(Ordering::Relaxed) {
+            strict_warnings().push(warning.clone());
+        }
+        if !QUIET.load(Ordering::Relaxed) {
+            eprintln!("{}", paint(warning, Stream::Stderr, AnsiColors::Yellow))
+        }
+    }};
+}
+
+/// `text` in `color` if `stream` is a terminal that allows color; plain when it is
+/// redirected or NO_COLOR is set. Only for the console: the log never sees this.
+fn paint(text: impl Display, stream: Stream, color: AnsiColors) -> String {
+    text.if_supports_color(stream, |t| t.color(color)).to_string()
+}
+
+/// Print an error in red on stderr, even with `--quiet`.
+fn print_error(text: impl Display) {
+    eprintln!("{}", paint(text, Stream::Stderr, AnsiColors::Red));
+}
+
+/// Print why a command failed as "<context>: <error>", or with `--error-format json` as
+/// one JSON line with the error's kind and message.
+fn print_failure(context: &str, e: &BackupError) {
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        eprintln!("{}", serde_json::json!({ "error_kind": e.kind(), "message": e.to_string() }));
+    } else {
+        print_error(format!("{}: {}", context, e));
+    }
+}
+
+/// What `--json` reports about a command besides whether it failed: noted by the command
+/// as it runs, printed by `print_json_result` once it is done.
+#[derive(Default)]
+struct JsonResult {
+    /// The command printed a JSON object of its own (a batch summary, a status, ...).
+    printed: bool,
+    /// The outcome if not plain "success": "unchanged", "cancelled", "mismatch", ...
+    outcome: Option<&'static str>,
+    backup_path: Option<PathBuf>,
+    bytes: Option<u64>,
+}
+
+static JSON_RESULT: Mutex<JsonResult> =
+    Mutex::new(JsonResult { printed: false, outcome: None, backup_path: None, bytes: None });
+
+fn json_result() -> MutexGuard<'static, JsonResult> {
+    JSON_RESULT.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Note how the command went, for `--json`.
+fn note_result(outcome: &'static str, backup_path: Option<&Path>, bytes: Option<u64>) {
+    let mut result = json_result();
+    result.outcome = Some(outcome);
+    result.backup_path = backup_path.map(Path::to_path_buf);
+    result.bytes = bytes;
+}
+
+/// Print `value` on stdout as one line of JSON, even with `--quiet`.
+fn print_json(value: serde_json::Value) {
+    json_result().printed = true;
+    println!("{}", value);
+}
+
+/// Back up a file or directory, or every match of a glob pattern, and report the result.
+/// With `json`, a glob's summary is printed as JSON instead of a table.
+fn backup_file(
+    mgr: &BackupManager,
+    filename: &str,
+    options: BackupOptions,
+    json: bool,
+) -> Result<(), BackupError> {
+    if is_glob(filename) {
+        return backup_matching(mgr, filename, options, json);
+    }
+    backup_one(mgr, filename, options).map(|_| ())
+}
+
+/// Back up each file matching `pattern`, carrying on past failures, then print a summary
+/// table with the time taken. With `jobs` above 1 in the config, that many files are
+/// backed up at once.
+fn backup_matching(
+    mgr: &BackupManager,
+    pattern: &str,
+    options: BackupOptions,
+    json: bool,
+) -> Result<(), BackupError> {
+    let matches = mgr.expand(pattern)?;
+    if matches.is_empty() {
+        return Err(BackupError::NotFound(format!("No files match '{}'", pattern)));
+    }
+
+    let started = Instant::now();
+    let back_up = |file: &PathBuf| {
+        let started = Instant::now();
+        let name = file.to_string_lossy().into_owned();
+        let (outcome, bytes) = match backup_one(mgr, file, options) {
+            Ok(Some(bytes)) => (BatchOutcome::Succeeded, Some(bytes)),
+            Ok(None) => (BatchOutcome::Skipped, None),
+            Err(e) => {
+                print_error(format!("Backup of '{}' failed: {}", name, e));
+                log_failure(mgr.config(), "backup", &name, &e);
+                (BatchOutcome::Failed, None)
+            }
+        };
+        let elapsed = started.elapsed();
+        BatchRow { file: name, action: "backup".to_string(), outcome, bytes, elapsed }
+    };
+    let jobs = mgr.config().jobs;
+    let rows: Vec<BatchRow> = if jobs > 1 {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .map_err(io::Error::other)?;
+        pool.install(|| matches.par_iter().map(back_up).collect())
+    } else {
+        matches.iter().map(back_up).collect()
+    };
+    let elapsed = format_duration(started.elapsed());
+
+    let jobs = match jobs {
+        1 => String::new(),
+        n => format!(", {} jobs", n),
+    };
+    let headline =
+        format!("{} files matching '{}' in {}{}", matches.len(), pattern, elapsed, jobs);
+    finish_batch(&rows, json, &headline)
+}
+
+/// A gzip backup at least this big compared to its file saves too little to be worth it.
+const POOR_COMPRESSION_RATIO: f64 = 0.95;
+
+/// Back up one file or directory. Returns the bytes backed up, or `None` if it was skipped
+/// as a backup or the log.
+fn backup_one(
+    mgr: &BackupManager,
+    filename: impl AsRef<OsStr>,
+    options: BackupOptions,
+) -> Result<Option<u64>, BackupError> {
+    let report = mgr.backup(filename, options)?;
+    let uploaded = matches!(report, BackupReport::File { .. } | BackupReport::Unchanged { .. });
+    let bytes = show_backup(mgr, report)?;
+    if let Some(url) = options.remote.filter(|_| uploaded && !mgr.config().dry_run) {
+        say!(Green: "Uploaded to {}", url);
+    }
+    Ok(bytes)
+}
+
+/// Print what a backup did; returns what `backup_one` does.
+fn show_backup(mgr: &BackupManager, report: BackupReport) -> Result<Option<u64>, BackupError> {
+    let dry_run = mgr.config().dry_run;
+    match report {
+        BackupReport::File {
+            source,
+            backup,
+            bytes,
+            compressed,
+            allocated,
+            sha256,
+            linked,
+            elapsed,
+        } => {
+            if dry_run {
+                say!(
+                    "[dry-run] would copy {} -> {} ({}, sha256 {})",
+                    source.display(),
+                    backup.display(),
+                    format_size(bytes),
+                    sha256
+                );
+                note_result("dry-run", Some(&backup), Some(bytes));
+                return Ok(Some(bytes));
+            }
+            note_result("success", Some(&backup), Some(bytes));
+            let stats = transfer_stats(bytes, elapsed).join(", ");
+            match linked {
+                Some(previous) => say!(
+                    Green: "Your backup created: {} (hardlink to {}, {})",
+                    backup.display(),
+                    previous.display(),
+                    stats
+                ),
+                None => say!(Green: "Your backup created: {} ({})", backup.display(), stats),
+            }
+            if let Some(allocated) = allocated {
+                say!(
+                    "Sparse: {} on disk for {}, saving {}",
+                    format_size(allocated),
+                    format_size(bytes),
+                    format_size(bytes.saturating_sub(allocated))
+                );
+            }
+            if let Some(compressed) = compressed {
+                let ratio = if bytes == 0 { 1.0 } else { compressed as f64 / bytes as f64 };
+                say!(
+                    "Compressed {} -> {} (ratio {:.2})",
+                    format_size(bytes),
+                    format_size(compressed),
+                    ratio
+                );
+                if bytes > 0 && ratio >= POOR_COMPRESSION_RATIO {
+                    say!(
+                        Yellow: "{} barely compresses (already compressed media?); a plain \
+                        backup without --gzip would be faster",
+                        source.display()
+                    );
+                }
+            }
+            Ok(Some(bytes))
+        }
+        BackupReport::Directory { source, backup, files, unchanged, bytes, skipped, elapsed } => {
+            for entry in &skipped {
+                warning!("Warning: skipping {} {}", entry.kind, entry.path.display());
+            }
+            if dry_run {
+                say!(
+                    "[dry-run] would copy {} files ({}, {} unchanged): {} -> {}",
+                    files - unchanged,
+                    format_size(bytes),
+                    unchanged,
+                    source.display(),
+                    backup.display()
+                );
+                note_result("dry-run", Some(&backup), Some(bytes));
+                return Ok(Some(bytes));
+            }
+            note_result("success", Some(&backup), Some(bytes));
+            let stats = transfer_stats(bytes, elapsed).join(", ");
+            if unchanged > 0 {
+                let copied = files - unchanged;
+                let counts = format!("{} files, {} copied, {} unchanged", files, copied, unchanged);
+                say!(Green: "Your backup created: {} ({}, {})", backup.display(), counts, stats);
+            } else {
+                let counts = format!("{} files", files);
+                say!(Green: "Your backup created: {} ({}, {})", backup.display(), counts, stats);
+            }
+            Ok(Some(bytes))
+        }
+        BackupReport::Delta {
+            source,
+            backup,
+            base,
+            bytes,
+            stored,
+            changed_blocks,
+            total_blocks,
+            elapsed,
+            ..
+        } => {
+            if dry_run {
+                say!(
+                    "[dry-run] would store the blocks of {} that changed since {} in {}",
+                    source.display(),
+                    base.display(),
+                    backup.display()
+                );
+                note_result("dry-run", Some(&backup), Some(bytes));
+                return Ok(Some(bytes));
+            }
+            note_result("success", Some(&backup), Some(bytes));
+            let stats = transfer_stats(bytes, elapsed).join(", ");
+            say!(Green: "Your incremental backup created: {} ({})", backup.display(), stats);
+            say!(
+                "Stored {} of {} blocks ({}) against {}",
+                changed_blocks,
+                total_blocks,
+                format_size(stored),
+                base.display()
+            );
+            Ok(Some(bytes))
+        }
+        BackupReport::Unchanged { source, backup } => {
+            note_result("unchanged", Some(&backup), None);
+            say!(
+                Yellow: "{} is unchanged; backup already current: {}",
+                source.display(),
+                backup.display()
+            );
+            Ok(Some(0))
+        }
+        BackupReport::Skipped { source, reason: "exists" } => {
+            note_result("skipped", None, None);
+            say!(
+                Yellow: "Skipping {}: it already has a backup, which existing_backup keeps",
+                source.display()
+            );
+            Ok(None)
+        }
+        BackupReport::Skipped { source, reason } => {
+            note_result("skipped", None, None);
+            let what = match reason {
+                "is-log" => "the log file",
+                "is-lock" => "the directory's lock file",
+                "excluded" => "excluded by the config's rule for its extension",
+                _ => "already a backup",
+            };
+            warning!(
+                "Skipping {}: it is {} (use --allow-backup to back it up anyway)",
+                source.display(),
+                what
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Restore each file matching `pattern` from its backup, carrying on past failures, then
+/// print a summary table. The pattern is matched against the files that have backups, or
+/// with `match_sources` against the files that exist now.
+fn restore_matching(
+    mgr: &BackupManager,
+    pattern: &str,
+    options: RestoreOptions,
+    match_sources: bool,
+    json: bool,
+) -> Result<(), BackupError> {
+    if options.destination.is_some() {
+        return Err(BackupError::InvalidInput(format!(
+            "--to takes one file; '{}' can only be restored in place",
+            pattern
+        )));
+    }
+    let matches = match match_sources {
+        true => mgr.expand(pattern)?,
+        false => mgr.expand_backed_up(pattern)?,
+    };
+    if matches.is_empty() {
+        let what = if match_sources { "files" } else { "backed-up files" };
+        return Err(BackupError::NotFound(format!("No {} match '{}'", what, pattern)));
+    }
+    let names: Vec<String> =
+        matches.iter().map(|file| file.to_string_lossy().into_owned()).collect();
+    let headline = format!("{} files matching '{}'", names.len(), pattern);
+    run_on_names(mgr, &names, "restore", json, &headline, |name| {
+        Ok((restore_file(mgr, name, options)?, None))
+    })
+}
+
+/// Restore a file (or a directory) from its backup, in place or into
+/// `options.destination`. If the live file has newer changes, or files of the directory
+/// differ, ask before overwriting them unless `options.force` is set. Returns whether it
+/// was restored, or skipped as kept or cancelled, for a batch's summary.
+fn restore_file(
+    mgr: &BackupManager,
+    filename: &str,
+    options: RestoreOptions,
+) -> Result<BatchOutcome, BackupError> {
+    let result = mgr.restore(filename, options, |target| {
+        let question = if target.is_dir() {
+            format!("Some files in '{}' differ from the backup. Overwrite them?", filename)
+        } else {
+            format!("'{}' has changes newer than the backup. Overwrite them?", filename)
+        };
+        ask_yes_no(mgr.config(), &format!("{} (yes/no): ", question))
+    });
+    if let Err(BackupError::RestoreMismatch { target, undoable: true }) = &result {
+        if offer_rollback(mgr, target)? {
+            let target = target.clone();
+            return Err(BackupError::RestoreMismatch { target, undoable: false });
+        }
+    }
+    let report = result?;
+    let outcome = match (mgr.config().dry_run, report.restored) {
+        (true, _) => "dry-run",
+        (false, true) => "success",
+        (false, false) if report.kept > 0 => "skipped",
+        (false, false) => "cancelled",
+    };
+    note_result(outcome, Some(&report.backup), Some(report.bytes));
+    let batch_outcome = match outcome {
+        "success" | "dry-run" => BatchOutcome::Succeeded,
+        _ => BatchOutcome::Skipped,
+    };
+    if let Some(files) = report.files {
+        print_directory_restore(mgr, filename, &report, files, options.overwrite);
+        return Ok(batch_outcome);
+    }
+    if report.kept > 0 {
+        let target = report.target.display();
+        let dry_run = if mgr.config().dry_run { "[dry-run] " } else { "" };
+        say!(Yellow: "{}{} exists; left unchanged (overwrite policy skip).", dry_run, target);
+        return Ok(batch_outcome);
+    }
+    if mgr.config().dry_run {
+        if report.live_newer {
+            say!("[dry-run] '{}' has changes newer than the backup", filename);
+        }
+        if let Some(dir) = &report.created_dir {
+            say!("[dry-run] would create directory {}", dir.display());
+        }
+        let content = match &report.sha256 {
+            Some(sha256) => format!("{}, sha256 {}", report.format.name(), sha256),
+            None => report.format.name().to_string(),
+        };
+        say!(
+            "[dry-run] would restore {} -> {} ({}, {})",
+            report.backup.display(),
+            report.target.display(),
+            format_size(report.bytes),
+            content
+        );
+    } else if report.restored {
+        if let Some(dir) = &report.created_dir {
+            say!("Created missing directory: {}", dir.display());
+        }
+        let stats = transfer_stats(report.bytes, report.elapsed).join(", ");
+        if options.destination.is_some() {
+            let (backup, target) = (report.backup.display(), report.target.display());
+            say!(Green: "File restored from: {} into {} ({})", backup, target, stats);
+        } else {
+            say!(Green: "File restored from: {} ({})", report.backup.display(), stats);
+        }
+        if let Some(snapshot) = &report.snapshot {
+            say!("Previous content kept in {} ('undo' brings it back)", snapshot.display());
+        }
+    } else {
+        say!(Yellow: "Restore cancelled; '{}' was left unchanged.", filename);
+    }
+    Ok(batch_outcome)
+}
+
+/// After a restore that did not read back as its backup, offer to undo it when someone is
+/// at the terminal to answer (stdin may hold the names of a batch instead). True if it was
+/// undone.
+fn offer_rollback(mgr: &BackupManager, target: &Path) -> Result<bool, BackupError> {
+    warning!("{} does not match its backup after the restore.", target.display());
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    let question = "Undo the restore and put back what was there before? (yes/no): ";
+    if !ask_yes_no(mgr.config(), question)? {
+        return Ok(false);
+    }
+    undo_last(mgr)?;
+    Ok(true)
+}
+
+/// `restore_file`'s output for a directory backup of `files` files.
+fn print_directory_restore(
+    mgr: &BackupManager,
+    filename: &str,
+    report: &RestoreReport,
+    files: usize,
+    policy: OverwritePolicy,
+) {
+    let (backup, target) = (report.backup.display(), report.target.display());
+    if mgr.config().dry_run {
+        if report.live_newer {
+            say!("[dry-run] some files in '{}' differ from the backup", filename);
+        }
+        let bytes = format_size(report.bytes);
+        say!("[dry-run] would restore {} files ({}): {} -> {}", files, bytes, backup, target);
+        if report.kept > 0 {
+            say!("[dry-run] would leave {} existing files unchanged", report.kept);
+        }
+    } else if report.restored {
+        let stats = transfer_stats(report.bytes, report.elapsed).join(", ");
+        say!(
+            Green: "Directory restored from: {} into {} ({} files, {})",
+            backup,
+            target,
+            files,
+            stats
+        );
+        if report.kept > 0 {
+            say!("Left {} existing files unchanged.", report.kept);
+        }
+        if report.live_newer && policy == OverwritePolicy::Backup {
+            say!("Previous content of overwritten files kept in .trash/");
+        }
+    } else {
+        say!(Yellow: "Restore cancelled; '{}' was left unchanged.", filename);
+    }
+}
+
+fn verify_backup(
+    mgr: &BackupManager,
+    filename: &str,
+    version: Option<&str>,
+) -> Result<(), BackupError> {
+    let report = mgr.verify(filename, version)?;
+    let outcome = if report.matches() { "match" } else { "mismatch" };
+    note_result(outcome, Some(&report.backup), None);
+    match report.basis {
+        VerifyBasis::Sidecar => {
+            let algorithm = report.algorithm.name();
+            say!("recorded  {} {}", algorithm, report.expected);
+            say!("current   {} {}  {}", algorithm, report.actual, report.backup.display());
+            if report.matches() {
+                say!(Green: "Backup matches its recorded checksum.");
+            } else {
+                say!(Red: "Backup does NOT match its recorded checksum (possible corruption).");
+            }
+        }
+        VerifyBasis::Source => {
+            warning!(
+                "Warning: no checksum sidecar for {}; comparing against the source instead.",
+                report.backup.display()
+            );
+            say!("source  {}  {}", report.expected, report.source.display());
+            say!("backup  {}  {}", report.actual, report.backup.display());
+            if report.matches() {
+                say!(Green: "Backup matches the source.");
+            } else {
+                say!(Red: "Backup does NOT match the source.");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Restore a backup into a temporary file, check it against what was recorded for it and
+/// throw it away. Fails (exit status 1) if a check does not match.
+fn test_restore(
+    mgr: &BackupManager,
+    filename: &str,
+    version: Option<&str>,
+    json: bool,
+) -> Result<(), BackupError> {
+    let report = mgr.test_restore(filename, version)?;
+    let verdict = |matches: Option<bool>| match matches {
+        Some(true) => "match",
+        Some(false) => "mismatch",
+        None => "-",
+    };
+    if json {
+        print_json(serde_json::json!({
+            "op": "test-restore",
+            "file": filename,
+            "outcome": if report.passed() { "success" } else { "mismatch" },
+            "backup_path": report.backup,
+            "format": report.format.name(),
+            "bytes": report.bytes,
+            "sha256": report.sha256,
+            "sidecar": verdict(report.stored_matches),
+            "content": verdict(report.content_matches()),
+            "recorded_in": report.expected.as_ref().map(|(record, _)| record.name()),
+        }));
+    } else {
+        say!(
+            "Restored {} ({}) to a temporary file: {} in {}",
+            report.backup.display(),
+            report.format.name(),
+            format_size(report.bytes),
+            format_duration(report.elapsed)
+        );
+        match report.stored_matches {
+            Some(true) => say!("sidecar   the backup matches its recorded checksum"),
+            Some(false) => say!(Red: "sidecar   the backup does NOT match its recorded checksum"),
+            None => say!("sidecar   none"),
+        }
+        match (&report.expected, report.content_matches()) {
+            (Some((record, expected)), Some(matches)) => {
+                say!("restored  sha256 {}", report.sha256);
+                say!("{:<9} sha256 {}", record.name(), expected);
+                if !matches {
+                    say!(Red: "The restored content does NOT match the {}.", record.name());
+                }
+            }
+            _ => say!("restored  sha256 {} (no recorded content to compare)", report.sha256),
+        }
+        if report.passed() {
+            say!(Green: "Backup restores correctly; the temporary copy was removed.");
+        }
+    }
+    match report.passed() {
+        true => Ok(()),
+        false => Err(BackupError::TestRestoreFailed(report.backup)),
+    }
+}
+
+/// How many differing byte ranges `diff` lists for binary files.
+const MAX_LISTED_RANGES: usize = 20;
+
+/// Show how the live file differs from its backup: a colored unified diff for text, the
+/// differing byte ranges for binary files.
+/// Write a backup's content to stdout and nothing else, so it can be piped; only when
+/// stdout is a terminal is the backup's name given after it, on stderr. A reader that
+/// stops early (`| head`) is not a failure.
+fn cat_backup(
+    mgr: &BackupManager,
+    filename: &str,
+    version: Option<&str>,
+    json: bool,
+) -> Result<(), BackupError> {
+    if json {
+        return Err(BackupError::InvalidInput(
+            "cat writes the backup itself to stdout; --json does not apply".to_string(),
+        ));
+    }
+    let backup = match mgr.cat(filename, version, &mut io::stdout().lock()) {
+        Ok(backup) => backup,
+        Err(BackupError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if io::stdout().is_terminal() && !QUIET.load(Ordering::Relaxed) {
+        eprintln!("({})", backup.display());
+    }
+    Ok(())
+}
+
+fn diff_backup(
+    mgr: &BackupManager,
+    filename: &str,
+    version: Option<&str>,
+) -> Result<(), BackupError> {
+    let report = mgr.diff(filename, version)?;
+    if let DiffChanges::Identical = report.changes {
+        let (source, backup) = (report.source.display(), report.backup.display());
+        say!(Green: "{} is identical to its backup {}", source, backup);
+    }
+    print_changes(&report.changes);
+    Ok(())
+}
+
+/// Compare two backups of a file: their checksums, then what changed between them.
+fn compare_backups(
+    mgr: &BackupManager,
+    filename: &str,
+    first: &str,
+    second: &str,
+) -> Result<(), BackupError> {
+    let report = mgr.compare(filename, first, second)?;
+    say!("old  sha256 {}  {}", report.old_sha256, report.old.display());
+    say!("new  sha256 {}  {}", report.new_sha256, report.new.display());
+    if let DiffChanges::Identical = report.changes {
+        say!(Green: "The backups are identical.");
+    }
+    print_changes(&report.changes);
+    Ok(())
+}
+
+/// Print a unified diff in color, or the differing byte ranges of binary content.
+/// Nothing for identical content.
+fn print_changes(changes: &DiffChanges) {
+    match changes {
+        DiffChanges::Identical => {}
+        DiffChanges::Text(diff) => {
+            for line in diff.lines() {
+                let color = match line.as_bytes().first() {
+                    _ if line.starts_with("+++") || line.starts_with("---") => None,
+                    Some(b'+') => Some(AnsiColors::Green),
+                    Some(b'-') => Some(AnsiColors::Red),
+                    Some(b'@') => Some(AnsiColors::Cyan),
+                    _ => None,
+                };
+                match color {
+                    Some(color) => say!("{}", paint(line, Stream::Stdout, color)),
+                    None => say!("{}", line),
+                }
+            }
+        }
+        DiffChanges::Binary { ranges, changed } => {
+            let changed = format_size(*changed);
+            say!("Binary files differ: {} changed in {} ranges", changed, ranges.len());
+            for (start, end) in ranges.iter().take(MAX_LISTED_RANGES) {
+                say!("  {:#010x}..{:#010x}  ({})", start, end, format_size(end - start));
+            }
+            if ranges.len() > MAX_LISTED_RANGES {
+                say!("  ... and {} more ranges", ranges.len() - MAX_LISTED_RANGES);
+            }
+        }
+    }
+}
+
+/// Print a file's backup state as one line of key=value pairs, or as a JSON object:
+/// "notes.txt source=present backup=notes.txt.bak age=3h12m match=yes".
+fn show_status(mgr: &BackupManager, filename: &str, json: bool) -> Result<(), BackupError> {
+    let report = mgr.status(filename)?;
+    let backup = report.backup.as_ref().map(|b| b.path.display().to_string());
+    if json {
+        let status = serde_json::json!({
+            "file": filename,
+            "source_exists": report.source_exists,
+            "backup": backup,
+            "matches": report.matches,
+            "age_secs": report.age.map(|age| age.as_secs()),
+            "stale": report.stale,
+        });
+        print_json(status);
+        return Ok(());
+    }
+    let source = if report.source_exists { "present" } else { "missing" };
+    let matches = match report.matches {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "unknown",
+    };
+    let age = report.age.map(format_duration).unwrap_or_else(|| "-".to_string());
+    say!(
+        "{} source={} backup={} age={} match={}",
+        filename,
+        source,
+        backup.as_deref().unwrap_or("none"),
+        age,
+        matches
+    );
+    if report.stale {
+        warning!("Backup is stale: '{}' has changed since its newest backup.", filename);
+    }
+    Ok(())
+}
+
+/// `check`: print the result only with `--verbose` (or as JSON), and fail with
+/// `CheckFailed` unless the backup is current, so the exit status tells.
+fn check_backup(
+    mgr: &BackupManager,
+    filename: &str,
+    max_age: Option<Duration>,
+    json: bool,
+) -> Result<(), BackupError> {
+    let report = mgr.check(filename, max_age)?;
+    let backup = report.backup.as_ref().map(|b| b.path.display().to_string());
+    if json {
+        print_json(serde_json::json!({
+            "file": filename,
+            "status": report.status.as_str(),
+            "backup": backup,
+            "age_secs": report.age.map(|age| age.as_secs()),
+        }));
+    } else if VERBOSE.load(Ordering::Relaxed) {
+        let age = report.age.map(format_duration).unwrap_or_else(|| "-".to_string());
+        let backup = backup.as_deref().unwrap_or("none");
+        say!("{} {} backup={} age={}", filename, report.status.as_str(), backup, age);
+    }
+    match report.status {
+        CheckStatus::Ok => Ok(()),
+        status => Err(BackupError::CheckFailed { file: report.source, status }),
+    }
+}
+
+/// `--max-age`: a whole number of seconds, minutes, hours or days ("90s", "30m", "24h",
+/// "7d").
+fn parse_age(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let invalid = || format!("'{}' is not an age such as 90s, 30m, 24h or 7d", text);
+    let split = text.len() - text.chars().last().map_or(0, char::len_utf8);
+    let (number, unit) = text.split_at(split);
+    let count: u64 = number.parse().map_err(|_| invalid())?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        _ => return Err(invalid()),
+    };
+    count.checked_mul(unit).map(Duration::from_secs).ok_or_else(invalid)
+}
+
+fn archive_file(
+    mgr: &BackupManager,
+    filename: &str,
+    options: ArchiveOptions,
+) -> Result<(), BackupError> {
+    let report = mgr.archive(filename, options)?;
+    for entry in &report.skipped {
+        warning!("Warning: skipping {} {}", entry.kind, entry.path.display());
+    }
+    let verb = if report.appended { "append" } else { "archive" };
+    if mgr.config().dry_run {
+        say!(
+            "[dry-run] would {} {} files ({}): {} -> {}",
+            verb,
+            report.files,
+            format_size(report.bytes),
+            report.source.display(),
+            report.archive.display()
+        );
+    } else if report.appended {
+        say!(
+            Green: "Appended {} files to {} ({} unchanged)",
+            report.files,
+            report.archive.display(),
+            report.unchanged
+        );
+    } else {
+        say!(Green: "Archive created: {} ({} files)", report.archive.display(), report.files);
+    }
+    Ok(())
+}
+
+/// Print each file stored in an archive, in the order it was added.
+fn list_archive(mgr: &BackupManager, filename: &str) -> Result<(), BackupError> {
+    let members = mgr.archive_members(filename)?;
+    if members.is_empty() {
+        say!("No files in '{}'.", filename);
+        return Ok(());
+    }
+    say!("Files in '{}', in the order they were added:", filename);
+    for member in &members {
+        let modified: chrono::DateTime<chrono::Local> = member.modified.into();
+        say!(
+            "  {}  {}  {}",
+            member.name.display(),
+            format_size(member.size),
+            modified.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    Ok(())
+}
+
+fn extract_archive(
+    mgr: &BackupManager,
+    filename: &str,
+    options: ExtractOptions,
+) -> Result<(), BackupError> {
+    let report = mgr.extract(filename, options)?;
+    for kept in &report.kept {
+        warning!("Keeping existing {} (use --overwrite to replace it)", kept.display());
+    }
+    if mgr.config().dry_run {
+        for file in &report.extracted {
+            say!("[dry-run] would extract {}", file.display());
+        }
+    } else {
+        say!(
+            Green: "Extracted {} files from {}",
+            report.extracted.len(),
+            report.archive.display()
+        );
     }
-    if trimmed.contains('\0') {
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid character in filename"));
+    Ok(())
+}
+
+/// Print the log entries the filter lets through, oldest first.
+fn show_log(mgr: &BackupManager, filter: LogFilter) -> Result<(), BackupError> {
+    let entries = mgr.search_log(filter)?;
+    for entry in &entries {
+        say!("{}", entry);
+    }
+    say!("{} matching entries.", entries.len());
+    Ok(())
+}
+
+/// Entries `log --follow` starts with, as `tail -f` does.
+const FOLLOW_TAIL: usize = 10;
+
+/// Print the last matching entries, then each new one as it is written, like `tail -f`.
+/// Ctrl-C ends it: with nothing being written the interrupt handler just exits.
+fn follow_log(mgr: &BackupManager, filter: LogFilter) -> Result<(), BackupError> {
+    let tail = filter.tail.unwrap_or(FOLLOW_TAIL);
+    for entry in mgr.search_log(LogFilter { tail: Some(tail), ..filter })? {
+        say!("{}", entry);
+    }
+    mgr.follow_log(filter, |entry| {
+        say!("{}", entry);
+        true
+    })
+}
+
+/// Time the copy strategies and print each one's speed as it finishes, then the buffer
+/// size to use; with `json`, one JSON object at the end.
+fn bench_copies(
+    mgr: &BackupManager,
+    size: u64,
+    buffers: &[u64],
+    json: bool,
+) -> Result<(), BackupError> {
+    let buffers = if buffers.is_empty() { &BENCH_BUFFER_SIZES[..] } else { buffers };
+    let method = |bench: &CopyBench| match bench.buffer_size {
+        Some(buffer) => format!("buffered {}", format_size(buffer)),
+        None => "fs::copy".to_string(),
+    };
+    let mut first = true;
+    let results = mgr.bench(size, buffers, |bench| {
+        if json {
+            return;
+        }
+        if std::mem::take(&mut first) {
+            say!("Copied a {} file with each method:", format_size(size));
+            say!("{:<18} {:>10} {:>12}", "METHOD", "TIME", "THROUGHPUT");
+        }
+        let time = format!("{:.1} ms", bench.elapsed.as_secs_f64() * 1000.0);
+        let speed = format!("{}/s", format_size(bench.bytes_per_sec() as u64));
+        say!("{:<18} {:>10} {:>12}", method(bench), time, speed);
+    })?;
+    let fastest = results
+        .iter()
+        .filter(|bench| bench.buffer_size.is_some())
+        .max_by(|a, b| a.bytes_per_sec().total_cmp(&b.bytes_per_sec()));
+    if json {
+        let rows: Vec<_> = results
+            .iter()
+            .map(|bench| {
+                serde_json::json!({
+                    "method": method(bench),
+                    "buffer_size": bench.buffer_size,
+                    "bytes": bench.bytes,
+                    "seconds": bench.elapsed.as_secs_f64(),
+                    "bytes_per_sec": bench.bytes_per_sec(),
+                })
+            })
+            .collect();
+        let fastest = fastest.and_then(|bench| bench.buffer_size);
+        print_json(serde_json::json!({ "results": rows, "fastest_buffer_size": fastest }));
+    } else if let Some(buffer) = fastest.and_then(|bench| bench.buffer_size) {
+        say!(
+            Green: "Fastest buffer: {}; use --buffer-size {} or copy_buffer_size = {}.",
+            format_size(buffer),
+            buffer,
+            buffer
+        );
+    }
+    Ok(())
+}
+
+/// Print the log's numbers: totals, a table per action and the most backed-up files, or
+/// with `json` one JSON object.
+fn log_report(mgr: &BackupManager, top: usize, json: bool) -> Result<(), BackupError> {
+    let report = mgr.log_report(top)?;
+    let succeeded: usize = report.actions.iter().map(|a| a.succeeded).sum();
+    let failed: usize = report.actions.iter().map(|a| a.failed).sum();
+    let rate = |succeeded: usize, failed: usize| match succeeded + failed {
+        0 => None,
+        n => Some(succeeded as f64 * 100.0 / n as f64),
+    };
+    if json {
+        let actions: Vec<_> = report
+            .actions
+            .iter()
+            .map(|a| {
+                serde_json::json!({
+                    "action": a.action,
+                    "entries": a.entries,
+                    "succeeded": a.succeeded,
+                    "failed": a.failed,
+                    "success_rate": rate(a.succeeded, a.failed),
+                })
+            })
+            .collect();
+        let top_backups: Vec<_> = report
+            .top_backups
+            .iter()
+            .map(|(file, count)| serde_json::json!({ "file": file, "backups": count }))
+            .collect();
+        let summary = serde_json::json!({
+            "entries": report.entries,
+            "first": report.first,
+            "last": report.last,
+            "succeeded": succeeded,
+            "failed": failed,
+            "success_rate": rate(succeeded, failed),
+            "bytes_backed_up": report.bytes_backed_up,
+            "actions": actions,
+            "top_backups": top_backups,
+        });
+        print_json(summary);
+        return Ok(());
+    }
+
+    let (Some(first), Some(last)) = (&report.first, &report.last) else {
+        say!("The log is empty.");
+        return Ok(());
+    };
+    let percent =
+        |rate: Option<f64>| rate.map_or_else(|| "-".to_string(), |r| format!("{:.1}%", r));
+    say!("{} entries from {} to {}", report.entries, first, last);
+    say!(
+        "{} succeeded, {} failed ({} success rate)",
+        succeeded,
+        failed,
+        percent(rate(succeeded, failed))
+    );
+    say!("About {} backed up", format_size(report.bytes_backed_up));
+    let cells: Vec<[String; 5]> = report
+        .actions
+        .iter()
+        .map(|a| {
+            [
+                a.action.clone(),
+                a.entries.to_string(),
+                a.succeeded.to_string(),
+                a.failed.to_string(),
+                percent(rate(a.succeeded, a.failed)),
+            ]
+        })
+        .collect();
+    say!();
+    print_table(["ACTION", "ENTRIES", "SUCCEEDED", "FAILED", "RATE"], &cells);
+    if !report.top_backups.is_empty() {
+        say!();
+        say!("Most backed up:");
+        for (file, count) in &report.top_backups {
+            say!("  {:>5}  {}", count, file);
+        }
+    }
+    Ok(())
+}
+
+/// `--since`: a date means from its start.
+fn parse_since(text: &str) -> Result<NaiveDateTime, String> {
+    parse_log_time(text, false)
+}
+
+/// `--until`: a date means up to its end.
+fn parse_until(text: &str) -> Result<NaiveDateTime, String> {
+    parse_log_time(text, true)
+}
+
+/// "2024-06-01T14:30:00" (a space instead of the 'T' works too), or "2024-06-01" for the
+/// start of that day, or with `end_of_day` the start of the next.
+fn parse_log_time(text: &str, end_of_day: bool) -> Result<NaiveDateTime, String> {
+    let text = text.trim();
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(text, format) {
+            return Ok(time);
+        }
+    }
+    let date = chrono::NaiveDate::parse_from_str(text, "%Y-%m-%d").map_err(|_| {
+        format!("'{}' is not a date (2024-06-01) or time (2024-06-01T14:30:00)", text)
+    })?;
+    let date = if end_of_day { date.succ_opt().unwrap_or(date) } else { date };
+    Ok(date.and_time(chrono::NaiveTime::MIN))
+}
+
+/// Check the log's hash chain and numbering and report, per log file, whether it is intact
+/// or the first line where it breaks. Fails if any file is broken.
+fn verify_log(mgr: &BackupManager) -> Result<(), BackupError> {
+    let checks = mgr.verify_log()?;
+    if checks.is_empty() {
+        say!("No log file found.");
+        return Ok(());
+    }
+
+    let mut broken = 0;
+    for check in &checks {
+        let unchained = match check.unchained {
+            0 => String::new(),
+            n => format!(", {} older unchained lines not checked", n),
+        };
+        match (check.broken_at, check.gap_at) {
+            (Some(_), _) if check.unlinked => {
+                broken += 1;
+                say!(
+                    Red: "{}: chain BROKEN at its first entry (the log file before it was \
+                          removed or replaced, or the start of this one cut off){}",
+                    check.path.display(),
+                    unchained
+                );
+            }
+            (Some(line), _) => {
+                broken += 1;
+                say!(
+                    Red: "{}: chain BROKEN at line {} (edited, inserted or removed entries){}",
+                    check.path.display(),
+                    line,
+                    unchained
+                );
+            }
+            (None, Some(line)) => {
+                broken += 1;
+                say!(
+                    Red: "{}: entries MISSING before line {} (gap in the numbering){}",
+                    check.path.display(),
+                    line,
+                    unchained
+                );
+            }
+            (None, None) => say!(
+                Green: "{}: {} lines, chain intact{}",
+                check.path.display(),
+                check.lines,
+                unchained
+            ),
+        }
+    }
+    if broken > 0 {
+        return Err(BackupError::BatchFailed { failed: broken, total: checks.len() });
+    }
+    Ok(())
+}
+
+/// Run each step of a backup's life on a scratch file in a fresh temporary directory, with
+/// its own log there, and print pass or fail with the time each took. Stops at the first
+/// failure. Real files and the real log are never touched; the directory is removed after.
+fn self_test() -> Result<(), BackupError> {
+    const NAME: &str = "selftest.dat";
+
+    let dir = tempfile::Builder::new().prefix("safe_backup-selftest.").tempdir()?;
+    let cfg = Config { log_path: dir.path().join("logfile.txt"), ..Config::default() };
+    let mgr = BackupManager::new(dir.path(), cfg);
+    let file = dir.path().join(NAME);
+    let content: Vec<u8> = (0..256 * 1024u32).map(|i| (i * 31 % 251) as u8).collect();
+    let failed = |msg: &str| Err(BackupError::InvalidInput(msg.to_string()));
+
+    type Step<'a> = (&'static str, Box<dyn Fn() -> Result<(), BackupError> + 'a>);
+    let steps: [Step; 5] = [
+        ("create", Box::new(|| Ok(std::fs::write(&file, &content)?))),
+        (
+            "backup",
+            Box::new(|| match mgr.backup(NAME, BackupOptions::default())? {
+                BackupReport::File { .. } => Ok(()),
+                _ => failed("no backup was made"),
+            }),
+        ),
+        (
+            "verify",
+            Box::new(|| {
+                if !mgr.verify(NAME, None)?.matches() {
+                    return failed("the checksum does not match");
+                }
+                Ok(())
+            }),
+        ),
+        (
+            "restore",
+            Box::new(|| {
+                std::fs::write(&file, b"changed")?;
+                let options = RestoreOptions { force: true, ..RestoreOptions::default() };
+                mgr.restore(NAME, options, |_| Ok(true))?;
+                if std::fs::read(&file)? != content {
+                    return failed("the restored file differs from the original");
+                }
+                Ok(())
+            }),
+        ),
+        (
+            "delete",
+            Box::new(|| {
+                let options = DeleteOptions { purge: true, ..DeleteOptions::default() };
+                mgr.delete(NAME, options, |_, _| Ok(Confirmation::Yes))?;
+                if file.exists() {
+                    return failed("the file is still there");
+                }
+                Ok(())
+            }),
+        ),
+    ];
+
+    say!("Self-test in {}", dir.path().display());
+    let started = Instant::now();
+    for (name, step) in &steps {
+        let step_started = Instant::now();
+        let result = step();
+        let took = format_duration(step_started.elapsed());
+        match result {
+            Ok(()) => {
+                let status = paint("PASS", Stream::Stdout, AnsiColors::Green);
+                say!("{}  {:<8} {}", status, name, took);
+            }
+            Err(e) => {
+                let status = paint("FAIL", Stream::Stdout, AnsiColors::Red);
+                say!("{}  {:<8} {} ({})", status, name, took, e);
+                return Err(BackupError::InvalidInput(format!("Self-test failed at '{}'", name)));
+            }
+        }
+    }
+    let elapsed = format_duration(started.elapsed());
+    say!(Green: "Self-test passed: {} steps in {}.", steps.len(), elapsed);
+    Ok(())
+}
+
+/// Check every backup at once and print one row per backup, then a summary. Fails if
+/// any backup is corrupt or unreadable; stale and orphaned backups are only reported.
+fn verify_all_backups(mgr: &BackupManager) -> Result<(), BackupError> {
+    let entries = mgr.verify_all()?;
+    if entries.is_empty() {
+        say!("No backups found.");
+        return Ok(());
+    }
+
+    let (mut ok, mut corrupt, mut stale, mut orphaned, mut errors) = (0, 0, 0, 0, 0);
+    say!("{:<9} BACKUP", "STATUS");
+    for entry in &entries {
+        let (status, color, note) = match &entry.status {
+            AuditStatus::Ok => {
+                ok += 1;
+                ("OK", AnsiColors::Green, String::new())
+            }
+            AuditStatus::Corrupt => {
+                corrupt += 1;
+                ("CORRUPT", AnsiColors::Red, String::new())
+            }
+            AuditStatus::Stale => {
+                stale += 1;
+                ("STALE", AnsiColors::Yellow, " (differs from source)".to_string())
+            }
+            AuditStatus::Orphaned => {
+                orphaned += 1;
+                ("ORPHANED", AnsiColors::Yellow, " (no checksum, source missing)".to_string())
+            }
+            AuditStatus::Error(msg) => {
+                errors += 1;
+                ("ERROR", AnsiColors::Red, format!(" ({})", msg))
+            }
+        };
+        // Pad before coloring, so the escape codes do not throw off the column.
+        let status = paint(format!("{:<9}", status), Stream::Stdout, color);
+        say!("{} {}{}", status, entry.backup.display(), note);
+    }
+    say!(
+        "{} backups: {} ok, {} corrupt, {} stale, {} orphaned, {} errors.",
+        entries.len(),
+        ok,
+        corrupt,
+        stale,
+        orphaned,
+        errors
+    );
+    if corrupt + errors > 0 {
+        return Err(BackupError::BatchFailed { failed: corrupt + errors, total: entries.len() });
+    }
+    Ok(())
+}
+
+/// `value` as it is given on the command line.
+fn value_name(value: impl clap::ValueEnum) -> String {
+    value.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default()
+}
+
+/// Print every setting in effect with its value and where it came from, after the config
+/// file and base directory; with `json`, all of it as one JSON object.
+fn show_info(mgr: &BackupManager, json: bool) -> Result<(), BackupError> {
+    let cfg = mgr.config();
+    let path = |p: &Path| p.display().to_string();
+    let size = |s: Option<u64>| s.map_or("-".to_string(), format_size);
+    let list = |items: Vec<&str>| match items.is_empty() {
+        true => "-".to_string(),
+        false => items.join(", "),
+    };
+    let settings = [
+        ("log_path", path(&cfg.log_path)),
+        ("log_format", value_name(cfg.log_format)),
+        ("log_level", value_name(cfg.log_level)),
+        ("log_max_size", format_size(cfg.log_max_size)),
+        ("log_max_files", cfg.log_max_files.to_string()),
+        ("log_time_format", cfg.log_time_format.clone()),
+        ("log_sequence", cfg.log_sequence.to_string()),
+        ("backup_suffix", cfg.backup_suffix.clone()),
+        ("backup_dir", cfg.backup_dir.as_deref().map_or("-".to_string(), path)),
+        ("preserve_structure", cfg.preserve_structure.to_string()),
+        ("existing_backup", cfg.existing_backup.name().to_string()),
+        ("create_parents", cfg.create_parents.to_string()),
+        ("versions_dir", cfg.versions_dir.as_deref().map_or("-".to_string(), path)),
+        ("version_stamp_format", cfg.version_stamp_format.clone()),
+        ("name_template", cfg.name_template.clone().unwrap_or_else(|| "-".to_string())),
+        ("keep_versions", cfg.keep_versions.map_or("-".to_string(), |n| n.to_string())),
+        ("jobs", cfg.jobs.to_string()),
+        ("copy_attempts", cfg.copy_attempts.to_string()),
+        ("retry_delay_ms", cfg.retry_delay_ms.to_string()),
+        ("copy_buffer_size", format_size(cfg.copy_buffer_size)),
+        ("compress_level", cfg.compress_level.to_string()),
+        ("durable", cfg.durable.to_string()),
+        ("hash", cfg.hash.name().to_string()),
+        ("checksum_cache", cfg.checksum_cache.to_string()),
+        ("extensions", list(cfg.extensions.keys().map(String::as_str).collect())),
+        ("max_size", size(cfg.max_size)),
+        ("sftp_identity", cfg.sftp_identity.as_deref().map_or("-".to_string(), path)),
+        ("follow_symlinks", cfg.follow_symlinks.to_string()),
+        ("confirm_words", list(cfg.confirm_words.iter().map(String::as_str).collect())),
+        ("confirm_name_size", size(cfg.confirm_name_size)),
+        ("pre_hook", cfg.pre_hook.clone().unwrap_or_else(|| "-".to_string())),
+        ("post_hook", cfg.post_hook.clone().unwrap_or_else(|| "-".to_string())),
+    ];
+    let config_file = cfg.loaded_from.as_deref().map(path);
+    if json {
+        let settings: serde_json::Map<_, _> = settings
+            .iter()
+            .map(|(key, value)| {
+                let from = cfg.origin(key).name();
+                (key.to_string(), serde_json::json!({ "value": value, "from": from }))
+            })
+            .collect();
+        print_json(serde_json::json!({
+            "config_file": config_file,
+            "base_dir": path(mgr.base()),
+            "settings": settings,
+        }));
+        return Ok(());
+    }
+    say!("Config file:    {}", config_file.as_deref().unwrap_or("none (defaults)"));
+    say!("Base directory: {}", path(mgr.base()));
+    say!();
+    let rows: Vec<_> = settings
+        .into_iter()
+        .map(|(key, value)| [key.to_string(), value, cfg.origin(key).name().to_string()])
+        .collect();
+    print_table(["SETTING", "VALUE", "FROM"], &rows);
+    Ok(())
+}
+
+/// Print one row per backup as `audit` checks it, then the count of each state; with
+/// `json`, one JSON object per backup and then the counts. Fails if any backup is corrupt
+/// or unreadable.
+fn audit_backups(mgr: &BackupManager, json: bool) -> Result<(), BackupError> {
+    if !json {
+        say!("{:<14} BACKUP", "STATUS");
+    }
+    let report = mgr.audit(|entry| {
+        if json {
+            let error = match &entry.status {
+                SyncStatus::Error(msg) => Some(msg.as_str()),
+                _ => None,
+            };
+            let row = serde_json::json!({
+                "backup": entry.backup.display().to_string(),
+                "source": entry.source.as_ref().map(|s| s.display().to_string()),
+                "status": entry.status.name(),
+                "error": error,
+            });
+            println!("{}", row);
+            return;
+        }
+        let (color, note) = match &entry.status {
+            SyncStatus::InSync => (AnsiColors::Green, String::new()),
+            SyncStatus::Stale => (AnsiColors::Yellow, " (the source has changed)".to_string()),
+            SyncStatus::MissingSource => (AnsiColors::Yellow, String::new()),
+            SyncStatus::Corrupt => (AnsiColors::Red, " (checksum mismatch)".to_string()),
+            SyncStatus::Error(msg) => (AnsiColors::Red, format!(" ({})", msg)),
+        };
+        // Pad before coloring, so the escape codes do not throw off the column.
+        let status = paint(format!("{:<14}", entry.status.name()), Stream::Stdout, color);
+        say!("{} {}{}", status, entry.backup.display(), note);
+    })?;
+    if json {
+        print_json(serde_json::json!({
+            "total": report.total(),
+            "in_sync": report.in_sync,
+            "stale": report.stale,
+            "missing_source": report.missing_source,
+            "corrupt": report.corrupt,
+            "errors": report.errors,
+        }));
+    } else if report.total() == 0 {
+        say!("No backups found.");
+    } else {
+        say!(
+            "{} backups: {} in sync, {} stale, {} missing source, {} corrupt, {} errors.",
+            report.total(),
+            report.in_sync,
+            report.stale,
+            report.missing_source,
+            report.corrupt,
+            report.errors
+        );
+    }
+    let failed = report.corrupt + report.errors;
+    if failed > 0 {
+        return Err(BackupError::BatchFailed { failed, total: report.total() });
+    }
+    Ok(())
+}
+
+/// List backups whose source is gone; with `remove`, delete them after a yes/no
+/// confirmation (skipped with `force`).
+fn orphaned_backups(mgr: &BackupManager, remove: bool, force: bool) -> Result<(), BackupError> {
+    if !remove {
+        let orphans = mgr.orphans()?;
+        if orphans.is_empty() {
+            say!("No orphaned backups found.");
+            return Ok(());
+        }
+        say!("Backups whose source no longer exists:");
+        for orphan in &orphans {
+            say!("  {}  {}", orphan.path.display(), format_size(orphan.size));
+        }
+        return Ok(());
+    }
+
+    let report = mgr.remove_orphans(|orphans| {
+        for orphan in orphans {
+            println!("  {}  {}", orphan.path.display(), format_size(orphan.size));
+        }
+        if force {
+            return Ok(true);
+        }
+        let question = format!("Delete these {} orphaned backups? (yes/no): ", orphans.len());
+        ask_yes_no(mgr.config(), &question)
+    })?;
+    let bytes: u64 = report.orphans.iter().map(|o| o.size).sum();
+    if report.orphans.is_empty() {
+        say!("No orphaned backups found.");
+    } else if mgr.config().dry_run {
+        for orphan in &report.orphans {
+            say!("[dry-run] would remove {} ({})", orphan.path.display(), format_size(orphan.size));
+        }
+    } else if report.removed {
+        let bytes = format_size(bytes);
+        say!(Green: "Removed {} orphaned backups ({}).", report.orphans.len(), bytes);
+    } else {
+        say!(Yellow: "Removal cancelled.");
+    }
+    Ok(())
+}
+
+/// Remove the store's unreferenced content (see `BackupManager::gc`) and say how much.
+fn collect_garbage(mgr: &BackupManager) -> Result<(), BackupError> {
+    let report = mgr.gc()?;
+    let bytes: u64 = report.removed.iter().map(|b| b.size).sum();
+    if report.removed.is_empty() {
+        say!("Nothing to collect in {}.", report.store.display());
+    } else if mgr.config().dry_run {
+        for blob in &report.removed {
+            say!("[dry-run] would remove {} ({})", blob.path.display(), format_size(blob.size));
+        }
+    } else {
+        say!(
+            Green: "Removed {} unreferenced blobs ({}); {} backups still point into the store.",
+            report.removed.len(),
+            format_size(bytes),
+            report.references
+        );
+    }
+    Ok(())
+}
+
+/// Move the plain backups into the versioned layout and print what moved and what stayed.
+fn migrate_backups(mgr: &BackupManager) -> Result<(), BackupError> {
+    let report = mgr.migrate()?;
+    let dry_run = mgr.config().dry_run;
+    for (backup, version) in &report.migrated {
+        match dry_run {
+            true => say!("[dry-run] would move {} -> {}", backup.display(), version.display()),
+            false => say!("Moved {} -> {}", backup.display(), version.display()),
+        }
+    }
+    for (backup, reason) in &report.skipped {
+        let why = match *reason {
+            "directory" => "directory backups are not versioned",
+            "delta-base" => "incremental backups restore against it",
+            "snapshot" => "a snapshot labels it",
+            _ => "it does not match its checksum sidecar",
+        };
+        say!(Yellow: "Left {}: {}", backup.display(), why);
+    }
+    match (report.migrated.len(), dry_run) {
+        (0, _) => say!("Nothing to migrate."),
+        (n, true) => say!("[dry-run] would migrate {} backups.", n),
+        (n, false) => say!(Green: "Migrated {} backups.", n),
+    }
+    Ok(())
+}
+
+/// Print a file's backups, newest first; with `formats`, each with the format restore
+/// would read it as.
+fn list_backups(mgr: &BackupManager, filename: &str, formats: bool) -> Result<(), BackupError> {
+    let backups = mgr.list(filename)?;
+    if backups.is_empty() {
+        say!("No backups found for '{}'.", filename);
+        return Ok(());
+    }
+
+    let stale = mgr.is_stale(filename, &backups[0])?;
+    say!("Backups for '{}' (newest first):", filename);
+    for (i, b) in backups.iter().enumerate() {
+        let modified: chrono::DateTime<chrono::Local> = b.modified.into();
+        let format = match formats {
+            true => match mgr.backup_format(&b.path) {
+                Ok(format) => format!("  {}", format.name()),
+                Err(_) => "  unrecognized".to_string(),
+            },
+            false => String::new(),
+        };
+        let marker = if i == 0 && stale { "  [stale]" } else { "" };
+        say!(
+            "  {:>2}. {}  {}  {}{}{}",
+            i + 1,
+            b.path.display(),
+            format_size(b.size),
+            modified.format("%Y-%m-%d %H:%M:%S"),
+            format,
+            marker
+        );
+    }
+    if stale {
+        warning!("Backup is stale: '{}' has changed since its newest backup.", filename);
+    }
+    Ok(())
+}
+
+fn prune_backups(
+    mgr: &BackupManager,
+    filename: &str,
+    options: PruneOptions,
+) -> Result<(), BackupError> {
+    let report = mgr.prune(filename, options)?;
+    if report.removed.is_empty() {
+        say!("Nothing to prune: {} backups, keeping {}.", report.total, report.kept.len());
+        return Ok(());
+    }
+    if mgr.config().dry_run {
+        let now = std::time::SystemTime::now();
+        let describe = |b: &BackupEntry| {
+            let age = format_duration(now.duration_since(mgr.made_at(b)).unwrap_or_default());
+            format!("{} ({} old, {})", b.path.display(), age, format_size(b.size))
+        };
+        for old in &report.removed {
+            say!("[dry-run] would remove {}", describe(old));
+        }
+        for kept in &report.kept {
+            say!("[dry-run] would keep {}", describe(kept));
+        }
+        return Ok(());
+    }
+    for old in &report.removed {
+        say!("Removed old backup: {}", old.path.display());
+    }
+    say!(Green: "Pruned {} backups, kept {}.", report.removed.len(), report.kept.len());
+    Ok(())
+}
+
+/// Delete a file, or preview what deleting every match of a glob would remove. With
+/// `json`, the preview's summary is printed as JSON instead of a table.
+fn delete_file(
+    mgr: &BackupManager,
+    filename: &str,
+    options: DeleteOptions,
+    json: bool,
+) -> Result<(), BackupError> {
+    if is_glob(filename) {
+        return delete_matching(mgr, filename, options, json);
+    }
+    delete_one(mgr, filename, options).map(|_| ())
+}
+
+/// With --dry-run, list the path and size of each file matching `pattern` that delete
+/// would remove, checked exactly as a real delete checks it, then print a summary table.
+/// Nothing is asked or removed; without --dry-run, patterns are refused.
+fn delete_matching(
+    mgr: &BackupManager,
+    pattern: &str,
+    options: DeleteOptions,
+    json: bool,
+) -> Result<(), BackupError> {
+    if !mgr.config().dry_run {
+        return Err(BackupError::InvalidInput(format!(
+            "delete takes one file; '{}' can only be previewed with --dry-run",
+            pattern
+        )));
+    }
+    let matches = mgr.expand(pattern)?;
+    if matches.is_empty() {
+        return Err(BackupError::NotFound(format!("No files match '{}'", pattern)));
+    }
+    let options = DeleteOptions { force: true, ..options };
+    let rows: Vec<BatchRow> = matches
+        .iter()
+        .map(|file| {
+            let started = Instant::now();
+            let name = file.to_string_lossy().into_owned();
+            let (outcome, bytes) = match delete_one(mgr, &name, options) {
+                Ok(bytes) => (BatchOutcome::Succeeded, Some(bytes)),
+                Err(e) => {
+                    print_error(format!("Delete of '{}' would fail: {}", name, e));
+                    log_failure(mgr.config(), "delete", &name, &e);
+                    (BatchOutcome::Failed, None)
+                }
+            };
+            let elapsed = started.elapsed();
+            BatchRow { file: name, action: "delete".to_string(), outcome, bytes, elapsed }
+        })
+        .collect();
+    let headline = format!("{} files matching '{}' would be deleted", rows.len(), pattern);
+    finish_batch(&rows, json, &headline)
+}
+
+/// Move a file to the trash (or, with `purge`, delete it for good) after a yes/no
+/// confirmation, or after the user retypes its name with `confirm_name`. With `force`
+/// there is no prompt and stdin is never read, so it works without a terminal.
+/// Returns the file's size.
+fn delete_one(
+    mgr: &BackupManager,
+    filename: &str,
+    options: DeleteOptions,
+) -> Result<u64, BackupError> {
+    let DeleteOptions { purge, secure, .. } = options;
+    if secure.is_some() {
+        warning!(
+            "Warning: overwriting is best effort; SSDs and journaling or copy-on-write \
+             filesystems may keep copies of the old data."
+        );
+    }
+    let report = mgr.delete(filename, options, |path, level| {
+        if level == ConfirmLevel::TypedName {
+            return ask_file_name(path);
+        }
+        let question = if secure.is_some() {
+            format!("Are you sure you want to overwrite and delete '{}'? (yes/no): ", filename)
+        } else if purge {
+            format!("Are you sure you want to permanently delete '{}'? (yes/no): ", filename)
+        } else {
+            format!("Are you sure you want to delete '{}'? (yes/no): ", filename)
+        };
+        ask_yes_no(mgr.config(), &question).map(Confirmation::from)
+    })?;
+    let outcome = match (mgr.config().dry_run, report.deleted) {
+        (true, _) => "dry-run",
+        (false, true) => "success",
+        (false, false) => "cancelled",
+    };
+    note_result(outcome, None, Some(report.bytes));
+    match (&report.trashed, mgr.config().dry_run, report.deleted) {
+        (None, true, _) if report.system_trash => say!(
+            "[dry-run] would move {} ({}) to the system trash",
+            report.path.display(),
+            format_size(report.bytes)
+        ),
+        (Some(dest), true, _) => say!(
+            "[dry-run] would move {} ({}) to {}",
+            report.path.display(),
+            format_size(report.bytes),
+            dest.display()
+        ),
+        (None, true, _) => say!(
+            "[dry-run] would delete {} ({})",
+            report.path.display(),
+            format_size(report.bytes)
+        ),
+        (Some(dest), false, true) => {
+            say!(Green: "File moved to the trash: {}", dest.display());
+            say!("Use 'undelete {}' to bring it back.", filename);
+        }
+        (None, false, true) if report.system_trash => {
+            say!(Green: "File moved to the system trash.")
+        }
+        (None, false, true) if secure.is_some() => say!(Green: "File overwritten and deleted."),
+        (None, false, true) => say!(Green: "File deleted."),
+        (_, false, false) => say!(Yellow: "Deletion cancelled."),
+    }
+    Ok(report.bytes)
+}
+
+fn move_file(mgr: &BackupManager, from: &str, to: &str, force: bool) -> Result<(), BackupError> {
+    let report = mgr.move_file(from, to, MoveOptions { force })?;
+    if mgr.config().dry_run {
+        say!(
+            "[dry-run] would move {} -> {} ({})",
+            report.source.display(),
+            report.target.display(),
+            format_size(report.bytes)
+        );
+    } else {
+        say!(Green: "File moved: {} -> {}", report.source.display(), report.target.display());
+    }
+    Ok(())
+}
+
+fn copy_file(mgr: &BackupManager, from: &str, to: &str, force: bool) -> Result<(), BackupError> {
+    let report = mgr.copy_file(from, to, CopyOptions { force })?;
+    if mgr.config().dry_run {
+        say!(
+            "[dry-run] would copy {} -> {} ({})",
+            report.source.display(),
+            report.target.display(),
+            format_size(report.bytes)
+        );
+    } else {
+        say!(Green: "File copied: {} -> {}", report.source.display(), report.target.display());
+    }
+    Ok(())
+}
+
+fn undelete_file(mgr: &BackupManager, filename: &str) -> Result<(), BackupError> {
+    let report = mgr.undelete(filename)?;
+    if mgr.config().dry_run {
+        say!(
+            "[dry-run] would move {} -> {}",
+            report.trashed.display(),
+            report.target.display()
+        );
+    } else {
+        say!(Green: "File restored from the trash: {}", report.target.display());
+    }
+    Ok(())
+}
+
+/// Reverse the last delete or restore and say what was put back.
+fn undo_last(mgr: &BackupManager) -> Result<(), BackupError> {
+    let report = mgr.undo()?;
+    let dry_run = if mgr.config().dry_run { "[dry-run] would undo" } else { "Undid" };
+    match (&report.from, &report.trashed) {
+        (Some(from), _) => say!(
+            Green: "{} the {} of {}: brought back from {}",
+            dry_run,
+            report.action,
+            report.file.display(),
+            from.display()
+        ),
+        (None, Some(trashed)) => say!(
+            Green: "{} the {} of {}: it did not exist before, moved to the trash: {}",
+            dry_run,
+            report.action,
+            report.file.display(),
+            trashed.display()
+        ),
+        (None, None) => say!(
+            Green: "{} the {} of {}: nothing left to put back",
+            dry_run,
+            report.action,
+            report.file.display()
+        ),
+    }
+    Ok(())
+}
+
+/// Read for the passphrase of encrypted backups before prompting, so scripts can run
+/// without a terminal.
+const PASSPHRASE_ENV: &str = "SAFE_BACKUP_PASSPHRASE";
+
+/// Passphrase prompt for `BackupManager::set_passphrase_prompt`: $SAFE_BACKUP_PASSPHRASE if
+/// set, else the content of `file` without its trailing newline, otherwise asked on the
+/// terminal without echo (twice when encrypting).
+fn passphrase_prompt(backup: &Path, new: bool, file: Option<&Path>) -> io::Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        if VERBOSE.load(Ordering::Relaxed) {
+            WARNED.call_once(|| {
+                warning!("A passphrase in {} may be visible in the process list", PASSPHRASE_ENV);
+            });
+        }
+        return Ok(passphrase);
+    }
+    if let Some(file) = file {
+        let mut passphrase = Zeroizing::new(std::fs::read_to_string(file).map_err(|e| {
+            let msg = format!("Cannot read the passphrase file {}: {}", file.display(), e);
+            io::Error::new(e.kind(), msg)
+        })?);
+        if passphrase.ends_with('\n') {
+            passphrase.pop();
+            if passphrase.ends_with('\r') {
+                passphrase.pop();
+            }
+        }
+        return Ok(std::mem::take(&mut *passphrase));
+    }
+    let prompt = format!("Passphrase for {}: ", backup.display());
+    let no_terminal = |e: io::Error| {
+        let msg = format!("Cannot ask for a passphrase ({}); set {}", e, PASSPHRASE_ENV);
+        io::Error::new(e.kind(), msg)
+    };
+    let mut passphrase = Zeroizing::new(rpassword::prompt_password(prompt).map_err(no_terminal)?);
+    if new {
+        let repeated = Zeroizing::new(rpassword::prompt_password("Repeat the passphrase: ")?);
+        if repeated != passphrase {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Passphrases do not match"));
+        }
     }
+    Ok(std::mem::take(&mut *passphrase))
+}
 
-    let p = Path::new(trimmed);
+/// Read for the password of SFTP servers before prompting, like `PASSPHRASE_ENV`.
+const SFTP_PASSWORD_ENV: &str = "SAFE_BACKUP_SFTP_PASSWORD";
 
-    // 1) No absolute paths (prevents /etc/passwd or C:\Windows\... etc.)
-    if p.is_absolute() {
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Absolute paths are not allowed"));
+/// Password prompt for `BackupManager::set_sftp_password_prompt`: $SAFE_BACKUP_SFTP_PASSWORD
+/// if set, otherwise asked on the terminal without echo.
+fn sftp_password_prompt(login: &str) -> io::Result<String> {
+    if let Ok(password) = std::env::var(SFTP_PASSWORD_ENV) {
+        return Ok(password);
     }
+    let no_terminal = |e: io::Error| {
+        let msg = format!("Cannot ask for the password ({}); set {}", e, SFTP_PASSWORD_ENV);
+        io::Error::new(e.kind(), msg)
+    };
+    rpassword::prompt_password(format!("Password for {}: ", login)).map_err(no_terminal)
+}
+
+/// Files smaller than this are copied without a progress bar, to avoid flicker.
+const PROGRESS_MIN_BYTES: u64 = 8 * 1024 * 1024;
 
-    // 2) No traversal components anywhere (prevents escaping the working directory)
-    for comp in p.components() {
-        if matches!(comp, Component::ParentDir) {
-            return Err(io::Error::new(
-                io::ErrorKind::PermissionDenied,
-                "Parent directory traversal is not allowed",
-            ));
+/// Progress callback for `BackupManager::set_progress`: draws a bar with percentage
+/// and throughput on stderr for each large file, and clears it once the copy is done.
+fn progress_bar() -> impl Fn(u64, u64) + Send + Sync + 'static {
+    let current: std::sync::Mutex<Option<indicatif::ProgressBar>> = Default::default();
+    move |done, total| {
+        if total < PROGRESS_MIN_BYTES {
+            return;
+        }
+        let mut current = current.lock().unwrap_or_else(|e| e.into_inner());
+        let bar = current.get_or_insert_with(|| {
+            let bar = indicatif::ProgressBar::new(total);
+            let style = indicatif::ProgressStyle::with_template(
+                "{bar:40} {percent:>3}% {bytes}/{total_bytes} {binary_bytes_per_sec} eta {eta}",
+            )
+            .expect("valid progress template");
+            bar.set_style(style);
+            bar
+        });
+        bar.set_length(total);
+        bar.set_position(done);
+        if done >= total {
+            bar.finish_and_clear();
+            *current = None;
         }
     }
+}
 
-    // 3) Join syntactically under the current working directory
-    let cwd = std::env::current_dir()?;
-    Ok(cwd.join(p))
+/// Progress callback for `BackupManager::set_dir_progress`: one bar for a whole directory
+/// backup, with the files done, the bytes copied, throughput and ETA, cleared at the end.
+fn dir_progress_bar() -> impl Fn(DirProgress) + Send + Sync + 'static {
+    let current: std::sync::Mutex<Option<indicatif::ProgressBar>> = Default::default();
+    move |progress| {
+        if progress.bytes_total < PROGRESS_MIN_BYTES {
+            return;
+        }
+        let mut current = current.lock().unwrap_or_else(|e| e.into_inner());
+        let bar = current.get_or_insert_with(|| {
+            let bar = indicatif::ProgressBar::new(progress.bytes_total);
+            let style = indicatif::ProgressStyle::with_template(
+                "{msg} files {bar:40} {percent:>3}% {bytes}/{total_bytes} \
+                 {binary_bytes_per_sec} eta {eta}",
+            )
+            .expect("valid progress template");
+            bar.set_style(style);
+            bar
+        });
+        bar.set_message(format!("{}/{}", progress.files_done, progress.files_total));
+        bar.set_position(progress.bytes_done);
+        if progress.files_done >= progress.files_total {
+            bar.finish_and_clear();
+            *current = None;
+        }
+    }
 }
 
-/// Create the backup path: "file.ext" -> "file.ext.bak", "file" -> "file.bak"
-fn backup_path_for(file: &Path) -> PathBuf {
-    let mut pb = PathBuf::from(file);
-    let new_ext = match file.extension() {
-        Some(ext) => {
-            let mut s = ext.to_string_lossy().to_string();
-            s.push_str(".bak");
-            s
+/// Print `question` and read the answer from stdin; only "y", "yes" and the config's
+/// `confirm_words` count as yes, ignoring case. End of input (Ctrl-D, or the end of a
+/// piped file) counts as "no".
+fn ask_yes_no(cfg: &Config, question: &str) -> io::Result<bool> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    match read_input_line()? {
+        Some(answer) => {
+            let answer = answer.trim();
+            let extra = cfg.confirm_words.iter().map(|w| w.trim());
+            Ok(["y", "yes"].into_iter().chain(extra).any(|w| answer.eq_ignore_ascii_case(w)))
         }
-        None => "bak".to_string(),
-    };
-    pb.set_extension(new_ext);
-    pb
+        None => {
+            println!();
+            Ok(false)
+        }
+    }
 }
 
-/// ---------- Operations (backup/restore/delete) ----------
+/// Ask the user to retype `path`'s file name; anything but the exact name is a mismatch.
+fn ask_file_name(path: &Path) -> io::Result<Confirmation> {
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    print!("Type the file name '{}' to confirm deletion: ", name);
+    io::stdout().flush()?;
+    let answer = read_input_line()?.unwrap_or_default();
+    if answer.trim_end_matches(['\r', '\n']) == name {
+        Ok(Confirmation::Yes)
+    } else {
+        warning!("'{}' does not match '{}'.", answer.trim_end(), name);
+        Ok(Confirmation::Mismatch)
+    }
+}
 
-fn backup_file(filename: &str) -> io::Result<()> {
-    let path = resolve_safe_path(filename)?;
-    if !path.exists() {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "Source file does not exist"));
+/// One line from stdin, or `None` at end of input.
+fn read_input_line() -> io::Result<Option<String>> {
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line)? {
+        0 => Ok(None),
+        _ => Ok(Some(line)),
     }
-    if !path.is_file() {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "Source path is not a regular file",
-        ));
+}
+
+// ---------- Batch summary ----------
+
+/// How one file of a glob backup, or one line of a script, went.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BatchOutcome {
+    Succeeded,
+    Failed,
+    Skipped,
+    /// A script stopped at an earlier failure before reaching it.
+    NotRun,
+}
+
+impl BatchOutcome {
+    fn name(self) -> &'static str {
+        match self {
+            BatchOutcome::Succeeded => "succeeded",
+            BatchOutcome::Failed => "failed",
+            BatchOutcome::Skipped => "skipped",
+            BatchOutcome::NotRun => "not run",
+        }
     }
+}
 
-    let backup = backup_path_for(&path);
-    fs::copy(&path, &backup)?;
-    println!("Your backup created: {}", backup.display());
-    log_action(&format!("backup | {} | success", filename))?;
-    Ok(())
+/// One row of the summary printed after a batch.
+struct BatchRow {
+    file: String,
+    action: String,
+    outcome: BatchOutcome,
+    /// Bytes backed up, where the action reports them.
+    bytes: Option<u64>,
+    elapsed: Duration,
 }
 
-fn restore_file(filename: &str) -> io::Result<()> {
-    let path = resolve_safe_path(filename)?;
-    let backup = backup_path_for(&path);
-    if !backup.exists() {
-        println!("Backup file not found.");
-        log_action(&format!("restore | {} | failure | no backup", filename))?;
-        return Ok(());
+fn count_outcome(rows: &[BatchRow], outcome: BatchOutcome) -> usize {
+    rows.iter().filter(|row| row.outcome == outcome).count()
+}
+
+/// "12 succeeded, 2 failed, 1 skipped", plus ", 3 not run" if a script stopped early.
+fn batch_totals(rows: &[BatchRow]) -> String {
+    let mut totals = format!(
+        "{} succeeded, {} failed, {} skipped",
+        count_outcome(rows, BatchOutcome::Succeeded),
+        count_outcome(rows, BatchOutcome::Failed),
+        count_outcome(rows, BatchOutcome::Skipped)
+    );
+    match count_outcome(rows, BatchOutcome::NotRun) {
+        0 => {}
+        n => totals.push_str(&format!(", {} not run", n)),
     }
-    fs::copy(&backup, &path)?;
-    println!("File restored from: {}", backup.display());
-    log_action(&format!("restore | {} | success", filename))?;
-    Ok(())
+    totals
 }
 
-fn delete_file(filename: &str) -> io::Result<()> {
-    let path = resolve_safe_path(filename)?;
-    if !path.exists() {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "File does not exist"));
+/// Print a table of `rows` (file, action, outcome, bytes, duration), or with `json` one
+/// JSON object holding the rows and the totals.
+fn print_batch_summary(rows: &[BatchRow], json: bool) {
+    if json {
+        let entries: Vec<_> = rows
+            .iter()
+            .map(|row| {
+                serde_json::json!({
+                    "file": row.file,
+                    "action": row.action,
+                    "outcome": row.outcome.name(),
+                    "bytes": row.bytes,
+                    "duration_ms": row.elapsed.as_millis() as u64,
+                })
+            })
+            .collect();
+        let summary = serde_json::json!({
+            "results": entries,
+            "succeeded": count_outcome(rows, BatchOutcome::Succeeded),
+            "failed": count_outcome(rows, BatchOutcome::Failed),
+            "skipped": count_outcome(rows, BatchOutcome::Skipped),
+            "not_run": count_outcome(rows, BatchOutcome::NotRun),
+        });
+        print_json(summary);
+        return;
     }
-    if !path.is_file() {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "Target is not a regular file",
-        ));
+
+    let cells: Vec<[String; 5]> = rows
+        .iter()
+        .map(|row| {
+            [
+                row.file.clone(),
+                row.action.clone(),
+                row.outcome.name().to_string(),
+                row.bytes.map(format_size).unwrap_or_else(|| "-".to_string()),
+                format_duration(row.elapsed),
+            ]
+        })
+        .collect();
+    say!();
+    print_table(["FILE", "ACTION", "OUTCOME", "BYTES", "DURATION"], &cells);
+}
+
+/// Print `header` and `rows` as left-aligned columns two spaces apart.
+fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    let header = header.map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows) {
+        let line: Vec<_> =
+            row.iter().zip(widths).map(|(cell, width)| format!("{:<width$}", cell)).collect();
+        say!("{}", line.join("  ").trim_end());
     }
+}
 
-    print!("Are you sure you want to delete '{}'? (yes/no): ", filename);
-    io::stdout().flush()?;
-    let mut confirm = String::new();
-    io::stdin().read_line(&mut confirm)?;
-    if confirm.trim().eq_ignore_ascii_case("yes") {
-        fs::remove_file(&path)?;
-        println!("File deleted.");
-        log_action(&format!("delete | {} | success", filename))?;
+/// Print the summary of a finished batch: the table (or JSON), then `headline` with the
+/// totals. Fails with `BatchFailed` if any row failed.
+fn finish_batch(rows: &[BatchRow], json: bool, headline: &str) -> Result<(), BackupError> {
+    print_batch_summary(rows, json);
+    let failed = count_outcome(rows, BatchOutcome::Failed);
+    if !json {
+        let summary = format!("{}: {}.", headline, batch_totals(rows));
+        if failed == 0 {
+            say!(Green: "{}", summary);
+        } else {
+            say!(Red: "{}", summary);
+        }
+    }
+    if failed == 0 {
+        Ok(())
     } else {
-        println!("Deletion cancelled.");
-        log_action(&format!("delete | {} | cancelled", filename))?;
+        Err(BackupError::BatchFailed { failed, total: rows.len() })
     }
-    Ok(())
 }
 
-/// ---------- Logging ----------
+// ---------- CLI ----------
 
-fn log_action(line: &str) -> io::Result<()> {
-    use std::fs::OpenOptions;
-    use std::io::Write;
+#[derive(clap::Parser)]
+#[command(
+    name = "safe_backup",
+    version,
+    about = "Back up, restore and delete files safely. Runs interactively when no command is given."
+)]
+struct Cli {
+    /// Report what would happen without changing any files
+    #[arg(long, global = true)]
+    dry_run: bool,
 
-    let mut f = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("logfile.txt")?;
-    writeln!(f, "{}", line)?;
-    Ok(())
+    /// Print only errors; the exit status tells how it went
+    #[arg(long, short, global = true)]
+    quiet: bool,
+
+    /// Print one JSON object per command (op, file, outcome, backup_path, bytes, error)
+    /// on stdout instead of the usual messages
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Print a failure on stderr as a message (human) or as one JSON line with its kind
+    /// (json)
+    #[arg(long, global = true, value_name = "FORMAT", value_enum, default_value_t)]
+    error_format: ErrorFormat,
+
+    /// Fail a command that gave warnings (skipped files, stale backups, a log that could
+    /// not be written, ...), naming them, instead of succeeding
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// If another safe_backup is changing files in this directory, wait for it to finish
+    /// instead of failing; the prompt, the menu and `run` keep others out until they end
+    #[arg(long, global = true)]
+    wait: bool,
+
+    /// Read the passphrase of encrypted backups from this file (one trailing newline is
+    /// dropped) instead of asking; $SAFE_BACKUP_PASSPHRASE still comes first
+    #[arg(long, global = true, value_name = "PATH")]
+    passphrase_file: Option<PathBuf>,
+
+    /// Trace each step (resolved path, backup target, bytes copied, ...) on stderr;
+    /// RUST_LOG=debug does the same
+    #[arg(long, short, global = true)]
+    verbose: bool,
+
+    /// Back up this many files of a glob at once (overrides jobs from the config)
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    jobs: Option<u16>,
+
+    /// Refuse to back up files larger than this, e.g. 500M or 1G (overrides max_size from
+    /// the config)
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Act on the target of a symlinked file instead of refusing it (overrides
+    /// follow_symlinks from the config)
+    #[arg(long, global = true)]
+    follow_symlinks: bool,
+
+    /// Accept an absolute path (e.g. /etc/hosts) for backup, restore, verify, diff, status,
+    /// list and prune; ".." is still refused and every use is logged
+    #[arg(long, global = true)]
+    allow_absolute: bool,
+
+    /// When a file's backup already exists: overwrite, skip, error or version (overrides
+    /// existing_backup from the config)
+    #[arg(long, global = true, value_name = "POLICY")]
+    if_exists: Option<ExistingBackup>,
+
+    /// Checksum new backups with this algorithm (overrides hash from the config)
+    #[arg(long, global = true, value_name = "ALGORITHM")]
+    hash: Option<HashAlgorithm>,
+
+    /// Gzip level for --gzip backups and archives, 0 (fastest) to 9 (smallest)
+    /// (overrides compress_level from the config)
+    #[arg(
+        long,
+        global = true,
+        value_name = "0-9",
+        value_parser = clap::value_parser!(u32).range(0..=9)
+    )]
+    compress_level: Option<u32>,
+
+    /// Sync backups and log entries to disk before reporting success; --durable=false is
+    /// faster but may lose them in a crash (overrides durable from the config)
+    #[arg(
+        long,
+        global = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    durable: Option<bool>,
+
+    /// Copy this many bytes at a time, e.g. 1M (overrides copy_buffer_size from the config)
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size)]
+    buffer_size: Option<u64>,
+
+    /// Name backups with this suffix, e.g. .backup or ~ (overrides backup_suffix from the
+    /// config); backups made with another suffix are only found with that one
+    #[arg(long, global = true, value_name = "SUFFIX")]
+    suffix: Option<String>,
+
+    /// Name versioned backups by this template of {name}, {ext}, {timestamp} and {counter},
+    /// e.g. "{timestamp}_{name}{ext}" (overrides name_template from the config); versions
+    /// named otherwise are only found with that template
+    #[arg(long, global = true, value_name = "TEMPLATE")]
+    name_template: Option<String>,
+
+    /// Keep backups in this directory (overrides $SAFE_BACKUP_DIR and backup_dir from the
+    /// config)
+    #[arg(long, global = true, value_name = "PATH")]
+    backup_dir: Option<PathBuf>,
+
+    /// In the backup directory, mirror each file's folders instead of flattening them into
+    /// the backup's name: src/main.rs -> <backup-dir>/src/main.rs.bak
+    #[arg(long, global = true)]
+    preserve_structure: bool,
+
+    /// Create missing folders in the backup directory for a new backup (overrides
+    /// create_parents from the config)
+    #[arg(long, global = true)]
+    parents: bool,
+
+    /// Hash every file again instead of reusing the digests cached for unchanged files
+    #[arg(long, global = true)]
+    rehash: bool,
+
+    /// Run no pre_hook or post_hook from the config around backups, restores and deletes
+    #[arg(long, global = true)]
+    no_hooks: bool,
+
+    /// Resolve file names (and a relative log_path) under this directory instead of the
+    /// current one; ".." still cannot leave it
+    #[arg(long, global = true, value_name = "PATH")]
+    base_dir: Option<PathBuf>,
+
+    /// Without a command, show a numbered menu instead of the free-text prompt
+    #[arg(long)]
+    menu: bool,
+
+    /// TOML config file (default: ./safe_backup.toml if it exists)
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Log entry format (overrides log_format from the config)
+    #[arg(long, global = true, value_enum)]
+    log_format: Option<LogFormat>,
+
+    /// Which entries to log: all, results, failures or off (overrides log_level from the
+    /// config)
+    #[arg(long, global = true, value_enum)]
+    log_level: Option<LogLevel>,
+
+    /// Write no log at all, e.g. where the directory is read-only; same as --log-level off
+    #[arg(long, global = true, conflicts_with = "log_level")]
+    no_log: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Back up a file, a whole directory, or every match of a glob such as '*.log'
+    Backup {
+        #[arg(required_unless_present = "stdin")]
+        file: Option<String>,
+        /// Keep a new timestamped version instead of overwriting the .bak
+        #[arg(long)]
+        versioned: bool,
+        /// Gzip-compress the backup
+        #[arg(long, conflicts_with = "encrypt")]
+        gzip: bool,
+        /// Encrypt the backup with a passphrase
+        #[arg(long)]
+        encrypt: bool,
+        /// Store only the blocks changed since the plain .bak (made in full if missing); for
+        /// a directory, copy only the files modified since its last backup
+        #[arg(long, conflicts_with_all = ["versioned", "gzip", "encrypt"])]
+        incremental: bool,
+        /// Store the content once in .store/ and write a small .ref to it, so identical
+        /// files share their backup
+        #[arg(long, conflicts_with_all = ["gzip", "encrypt", "incremental"])]
+        dedup: bool,
+        /// Make a new version of an unchanged file a hardlink to the latest backup (copied
+        /// where hardlinks are not supported) instead of skipping it
+        #[arg(long, requires = "versioned", conflicts_with_all = ["gzip", "encrypt", "dedup"])]
+        hardlink_unchanged: bool,
+        /// Give the backup the source's modification time instead of the time it was made
+        #[arg(long)]
+        preserve_mtime: bool,
+        /// Leave the file's holes and blocks of zeros unwritten, for sparse files such as
+        /// disk images
+        #[arg(long, conflicts_with_all = ["gzip", "encrypt", "dedup", "incremental"])]
+        sparse: bool,
+        /// On Windows, if another process has the file open, try reading it anyway with
+        /// every sharing mode and back up that copy (works where the owner allows reading)
+        #[arg(long)]
+        shared_read: bool,
+        /// Back up the file even if it is itself a backup or the log
+        #[arg(long)]
+        allow_backup: bool,
+        /// Copy even if the latest backup is already current
+        #[arg(long)]
+        force: bool,
+        /// Leave entries matching this glob out of a directory backup (repeatable); wins
+        /// over the directory's .backupignore files
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+        /// Leave dot-prefixed entries out of a directory backup
+        #[arg(long)]
+        no_hidden: bool,
+        /// Back up each file named on stdin, one per line, e.g. from `find`
+        #[arg(long, conflicts_with = "file")]
+        stdin: bool,
+        /// With a directory, copy only the files modified since this date or time and carry
+        /// the others over from its last backup
+        #[arg(
+            long,
+            value_name = "WHEN",
+            value_parser = parse_since,
+            conflicts_with_all = ["versioned", "gzip", "encrypt", "dedup"]
+        )]
+        since: Option<NaiveDateTime>,
+        /// Also upload the backup and its sidecars to sftp://[user@]host[:port]/dir, logging in
+        /// with a key (sftp_identity, the ssh agent or ~/.ssh) or else a password
+        #[arg(long, value_name = "URL", conflicts_with_all = ["incremental", "dedup"])]
+        remote: Option<String>,
+        /// Label the new versions with this snapshot name, for `rollback`; implies
+        /// --versioned
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["incremental", "since"])]
+        snapshot: Option<String>,
+        /// After each new version, delete the oldest backups beyond this many (overrides
+        /// keep_versions from the config)
+        #[arg(long, value_name = "N")]
+        keep: Option<usize>,
+    },
+    /// Restore a file from its backup
+    Restore {
+        /// File or directory to restore, or a glob such as "*.conf" to restore every match
+        file: String,
+        /// Backup to restore: index from `list` or a timestamp prefix
+        #[arg(long)]
+        version: Option<String>,
+        /// Restore into this file instead of the original
+        #[arg(long, value_name = "PATH")]
+        to: Option<String>,
+        /// Take the backup from this directory (a copied or moved backup set), or from an
+        /// sftp:// location backups were uploaded to, instead of where backups are kept
+        #[arg(long, value_name = "DIR")]
+        from: Option<String>,
+        /// Overwrite newer changes in the live file (or an existing --to file) without asking
+        #[arg(long)]
+        force: bool,
+        /// What to do with a file that already exists: leave it alone (skip), replace it
+        /// (overwrite), or keep it in the trash first and then replace it (backup)
+        #[arg(long, value_name = "POLICY", conflicts_with = "no_snapshot")]
+        overwrite_policy: Option<OverwritePolicy>,
+        /// Do not keep the overwritten content in the trash first (no undo); the same as
+        /// --overwrite-policy overwrite
+        #[arg(long)]
+        no_snapshot: bool,
+        /// Match a glob against the files that exist now rather than the files that have
+        /// backups (which also finds deleted files)
+        #[arg(long)]
+        match_sources: bool,
+    },
+    /// Restore every file labelled with a snapshot (backup --snapshot) to its version
+    Rollback {
+        /// Name given to `backup --snapshot`
+        #[arg(long, value_name = "NAME")]
+        snapshot: String,
+        /// Overwrite newer changes in the live files without asking
+        #[arg(long)]
+        force: bool,
+        /// What to do with a file that already exists (see `restore`)
+        #[arg(long, value_name = "POLICY")]
+        overwrite_policy: Option<OverwritePolicy>,
+    },
+    /// Check a backup's integrity
+    Verify {
+        #[arg(required_unless_present = "stdin")]
+        file: Option<String>,
+        /// Backup to verify: index from `list` or a timestamp prefix
+        #[arg(long)]
+        version: Option<String>,
+        /// Verify the backups of each file named on stdin, one per line
+        #[arg(long, conflicts_with = "file")]
+        stdin: bool,
+    },
+    /// Restore a backup into a temporary file and check it, without touching the file
+    TestRestore {
+        file: String,
+        /// Backup to test: index from `list` or a timestamp prefix
+        #[arg(long)]
+        version: Option<String>,
+    },
+    /// Show how a file differs from its backup
+    Diff {
+        file: String,
+        /// Backup to compare with: index from `list` or a timestamp prefix
+        #[arg(long)]
+        version: Option<String>,
+    },
+    /// Write a backup's content to stdout, decompressed or decrypted, e.g. to pipe it into
+    /// less; no file is restored
+    Cat {
+        file: String,
+        /// Backup to write: index from `list` or a timestamp prefix
+        #[arg(long)]
+        version: Option<String>,
+    },
+    /// Compare two backups of a file: checksums, and a unified diff for text
+    Compare {
+        file: String,
+        /// The old side: index from `list`, a timestamp prefix, or the backup's path
+        first: String,
+        /// The new side, picked the same way
+        second: String,
+    },
+    /// Summarize a file's backup state in one line
+    Status { file: String },
+    /// For monitoring: exit 0 if a file's newest backup is current, 1 if it is stale,
+    /// corrupt or too old, 2 if there is none; prints nothing without --verbose or --json
+    Check {
+        file: String,
+        /// Also fail if the newest backup is older than this, e.g. 30m, 24h or 7d
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        max_age: Option<Duration>,
+    },
+    /// Keep a versioned backup of every change to a file or directory, until Ctrl-C
+    Watch {
+        file: String,
+        /// Wait until the file has been quiet this long before backing it up
+        #[arg(long, value_name = "MS", default_value_t = 1000)]
+        debounce_ms: u64,
+    },
+    /// Bundle a file or directory into a .tar archive
+    Archive {
+        file: String,
+        /// Write a .tar.gz
+        #[arg(long)]
+        gzip: bool,
+        /// Add only new or changed files to the existing .tar, after the copies it holds
+        #[arg(long, conflicts_with = "gzip")]
+        append: bool,
+    },
+    /// Unpack an archive made by `archive` back into place
+    Extract {
+        archive: String,
+        /// Replace files that already exist
+        #[arg(long)]
+        overwrite: bool,
+        /// Extract only this file, named as --list shows it
+        #[arg(long, value_name = "NAME")]
+        member: Option<String>,
+        /// List the files in the archive instead of extracting them
+        #[arg(long, conflicts_with_all = ["overwrite", "member"])]
+        list: bool,
+    },
+    /// Check that no log entry was altered or removed since it was written (and, with
+    /// log_sequence, that the numbering has no gaps)
+    VerifyLog,
+    /// Summarize the log: entries per action, success rates, most backed-up files
+    Report {
+        /// How many of the most backed-up files to list
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        top: usize,
+    },
+    /// Show the log entries that match every filter given
+    Log {
+        /// Only entries of this action, e.g. delete
+        #[arg(long)]
+        action: Option<String>,
+        /// Only entries whose filename contains this
+        #[arg(long, value_name = "TEXT")]
+        file: Option<String>,
+        /// Only entries with this outcome, e.g. success or failure
+        #[arg(long)]
+        outcome: Option<String>,
+        /// Only entries from this date or time on (2024-06-01 or 2024-06-01T14:30:00)
+        #[arg(long, value_name = "WHEN", value_parser = parse_since)]
+        since: Option<NaiveDateTime>,
+        /// Only entries up to this date (inclusive) or before this time
+        #[arg(long, value_name = "WHEN", value_parser = parse_until)]
+        until: Option<NaiveDateTime>,
+        /// Only the last N matching entries
+        #[arg(long, value_name = "N")]
+        tail: Option<usize>,
+        /// Keep printing matching entries as they are written, until Ctrl-C; starts with
+        /// the last 10 unless --tail says otherwise
+        #[arg(long)]
+        follow: bool,
+    },
+    /// Print the settings in effect and where each comes from: the default, the config
+    /// file, the environment or a flag
+    Info,
+    /// Check every backup against its recorded checksum (or its source)
+    VerifyAll,
+    /// Pair every backup with its live file: in sync, stale, missing its source or corrupt
+    Audit,
+    /// Back up, verify, restore and delete a scratch file in a temporary directory, to
+    /// check that the tool works on this machine
+    #[command(name = "selftest")]
+    SelfTest,
+    /// Time fs::copy and the buffered copy at several buffer sizes on a generated file, to
+    /// pick --buffer-size for this disk; only temporary files are written
+    Bench {
+        /// Size of the generated file, e.g. 256M
+        #[arg(long, value_name = "SIZE", default_value = "64M", value_parser = parse_size)]
+        size: u64,
+        /// A buffer size to try, e.g. 128K; repeat for several (default: 4K to 8M)
+        #[arg(long = "buffer", value_name = "SIZE", value_parser = parse_size)]
+        buffers: Vec<u64>,
+    },
+    /// List backups whose source file no longer exists
+    Orphans {
+        /// Delete them (asks for confirmation)
+        #[arg(long)]
+        remove: bool,
+        /// Delete without asking
+        #[arg(long, requires = "remove")]
+        force: bool,
+    },
+    /// Remove content from .store/ that no deduplicated (.ref) backup points to any more
+    Gc,
+    /// Rebuild the index of each file's newest backup that status and check read
+    Reindex,
+    /// Turn every plain .bak into a version stamped with its modification time, in the
+    /// versioned layout in effect (versions_dir when set); restore them with --version
+    Migrate,
+    /// List the backups of a file, newest first
+    List {
+        file: String,
+        /// Also show how each backup holds its content (plain, gzip, encrypted, incremental
+        /// or deduplicated), as restore detects it
+        #[arg(long)]
+        formats: bool,
+    },
+    /// Delete old backups, keeping the most recent ones
+    Prune {
+        file: String,
+        /// How many backups to keep (default: keep_versions from the config); with
+        /// --older-than, how many recent ones to keep whatever their age (default:
+        /// keep_versions, or 1)
+        #[arg(long)]
+        keep: Option<usize>,
+        /// Remove the backups made longer ago than this, e.g. 90d, 12h or 30m
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        older_than: Option<Duration>,
+    },
+    /// Move a file to the trash (asks for confirmation)
+    Delete {
+        file: String,
+        /// Delete without asking
+        #[arg(long, conflicts_with = "confirm_name")]
+        force: bool,
+        /// Confirm by retyping the file name instead of answering yes/no, as files of at
+        /// least confirm_name_size from the config always are
+        #[arg(long)]
+        confirm_name: bool,
+        /// Delete permanently instead of moving to the trash
+        #[arg(long)]
+        purge: bool,
+        /// Move to the desktop's trash instead of .trash/ (which is used where there is none)
+        #[arg(long = "trash", conflicts_with_all = ["purge", "secure"])]
+        system_trash: bool,
+        /// Overwrite the content (with zeros unless --secure=random) before deleting
+        #[arg(
+            long,
+            value_name = "PATTERN",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "zeros"
+        )]
+        secure: Option<WipePattern>,
+    },
+    /// Rename or move a file
+    #[command(alias = "rename")]
+    Move {
+        from: String,
+        to: String,
+        /// Overwrite an existing target
+        #[arg(long)]
+        force: bool,
+    },
+    /// Copy a file to another name (no backup suffix)
+    Copy {
+        from: String,
+        to: String,
+        /// Overwrite an existing target
+        #[arg(long)]
+        force: bool,
+    },
+    /// Run "command filename [arg]" lines from a script, like the interactive prompt
+    Run {
+        script: String,
+        /// Carry on after a failed line instead of stopping
+        #[arg(long)]
+        keep_going: bool,
+    },
+    /// Bring back the most recently deleted copy of a file from the trash
+    #[command(alias = "restore-trash")]
+    Undelete { file: String },
+    /// Reverse the last delete or restore
+    Undo,
+}
+
+impl Command {
+    /// Name and target used when logging a failure.
+    fn describe(&self) -> (&'static str, &str) {
+        match self {
+            Command::Backup { file, .. } => ("backup", file.as_deref().unwrap_or("-")),
+            Command::Restore { file, .. } => ("restore", file),
+            Command::Rollback { snapshot, .. } => ("rollback", snapshot),
+            Command::Verify { file, .. } => ("verify", file.as_deref().unwrap_or("-")),
+            Command::TestRestore { file, .. } => ("test-restore", file),
+            Command::Diff { file, .. } => ("diff", file),
+            Command::Cat { file, .. } => ("cat", file),
+            Command::Compare { file, .. } => ("compare", file),
+            Command::Status { file, .. } => ("status", file),
+            Command::Check { file, .. } => ("check", file),
+            Command::Watch { file, .. } => ("watch", file),
+            Command::Archive { file, .. } => ("archive", file),
+            Command::Extract { archive, .. } => ("extract", archive),
+            Command::VerifyLog => ("verify-log", "log"),
+            Command::Log { .. } => ("log-search", "log"),
+            Command::Report { .. } => ("log-report", "log"),
+            Command::Info => ("info", "."),
+            Command::VerifyAll => ("verify-all", "."),
+            Command::Audit => ("audit", "."),
+            Command::SelfTest => ("selftest", "."),
+            Command::Bench { .. } => ("bench", "."),
+            Command::Orphans { .. } => ("orphans", "."),
+            Command::Gc => ("gc", "."),
+            Command::Reindex => ("reindex", "."),
+            Command::Migrate => ("migrate", "."),
+            Command::List { file, .. } => ("list", file),
+            Command::Prune { file, .. } => ("prune", file),
+            Command::Delete { file, .. } => ("delete", file),
+            Command::Move { from, .. } => ("move", from),
+            Command::Copy { from, .. } => ("copy", from),
+            Command::Run { script, .. } => ("run", script),
+            Command::Undelete { file } => ("undelete", file),
+            Command::Undo => ("undo", "."),
+        }
+    }
+}
+
+/// Commands run without the base directory's lock: those that only read files and the
+/// log. The prompt, the menu and `run` take it once for the whole session, so that no
+/// other process changes the files between one of their commands and the next.
+const UNLOCKED_COMMANDS: &[&str] = &[
+    "verify",
+    "verify-all",
+    "test-restore",
+    "audit",
+    "diff",
+    "cat",
+    "compare",
+    "status",
+    "check",
+    "list",
+    "log-search",
+    "log-report",
+    "verify-log",
+    "selftest",
+    "bench",
+    "info",
+];
+
+/// The base directory's lock for running `command` (as named in the log), or `None` when
+/// it needs none or in a dry run.
+fn lock_for(mgr: &BackupManager, command: &str) -> Result<Option<BaseLock>, BackupError> {
+    if mgr.config().dry_run || UNLOCKED_COMMANDS.contains(&command) {
+        return Ok(None);
+    }
+    let waiting = |pid: Option<u32>| {
+        let holder = pid.map(|pid| format!(" (process {})", pid)).unwrap_or_default();
+        warning!("Waiting for another safe_backup{} to finish in this directory...", holder);
+    };
+    mgr.lock(WAIT_FOR_LOCK.load(Ordering::Relaxed), waiting).map(Some)
+}
+
+/// The count `prune` should keep: explicit, else `keep_versions` from the config.
+fn keep_count(cfg: &Config, keep: Option<usize>) -> Result<usize, BackupError> {
+    keep.or(cfg.keep_versions).ok_or_else(|| {
+        BackupError::InvalidInput(
+            "prune needs the number of backups to keep, e.g. 'prune 5' \
+             (or set keep_versions in the config)"
+                .to_string(),
+        )
+    })
+}
+
+/// Run `command`; with `json`, commands that print a summary or status print it as JSON.
+fn run_command(mgr: &BackupManager, command: &Command, json: bool) -> Result<(), BackupError> {
+    let cfg = mgr.config();
+    match command {
+        Command::Backup {
+            file,
+            versioned,
+            gzip,
+            encrypt,
+            incremental,
+            dedup,
+            hardlink_unchanged,
+            preserve_mtime,
+            sparse,
+            shared_read,
+            allow_backup,
+            force,
+            exclude,
+            no_hidden,
+            stdin,
+            since,
+            remote,
+            snapshot,
+            keep,
+        } => {
+            let options = BackupOptions {
+                versioned: *versioned || snapshot.is_some(),
+                compress: *gzip,
+                encrypt: *encrypt,
+                incremental: *incremental,
+                dedup: *dedup,
+                hardlink_unchanged: *hardlink_unchanged,
+                preserve_mtime: *preserve_mtime,
+                sparse: *sparse,
+                shared_read: *shared_read,
+                allow_backup: *allow_backup,
+                force: *force,
+                exclude,
+                skip_hidden: *no_hidden,
+                since: *since,
+                remote: remote.as_deref(),
+                snapshot: snapshot.as_deref(),
+                keep: *keep,
+            };
+            match file {
+                Some(file) if !*stdin => backup_file(mgr, file, options, json),
+                _ => run_on_stdin(mgr, "backup", json, |name| {
+                    Ok(match backup_one(mgr, name, options)? {
+                        Some(bytes) => (BatchOutcome::Succeeded, Some(bytes)),
+                        None => (BatchOutcome::Skipped, None),
+                    })
+                }),
+            }
+        }
+        Command::Restore {
+            file,
+            version,
+            to,
+            from,
+            force,
+            overwrite_policy,
+            no_snapshot,
+            match_sources,
+        } => {
+            let overwrite = match no_snapshot {
+                true => OverwritePolicy::Overwrite,
+                false => overwrite_policy.unwrap_or_default(),
+            };
+            let options = RestoreOptions {
+                version: version.as_deref(),
+                destination: to.as_deref(),
+                from: from.as_deref(),
+                force: *force,
+                overwrite,
+                ..RestoreOptions::default()
+            };
+            if is_glob(file) {
+                return restore_matching(mgr, file, options, *match_sources, json);
+            }
+            restore_file(mgr, file, options).map(|_| ())
+        }
+        Command::Rollback { snapshot, force, overwrite_policy } => {
+            let options = RestoreOptions {
+                snapshot: Some(snapshot),
+                force: *force,
+                overwrite: overwrite_policy.unwrap_or_default(),
+                ..RestoreOptions::default()
+            };
+            let files: Vec<String> = mgr
+                .snapshot_files(snapshot)?
+                .iter()
+                .map(|file| file.to_string_lossy().to_string())
+                .collect();
+            let headline = format!("{} files of snapshot '{}'", files.len(), snapshot);
+            run_on_names(mgr, &files, "restore", json, &headline, |name| {
+                Ok((restore_file(mgr, name, options)?, None))
+            })
+        }
+        Command::Verify { file, version, stdin } => match file {
+            Some(file) if !*stdin => verify_backup(mgr, file, version.as_deref()),
+            _ => run_on_stdin(mgr, "verify", json, |name| {
+                verify_backup(mgr, name, version.as_deref())?;
+                Ok((BatchOutcome::Succeeded, None))
+            }),
+        },
+        Command::TestRestore { file, version } => {
+            test_restore(mgr, file, version.as_deref(), json)
+        }
+        Command::Diff { file, version } => diff_backup(mgr, file, version.as_deref()),
+        Command::Cat { file, version } => cat_backup(mgr, file, version.as_deref(), json),
+        Command::Compare { file, first, second } => compare_backups(mgr, file, first, second),
+        Command::Status { file } => show_status(mgr, file, json),
+        Command::Check { file, max_age } => check_backup(mgr, file, *max_age, json),
+        Command::Watch { file, debounce_ms } => {
+            watch(mgr, file, Duration::from_millis(*debounce_ms))
+        }
+        Command::Archive { file, gzip, append } => {
+            archive_file(mgr, file, ArchiveOptions { compress: *gzip, append: *append })
+        }
+        Command::Extract { archive, list: true, .. } => list_archive(mgr, archive),
+        Command::Extract { archive, overwrite, member, .. } => {
+            let options = ExtractOptions { overwrite: *overwrite, member: member.as_deref() };
+            extract_archive(mgr, archive, options)
+        }
+        Command::VerifyLog => verify_log(mgr),
+        Command::Report { top } => log_report(mgr, *top, json),
+        Command::Log { action, file, outcome, since, until, tail, follow } => {
+            let filter = LogFilter {
+                action: action.as_deref(),
+                file: file.as_deref(),
+                outcome: outcome.as_deref(),
+                since: *since,
+                until: *until,
+                tail: *tail,
+            };
+            match follow {
+                true => follow_log(mgr, filter),
+                false => show_log(mgr, filter),
+            }
+        }
+        Command::Info => show_info(mgr, json),
+        Command::VerifyAll => verify_all_backups(mgr),
+        Command::Audit => audit_backups(mgr, json),
+        Command::SelfTest => self_test(),
+        Command::Bench { size, buffers } => bench_copies(mgr, *size, buffers, json),
+        Command::Orphans { remove, force } => orphaned_backups(mgr, *remove, *force),
+        Command::Gc => collect_garbage(mgr),
+        Command::Migrate => migrate_backups(mgr),
+        Command::Reindex => {
+            let files = mgr.reindex()?;
+            match mgr.config().dry_run {
+                true => say!("[dry-run] would index {} files.", files),
+                false => say!(Green: "Indexed {} files.", files),
+            }
+            Ok(())
+        }
+        Command::List { file, formats } => list_backups(mgr, file, *formats),
+        Command::Prune { file, keep, older_than } => {
+            let keep = match older_than {
+                Some(_) => keep.or(cfg.keep_versions),
+                None => Some(keep_count(cfg, *keep)?),
+            };
+            prune_backups(mgr, file, PruneOptions { keep, older_than: *older_than })
+        }
+        Command::Delete { file, force, confirm_name, purge, system_trash, secure } => {
+            let options = DeleteOptions {
+                purge: *purge,
+                secure: *secure,
+                system_trash: *system_trash,
+                force: *force,
+                confirm_name: *confirm_name,
+            };
+            delete_file(mgr, file, options, json)
+        }
+        Command::Move { from, to, force } => move_file(mgr, from, to, *force),
+        Command::Copy { from, to, force } => copy_file(mgr, from, to, *force),
+        Command::Run { script, keep_going } => run_script(mgr, script, *keep_going, json),
+        Command::Undelete { file } => undelete_file(mgr, file),
+        Command::Undo => undo_last(mgr),
+    }
+}
+
+/// Process exit status for a failed operation: 2 when something was not found (for
+/// `check`, the backup), 3 when the path was rejected by `resolve_safe_path`, 130 when
+/// Ctrl-C cancelled it, 1 for anything else.
+fn exit_code_for(e: &BackupError) -> ExitCode {
+    match e {
+        BackupError::NotFound(_) => ExitCode::from(2),
+        BackupError::CheckFailed { status: CheckStatus::Missing, .. } => ExitCode::from(2),
+        BackupError::Cancelled => ExitCode::from(130),
+        BackupError::Io(io) if io.kind() == io::ErrorKind::NotFound => ExitCode::from(2),
+        BackupError::PathRejected(_) | BackupError::ReservedName(_) | BackupError::Symlink(_) => {
+            ExitCode::from(3)
+        }
+        _ => ExitCode::FAILURE,
+    }
+}
+
+/// Log a failed operation, tagged with the kind of error: "failure | not-found | ...".
+fn log_failure(cfg: &Config, action: &str, filename: &str, e: &BackupError) {
+    if let BackupError::Cancelled = e {
+        log_action(cfg, action, filename, "cancelled", None);
+        return;
+    }
+    let detail = format!("{} | {}", e.tag(), e);
+    log_action(cfg, action, filename, "failure", Some(&detail));
+}
+
+/// Run one of the interactive commands (also used by `run` scripts); `None` if the
+/// command is unknown. The caller holds the base directory's lock for its session.
+fn run_menu_command(
+    mgr: &BackupManager,
+    command: &str,
+    filename: &str,
+    arg: Option<&str>,
+) -> Option<Result<(), BackupError>> {
+    let cfg = mgr.config();
+    let result = match command {
+        "backup" => backup_file(mgr, filename, BackupOptions::default(), false),
+        "backup-versioned" => {
+            let options = BackupOptions { versioned: true, ..BackupOptions::default() };
+            backup_file(mgr, filename, options, false)
+        }
+        "backup-gz" => {
+            let options = BackupOptions { compress: true, ..BackupOptions::default() };
+            backup_file(mgr, filename, options, false)
+        }
+        "backup-enc" => {
+            let options = BackupOptions { encrypt: true, ..BackupOptions::default() };
+            backup_file(mgr, filename, options, false)
+        }
+        "restore" => {
+            let options = RestoreOptions { version: arg, ..RestoreOptions::default() };
+            restore_file(mgr, filename, options).map(|_| ())
+        }
+        "verify" => verify_backup(mgr, filename, arg),
+        "diff" => diff_backup(mgr, filename, arg),
+        "status" => show_status(mgr, filename, false),
+        "list" => list_backups(mgr, filename, false),
+        "prune" => match arg.map(str::parse::<usize>).transpose() {
+            Ok(keep) => keep_count(cfg, keep).and_then(|keep| {
+                let options = PruneOptions { keep: Some(keep), ..PruneOptions::default() };
+                prune_backups(mgr, filename, options)
+            }),
+            Err(_) => Err(BackupError::InvalidInput(
+                "prune expects a number, e.g. 'prune 5'".to_string(),
+            )),
+        },
+        "delete" => delete_file(mgr, filename, DeleteOptions::default(), false),
+        "purge" => {
+            let options = DeleteOptions { purge: true, ..DeleteOptions::default() };
+            delete_file(mgr, filename, options, false)
+        }
+        "undelete" => undelete_file(mgr, filename),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Run a script of "command filename [arg]" lines using the interactive commands,
+/// skipping blank lines and '#' comments, then print a summary table. Stops at the first
+/// failure unless `keep_going` is set.
+fn run_script(
+    mgr: &BackupManager,
+    script: &str,
+    keep_going: bool,
+    json: bool,
+) -> Result<(), BackupError> {
+    let cfg = mgr.config();
+    let text = std::fs::read_to_string(script)?;
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    let mut stopped = false;
+    let rows: Vec<BatchRow> = lines
+        .iter()
+        .map(|&(number, line)| {
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("").to_lowercase();
+            let filename = words.next().unwrap_or("");
+            let arg = words.next();
+            let started = Instant::now();
+            let outcome = if stopped {
+                BatchOutcome::NotRun
+            } else {
+                say!("> {}", line);
+                let unknown = || {
+                    Err(BackupError::InvalidInput(format!("Unknown command '{}'", command)))
+                };
+                match run_menu_command(mgr, &command, filename, arg).unwrap_or_else(unknown) {
+                    Ok(()) => BatchOutcome::Succeeded,
+                    Err(e) => {
+                        print_error(format!("Line {}: operation failed: {}", number, e));
+                        log_failure(cfg, &command, filename, &e);
+                        stopped = !keep_going;
+                        BatchOutcome::Failed
+                    }
+                }
+            };
+            let elapsed = started.elapsed();
+            let file = filename.to_string();
+            BatchRow { file, action: command, outcome, bytes: None, elapsed }
+        })
+        .collect();
+
+    finish_batch(&rows, json, "Script finished")
+}
+
+/// Run `op` on each file named on stdin, one per line, skipping blank lines and '#'
+/// comments. A failure is reported and logged, and the next name is still tried; a summary
+/// table (JSON with `json`) follows at the end.
+fn run_on_stdin(
+    mgr: &BackupManager,
+    action: &str,
+    json: bool,
+    op: impl Fn(&str) -> Result<(BatchOutcome, Option<u64>), BackupError>,
+) -> Result<(), BackupError> {
+    let names: Vec<String> = io::stdin()
+        .lines()
+        .map(|line| line.map(|l| l.trim().to_string()))
+        .filter(|line| !line.as_ref().is_ok_and(|l| l.is_empty() || l.starts_with('#')))
+        .collect::<io::Result<_>>()?;
+    let headline = format!("{} files from stdin", names.len());
+    run_on_names(mgr, &names, action, json, &headline, op)
+}
+
+/// Run `op` on each of `names`, reporting and logging a failure and going on with the
+/// next name, then print the summary table (JSON with `json`) headed by `headline`.
+fn run_on_names(
+    mgr: &BackupManager,
+    names: &[String],
+    action: &str,
+    json: bool,
+    headline: &str,
+    op: impl Fn(&str) -> Result<(BatchOutcome, Option<u64>), BackupError>,
+) -> Result<(), BackupError> {
+    let rows: Vec<BatchRow> = names
+        .iter()
+        .map(|name| {
+            let started = Instant::now();
+            let (outcome, bytes) = op(name).unwrap_or_else(|e| {
+                print_error(format!("{} of '{}' failed: {}", capitalize(action), name, e));
+                log_failure(mgr.config(), action, name, &e);
+                (BatchOutcome::Failed, None)
+            });
+            let elapsed = started.elapsed();
+            BatchRow { file: name.clone(), action: action.to_string(), outcome, bytes, elapsed }
+        })
+        .collect();
+
+    finish_batch(&rows, json, headline)
 }
 
-/// ---------- CLI ----------
+/// "backup" -> "Backup", for the start of a message.
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+/// Watch a file, or every file under a directory, and keep a versioned backup of each
+/// change until interrupted. Changes are collected until the files have been quiet for
+/// `debounce`, so a burst of writes makes one backup.
+fn watch(mgr: &BackupManager, target: &str, debounce: Duration) -> Result<(), BackupError> {
+    use notify::{EventKind, RecursiveMode, Watcher};
+
+    let path = resolve_safe_path(mgr.base(), target)?;
+    if !path.exists() {
+        return Err(BackupError::NotFound(format!("'{}' does not exist", target)));
+    }
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(io::Error::other)?;
+    // Editors often replace a file instead of writing it, so watch its directory.
+    let (watched, mode) = if path.is_dir() {
+        (path.clone(), RecursiveMode::Recursive)
+    } else {
+        (path.parent().unwrap_or(mgr.base()).to_path_buf(), RecursiveMode::NonRecursive)
+    };
+    watcher.watch(&watched, mode).map_err(io::Error::other)?;
+    say!("Watching {} (Ctrl-C to stop)...", path.display());
+
+    let options = BackupOptions { versioned: true, ..BackupOptions::default() };
+    let mut pending: BTreeSet<PathBuf> = BTreeSet::new();
+    loop {
+        let event = if pending.is_empty() {
+            rx.recv().map_err(io::Error::other)?
+        } else {
+            match rx.recv_timeout(debounce) {
+                Ok(event) => event,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    for changed in std::mem::take(&mut pending) {
+                        watch_backup(mgr, &changed, options);
+                    }
+                    continue;
+                }
+                Err(e) => return Err(io::Error::other(e).into()),
+            }
+        };
+        let event = event.map_err(io::Error::other)?;
+        if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            continue;
+        }
+        for changed in event.paths {
+            if changed == path || (path.is_dir() && changed.starts_with(&path)) {
+                pending.insert(changed);
+            }
+        }
+    }
+}
+
+/// One automatic backup for `watch`. Its own backups, sidecars and the log (which change
+/// under a watched directory too), unchanged files and files that vanished again are
+/// passed over quietly; failures are reported and logged without ending the watch.
+fn watch_backup(mgr: &BackupManager, changed: &Path, options: BackupOptions) {
+    if !changed.is_file() {
+        return;
+    }
+    let rel = changed.strip_prefix(mgr.base()).unwrap_or(changed);
+    let filename = rel.to_string_lossy();
+    let time = chrono::Local::now().format("%H:%M:%S");
+    match mgr.backup(rel, options) {
+        Ok(BackupReport::File { backup, .. }) => {
+            say!(Green: "[{}] {} backed up to {}", time, filename, backup.display());
+        }
+        Ok(_) => {}
+        Err(e) => {
+            print_error(format!("[{}] Backup of '{}' failed: {}", time, filename, e));
+            log_failure(mgr.config(), "backup", &filename, &e);
+        }
+    }
+}
+
+/// The commands the prompt loop offers, with their optional argument; the prompt and the
+/// help for an unknown command both list them.
+const INTERACTIVE_COMMANDS: &[&str] = &[
+    "backup",
+    "backup-versioned",
+    "backup-gz",
+    "backup-enc",
+    "restore [version]",
+    "verify [version]",
+    "diff [version]",
+    "status",
+    "list",
+    "prune [keep]",
+    "delete",
+    "purge",
+    "undelete",
+    "exit",
+];
 
-fn main() {
+/// Run the prompt loop until "exit" or end of input. The exit status reflects the last
+/// failed operation of the session, if any.
+fn run_interactive(mgr: &BackupManager) -> ExitCode {
+    let cfg = mgr.config();
+    let mut status = ExitCode::SUCCESS;
     println!("safe_backup (Rust) — type 'exit' to quit");
+    if cfg.dry_run {
+        println!("Dry run: no files will be changed.");
+    }
 
     loop {
         // filename
         print!("Please enter your file name: ");
         io::stdout().flush().expect("flush stdout");
-        let mut filename = String::new();
-        if io::stdin().read_line(&mut filename).is_err() {
-            eprintln!("Failed to read filename");
-            continue;
-        }
+        let filename = match read_input_line() {
+            Ok(Some(line)) => line,
+            Ok(None) => {
+                println!("\nExiting.");
+                break;
+            }
+            Err(_) => {
+                print_error("Failed to read filename");
+                continue;
+            }
+        };
         let filename = filename.trim();
         if filename.eq_ignore_ascii_case("exit") {
             println!("Exiting.");
@@ -153,33 +3168,413 @@ fn main() {
         }
 
         // command
-        print!("Please enter your command (backup, restore, delete, exit): ");
+        print!("Please enter your command ({}): ", INTERACTIVE_COMMANDS.join(", "));
         io::stdout().flush().expect("flush stdout");
-        let mut command = String::new();
-        if io::stdin().read_line(&mut command).is_err() {
-            eprintln!("Failed to read command");
-            continue;
-        }
-        let command = command.trim().to_lowercase();
+        let command = match read_input_line() {
+            Ok(Some(line)) => line,
+            Ok(None) => {
+                println!("\nExiting.");
+                break;
+            }
+            Err(_) => {
+                print_error("Failed to read command");
+                continue;
+            }
+        };
+        let mut words = command.split_whitespace();
+        let command = words.next().unwrap_or("").to_lowercase();
+        let arg = words.next();
         if command == "exit" {
             println!("Exiting.");
             break;
         }
 
         // execute
-        let result = match command.as_str() {
-            "backup" => backup_file(filename),
-            "restore" => restore_file(filename),
-            "delete" => delete_file(filename),
+        let Some(result) = run_interactive_command(mgr, &command, filename, arg) else {
+            println!("Unknown command. Allowed: {}", INTERACTIVE_COMMANDS.join(" | "));
+            continue;
+        };
+
+        if let Err(e) = result {
+            print_failure("Operation failed", &e);
+            log_failure(cfg, &command, filename, &e);
+            status = exit_code_for(&e);
+        }
+    }
+    status
+}
+
+/// Operations offered by the numbered menu, as `run_menu_command` names them; the choice
+/// after the last one exits.
+const MENU_CHOICES: [&str; 4] = ["backup", "restore", "delete", "list"];
+
+/// Run the numbered menu until its exit choice or end of input. The file name is asked for
+/// after each choice and remembered, so pressing Enter reuses it. Like `run_interactive`,
+/// the exit status reflects the last failed operation.
+fn run_numbered_menu(mgr: &BackupManager) -> ExitCode {
+    let cfg = mgr.config();
+    let mut status = ExitCode::SUCCESS;
+    let mut last_file: Option<String> = None;
+    let exit_choice = MENU_CHOICES.len() + 1;
+    println!("safe_backup (Rust) — numbered menu");
+    if cfg.dry_run {
+        println!("Dry run: no files will be changed.");
+    }
+
+    loop {
+        println!();
+        for (i, choice) in MENU_CHOICES.iter().enumerate() {
+            println!("  {}) {}", i + 1, choice);
+        }
+        println!("  {}) exit", exit_choice);
+        let Some(answer) = ask_line(&format!("Choose 1-{}: ", exit_choice)) else {
+            println!("\nExiting.");
+            return status;
+        };
+        let answer = answer.trim();
+        let command = match answer.parse::<usize>() {
+            Ok(n) if n == exit_choice => {
+                println!("Exiting.");
+                return status;
+            }
+            Ok(n) if (1..exit_choice).contains(&n) => MENU_CHOICES[n - 1],
             _ => {
-                println!("Unknown command. Allowed: backup | restore | delete | exit");
-                Ok(())
+                warning!("Invalid choice '{}': enter a number from 1 to {}.", answer, exit_choice);
+                continue;
             }
         };
 
-        if let Err(e) = result {
-            eprintln!("Operation failed: {}", e);
-            let _ = log_action(&format!("{} | {} | failure | {}", command, filename, e));
+        let prompt = match &last_file {
+            Some(file) => format!("File name [{}]: ", file),
+            None => "File name: ".to_string(),
+        };
+        let filename = loop {
+            let Some(answer) = ask_line(&prompt) else {
+                println!("\nExiting.");
+                return status;
+            };
+            match (answer.trim(), &last_file) {
+                ("", Some(file)) => break file.clone(),
+                ("", None) => warning!("Please enter a file name."),
+                (name, _) => break name.to_string(),
+            }
+        };
+        last_file = Some(filename.clone());
+
+        if let Some(Err(e)) = run_interactive_command(mgr, command, &filename, None) {
+            print_failure("Operation failed", &e);
+            log_failure(cfg, command, &filename, &e);
+            status = exit_code_for(&e);
+        }
+    }
+}
+
+/// `run_menu_command` for a user at the prompt: a restore without a version first offers
+/// the file's backups to choose from, if it has more than one. A Ctrl-C that cancelled
+/// the previous command does not carry over to this one.
+fn run_interactive_command(
+    mgr: &BackupManager,
+    command: &str,
+    filename: &str,
+    arg: Option<&str>,
+) -> Option<Result<(), BackupError>> {
+    safe_backup::clear_cancel();
+    if command != "restore" || arg.is_some() {
+        return run_menu_command(mgr, command, filename, arg);
+    }
+    let backups = match mgr.list(filename) {
+        Ok(backups) if backups.len() > 1 => backups,
+        _ => return run_menu_command(mgr, command, filename, None),
+    };
+    println!("Backups of '{}' (newest first):", filename);
+    let now = std::time::SystemTime::now();
+    for (i, b) in backups.iter().enumerate() {
+        let age = now.duration_since(b.modified).unwrap_or_default();
+        let name = b.path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let (age, size) = (format_duration(age), format_size(b.size));
+        println!("  {:>2}) {}  {} ago  {}", i + 1, name, age, size);
+    }
+    let count = backups.len();
+    let prompt = format!("Version to restore, 1-{} [1]: ", count);
+    loop {
+        let Some(answer) = ask_line(&prompt) else {
+            println!();
+            say!(Yellow: "Restore cancelled; '{}' was left unchanged.", filename);
+            return Some(Ok(()));
+        };
+        match answer.trim() {
+            "" => return run_menu_command(mgr, command, filename, Some("1")),
+            n => match n.parse::<usize>() {
+                Ok(i) if (1..=count).contains(&i) => {
+                    return run_menu_command(mgr, command, filename, Some(n));
+                }
+                _ => warning!("Invalid choice '{}': enter a number from 1 to {}.", n, count),
+            },
+        }
+    }
+}
+
+/// Print `prompt` and read the answer; `None` at end of input or if stdin fails.
+fn ask_line(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().expect("flush stdout");
+    match read_input_line() {
+        Ok(line) => line,
+        Err(e) => {
+            print_error(format!("Failed to read input: {}", e));
+            None
+        }
+    }
+}
+
+/// Backup directory used when no --backup-dir is given; the config's backup_dir comes after.
+const BACKUP_DIR_ENV: &str = "SAFE_BACKUP_DIR";
+
+/// With `--json`, print what `command` did as one object, unless it printed its own.
+fn print_json_result(command: &Command, result: &Result<(), BackupError>) {
+    let noted = std::mem::take(&mut *json_result());
+    if noted.printed {
+        return;
+    }
+    let (op, file) = command.describe();
+    let outcome = match result {
+        Ok(()) => noted.outcome.unwrap_or("success"),
+        Err(_) => "failure",
+    };
+    print_json(serde_json::json!({
+        "op": op,
+        "file": file,
+        "outcome": outcome,
+        "backup_path": noted.backup_path,
+        "bytes": noted.bytes,
+        "error": result.as_ref().err().map(ToString::to_string),
+    }));
+}
+
+/// On Ctrl-C, let the copies in progress stop at their next chunk and clean up (see
+/// `safe_backup::cancel`); the operation then fails as cancelled. With nothing being
+/// written, or on a second Ctrl-C, exit at once.
+fn handle_interrupts() {
+    let installed = ctrlc::set_handler(|| {
+        if safe_backup::cancel() {
+            eprintln!("\nCancelling; press Ctrl-C again to quit now");
+        } else {
+            std::process::exit(130);
+        }
+    });
+    if let Err(e) = installed {
+        warning!("Ctrl-C cannot be handled ({}); interrupting a copy ends it at once", e);
+    }
+}
+
+/// Mark the settings the flags (or $SAFE_BACKUP_DIR) set as coming from there, for `info`.
+fn note_cli_origins(cli: &Cli, cfg: &mut Config) {
+    let given = [
+        ("log_format", cli.log_format.is_some()),
+        ("log_level", cli.log_level.is_some() || cli.no_log),
+        ("follow_symlinks", cli.follow_symlinks),
+        ("existing_backup", cli.if_exists.is_some()),
+        ("hash", cli.hash.is_some()),
+        ("jobs", cli.jobs.is_some()),
+        ("max_size", cli.max_size.is_some()),
+        ("compress_level", cli.compress_level.is_some()),
+        ("durable", cli.durable.is_some()),
+        ("copy_buffer_size", cli.buffer_size.is_some()),
+        ("backup_suffix", cli.suffix.is_some()),
+        ("name_template", cli.name_template.is_some()),
+        ("backup_dir", cli.backup_dir.is_some()),
+        ("preserve_structure", cli.preserve_structure),
+        ("create_parents", cli.parents),
+        ("checksum_cache", cli.rehash),
+        ("pre_hook", cli.no_hooks),
+        ("post_hook", cli.no_hooks),
+    ];
+    for (key, _) in given.into_iter().filter(|(_, given)| *given) {
+        cfg.origins.insert(key.to_string(), SettingOrigin::Cli);
+    }
+    let from_env = std::env::var_os(BACKUP_DIR_ENV).is_some_and(|d| !d.is_empty());
+    if cli.backup_dir.is_none() && from_env {
+        cfg.origins.insert("backup_dir".to_string(), SettingOrigin::Env);
+    }
+}
+
+/// `--base-dir` as an absolute path, if it is an existing directory.
+fn base_dir(dir: &Path) -> io::Result<PathBuf> {
+    if !std::fs::metadata(dir)?.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotADirectory, "not a directory"));
+    }
+    std::path::absolute(dir)
+}
+
+fn main() -> ExitCode {
+    let cli = <Cli as clap::Parser>::parse();
+    handle_interrupts();
+    let env = env_logger::Env::default().default_filter_or("warn");
+    let mut logger = env_logger::Builder::from_env(env);
+    if cli.verbose {
+        logger.filter_module("safe_backup", log::LevelFilter::Debug);
+    }
+    logger.format_timestamp(None).init();
+    JSON_ERRORS.store(cli.error_format == ErrorFormat::Json, Ordering::Relaxed);
+    let mut cfg = match Config::load(cli.config.as_deref()) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            print_failure("Failed to load config", &e);
+            return ExitCode::FAILURE;
+        }
+    };
+    cfg.dry_run = cli.dry_run;
+    if let Some(format) = cli.log_format {
+        cfg.log_format = format;
+    }
+    if let Some(level) = cli.log_level {
+        cfg.log_level = level;
+    }
+    if cli.no_log {
+        cfg.log_level = LogLevel::Off;
+    }
+    if cli.follow_symlinks {
+        cfg.follow_symlinks = true;
+    }
+    cfg.allow_absolute = cli.allow_absolute;
+    if let Some(policy) = cli.if_exists {
+        cfg.existing_backup = policy;
+    }
+    if let Some(hash) = cli.hash {
+        cfg.hash = hash;
+    }
+    if let Some(jobs) = cli.jobs {
+        cfg.jobs = jobs.into();
+    }
+    if cli.max_size.is_some() {
+        cfg.max_size = cli.max_size;
+    }
+    if let Some(level) = cli.compress_level {
+        cfg.compress_level = level;
+    }
+    if let Some(durable) = cli.durable {
+        cfg.durable = durable;
+    }
+    if let Some(size) = cli.buffer_size {
+        cfg.copy_buffer_size = size;
+        if let Err(e) = cfg.validate() {
+            print_error(format!("Invalid buffer size: {}", e));
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(suffix) = &cli.suffix {
+        cfg.backup_suffix = suffix.clone();
+        if let Err(e) = cfg.validate() {
+            print_error(format!("Invalid suffix: {}", e));
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(template) = &cli.name_template {
+        cfg.name_template = Some(template.clone());
+        if let Err(e) = cfg.validate() {
+            print_error(format!("Invalid name template: {}", e));
+            return ExitCode::FAILURE;
+        }
+    }
+    let backup_dir = cli.backup_dir.clone().or_else(|| {
+        std::env::var_os(BACKUP_DIR_ENV).filter(|d| !d.is_empty()).map(PathBuf::from)
+    });
+    if let Some(dir) = backup_dir {
+        cfg.backup_dir = Some(dir);
+        if let Err(e) = cfg.validate() {
+            print_error(format!("Invalid backup directory: {}", e));
+            return ExitCode::FAILURE;
+        }
+    }
+    if cli.preserve_structure {
+        cfg.preserve_structure = true;
+    }
+    if cli.parents {
+        cfg.create_parents = true;
+    }
+    if cli.rehash {
+        cfg.checksum_cache = false;
+    }
+    if cli.no_hooks {
+        cfg.pre_hook = None;
+        cfg.post_hook = None;
+    }
+    note_cli_origins(&cli, &mut cfg);
+
+    let base = match &cli.base_dir {
+        Some(dir) => match base_dir(dir) {
+            Ok(dir) => {
+                cfg.log_path = dir.join(&cfg.log_path);
+                dir
+            }
+            Err(e) => {
+                let context = format!("Invalid base directory {}", dir.display());
+                print_failure(&context, &BackupError::Io(e));
+                return ExitCode::FAILURE;
+            }
+        },
+        None => match std::env::current_dir() {
+            Ok(dir) => dir,
+            Err(e) => {
+                print_failure("Failed to read the current directory", &BackupError::Io(e));
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+    let mut mgr = BackupManager::new(base, cfg);
+    QUIET.store(cli.quiet || cli.json, Ordering::Relaxed);
+    VERBOSE.store(cli.verbose, Ordering::Relaxed);
+    WAIT_FOR_LOCK.store(cli.wait, Ordering::Relaxed);
+    STRICT.store(cli.strict, Ordering::Relaxed);
+    if !cli.quiet && !cli.json {
+        mgr.set_progress(progress_bar());
+        mgr.set_dir_progress(dir_progress_bar());
+    }
+    let passphrase_file = cli.passphrase_file.clone();
+    mgr.set_passphrase_prompt(move |backup: &Path, new: bool| {
+        passphrase_prompt(backup, new, passphrase_file.as_deref())
+    });
+    mgr.set_sftp_password_prompt(sftp_password_prompt);
+
+    let Some(command) = cli.command else {
+        if cli.json {
+            print_error("--json needs a command");
+            return ExitCode::FAILURE;
+        }
+        let _lock = match lock_for(&mgr, "interactive") {
+            Ok(lock) => lock,
+            Err(e) => {
+                print_failure("Operation failed", &e);
+                return exit_code_for(&e);
+            }
+        };
+        if cli.menu {
+            return run_numbered_menu(&mgr);
+        }
+        return run_interactive(&mgr);
+    };
+    let result = lock_for(&mgr, command.describe().0)
+        .and_then(|_lock| run_command(&mgr, &command, cli.json))
+        .and_then(|()| strict_result());
+    if cli.json {
+        print_json_result(&command, &result);
+    }
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        // `check` answers with its exit status alone; it has logged the outcome already.
+        Err(e @ BackupError::CheckFailed { .. }) => exit_code_for(&e),
+        // Shown and logged by `test-restore` itself.
+        Err(e @ BackupError::TestRestoreFailed(_)) => exit_code_for(&e),
+        // Logged by `restore`, with the digests.
+        Err(e @ BackupError::RestoreMismatch { .. }) => {
+            print_failure("Operation failed", &e);
+            exit_code_for(&e)
+        }
+        Err(e) => {
+            let (name, file) = command.describe();
+            print_failure("Operation failed", &e);
+            log_failure(mgr.config(), name, file, &e);
+            exit_code_for(&e)
         }
     }
 }
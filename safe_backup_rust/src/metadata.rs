@@ -0,0 +1,147 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::checksum::sidecar_path_for;
+use crate::paths::file_name_of;
+
+// ---------- File metadata ----------
+
+/// Source metadata recorded at backup time and reapplied on restore.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FileMetadata {
+    /// Modification time as seconds + nanoseconds since the Unix epoch.
+    modified_secs: u64,
+    modified_nanos: u32,
+    /// Unix permission bits, including setuid, setgid and sticky; absent for backups made
+    /// on other platforms.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<u32>,
+}
+
+/// Metadata sidecar for a backup: "file.ext.bak" -> "file.ext.bak.meta".
+pub(crate) fn meta_path_for(backup: &Path) -> PathBuf {
+    backup.with_file_name(format!("{}.meta", file_name_of(backup)))
+}
+
+/// Record `source`'s mtime (and Unix permissions) next to its backup.
+pub(crate) fn write_metadata_sidecar(source: &Path, backup: &Path) -> io::Result<()> {
+    let meta = fs::metadata(source)?;
+    let since_epoch = meta
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(meta.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let mode = None;
+
+    let record = FileMetadata {
+        modified_secs: since_epoch.as_secs(),
+        modified_nanos: since_epoch.subsec_nanos(),
+        mode,
+    };
+    let json = serde_json::to_string(&record).map_err(io::Error::other)?;
+    fs::write(meta_path_for(backup), json)
+}
+
+/// The metadata recorded for `backup`, or `None` if it has no sidecar (older backups).
+fn read_metadata_sidecar(backup: &Path) -> io::Result<Option<FileMetadata>> {
+    let meta_path = meta_path_for(backup);
+    if !meta_path.exists() {
+        return Ok(None);
+    }
+    let record = serde_json::from_str(&fs::read_to_string(&meta_path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Some(record))
+}
+
+impl FileMetadata {
+    fn modified(&self) -> std::time::SystemTime {
+        std::time::UNIX_EPOCH + std::time::Duration::new(self.modified_secs, self.modified_nanos)
+    }
+}
+
+/// The source mtime recorded when `backup` was taken, if it has a metadata sidecar.
+pub(crate) fn recorded_mtime(backup: &Path) -> io::Result<Option<std::time::SystemTime>> {
+    Ok(read_metadata_sidecar(backup)?.map(|record| record.modified()))
+}
+
+/// Reapply the metadata recorded for `backup` to `target`. Returns false if the backup
+/// has no metadata sidecar (older backups), in which case nothing is changed.
+pub(crate) fn apply_metadata_sidecar(backup: &Path, target: &Path) -> io::Result<bool> {
+    let Some(record) = read_metadata_sidecar(backup)? else {
+        return Ok(false);
+    };
+    // Before the mode, which may make the target read-only; filetime opens it for writing
+    // attributes where the platform needs a handle (Windows).
+    filetime::set_file_mtime(target, filetime::FileTime::from_system_time(record.modified()))?;
+    #[cfg(unix)]
+    if let Some(mode) = record.mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(target, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(true)
+}
+
+/// Give `target` the modification time of `source`.
+pub(crate) fn copy_mtime(source: &Path, target: &Path) -> io::Result<()> {
+    let modified = fs::metadata(source)?.modified()?;
+    filetime::set_file_mtime(target, filetime::FileTime::from_system_time(modified))
+}
+
+/// When a directory backup started, recorded next to it so the next incremental one can
+/// carry over the files not modified since.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DirectoryState {
+    started_secs: u64,
+    started_nanos: u32,
+}
+
+/// State file of a directory backup: "photos.bak" -> "photos.bak.state".
+pub(crate) fn state_path_for(backup: &Path) -> PathBuf {
+    backup.with_file_name(format!("{}.state", file_name_of(backup)))
+}
+
+/// Record that the directory backup at `backup` started at `started`.
+pub(crate) fn write_directory_state(
+    backup: &Path,
+    started: std::time::SystemTime,
+) -> io::Result<()> {
+    let since_epoch = started
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let state = DirectoryState {
+        started_secs: since_epoch.as_secs(),
+        started_nanos: since_epoch.subsec_nanos(),
+    };
+    let json = serde_json::to_string(&state).map_err(io::Error::other)?;
+    fs::write(state_path_for(backup), json)
+}
+
+/// When the directory backup at `backup` started, or `None` if it has no state file
+/// (made before these were written).
+pub(crate) fn read_directory_state(backup: &Path) -> io::Result<Option<std::time::SystemTime>> {
+    let path = state_path_for(backup);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let state: DirectoryState = serde_json::from_str(&fs::read_to_string(&path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let started = std::time::Duration::new(state.started_secs, state.started_nanos);
+    Ok(Some(std::time::UNIX_EPOCH + started))
+}
+
+/// Delete a backup together with its checksum and metadata sidecars.
+pub(crate) fn remove_backup(backup: &Path) -> io::Result<()> {
+    fs::remove_file(backup)?;
+    for sidecar in [sidecar_path_for(backup), meta_path_for(backup)] {
+        if sidecar.exists() {
+            fs::remove_file(&sidecar)?;
+        }
+    }
+    Ok(())
+}
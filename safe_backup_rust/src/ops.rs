@@ -0,0 +1,4142 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use tempfile::{NamedTempFile, TempDir};
+use zeroize::Zeroizing;
+
+use crate::archive::{
+    append_tar, archive_path_for, extract_tar, list_tar, read_tar_entries, write_tar,
+};
+use crate::atomic::{copy_atomically, copy_verified, link_atomically, sync_dir, sync_file};
+use crate::bench::{bench_copies, CopyBench};
+use crate::cache::ChecksumCache;
+use crate::cancel::{check_cancelled, Writing};
+use crate::checksum::{
+    check_backup_matches, digest_file, read_checksum_sidecar, sha256_backup_content, sha256_file,
+    sha256_of, sidecar_path_for, write_checksum_sidecar, HashAlgorithm,
+};
+use crate::compress::{compress_file, decompress_file};
+use crate::config::{Config, ExistingBackup};
+use crate::crypto::{decrypt_file, decrypt_to_vec, encrypt_file, PassphraseFn};
+use crate::delta::{delta_base, read_delta, restore_delta, write_delta};
+use crate::error::BackupError;
+use crate::hooks::{run_hook, Hook};
+use crate::ignore::{is_ignored, read_ignore_rules, IgnoreRule};
+use crate::index::{indexed_backup, indexed_sha256, rebuild_index, record_backup};
+use crate::lock::{is_lock_file, lock_base, BaseLock};
+use crate::log::{
+    check_log_chains, follow_entries, is_log_file, log_action, read_entries, LogCheck, LogEntry,
+};
+use crate::metadata::{
+    apply_metadata_sidecar, copy_mtime, meta_path_for, read_directory_state, recorded_mtime,
+    remove_backup, state_path_for, write_directory_state, write_metadata_sidecar,
+};
+use crate::paths::{
+    all_backups, backup_format, backup_location, backup_time, backup_path_for, case_variant,
+    default_backup_for, delta_backup_path_for, enc_path_for, expand_glob, expand_glob_in_backups,
+    file_name_of, find_backups, flat_name, gz_path_for, is_delta, is_enc, is_gz, is_ref,
+    ref_path_for, resolve_os_path, resolve_safe_os_path, resolve_safe_path, select_backup,
+    is_version_stamp, source_for_any_backup, source_for_backup, stays_inside, strip_backup_ext,
+    versioned_backup_path_at, versioned_backup_path_for, versions_dir_for, BackupEntry,
+    BackupFormat,
+};
+use crate::progress::{
+    format_size, parse_size, transfer_stats, DirProgress, DirProgressFn, ProgressFn,
+};
+use crate::remote::{is_sftp_url, SftpAuth, SftpDir, SftpPasswordFn};
+use crate::share::{is_sharing_violation, stage_shared};
+use crate::snapshot::{check_snapshot_name, label_backup, labelled_backups, snapshot_backups};
+use crate::sparse::allocated_size;
+use crate::store::{ref_blob, restore_ref, store_root, unreferenced_blobs, write_ref};
+use crate::trash::{latest_trashed, move_to_system_trash, trash_path_for, TRASH_DIR};
+use crate::undo::{clear_last_operation, last_operation, record_last_operation, LastOperation};
+use crate::wipe::{wipe_file, WipePattern};
+
+// ---------- Reports ----------
+
+/// How `BackupManager::backup` should store the copy.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BackupOptions<'a> {
+    /// Keep a new timestamped copy instead of overwriting the single ".bak".
+    pub versioned: bool,
+    /// Gzip-compress the backup (adds a ".gz" suffix).
+    pub compress: bool,
+    /// Encrypt the backup with a passphrase (adds a ".enc" suffix); the passphrase comes
+    /// from the prompt given to `BackupManager::set_passphrase_prompt`.
+    pub encrypt: bool,
+    /// Store only the blocks that changed since the plain ".bak", as a timestamped ".delta"
+    /// that restores against it. Without a plain ".bak" yet, that full backup is made.
+    /// A later full backup replaces the base, and deltas made against the old one then
+    /// refuse to restore.
+    ///
+    /// For a directory: copy only the files modified since the last directory backup
+    /// started (recorded in its ".state" file) and carry the others over from that backup,
+    /// hardlinked where possible. Without a previous backup, every file is copied.
+    pub incremental: bool,
+    /// Keep the content once in the shared store (see `STORE_DIR`), under its SHA-256, and
+    /// write a small ".ref" pointing at it: identical files are then stored only once.
+    /// `gc` removes content no ".ref" points to any more.
+    pub dedup: bool,
+    /// When a versioned backup's source still matches its latest plain backup (compared by
+    /// SHA-256), make the new version a hardlink to that backup instead of reporting the
+    /// file unchanged. Where hardlinks are not supported, or the backups are on different
+    /// devices, the content is copied as usual. No effect without `versioned`.
+    pub hardlink_unchanged: bool,
+    /// Give the backup (each file of a directory backup) the source's modification time
+    /// instead of the time it was made, so tools that go by mtime see the source's age.
+    /// Versions are still ordered by the time in their names.
+    pub preserve_mtime: bool,
+    /// Copy the file sparse: its holes (found with SEEK_DATA/SEEK_HOLE on Linux) and
+    /// blocks of zeros are not written, so a mostly empty disk image does not take its full
+    /// size. Not for compressed, encrypted, deduplicated or incremental backups.
+    pub sparse: bool,
+    /// When another process has the file open without letting others read it (Windows),
+    /// try reading it anyway through the most lenient open Windows offers and back up that
+    /// copy. No effect on Unix, where that does not happen.
+    pub shared_read: bool,
+    /// Back up the file even if it is itself a backup or the log.
+    pub allow_backup: bool,
+    /// Copy even if the latest backup already has the same content.
+    pub force: bool,
+    /// Directory backups leave out entries whose name or path (relative to the directory)
+    /// matches one of these globs, e.g. "*.tmp" or "cache". The log is always left out.
+    /// These come on top of the `.backupignore` files in the directory (see `IGNORE_FILE`):
+    /// an entry excluded here stays out even if a "!" rule there brings it back.
+    pub exclude: &'a [String],
+    /// Directory backups leave out entries whose name starts with '.'.
+    pub skip_hidden: bool,
+    /// Also upload the backup and its sidecars to this "sftp://[user@]host[:port]/dir"
+    /// (see `SftpDir`), logging in with a key or a password; `RestoreOptions::from` fetches
+    /// them back. Not for directories, deduplicated or incremental backups.
+    pub remote: Option<&'a str>,
+    /// An incremental directory backup with this (local) time instead of the recorded
+    /// one: files not modified since are carried over from the previous backup.
+    pub since: Option<chrono::NaiveDateTime>,
+    /// Label the version this backup creates (or the latest one, if the file is unchanged)
+    /// with this snapshot name, in ".snapshots/<name>.json" (see `SNAPSHOT_DIR`), so
+    /// `rollback` can later restore every file labelled with it. Needs `versioned`.
+    pub snapshot: Option<&'a str>,
+    /// After a new version is written, prune the file's backups down to this many (see
+    /// `BackupManager::prune`); `keep_versions` from the config when `None`. No effect
+    /// without `versioned`.
+    pub keep: Option<usize>,
+}
+
+/// How `BackupManager::restore` should pick the backup and treat the live file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RestoreOptions<'a> {
+    /// Backup to restore (see `select_backup`); the plain ".bak" when `None`.
+    pub version: Option<&'a str>,
+    /// Restore the version labelled with this snapshot name instead (see
+    /// `BackupOptions::snapshot`).
+    pub snapshot: Option<&'a str>,
+    /// Restore into this file (resolved under the base) instead of the original.
+    pub destination: Option<&'a str>,
+    /// Look for the backup in this directory (relative to the base, or absolute) instead
+    /// of where backups are kept: a backup set that was copied or moved. The backup is
+    /// looked for under the name `backup_dir` would give it, then under the file's name.
+    /// An "sftp://" location (see `BackupOptions::remote`) is downloaded from first.
+    pub from: Option<&'a str>,
+    /// Overwrite a live file with newer changes without asking, or an existing
+    /// `destination` at all.
+    pub force: bool,
+    /// What to do with a target file that already exists.
+    pub overwrite: OverwritePolicy,
+}
+
+/// What a restore does with a target file that already exists, for a single file and for
+/// each file of a directory backup alike.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OverwritePolicy {
+    /// Leave it alone: only missing files are restored.
+    Skip,
+    /// Replace it without keeping its content; `undo` cannot bring it back.
+    Overwrite,
+    /// Keep its content in the trash first, then replace it.
+    #[default]
+    Backup,
+}
+
+impl OverwritePolicy {
+    /// Name as logged and accepted on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            OverwritePolicy::Skip => "skip",
+            OverwritePolicy::Overwrite => "overwrite",
+            OverwritePolicy::Backup => "backup",
+        }
+    }
+}
+
+/// How `BackupManager::delete` should get rid of the file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeleteOptions {
+    /// Remove the file for good instead of moving it into ".trash/".
+    pub purge: bool,
+    /// Overwrite the file's content with this pattern before removing it; implies `purge`.
+    pub secure: Option<WipePattern>,
+    /// Move the file to the desktop's trash instead of ".trash/"; where there is none,
+    /// ".trash/" is used after all.
+    pub system_trash: bool,
+    /// Delete without calling `confirm`.
+    pub force: bool,
+    /// Confirm by the retyped file name whatever the file's size.
+    pub confirm_name: bool,
+}
+
+/// How `BackupManager::delete` wants the deletion confirmed: a plain yes/no, or the file
+/// name typed again, for files of at least `confirm_name_size` or with `confirm_name`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmLevel {
+    YesNo,
+    TypedName,
+}
+
+impl ConfirmLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfirmLevel::YesNo => "yes-no",
+            ConfirmLevel::TypedName => "typed-name",
+        }
+    }
+}
+
+/// The answer to `BackupManager::delete`'s confirmation; a plain yes/no converts from `bool`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    Yes,
+    No,
+    /// Asked to retype the file name, the user typed something else.
+    Mismatch,
+}
+
+impl From<bool> for Confirmation {
+    fn from(yes: bool) -> Self {
+        if yes {
+            Confirmation::Yes
+        } else {
+            Confirmation::No
+        }
+    }
+}
+
+/// How `BackupManager::move_file` should treat an existing target.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MoveOptions {
+    /// Replace an existing target file.
+    pub force: bool,
+}
+
+/// How `BackupManager::copy_file` should treat an existing target.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CopyOptions {
+    /// Replace an existing target file.
+    pub force: bool,
+}
+
+/// How `BackupManager::archive` should write the archive.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArchiveOptions {
+    /// Write a ".tar.gz" instead of a plain ".tar".
+    pub compress: bool,
+    /// Add only the files that are new or changed since they were last stored to an
+    /// existing ".tar", after its entries, instead of writing the archive afresh.
+    pub append: bool,
+}
+
+/// How `BackupManager::extract` should treat files that already exist.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExtractOptions<'a> {
+    /// Replace existing files instead of keeping them.
+    pub overwrite: bool,
+    /// Extract only this file, named as `archive_members` lists it.
+    pub member: Option<&'a str>,
+}
+
+/// What a backup did (or, in a dry run, would do).
+#[derive(Debug)]
+pub enum BackupReport {
+    File {
+        source: PathBuf,
+        backup: PathBuf,
+        /// Size of the source.
+        bytes: u64,
+        /// Size on disk after compression, for compressed backups.
+        compressed: Option<u64>,
+        /// Bytes the backup takes on disk, for sparse backups where the platform tells.
+        allocated: Option<u64>,
+        /// SHA-256 of the content.
+        sha256: String,
+        /// The earlier backup this one is a hardlink to, if it was linked instead of copied.
+        linked: Option<PathBuf>,
+        /// Wall-clock time the backup took.
+        elapsed: Duration,
+    },
+    Directory {
+        source: PathBuf,
+        backup: PathBuf,
+        files: usize,
+        /// Of those, the files an incremental backup carried over from the previous one.
+        unchanged: usize,
+        /// Bytes copied; carried-over files are not counted.
+        bytes: u64,
+        /// Symlinks and special files that were left out.
+        skipped: Vec<SkippedEntry>,
+        /// Wall-clock time the backup took.
+        elapsed: Duration,
+    },
+    /// An incremental backup: only the blocks that differ from `base` were stored.
+    Delta {
+        source: PathBuf,
+        backup: PathBuf,
+        /// The full backup the delta restores against.
+        base: PathBuf,
+        /// Size of the source.
+        bytes: u64,
+        /// Size of the delta on disk.
+        stored: u64,
+        changed_blocks: usize,
+        total_blocks: u64,
+        /// SHA-256 of the content.
+        sha256: String,
+        /// Wall-clock time the backup took.
+        elapsed: Duration,
+    },
+    /// Nothing was copied because the latest backup already matches the source.
+    Unchanged { source: PathBuf, backup: PathBuf },
+    /// Nothing was backed up because the source is a backup, the log or the lock file, its
+    /// extension is excluded, or its backup exists and `existing_backup` is "skip".
+    Skipped {
+        source: PathBuf,
+        /// "is-backup", "is-log", "is-lock", "excluded" or "exists", as logged.
+        reason: &'static str,
+    },
+}
+
+/// An entry of a directory backup that is not a regular file and was not copied.
+#[derive(Debug)]
+pub struct SkippedEntry {
+    /// Path relative to the backed-up directory.
+    pub path: PathBuf,
+    /// "symlink" or "special file".
+    pub kind: &'static str,
+}
+
+/// What a move did (or would do).
+#[derive(Debug)]
+pub struct MoveReport {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub bytes: u64,
+    /// The target was on another filesystem, so the file was copied and then removed.
+    pub copied: bool,
+}
+
+/// What a copy did (or would do).
+#[derive(Debug)]
+pub struct CopyReport {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub bytes: u64,
+}
+
+/// What an archive did (or would do).
+#[derive(Debug)]
+pub struct ArchiveReport {
+    pub source: PathBuf,
+    pub archive: PathBuf,
+    /// Files written into the archive.
+    pub files: usize,
+    /// Files an append left out, as the archive already holds them unchanged.
+    pub unchanged: usize,
+    /// True if the files were added to an existing archive.
+    pub appended: bool,
+    /// Total size of the archived files, before any compression.
+    pub bytes: u64,
+    /// Symlinks and special files that were left out.
+    pub skipped: Vec<SkippedEntry>,
+}
+
+/// A file stored in an archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveMember {
+    /// The name it is stored under, relative to the base.
+    pub name: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// What an extract did (or would do).
+#[derive(Debug)]
+pub struct ExtractReport {
+    pub archive: PathBuf,
+    /// Files written (or, in a dry run, that would be written).
+    pub extracted: Vec<PathBuf>,
+    /// Files that already existed and were left alone.
+    pub kept: Vec<PathBuf>,
+}
+
+/// What a restore did (or would do).
+#[derive(Debug)]
+pub struct RestoreReport {
+    pub backup: PathBuf,
+    /// How the backup holds the content; decides how it was restored. Directory
+    /// backups are `Plain`.
+    pub format: BackupFormat,
+    pub target: PathBuf,
+    /// Size of the backup file on disk.
+    pub bytes: u64,
+    /// SHA-256 of the content that would be restored; only computed in dry runs.
+    pub sha256: Option<String>,
+    /// The live file had changes newer than the backup; for a directory, some of its files
+    /// differ from their backups and are overwritten.
+    pub live_newer: bool,
+    /// The target's parent directory, if it was missing and had to be created.
+    pub created_dir: Option<PathBuf>,
+    /// False when overwriting newer changes was declined, or in a dry run.
+    pub restored: bool,
+    /// Where the target's previous content was kept before it was overwritten.
+    pub snapshot: Option<PathBuf>,
+    /// For a directory backup, the number of files restored (or that would be).
+    pub files: Option<usize>,
+    /// Existing target files left alone under `OverwritePolicy::Skip`.
+    pub kept: usize,
+    /// Wall-clock time the restore took.
+    pub elapsed: Duration,
+}
+
+/// What a backup was verified against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyBasis {
+    /// The digest recorded in the backup's ".sha256" sidecar.
+    Sidecar,
+    /// The live source file (the backup has no sidecar).
+    Source,
+}
+
+/// Result of `BackupManager::verify`. A mismatch is reported here, not as an error.
+#[derive(Debug)]
+pub struct VerifyReport {
+    pub backup: PathBuf,
+    pub source: PathBuf,
+    pub basis: VerifyBasis,
+    /// How both digests were computed: as the sidecar records, or SHA-256.
+    pub algorithm: HashAlgorithm,
+    /// The digest the backup should have (recorded, or the source's).
+    pub expected: String,
+    /// The digest it actually has.
+    pub actual: String,
+}
+
+impl VerifyReport {
+    pub fn matches(&self) -> bool {
+        self.expected == self.actual
+    }
+}
+
+/// Where a test restore found the SHA-256 the restored content should have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentRecord {
+    /// Recorded in the index when the backup was written (the newest backup of a file).
+    Index,
+    /// The live file, unchanged since the backup by its recorded modification time.
+    Source,
+}
+
+impl ContentRecord {
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentRecord::Index => "index",
+            ContentRecord::Source => "source",
+        }
+    }
+}
+
+/// Result of `BackupManager::test_restore`. A failed check is reported here, not as an
+/// error; a backup that cannot be read back at all is an error.
+#[derive(Debug)]
+pub struct TestRestoreReport {
+    pub backup: PathBuf,
+    pub format: BackupFormat,
+    /// Bytes the restore wrote.
+    pub bytes: u64,
+    /// SHA-256 of the restored content.
+    pub sha256: String,
+    /// Whether the backup matches its ".sha256" sidecar; `None` if it has none.
+    pub stored_matches: Option<bool>,
+    /// The SHA-256 the restored content should have and where it was found; `None` if
+    /// nothing records it.
+    pub expected: Option<(ContentRecord, String)>,
+    pub elapsed: Duration,
+}
+
+impl TestRestoreReport {
+    pub fn content_matches(&self) -> Option<bool> {
+        self.expected.as_ref().map(|(_, sha256)| *sha256 == self.sha256)
+    }
+
+    /// No check failed. With neither a sidecar nor a content record, that only says the
+    /// backup could be read back.
+    pub fn passed(&self) -> bool {
+        self.stored_matches != Some(false) && self.content_matches() != Some(false)
+    }
+}
+
+/// Result of `BackupManager::diff`: how the live file differs from a backup.
+#[derive(Debug)]
+pub struct DiffReport {
+    pub source: PathBuf,
+    pub backup: PathBuf,
+    pub changes: DiffChanges,
+}
+
+/// Result of `BackupManager::compare`: how one backup of a file differs from another.
+#[derive(Debug)]
+pub struct CompareReport {
+    pub old: PathBuf,
+    pub new: PathBuf,
+    /// SHA-256 of each backup's content (decompressed, decrypted, ... as needed).
+    pub old_sha256: String,
+    pub new_sha256: String,
+    pub changes: DiffChanges,
+}
+
+#[derive(Debug)]
+pub enum DiffChanges {
+    /// Both sides have the exact same content.
+    Identical,
+    /// Both sides are text: a unified diff from the old side (the backup) to the new one.
+    Text(String),
+    /// At least one side is binary.
+    Binary {
+        /// Differing byte ranges as (start, end) offsets, end exclusive. Bytes past the end
+        /// of the shorter side count as differing.
+        ranges: Vec<(u64, u64)>,
+        /// Total bytes covered by `ranges`.
+        changed: u64,
+    },
+}
+
+/// Outcome of checking one backup in `BackupManager::verify_all`.
+#[derive(Debug)]
+pub enum AuditStatus {
+    /// Matches its recorded checksum (or, without one, its source).
+    Ok,
+    /// Does not match its recorded checksum.
+    Corrupt,
+    /// No checksum recorded and differs from the live source.
+    Stale,
+    /// No checksum recorded and no source to compare with.
+    Orphaned,
+    /// Could not be read.
+    Error(String),
+}
+
+/// One row of `BackupManager::verify_all`.
+#[derive(Debug)]
+pub struct AuditEntry {
+    pub backup: PathBuf,
+    /// The file it is a backup of, if that still exists.
+    pub source: Option<PathBuf>,
+    pub status: AuditStatus,
+}
+
+/// How one backup stands against its live file, as found by `BackupManager::audit`.
+#[derive(Debug)]
+pub enum SyncStatus {
+    /// Intact and holding what the live file has.
+    InSync,
+    /// The live file has changes the backup lacks.
+    Stale,
+    /// Intact as far as can be told, but its live file is gone.
+    MissingSource,
+    /// Does not match its recorded checksum.
+    Corrupt,
+    /// Could not be read.
+    Error(String),
+}
+
+impl SyncStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SyncStatus::InSync => "in-sync",
+            SyncStatus::Stale => "stale",
+            SyncStatus::MissingSource => "missing-source",
+            SyncStatus::Corrupt => "corrupt",
+            SyncStatus::Error(_) => "error",
+        }
+    }
+}
+
+/// One row of `BackupManager::audit`.
+#[derive(Debug)]
+pub struct SyncEntry {
+    pub backup: PathBuf,
+    /// The file it is a backup of, if that still exists.
+    pub source: Option<PathBuf>,
+    pub status: SyncStatus,
+}
+
+/// How many backups `BackupManager::audit` found in each state.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub in_sync: usize,
+    pub stale: usize,
+    pub missing_source: usize,
+    pub corrupt: usize,
+    pub errors: usize,
+}
+
+impl SyncReport {
+    pub fn total(&self) -> usize {
+        self.in_sync + self.stale + self.missing_source + self.corrupt + self.errors
+    }
+}
+
+/// How the newest backup of a file stands, as found by `BackupManager::check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// Matches its recorded checksum and the live file, and is recent enough.
+    Ok,
+    /// The live file has changed since.
+    Stale,
+    /// Does not match its recorded checksum.
+    Corrupt,
+    /// Older than the allowed age.
+    TooOld,
+    /// The file has no backup.
+    Missing,
+}
+
+impl CheckStatus {
+    /// Name used in the log and in JSON: "ok", "stale", "corrupt", "too-old", "missing".
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Stale => "stale",
+            CheckStatus::Corrupt => "corrupt",
+            CheckStatus::TooOld => "too-old",
+            CheckStatus::Missing => "missing",
+        }
+    }
+
+    /// What the status means, for messages.
+    pub fn describe(self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "the backup is current",
+            CheckStatus::Stale => "the file has changed since its backup",
+            CheckStatus::Corrupt => "the backup does not match its recorded checksum",
+            CheckStatus::TooOld => "the backup is older than allowed",
+            CheckStatus::Missing => "there is no backup",
+        }
+    }
+}
+
+/// Result of `BackupManager::check`.
+#[derive(Debug)]
+pub struct CheckReport {
+    pub source: PathBuf,
+    /// The newest backup, if there is one.
+    pub backup: Option<BackupEntry>,
+    /// Time since the newest backup was written.
+    pub age: Option<Duration>,
+    pub status: CheckStatus,
+}
+
+/// Backups whose source no longer exists, as found by `BackupManager::remove_orphans`.
+#[derive(Debug)]
+pub struct OrphanReport {
+    pub orphans: Vec<BackupEntry>,
+    /// False if nothing was found, the removal was declined, or this is a dry run.
+    pub removed: bool,
+}
+
+/// What `BackupManager::migrate` did (or would do) with the plain backups.
+#[derive(Debug, Default)]
+pub struct MigrateReport {
+    /// Each plain backup moved, with the version it became.
+    pub migrated: Vec<(PathBuf, PathBuf)>,
+    /// Plain backups left where they are, each with why: "directory", "delta-base" (an
+    /// incremental backup restores against it), "snapshot" (a snapshot labels it) or
+    /// "corrupt" (it does not match its checksum sidecar).
+    pub skipped: Vec<(PathBuf, &'static str)>,
+}
+
+/// Content in the store that `BackupManager::gc` found no deduplicated backup pointing to.
+#[derive(Debug)]
+pub struct GcReport {
+    pub store: PathBuf,
+    /// The unreferenced content; removed unless this is a dry run.
+    pub removed: Vec<BackupEntry>,
+    /// How many ".ref" backups point into the store.
+    pub references: usize,
+}
+
+/// Result of `BackupManager::status`: a file's backup state at a glance.
+#[derive(Debug)]
+pub struct StatusReport {
+    pub source: PathBuf,
+    pub source_exists: bool,
+    /// The newest backup, if there is one.
+    pub backup: Option<BackupEntry>,
+    /// Whether the source and the newest backup have the same content; `None` when either
+    /// is missing or the backup is encrypted.
+    pub matches: Option<bool>,
+    /// Time since the newest backup was written.
+    pub age: Option<Duration>,
+    /// The live file has changes the newest backup lacks (see `BackupManager::is_stale`).
+    pub stale: bool,
+}
+
+/// Which backups `BackupManager::prune` removes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PruneOptions {
+    /// Keep this many of the most recent backups. Along with `older_than` it is a floor,
+    /// kept whatever their age; left out there, the newest backup is kept.
+    pub keep: Option<usize>,
+    /// Remove only backups made longer ago than this, by the time in a version's name (the
+    /// modification time for a plain backup).
+    pub older_than: Option<Duration>,
+}
+
+/// What a prune removed (or would remove).
+#[derive(Debug)]
+pub struct PruneReport {
+    /// How many backups existed before pruning.
+    pub total: usize,
+    /// How many of the most recent were kept whatever their age.
+    pub keep: usize,
+    /// The backups removed (or to remove), newest first.
+    pub removed: Vec<BackupEntry>,
+    /// The backups left in place, newest first: the `keep` most recent, plus older ones a
+    /// kept delta needs or a snapshot labels.
+    pub kept: Vec<BackupEntry>,
+}
+
+/// What a delete did (or would do).
+#[derive(Debug)]
+pub struct DeleteReport {
+    pub path: PathBuf,
+    pub bytes: u64,
+    /// Where the file was (or would be) moved; `None` when purging or when it went to the
+    /// desktop's trash.
+    pub trashed: Option<PathBuf>,
+    /// The file went (or would go) to the desktop's trash.
+    pub system_trash: bool,
+    /// False when the confirmation was declined, or in a dry run.
+    pub deleted: bool,
+}
+
+/// What an undelete did (or would do).
+#[derive(Debug)]
+pub struct UndeleteReport {
+    /// The trashed copy that was brought back.
+    pub trashed: PathBuf,
+    pub target: PathBuf,
+}
+
+/// Which log entries `BackupManager::search_log` returns; every filter given must match.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogFilter<'a> {
+    /// Exactly this action, e.g. "delete".
+    pub action: Option<&'a str>,
+    /// A filename containing this.
+    pub file: Option<&'a str>,
+    /// Exactly this outcome, e.g. "success" or "failure".
+    pub outcome: Option<&'a str>,
+    /// Written at or after this time.
+    pub since: Option<chrono::NaiveDateTime>,
+    /// Written before this time.
+    pub until: Option<chrono::NaiveDateTime>,
+    /// Only the last this many of the matching entries.
+    pub tail: Option<usize>,
+}
+
+impl LogFilter<'_> {
+    fn matches(&self, entry: &LogEntry, cfg: &Config) -> bool {
+        let dated = self.since.is_some() || self.until.is_some();
+        let in_range = |t: chrono::NaiveDateTime| {
+            self.since.is_none_or(|since| t >= since) && self.until.is_none_or(|until| t < until)
+        };
+        self.action.is_none_or(|a| entry.action == a)
+            && self.file.is_none_or(|f| entry.filename.contains(f))
+            && self.outcome.is_none_or(|o| entry.outcome == o)
+            && (!dated || entry.time(cfg).is_some_and(in_range))
+    }
+}
+
+/// What an undo did (or would do).
+#[derive(Debug)]
+pub struct UndoReport {
+    /// The operation that was reversed: "delete" or "restore".
+    pub action: &'static str,
+    pub file: PathBuf,
+    /// The copy the file's previous content came back from; `None` if a restore created
+    /// the file, which is then moved to the trash (`trashed`) instead.
+    pub from: Option<PathBuf>,
+    pub trashed: Option<PathBuf>,
+}
+
+/// Numbers over the whole log (rotated files included), from `BackupManager::log_report`.
+#[derive(Debug, Default)]
+pub struct LogReport {
+    pub entries: usize,
+    /// Timestamps of the oldest and the newest entry.
+    pub first: Option<String>,
+    pub last: Option<String>,
+    /// One row per action, most frequent first.
+    pub actions: Vec<ActionStats>,
+    /// The files backed up successfully most often, with how many times, most often first.
+    pub top_backups: Vec<(String, usize)>,
+    /// Sizes summed over the successful backups that logged one. Sizes are logged rounded
+    /// ("1.5 MB"), so this is approximate.
+    pub bytes_backed_up: u64,
+}
+
+/// How one action fared in the log. Entries with other outcomes (skipped, unchanged,
+/// dry-run, ...) count towards `entries` only.
+#[derive(Debug, Default)]
+pub struct ActionStats {
+    pub action: String,
+    pub entries: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+// ---------- Operations (backup/restore/delete) ----------
+
+/// The backup operations, for use from other programs. Filenames are resolved with
+/// `resolve_safe_path` under `base`. Nothing is printed and stdin is never read;
+/// every operation is logged through `log_action` and returns a report instead. Logging is
+/// best effort: an entry that cannot be written is a warning, never the operation's error.
+pub struct BackupManager {
+    base: PathBuf,
+    config: Config,
+    progress: Option<Box<ProgressFn>>,
+    dir_progress: Option<Arc<DirProgressFn>>,
+    passphrase: Option<Box<PassphraseFn>>,
+    sftp_password: Option<Box<SftpPasswordFn>>,
+    digests: ChecksumCache,
+}
+
+impl BackupManager {
+    /// Operate on files under `base`; a relative `backup_dir` is taken relative to it too.
+    pub fn new(base: impl Into<PathBuf>, config: Config) -> Self {
+        let base = base.into();
+        BackupManager {
+            digests: ChecksumCache::new(&base, config.checksum_cache),
+            base,
+            config,
+            progress: None,
+            dir_progress: None,
+            passphrase: None,
+            sftp_password: None,
+        }
+    }
+
+    /// Have every file copy report its progress to `report` (bytes done, bytes total).
+    pub fn set_progress(&mut self, report: impl Fn(u64, u64) + Send + Sync + 'static) {
+        self.progress = Some(Box::new(report));
+    }
+
+    fn progress(&self) -> Option<&ProgressFn> {
+        self.progress.as_deref()
+    }
+
+    /// Have directory backups report their overall progress to `report` instead of each
+    /// file's to the `set_progress` callback.
+    pub fn set_dir_progress(&mut self, report: impl Fn(DirProgress) + Send + Sync + 'static) {
+        self.dir_progress = Some(Arc::new(report));
+    }
+
+    /// Ask `prompt` for the passphrase whenever a backup is encrypted or decrypted.
+    /// Without one, encrypted backups cannot be made or restored.
+    pub fn set_passphrase_prompt(
+        &mut self,
+        prompt: impl Fn(&Path, bool) -> io::Result<String> + Send + Sync + 'static,
+    ) {
+        self.passphrase = Some(Box::new(prompt));
+    }
+
+    /// Ask `prompt` for the password of "user@host" when an SFTP server accepts none of the
+    /// keys (see `Config::sftp_identity`). Without one, only key authentication is tried.
+    pub fn set_sftp_password_prompt(
+        &mut self,
+        prompt: impl Fn(&str) -> io::Result<String> + Send + Sync + 'static,
+    ) {
+        self.sftp_password = Some(Box::new(prompt));
+    }
+
+    /// How to log in to SFTP servers.
+    fn sftp_auth(&self) -> SftpAuth<'_> {
+        SftpAuth {
+            identity: self.config.sftp_identity.as_deref(),
+            password: self.sftp_password.as_deref(),
+        }
+    }
+
+    /// The passphrase for the encrypted backup at `backup`, wiped once dropped.
+    fn passphrase(&self, backup: &Path, new: bool) -> Result<Zeroizing<String>, BackupError> {
+        let Some(prompt) = &self.passphrase else {
+            return Err(BackupError::InvalidInput(
+                "Encrypted backups need a passphrase, but none can be asked for".to_string(),
+            ));
+        };
+        let passphrase = Zeroizing::new(prompt(backup, new)?);
+        if passphrase.is_empty() {
+            return Err(BackupError::InvalidInput("The passphrase must not be empty".to_string()));
+        }
+        Ok(passphrase)
+    }
+
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Keep other processes from working in the base directory until the lock is dropped,
+    /// so that a cron job and a shell cannot change the same files at once. If another
+    /// process holds it, fail with `BackupError::Locked`, or with `wait` call `waiting`
+    /// (with its PID, when known) and block until it is released.
+    pub fn lock(
+        &self,
+        wait: bool,
+        waiting: impl FnOnce(Option<u32>),
+    ) -> Result<BaseLock, BackupError> {
+        lock_base(&self.base, wait, waiting)
+    }
+
+    /// The files under `base` matching a glob pattern, relative to `base`.
+    pub fn expand(&self, pattern: &str) -> Result<Vec<PathBuf>, BackupError> {
+        expand_glob(&self.base, pattern)
+    }
+
+    /// The files with backups matching a glob pattern, relative to `base`, including files
+    /// that have since been deleted.
+    pub fn expand_backed_up(&self, pattern: &str) -> Result<Vec<PathBuf>, BackupError> {
+        expand_glob_in_backups(&self.config, &self.base, pattern)
+    }
+
+    /// Refuse to write `backup` if its filesystem has less than `needed` bytes free, rather
+    /// than fail halfway through the copy. Call after `ensure_backup_dir`.
+    fn ensure_space(&self, backup: &Path, needed: u64) -> Result<(), BackupError> {
+        let dir = backup.parent().unwrap_or(&self.base);
+        let available = fs2::available_space(dir)?;
+        debug!("{} bytes free in {}, {} needed", available, dir.display(), needed);
+        if available < needed {
+            return Err(BackupError::InsufficientSpace {
+                path: backup.to_path_buf(),
+                needed,
+                available,
+            });
+        }
+        Ok(())
+    }
+
+    /// Run `copy`, trying again after a growing delay while it fails with a transient I/O
+    /// error, up to `copy_attempts` times in all. Each retry is logged against `filename`.
+    fn with_retries<T>(
+        &self,
+        filename: &str,
+        mut copy: impl FnMut() -> Result<T, BackupError>,
+    ) -> Result<T, BackupError> {
+        let cfg = &self.config;
+        let mut delay = Duration::from_millis(cfg.retry_delay_ms);
+        let mut attempt = 1;
+        loop {
+            match copy() {
+                Err(BackupError::Io(e)) if attempt < cfg.copy_attempts && is_transient(&e) => {
+                    attempt += 1;
+                    let detail = format!(
+                        "attempt {} of {} in {}ms | {}",
+                        attempt,
+                        cfg.copy_attempts,
+                        delay.as_millis(),
+                        e
+                    );
+                    debug!("transient error, retrying: {}", detail);
+                    log_action(cfg, "retry", filename, "transient-error", Some(&detail));
+                    std::thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// If another process holds `path` open so that it cannot be read (even after the
+    /// `copy_attempts`), log it and either fail with `InUse` or, with `shared_read`, read it
+    /// into a temporary copy in the trash to back up instead. `None` when it can be read as
+    /// usual, always so on Unix.
+    fn stage_if_in_use(
+        &self,
+        filename: &str,
+        path: &Path,
+        options: BackupOptions,
+    ) -> Result<Option<NamedTempFile>, BackupError> {
+        let cfg = &self.config;
+        let open = || Ok(fs::File::open(path).map(drop)?);
+        match self.with_retries(filename, open) {
+            Err(BackupError::Io(e)) if is_sharing_violation(&e) => {
+                debug!("{} is held by another process: {}", path.display(), e);
+            }
+            // Anything else shows when the file is read.
+            _ => return Ok(None),
+        }
+        if !options.shared_read {
+            log_action(cfg, "backup", filename, "in-use", Some("sharing violation"));
+            return Err(BackupError::InUse(path.to_path_buf()));
+        }
+        let trash = self.base.join(TRASH_DIR);
+        fs::create_dir_all(&trash)?;
+        match stage_shared(path, &trash) {
+            Ok(staged) => {
+                log_action(cfg, "backup", filename, "in-use", Some("read shared"));
+                Ok(Some(staged))
+            }
+            Err(e) if is_sharing_violation(&e) => {
+                log_action(cfg, "backup", filename, "in-use", Some("shared read refused"));
+                Err(BackupError::InUse(path.to_path_buf()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Run `operation` on `input` between the `pre_hook` and the `post_hook`. A `pre_hook`
+    /// that fails stops it with `HookFailed`; the `post_hook` runs whether it worked or not,
+    /// and when it fails that is only logged.
+    fn with_hooks<T>(
+        &self,
+        operation: &'static str,
+        input: &OsStr,
+        run: impl FnOnce() -> Result<T, BackupError>,
+    ) -> Result<T, BackupError> {
+        let cfg = &self.config;
+        let filename = &*input.to_string_lossy();
+        if let Some(command) = &cfg.pre_hook {
+            if let Err(reason) = self.run_hook(Hook::Pre, command, operation, filename, None) {
+                return Err(BackupError::HookFailed { operation, reason });
+            }
+        }
+        let result = run();
+        if let Some(command) = &cfg.post_hook {
+            let outcome = if result.is_ok() { "success" } else { "failure" };
+            let _ = self.run_hook(Hook::Post, command, operation, filename, Some(outcome));
+        }
+        result
+    }
+
+    /// Run one hook and log how it went; `Err` says why it failed, for `HookFailed`. In a
+    /// dry run the hook is only logged.
+    fn run_hook(
+        &self,
+        hook: Hook,
+        command: &str,
+        operation: &str,
+        filename: &str,
+        outcome: Option<&str>,
+    ) -> Result<(), String> {
+        let cfg = &self.config;
+        if cfg.dry_run {
+            let detail = format!("{} | {}", hook.key(), operation);
+            log_action(cfg, "hook", filename, "dry-run", Some(&detail));
+            return Ok(());
+        }
+        let failed = match run_hook(command, &self.base, hook, operation, filename, outcome) {
+            Ok(status) if status.success() => None,
+            Ok(status) => Some(match status.code() {
+                Some(code) => format!("exited with {}", code),
+                None => "was killed by a signal".to_string(),
+            }),
+            Err(e) => Some(format!("could not be run: {}", e)),
+        };
+        debug!("{} for {} of {}: {:?}", hook.key(), operation, filename, failed);
+        let status = failed.as_deref().unwrap_or("exit 0");
+        let detail = format!("{} | {} | {}", hook.key(), operation, status);
+        let outcome = if failed.is_some() { "failure" } else { "success" };
+        log_action(cfg, "hook", filename, outcome, Some(&detail));
+        failed.map_or(Ok(()), Err)
+    }
+
+    /// Refuse `path` if it only exists under a differently cased name, rather than quietly
+    /// work on that file (see `case_variant`).
+    fn check_case(&self, path: &Path) -> Result<(), BackupError> {
+        match case_variant(path)? {
+            Some(existing) => {
+                Err(BackupError::CaseMismatch { requested: path.to_path_buf(), existing })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Resolve a file named by the user with `resolve_safe_os_path`, or, with
+    /// `allow_absolute` set, let an absolute path through as well; each such use is logged.
+    fn resolve_source(&self, input: &OsStr) -> Result<PathBuf, BackupError> {
+        let cfg = &self.config;
+        let path = resolve_os_path(&self.base, input, cfg.allow_absolute)?;
+        let trimmed = input.to_str().map_or(input, |s| OsStr::new(s.trim()));
+        if cfg.allow_absolute && Path::new(trimmed).is_absolute() {
+            let detail = path.display().to_string();
+            log_action(cfg, "allow-absolute", &input.to_string_lossy(), "used", Some(&detail));
+        }
+        Ok(path)
+    }
+
+    /// Refuse `path` if it is a symlink, unless `follow_symlinks` is set: its target could
+    /// be anywhere, outside the base included.
+    fn check_symlink(&self, path: &Path) -> Result<(), BackupError> {
+        if path.is_symlink() && !self.config.follow_symlinks {
+            return Err(BackupError::Symlink(path.to_path_buf()));
+        }
+        Ok(())
+    }
+
+    /// The config to find backups in `dir` with, as if it were the backup directory:
+    /// `restore --from`. Fails unless `dir` is an existing directory.
+    fn backup_set(&self, dir: &str) -> Result<Config, BackupError> {
+        let path = self.base.join(dir.trim());
+        if !path.is_dir() {
+            return Err(BackupError::NotFound(format!(
+                "Backup directory {} not found",
+                path.display()
+            )));
+        }
+        debug!("looking for backups in {}", path.display());
+        Ok(Config { backup_dir: Some(path), versions_dir: None, ..self.config.clone() })
+    }
+
+    /// `restore --from sftp://...`: download the file's backups, as `upload` named them,
+    /// into a temporary directory to be looked up there like a `backup_set`.
+    fn remote_set(&self, url: &str, file: &Path) -> Result<(TempDir, Config), BackupError> {
+        let remote = SftpDir::parse(url)?;
+        let dir = tempfile::tempdir()?;
+        let name = flat_name(&self.base, file);
+        remote.download(&name, dir.path(), self.sftp_auth())?;
+        debug!("fetched the backups of {} from {}", file.display(), remote);
+        let backup_dir = Some(dir.path().to_path_buf());
+        let cfg = Config {
+            backup_dir,
+            preserve_structure: false,
+            versions_dir: None,
+            ..self.config.clone()
+        };
+        Ok((dir, cfg))
+    }
+
+    /// Upload `backup` and its sidecars to `remote`, named as in a backup directory
+    /// ("sub%2Fnotes.txt.bak", versions as "sub%2Fnotes.txt.<stamp>.bak"), so files of the
+    /// same name from different folders do not collide and `restore --from` finds them.
+    fn upload(
+        &self,
+        filename: &str,
+        source: &Path,
+        backup: &Path,
+        remote: &SftpDir,
+    ) -> Result<(), BackupError> {
+        let cfg = &self.config;
+        let (_, local) = backup_location(cfg, &self.base, source);
+        let flat = flat_name(&self.base, source);
+        let name = file_name_of(backup);
+        let in_versions_dir = versions_dir_for(cfg, &self.base, source)
+            .is_some_and(|dir| backup.parent() == Some(dir.as_path()));
+        let remote_name = match name.strip_prefix(&local) {
+            Some(rest) if !in_versions_dir => format!("{}{}", flat, rest),
+            _ => format!("{}.{}", flat, name),
+        };
+        let mut files = vec![(backup, remote_name.clone())];
+        let sidecars = [(sidecar_path_for(backup), ".sha256"), (meta_path_for(backup), ".meta")];
+        for (sidecar, ext) in &sidecars {
+            if sidecar.is_file() {
+                files.push((sidecar, format!("{}{}", remote_name, ext)));
+            }
+        }
+        remote.upload(&files, self.sftp_auth())?;
+        let detail = format!("{} -> {}", remote_name, remote);
+        log_action(cfg, "upload", filename, "success", Some(&detail));
+        Ok(())
+    }
+
+    /// Whether the live file has changes `backup` lacks: its content differs or, where the
+    /// two cannot be compared (an encrypted backup), it was modified after the backup was
+    /// written. A file that is gone is not stale.
+    pub fn is_stale(
+        &self,
+        filename: impl AsRef<OsStr>,
+        backup: &BackupEntry,
+    ) -> Result<bool, BackupError> {
+        let path = self.resolve_source(filename.as_ref())?;
+        Ok(self.stale(&path, backup)?)
+    }
+
+    /// When `backup` was made: the time in its version stamp, or its modification time if
+    /// it has none. `prune` goes by it.
+    pub fn made_at(&self, backup: &BackupEntry) -> SystemTime {
+        backup_time(&self.config, backup)
+    }
+
+    fn stale(&self, file: &Path, backup: &BackupEntry) -> io::Result<bool> {
+        if !file.is_file() {
+            return Ok(false);
+        }
+        if backup.path.is_file() && !is_enc(&backup.path) {
+            return Ok(!self.same_as_backup(file, &backup.path)?);
+        }
+        Ok(fs::metadata(file)?.modified()? > backup.modified)
+    }
+
+    /// True if `file` has the content of `backup`, by SHA-256 through the checksum cache.
+    fn same_as_backup(&self, file: &Path, backup: &Path) -> io::Result<bool> {
+        Ok(self.digests.file(HashAlgorithm::Sha256, file)? == self.digests.content(backup)?)
+    }
+
+    /// Create the configured backup directory if it does not exist yet, and with
+    /// `create_parents` the folders of `backup` under it (as `preserve_structure` needs).
+    /// Without, a missing folder is refused with a hint instead of failing the copy.
+    fn ensure_backup_dir(&self, backup: &Path) -> Result<(), BackupError> {
+        let (Some(dir), Some(folder)) = (&self.config.backup_dir, backup.parent()) else {
+            return Ok(());
+        };
+        let root = self.base.join(dir);
+        fs::create_dir_all(&root)?;
+        if folder.is_dir() {
+            return Ok(());
+        }
+        if !self.config.create_parents {
+            return Err(BackupError::NotFound(format!(
+                "The backup folder {} does not exist; use --parents to create it",
+                folder.display()
+            )));
+        }
+        if !folder.starts_with(&root) || !stays_inside(&root, folder)? {
+            return Err(BackupError::PathRejected(format!(
+                "{} is outside the backup directory",
+                folder.display()
+            )));
+        }
+        fs::create_dir_all(folder)?;
+        Ok(())
+    }
+
+    /// With `durable`, flush a new backup's sidecars to disk and then the directories the
+    /// backup and, for a deduplicated one, its stored content were renamed into. The content
+    /// itself was flushed before the rename.
+    fn sync_backup(&self, backup: &Path) -> io::Result<()> {
+        if !self.config.durable {
+            return Ok(());
+        }
+        for sidecar in [sidecar_path_for(backup), meta_path_for(backup), state_path_for(backup)] {
+            if sidecar.is_file() {
+                sync_file(&sidecar)?;
+            }
+        }
+        if is_ref(backup) {
+            if let Some(folder) = ref_blob(backup)?.parent() {
+                sync_dir(folder)?;
+            }
+        }
+        match backup.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+            _ => sync_dir(Path::new(".")),
+        }
+    }
+
+    /// Back up a file. With `versioned`, every run keeps a new timestamped copy
+    /// instead of overwriting the single ".bak". With `compress`, the backup is
+    /// gzip-compressed and gets an extra ".gz" suffix; with `encrypt`, it is encrypted with
+    /// AES-256-GCM under an Argon2-derived key and gets an extra ".enc" suffix. With
+    /// `remote`, the backup (a new one or the current one) is then also uploaded there;
+    /// with `snapshot`, it is labelled for `rollback`. Runs between the `pre_hook` and the
+    /// `post_hook`.
+    pub fn backup(
+        &self,
+        filename: impl AsRef<OsStr>,
+        options: BackupOptions,
+    ) -> Result<BackupReport, BackupError> {
+        let input = filename.as_ref();
+        self.with_hooks("backup", input, || self.run_backup(input, options))
+    }
+
+    fn run_backup(
+        &self,
+        input: &OsStr,
+        options: BackupOptions,
+    ) -> Result<BackupReport, BackupError> {
+        if options.keep == Some(0) {
+            return Err(BackupError::InvalidInput("Keep at least 1 backup".to_string()));
+        }
+        if let Some(name) = options.snapshot {
+            check_snapshot_name(name)?;
+            if !options.versioned {
+                return Err(BackupError::InvalidInput(
+                    "Only versioned backups can be labelled with a snapshot".to_string(),
+                ));
+            }
+        }
+        let report = match options.remote {
+            Some(url) => self.backup_remote(input, url, options)?,
+            None => self.backup_local(input, options)?,
+        };
+        if let Some(name) = options.snapshot.filter(|_| !self.config.dry_run) {
+            if let BackupReport::File { source, backup, .. }
+            | BackupReport::Unchanged { source, backup } = &report
+            {
+                label_backup(&self.base, name, source, backup)?;
+                let detail = format!("{} | {}", name, file_name_of(backup));
+                let filename = &*input.to_string_lossy();
+                log_action(&self.config, "snapshot", filename, "labelled", Some(&detail));
+            }
+        }
+        let keep = options.keep.or(self.config.keep_versions).filter(|_| options.versioned);
+        if let (Some(keep), BackupReport::File { .. }) = (keep, &report) {
+            self.auto_prune(input, keep);
+        }
+        if let BackupReport::File { source, backup, sha256, .. } = &report {
+            if !self.config.dry_run {
+                self.index_backup(input, source, backup, sha256);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Record a new backup in the index of newest backups. A failure is logged as a
+    /// warning: `status` then finds the backup by looking for it.
+    fn index_backup(&self, input: &OsStr, source: &Path, backup: &Path, sha256: &str) {
+        let record = || {
+            let meta = fs::metadata(backup)?;
+            let (size, modified) = (meta.len(), meta.modified()?);
+            let entry = BackupEntry { path: backup.to_path_buf(), size, modified };
+            record_backup(&self.config, &self.base, source, &entry, sha256)
+        };
+        if let Err(e) = record() {
+            let filename = &*input.to_string_lossy();
+            debug!("indexing the backup of {} failed: {}", filename, e);
+            log_action(&self.config, "index", filename, "warning", Some(&e.to_string()));
+        }
+    }
+
+    /// The newest backup of `file`: from the index when it is up to date, else by looking
+    /// through its backups.
+    fn newest_backup(&self, file: &Path) -> io::Result<Option<BackupEntry>> {
+        if let Some(backup) = indexed_backup(&self.config, &self.base, file) {
+            return Ok(Some(backup));
+        }
+        Ok(find_backups(&self.config, &self.base, file)?.into_iter().next())
+    }
+
+    /// Rebuild the index of newest backups from the backups on disk, for `status` and
+    /// `check` to answer without looking through them. Returns how many files it holds.
+    pub fn reindex(&self) -> Result<usize, BackupError> {
+        let cfg = &self.config;
+        let target = self.scan_target();
+        let sources: BTreeSet<PathBuf> = all_backups(cfg, &self.base)?
+            .iter()
+            .filter_map(|backup| source_for_any_backup(cfg, &self.base, backup))
+            .collect();
+        let mut entries = Vec::new();
+        for source in sources {
+            let Some(newest) = find_backups(cfg, &self.base, &source)?.into_iter().next() else {
+                continue;
+            };
+            let sha256 = match is_enc(&newest.path) {
+                true => None,
+                false => Some(self.digests.content(&newest.path)?),
+            };
+            entries.push((source, newest, sha256));
+        }
+        if !cfg.dry_run {
+            rebuild_index(cfg, &self.base, &entries)?;
+        }
+        let outcome = if cfg.dry_run { "dry-run" } else { "success" };
+        let detail = format!("{} files", entries.len());
+        log_action(cfg, "reindex", &target, outcome, Some(&detail));
+        Ok(entries.len())
+    }
+
+    /// Prune after a new version: a failure is logged as a warning and leaves the new
+    /// backup in place.
+    fn auto_prune(&self, input: &OsStr, keep: usize) {
+        let options = PruneOptions { keep: Some(keep), ..PruneOptions::default() };
+        if let Err(e) = self.prune(input, options) {
+            let filename = &*input.to_string_lossy();
+            debug!("pruning {} failed: {}", filename, e);
+            log_action(&self.config, "prune", filename, "warning", Some(&e.to_string()));
+        }
+    }
+
+    /// `backup` with `remote`: back up locally, then upload.
+    fn backup_remote(
+        &self,
+        input: &OsStr,
+        url: &str,
+        options: BackupOptions,
+    ) -> Result<BackupReport, BackupError> {
+        let remote = SftpDir::parse(url)?;
+        if options.dedup || options.incremental || self.resolve_source(input)?.is_dir() {
+            return Err(BackupError::InvalidInput(
+                "Only file backups that are not deduplicated or incremental can be uploaded"
+                    .to_string(),
+            ));
+        }
+        let report = self.backup_local(input, options)?;
+        let (source, backup) = match &report {
+            BackupReport::File { source, backup, .. }
+            | BackupReport::Unchanged { source, backup } => (source, backup),
+            _ => return Ok(report),
+        };
+        let filename = &*input.to_string_lossy();
+        if self.config.dry_run {
+            log_action(&self.config, "upload", filename, "dry-run", None);
+        } else if is_ref(backup) || is_delta(backup) {
+            return Err(BackupError::InvalidInput(format!(
+                "{} needs the files beside it and cannot be uploaded",
+                backup.display()
+            )));
+        } else {
+            self.upload(filename, source, backup, &remote)?;
+        }
+        Ok(report)
+    }
+
+    fn backup_local(
+        &self,
+        input: &OsStr,
+        options: BackupOptions,
+    ) -> Result<BackupReport, BackupError> {
+        let started = Instant::now();
+        let cfg = &self.config;
+        let filename = &*input.to_string_lossy();
+        let path = self.resolve_source(input)?;
+        self.check_symlink(&path)?;
+        if !path.exists() {
+            return Err(BackupError::NotFound("Source file does not exist".to_string()));
+        }
+        self.check_case(&path)?;
+        if !options.allow_backup {
+            if let Some(reason) = self.skip_reason(&path) {
+                log_action(cfg, "backup", filename, "skipped", Some(reason));
+                return Ok(BackupReport::Skipped { source: path, reason });
+            }
+        }
+        if path.is_dir() {
+            if options.versioned || options.compress || options.encrypt || options.dedup {
+                return Err(BackupError::InvalidInput(
+                    "Directory backups cannot be versioned, compressed, encrypted or deduplicated"
+                        .to_string(),
+                ));
+            }
+            return self.backup_directory(filename, &path, options);
+        }
+        if options.since.is_some() {
+            return Err(BackupError::InvalidInput(
+                "Only directory backups can be limited to files modified since a time"
+                    .to_string(),
+            ));
+        }
+        if !path.is_file() {
+            return Err(BackupError::NotRegularFile(path));
+        }
+        let mut options = self.with_extension_rule(&path, options);
+        if options.compress && options.encrypt {
+            return Err(BackupError::InvalidInput(
+                "A backup can be compressed or encrypted, not both".to_string(),
+            ));
+        }
+        if options.incremental && (options.versioned || options.compress || options.encrypt) {
+            return Err(BackupError::InvalidInput(
+                "An incremental backup cannot also be versioned, compressed or encrypted"
+                    .to_string(),
+            ));
+        }
+        if options.dedup && (options.compress || options.encrypt || options.incremental) {
+            return Err(BackupError::InvalidInput(
+                "A deduplicated backup cannot also be compressed, encrypted or incremental"
+                    .to_string(),
+            ));
+        }
+        let packed = options.compress || options.encrypt || options.dedup || options.incremental;
+        if options.sparse && packed {
+            return Err(BackupError::InvalidInput(
+                "A sparse backup cannot also be compressed, encrypted, deduplicated or incremental"
+                    .to_string(),
+            ));
+        }
+        if options.hardlink_unchanged && (options.compress || options.encrypt || options.dedup) {
+            return Err(BackupError::InvalidInput(
+                "A hardlinked backup cannot also be compressed, encrypted or deduplicated"
+                    .to_string(),
+            ));
+        }
+        let bytes = fs::metadata(&path)?.len();
+        if let Some(limit) = cfg.max_size.filter(|&limit| bytes > limit) {
+            return Err(BackupError::TooLarge { path, size: bytes, limit });
+        }
+        // The content is read from `source`: the file itself, or a copy of it if another
+        // process holds it open.
+        let staged = self.stage_if_in_use(filename, &path, options)?;
+        let source = staged.as_ref().map_or(path.as_path(), |staged| staged.path());
+
+        let plain = backup_path_for(cfg, &self.base, &path);
+        refuse_backup_onto_source(&path, &plain)?;
+
+        let mut link_to = None;
+        if !options.force {
+            if let Some(current) = self.current_backup(&path, source, options)? {
+                let packed = is_gz(&current) || is_enc(&current) || is_delta(&current);
+                let plain = !packed && !is_ref(&current);
+                if !(options.versioned && options.hardlink_unchanged && plain) {
+                    log_action(cfg, "backup", filename, "unchanged", None);
+                    return Ok(BackupReport::Unchanged { source: path, backup: current });
+                }
+                link_to = Some(current);
+            }
+        }
+
+        let delta_base = (options.incremental && plain.is_file()).then(|| plain.clone());
+        let packed = |backup: PathBuf| match options {
+            BackupOptions { compress: true, .. } => gz_path_for(&backup),
+            BackupOptions { encrypt: true, .. } => enc_path_for(&backup),
+            BackupOptions { dedup: true, .. } => ref_path_for(&backup),
+            _ => backup,
+        };
+        let mut backup = packed(if options.versioned {
+            versioned_backup_path_for(cfg, &self.base, &path)
+        } else if delta_base.is_some() {
+            delta_backup_path_for(cfg, &self.base, &path)
+        } else {
+            plain
+        });
+        if !options.versioned && delta_base.is_none() && backup.exists() {
+            let policy = cfg.existing_backup;
+            debug!("{} exists; existing_backup is {}", backup.display(), policy.name());
+            match policy {
+                ExistingBackup::Overwrite => {}
+                ExistingBackup::Skip => {
+                    let detail = format!("exists | {}", file_name_of(&backup));
+                    log_action(cfg, "backup", filename, "skipped", Some(&detail));
+                    return Ok(BackupReport::Skipped { source: path, reason: "exists" });
+                }
+                ExistingBackup::Error => return Err(BackupError::BackupExists(backup)),
+                ExistingBackup::Version => {
+                    let version = packed(versioned_backup_path_for(cfg, &self.base, &path));
+                    let detail = format!("version | {}", file_name_of(&version));
+                    log_action(cfg, "backup", filename, "exists", Some(&detail));
+                    options.versioned = true;
+                    backup = version;
+                }
+            }
+        }
+        refuse_backup_onto_source(&path, &backup)?;
+        debug!("backup target {}", backup.display());
+        if cfg.dry_run {
+            let sha256 = sha256_file(source)?;
+            log_action(cfg, "backup", filename, "dry-run", None);
+            return Ok(BackupReport::File {
+                source: path,
+                backup,
+                bytes,
+                compressed: None,
+                allocated: None,
+                sha256,
+                linked: None,
+                elapsed: started.elapsed(),
+            });
+        }
+        self.ensure_backup_dir(&backup)?;
+        if options.versioned {
+            if let Some(folder) = versions_dir_for(cfg, &self.base, &path) {
+                fs::create_dir_all(folder)?;
+            }
+        }
+        self.ensure_space(&backup, bytes)?;
+        if let Some(base) = delta_base {
+            let delta = write_delta(source, &base, &backup, self.progress())?;
+            let base_name = base.display();
+            debug!("stored {} of {} blocks against {}", delta.changed, delta.blocks, base_name);
+            let sha256 = check_backup_matches(source, &backup)?;
+            debug!("checksum verified after rebuilding: {}", sha256);
+            write_checksum_sidecar(&backup, cfg.hash)?;
+            write_metadata_sidecar(&path, &backup)?;
+            if options.preserve_mtime {
+                copy_mtime(&path, &backup)?;
+            }
+            self.sync_backup(&backup)?;
+            let elapsed = started.elapsed();
+            let stats = transfer_stats(bytes, elapsed).join(" | ");
+            let (name, changed, blocks) = (file_name_of(&backup), delta.changed, delta.blocks);
+            let detail = format!("{} | {} of {} blocks | {}", name, changed, blocks, stats);
+            log_action(cfg, "backup-delta", filename, "success", Some(&detail));
+            return Ok(BackupReport::Delta {
+                source: path,
+                stored: fs::metadata(&backup)?.len(),
+                backup,
+                base,
+                bytes,
+                changed_blocks: delta.changed,
+                total_blocks: delta.blocks,
+                sha256,
+                elapsed,
+            });
+        }
+        if options.compress {
+            let level = cfg.compress_level;
+            let (original, compressed) = compress_file(source, &backup, level, self.progress())?;
+            debug!("compressed {} -> {} bytes at level {}", original, compressed, level);
+            let sha256 = check_backup_matches(source, &backup)?;
+            debug!("checksum verified: {}", sha256);
+            write_checksum_sidecar(&backup, cfg.hash)?;
+            write_metadata_sidecar(&path, &backup)?;
+            if options.preserve_mtime {
+                copy_mtime(&path, &backup)?;
+            }
+            self.sync_backup(&backup)?;
+            let elapsed = started.elapsed();
+            let ratio = if original == 0 { 1.0 } else { compressed as f64 / original as f64 };
+            let stats = transfer_stats(original, elapsed).join(" | ");
+            let detail = format!(
+                "{} | {} -> {} | ratio {:.2} | level {} | {}",
+                file_name_of(&backup),
+                format_size(original),
+                format_size(compressed),
+                ratio,
+                level,
+                stats
+            );
+            log_action(cfg, "backup-gz", filename, "success", Some(&detail));
+            return Ok(BackupReport::File {
+                source: path,
+                backup,
+                bytes: original,
+                compressed: Some(compressed),
+                allocated: None,
+                sha256,
+                linked: None,
+                elapsed,
+            });
+        }
+        if options.dedup {
+            let store = store_root(cfg, &self.base);
+            let buffer = cfg.copy_buffer_size;
+            let stats = write_ref(source, &store, &backup, buffer, self.progress())?;
+            let how = if stats.stored { "stored" } else { "shared" };
+            debug!("{} content {} in {}", how, stats.sha256, store.display());
+            let sha256 = check_backup_matches(source, &backup)?;
+            write_checksum_sidecar(&backup, cfg.hash)?;
+            write_metadata_sidecar(&path, &backup)?;
+            if options.preserve_mtime {
+                copy_mtime(&path, &backup)?;
+            }
+            self.sync_backup(&backup)?;
+            let elapsed = started.elapsed();
+            let detail = format!(
+                "{} | {} {} | {}",
+                file_name_of(&backup),
+                how,
+                &sha256[..12],
+                transfer_stats(bytes, elapsed).join(" | ")
+            );
+            log_action(cfg, "backup-dedup", filename, "success", Some(&detail));
+            return Ok(BackupReport::File {
+                source: path,
+                backup,
+                bytes,
+                compressed: None,
+                allocated: None,
+                sha256,
+                linked: None,
+                elapsed,
+            });
+        }
+        if options.encrypt {
+            let passphrase = self.passphrase(&backup, true)?;
+            let copied = encrypt_file(source, &backup, &passphrase, self.progress())?;
+            debug!("encrypted {} bytes", copied);
+            let sha256 = sha256_file(source)?;
+            let decrypted = decrypt_to_vec(&backup, &passphrase, None)?;
+            if sha256_of(decrypted.as_slice())? != sha256 {
+                return Err(BackupError::Mismatch(backup));
+            }
+            debug!("checksum verified after decryption: {}", sha256);
+            write_checksum_sidecar(&backup, cfg.hash)?;
+            write_metadata_sidecar(&path, &backup)?;
+            if options.preserve_mtime {
+                copy_mtime(&path, &backup)?;
+            }
+            self.sync_backup(&backup)?;
+            let elapsed = started.elapsed();
+            let stats = transfer_stats(bytes, elapsed).join(" | ");
+            let detail = format!("{} | {}", file_name_of(&backup), stats);
+            log_action(cfg, "backup-enc", filename, "success", Some(&detail));
+            return Ok(BackupReport::File {
+                source: path,
+                backup,
+                bytes,
+                compressed: None,
+                allocated: None,
+                sha256,
+                linked: None,
+                elapsed,
+            });
+        }
+        let (sha256, linked) = match self.link_unchanged(source, link_to.as_deref(), &backup)? {
+            Some(sha256) => (sha256, link_to),
+            None => {
+                // Verified before it replaces the old backup, which survives any failure here.
+                let buffer = cfg.copy_buffer_size;
+                let sparse = options.sparse;
+                let copy = || copy_verified(source, &backup, buffer, sparse, self.progress());
+                let (copied, sha256) = self.with_retries(filename, copy)?;
+                debug!("copied {} bytes, checksum verified: {}", copied, sha256);
+                (sha256, None)
+            }
+        };
+        write_checksum_sidecar(&backup, cfg.hash)?;
+        write_metadata_sidecar(&path, &backup)?;
+        // A hardlink shares its time with the version it links to.
+        if options.preserve_mtime && linked.is_none() {
+            copy_mtime(&path, &backup)?;
+        }
+        self.sync_backup(&backup)?;
+        let elapsed = started.elapsed();
+        let allocated = match options.sparse && linked.is_none() {
+            true => allocated_size(&backup)?,
+            false => None,
+        };
+        let mut stats = transfer_stats(bytes, elapsed).join(" | ");
+        if let Some(allocated) = allocated {
+            stats = format!("{} | sparse, {} on disk", stats, format_size(allocated));
+        }
+        if options.versioned {
+            let how = match (&linked, options.hardlink_unchanged) {
+                (Some(previous), _) => format!(" | hardlink to {}", file_name_of(previous)),
+                (None, true) => " | copy".to_string(),
+                (None, false) => String::new(),
+            };
+            let detail = format!("{}{} | {}", file_name_of(&backup), how, stats);
+            log_action(cfg, "backup-versioned", filename, "success", Some(&detail));
+        } else {
+            log_action(cfg, "backup", filename, "success", Some(&stats));
+        }
+        Ok(BackupReport::File {
+            source: path,
+            backup,
+            bytes,
+            compressed: None,
+            allocated,
+            sha256,
+            linked,
+            elapsed,
+        })
+    }
+
+    /// Make `backup` a hardlink to the earlier backup `previous` if it still has `path`'s
+    /// content. Returns the SHA-256 if it did; `None` (and nothing changed) if there is no
+    /// `previous`, its content differs, or the filesystem cannot link it.
+    fn link_unchanged(
+        &self,
+        path: &Path,
+        previous: Option<&Path>,
+        backup: &Path,
+    ) -> io::Result<Option<String>> {
+        let Some(previous) = previous else {
+            return Ok(None);
+        };
+        let sha256 = sha256_file(path)?;
+        if sha256_file(previous)? != sha256 {
+            debug!("{} differs from {}, copying", previous.display(), path.display());
+            return Ok(None);
+        }
+        match link_atomically(previous, backup) {
+            Ok(()) => {
+                debug!("hardlinked {} -> {}", backup.display(), previous.display());
+                Ok(Some(sha256))
+            }
+            Err(e) => {
+                debug!("cannot hardlink {}: {}; copying", previous.display(), e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// `options` changed as the `extensions` rule for `path` has it, if there is one.
+    fn with_extension_rule<'a>(
+        &self,
+        path: &Path,
+        mut options: BackupOptions<'a>,
+    ) -> BackupOptions<'a> {
+        let Some(rule) = self.config.extension_rule(path) else {
+            return options;
+        };
+        if let Some(encrypt) = rule.encrypt {
+            options.encrypt = encrypt;
+            options.compress &= !encrypt;
+        }
+        if let Some(compress) = rule.compress {
+            options.compress =
+                compress && !options.encrypt && !options.incremental && !options.dedup;
+        }
+        debug!("extension rule for {}: {:?}", path.display(), rule);
+        options
+    }
+
+    /// Why `path` should not be backed up, if it is one of our own files (a backup, plain,
+    /// versioned or compressed, or one of its sidecars or state file, the log or the lock
+    /// file) or its extension is excluded.
+    fn skip_reason(&self, path: &Path) -> Option<&'static str> {
+        if is_log_file(&self.config, path) {
+            return Some("is-log");
+        }
+        if is_lock_file(&self.base, path) {
+            return Some("is-lock");
+        }
+        if self.config.extension_rule(path).is_some_and(|rule| rule.exclude) {
+            return Some("excluded");
+        }
+        let name = file_name_of(path);
+        let name = name
+            .strip_suffix(".sha256")
+            .or_else(|| name.strip_suffix(".meta"))
+            .or_else(|| name.strip_suffix(".state"))
+            .unwrap_or(&name);
+        // Every name ends in an empty suffix; such a config is refused further on instead.
+        let suffix = self.config.backup_suffix.as_str();
+        (!suffix.is_empty() && strip_backup_ext(name).ends_with(suffix)).then_some("is-backup")
+    }
+
+    /// The backup a new one would duplicate: the plain ".bak" (or the newest version, for
+    /// versioned backups) if it already holds exactly the source's content, as read from
+    /// `content` (`path` itself, or a copy of it). A matching
+    /// recorded mtime and size count as a match without reading either file; that is the
+    /// only check for encrypted backups, which cannot be read without the passphrase.
+    fn current_backup(
+        &self,
+        path: &Path,
+        content: &Path,
+        options: BackupOptions,
+    ) -> io::Result<Option<PathBuf>> {
+        let latest = if options.versioned || options.incremental {
+            match find_backups(&self.config, &self.base, path)?.into_iter().next() {
+                Some(entry) => entry.path,
+                None => return Ok(None),
+            }
+        } else {
+            let plain = backup_path_for(&self.config, &self.base, path);
+            if options.compress {
+                gz_path_for(&plain)
+            } else if options.encrypt {
+                enc_path_for(&plain)
+            } else if options.dedup {
+                ref_path_for(&plain)
+            } else {
+                plain
+            }
+        };
+        if !latest.is_file() {
+            return Ok(None);
+        }
+
+        let source = fs::metadata(path)?;
+        let packed = is_gz(&latest) || is_enc(&latest) || is_delta(&latest) || is_ref(&latest);
+        if !packed && fs::metadata(&latest)?.len() != source.len() {
+            return Ok(None);
+        }
+        if recorded_mtime(&latest)? == Some(source.modified()?) {
+            return Ok(Some(latest));
+        }
+        if is_enc(&latest) {
+            return Ok(None);
+        }
+        Ok(self.same_as_backup(content, &latest)?.then_some(latest))
+    }
+
+    /// Back up a whole directory into a mirrored "<dir>.bak/" tree.
+    /// The copy is staged in "<dir>.bak.tmp/" and only swapped in once every file made it,
+    /// so a failure partway leaves any previous "<dir>.bak/" untouched. The previous backup
+    /// is moved aside to "<dir>.bak.old/" for the swap and only removed once the new one is
+    /// in place; if a run dies in between, the next one moves it back. A backup directory
+    /// inside `dir` is left out of the copy.
+    fn backup_directory(
+        &self,
+        filename: &str,
+        dir: &Path,
+        options: BackupOptions,
+    ) -> Result<BackupReport, BackupError> {
+        let started = Instant::now();
+        let started_at = SystemTime::now();
+        let cfg = &self.config;
+        let backup = backup_path_for(cfg, &self.base, dir);
+        let old = backup.with_file_name(format!("{}.old", file_name_of(&backup)));
+        if !cfg.dry_run {
+            recover_old_backup(&backup, &old)?;
+        }
+        let mut filter = DirFilter::new(options)?;
+        filter.backup_dir = self.backup_dir_inside(dir)?;
+        let mut files = Vec::new();
+        let mut skipped = Vec::new();
+        self.collect_files(filename, dir, Path::new(""), &filter, &mut files, &mut skipped)?;
+        let cutoff = match options.since {
+            Some(since) => Some(local_time(since)?),
+            None if options.incremental => read_directory_state(&backup)?,
+            None => None,
+        };
+        let cutoff = cutoff.filter(|_| backup.is_dir());
+        // A file is carried over if it was last modified before the cutoff and the previous
+        // backup still holds a copy of the same size; 0 bytes are copied for it.
+        let sizes = files
+            .iter()
+            .map(|rel| {
+                let meta = fs::metadata(dir.join(rel))?;
+                let Some(cutoff) = cutoff else {
+                    return Ok(Some(meta.len()));
+                };
+                let kept = fs::symlink_metadata(backup.join(rel))
+                    .is_ok_and(|old| old.is_file() && old.len() == meta.len());
+                Ok((!(kept && meta.modified()? < cutoff)).then_some(meta.len()))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let unchanged = sizes.iter().filter(|size| size.is_none()).count();
+        let bytes = sizes.iter().flatten().sum();
+        let mut report = BackupReport::Directory {
+            source: dir.to_path_buf(),
+            backup: backup.clone(),
+            files: files.len(),
+            unchanged,
+            bytes,
+            skipped,
+            elapsed: Duration::ZERO,
+        };
+
+        if cfg.dry_run {
+            log_action(cfg, "backup", filename, "dry-run", None);
+            return Ok(report);
+        }
+
+        self.ensure_backup_dir(&backup)?;
+        self.ensure_space(&backup, bytes)?;
+        let staging = backup.with_file_name(format!("{}.tmp", file_name_of(&backup)));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        // Until the staging directory is renamed or removed.
+        let _writing = Writing::start();
+        let buffer = cfg.copy_buffer_size;
+        let copy_all = || -> Result<(), BackupError> {
+            fs::create_dir_all(&staging)?;
+            let mut done = DirProgress {
+                files_done: 0,
+                files_total: files.len(),
+                bytes_done: 0,
+                bytes_total: bytes,
+            };
+            for (rel, size) in files.iter().zip(&sizes) {
+                check_cancelled()?;
+                let target = staging.join(rel);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let Some(size) = size else {
+                    carry_over(&backup.join(rel), &target, buffer)?;
+                    done.files_done += 1;
+                    if let Some(report) = &self.dir_progress {
+                        report(done);
+                    }
+                    continue;
+                };
+                let source = dir.join(rel);
+                let before = done;
+                let overall = self.dir_progress.clone().map(|report| {
+                    move |copied, _| {
+                        report(DirProgress { bytes_done: before.bytes_done + copied, ..before })
+                    }
+                });
+                let progress = match &overall {
+                    Some(overall) => Some(overall as &ProgressFn),
+                    None => self.progress(),
+                };
+                let copy = || Ok(copy_atomically(&source, &target, buffer, progress)?);
+                self.with_retries(filename, copy)?;
+                if options.preserve_mtime {
+                    copy_mtime(&source, &target)?;
+                }
+                done.files_done += 1;
+                done.bytes_done += size;
+                if let Some(report) = &self.dir_progress {
+                    report(done);
+                }
+            }
+            Ok(())
+        };
+        if let Err(e) = copy_all() {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+        let replacing = backup.exists();
+        if replacing {
+            fs::rename(&backup, &old)?;
+        }
+        if let Err(e) = fs::rename(&staging, &backup) {
+            if replacing {
+                let _ = fs::rename(&old, &backup);
+            }
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e.into());
+        }
+        if replacing {
+            if let Err(e) = fs::remove_dir_all(&old) {
+                // The next run removes it.
+                debug!("cannot remove the previous backup {}: {}", old.display(), e);
+            }
+        }
+        write_directory_state(&backup, started_at)?;
+        self.sync_backup(&backup)?;
+
+        let elapsed = started.elapsed();
+        if let BackupReport::Directory { elapsed: took, .. } = &mut report {
+            *took = elapsed;
+        }
+        let stats = transfer_stats(bytes, elapsed).join(" | ");
+        let detail = match cutoff {
+            Some(_) => format!("{} files | {} unchanged | {}", files.len(), unchanged, stats),
+            None => format!("{} files | {}", files.len(), stats),
+        };
+        log_action(cfg, "backup", filename, "success", Some(&detail));
+        Ok(report)
+    }
+
+    /// The configured backup directory relative to `dir`, if it lies inside `dir` and must
+    /// be left out of its backup. Backing up the backup directory itself, which would copy
+    /// every earlier backup into the new one, is refused.
+    fn backup_dir_inside(&self, dir: &Path) -> Result<Option<PathBuf>, BackupError> {
+        let Some(backup_dir) = &self.config.backup_dir else {
+            return Ok(None);
+        };
+        let backup_dir = match fs::canonicalize(self.base.join(backup_dir)) {
+            Ok(path) => path,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let dir = fs::canonicalize(dir)?;
+        if dir == backup_dir {
+            return Err(BackupError::InvalidInput(format!(
+                "'{}' is the backup directory; it cannot be backed up into itself",
+                dir.display()
+            )));
+        }
+        let inside = backup_dir.strip_prefix(&dir).ok().map(Path::to_path_buf);
+        if let Some(rel) = &inside {
+            debug!("leaving out the backup directory {}", rel.display());
+        }
+        Ok(inside)
+    }
+
+    /// Recursively collect the regular files under `root/rel` as paths relative to `root`.
+    /// Symlinks and special files are skipped with a logged warning; entries `filter`
+    /// excludes (with the `.backupignore` in `rel` added to it), and the log, are left out
+    /// silently (traced with `--verbose`).
+    fn collect_files(
+        &self,
+        filename: &str,
+        root: &Path,
+        rel: &Path,
+        filter: &DirFilter,
+        out: &mut Vec<PathBuf>,
+        skipped: &mut Vec<SkippedEntry>,
+    ) -> Result<(), BackupError> {
+        let filter = filter.below(root, rel)?;
+        for entry in fs::read_dir(root.join(rel))? {
+            let entry = entry?;
+            let child = rel.join(entry.file_name());
+            let kind = entry.file_type()?;
+            let path = root.join(&child);
+            let ours = is_log_file(&self.config, &path)
+                || is_lock_file(&self.base, &path)
+                || self.config.extension_rule(&path).is_some_and(|rule| rule.exclude);
+            if ours || filter.excludes(&child, kind) {
+                debug!("excluded {}", child.display());
+                continue;
+            }
+            if kind.is_dir() {
+                self.collect_files(filename, root, &child, &filter, out, skipped)?;
+            } else if kind.is_file() {
+                out.push(child);
+            } else {
+                let what = if kind.is_symlink() { "symlink" } else { "special file" };
+                let detail = format!("{} {}", what, child.display());
+                log_action(&self.config, "backup", filename, "skipped", Some(&detail));
+                skipped.push(SkippedEntry { path: child, kind: what });
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore a file from its ".bak" (or ".bak.gz"), or from a specific version when
+    /// `version` is given (see `select_backup`). Compressed backups are decompressed
+    /// transparently; encrypted ones are decrypted with the prompted passphrase, and a
+    /// wrong passphrase fails without touching the live file. Other backups are left
+    /// untouched.
+    ///
+    /// If the live file was modified after the backup was taken and its content differs,
+    /// `confirm` decides whether to overwrite it (unless `force` is set). With
+    /// `destination`, the backup is written there and the original is left alone; an
+    /// existing destination is only replaced with `force`. `overwrite` decides whether an
+    /// existing target is left alone, replaced, or kept in the trash first. Runs between
+    /// the `pre_hook` and the `post_hook`.
+    pub fn restore(
+        &self,
+        filename: impl AsRef<OsStr>,
+        options: RestoreOptions,
+        confirm: impl FnOnce(&Path) -> io::Result<bool>,
+    ) -> Result<RestoreReport, BackupError> {
+        let input = filename.as_ref();
+        self.with_hooks("restore", input, || self.run_restore(input, options, confirm))
+    }
+
+    fn run_restore(
+        &self,
+        input: &OsStr,
+        options: RestoreOptions,
+        confirm: impl FnOnce(&Path) -> io::Result<bool>,
+    ) -> Result<RestoreReport, BackupError> {
+        let cfg = &self.config;
+        let version = options.version;
+        let filename = &*input.to_string_lossy();
+        let mut path = self.resolve_source(input)?;
+        // Held until the restore is done: the downloaded backups are removed with it.
+        let mut _downloaded = None;
+        let set = match options.from {
+            Some(url) if is_sftp_url(url) => {
+                let (dir, set) = self.remote_set(url, &path)?;
+                _downloaded = Some(dir);
+                Some(set)
+            }
+            Some(dir) => Some(self.backup_set(dir)?),
+            None => None,
+        };
+        if options.snapshot.is_some() && (version.is_some() || set.is_some()) {
+            return Err(BackupError::InvalidInput(
+                "A snapshot's version is restored from where it was labelled".to_string(),
+            ));
+        }
+        let lookup = set.as_ref().unwrap_or(cfg);
+        let find = |file: &Path| match (options.snapshot, version) {
+            (Some(name), _) => self.snapshot_backup(name, file),
+            (None, Some(id)) => select_backup(lookup, &self.base, file, id),
+            (None, None) => Ok(default_backup_for(lookup, &self.base, file)),
+        };
+        let mut backup = find(&path);
+        // A set copied from beside the files names each backup after the file alone.
+        if set.is_some() && !backup.as_ref().is_ok_and(|b| b.exists()) {
+            if let Ok(beside) = find(&self.base.join(file_name_of(&path))) {
+                if beside.exists() {
+                    backup = Ok(beside);
+                }
+            }
+        }
+        let mut backup = backup?;
+
+        // Also accept the backup's own name ("notes.txt.bak"): strip exactly the suffix and
+        // restore its source, provided the mapping round-trips.
+        let chosen = version.is_some() || options.snapshot.is_some();
+        if !chosen && set.is_none() && !backup.exists() && path.is_file() {
+            if let Some(source) = source_for_backup(cfg, &self.base, &path) {
+                if default_backup_for(cfg, &self.base, &source) == path {
+                    backup = path;
+                    path = source;
+                }
+            }
+        }
+        if !backup.exists() {
+            return Err(BackupError::NotFound("Backup file not found".to_string()));
+        }
+        self.check_symlink(&path)?;
+        self.check_symlink(&backup)?;
+        self.check_case(&path)?;
+        self.check_case(&backup)?;
+        if backup.is_dir() {
+            return self.restore_directory(filename, path, backup, options, confirm);
+        }
+        let source = path.clone();
+        let mut live_newer = false;
+        match options.destination {
+            Some(destination) => {
+                path = resolve_safe_path(&self.base, destination)?;
+                if path.is_dir() {
+                    return Err(BackupError::NotRegularFile(path));
+                }
+                let skip = options.overwrite == OverwritePolicy::Skip;
+                if path.exists() && !options.force && !skip {
+                    return Err(BackupError::InvalidInput(format!(
+                        "'{}' already exists; use --force to overwrite it",
+                        destination
+                    )));
+                }
+            }
+            None => live_newer = live_is_newer(&path, &backup)?,
+        }
+        let format = backup_format(&backup)?;
+        if format == BackupFormat::Encrypted && self.passphrase.is_none() {
+            return Err(BackupError::InvalidInput(format!(
+                "{} is an encrypted backup and needs a passphrase, but none can be asked for",
+                file_name_of(&backup)
+            )));
+        }
+        debug!("restoring {} from {} ({})", path.display(), backup.display(), format.name());
+        let bytes = fs::metadata(&backup)?.len();
+        let created_dir = match path.parent() {
+            Some(parent) if !parent.exists() => {
+                // The target came through `resolve_safe_path`, so this only fails if the
+                // backup-name mapping pointed outside the base.
+                if !parent.starts_with(&self.base) {
+                    return Err(BackupError::PathRejected(format!(
+                        "{} is outside the base directory",
+                        parent.display()
+                    )));
+                }
+                Some(parent.to_path_buf())
+            }
+            _ => None,
+        };
+        let mut report = RestoreReport {
+            backup,
+            format,
+            target: path,
+            bytes,
+            sha256: None,
+            live_newer,
+            created_dir,
+            restored: false,
+            snapshot: None,
+            files: None,
+            kept: 0,
+            elapsed: Duration::ZERO,
+        };
+        if options.overwrite == OverwritePolicy::Skip && report.target.exists() {
+            report.kept = 1;
+            let outcome = if cfg.dry_run { "dry-run" } else { "skipped" };
+            log_action(cfg, "restore", filename, outcome, Some("exists | policy skip"));
+            return Ok(report);
+        }
+        if cfg.dry_run {
+            if format != BackupFormat::Encrypted {
+                report.sha256 = Some(sha256_backup_content(&report.backup)?);
+            }
+            log_action(cfg, "restore", filename, "dry-run", None);
+            return Ok(report);
+        }
+        if live_newer {
+            if options.force {
+                log_action(cfg, "restore", filename, "overwrite", Some("forced"));
+            } else if confirm(&report.target)? {
+                log_action(cfg, "restore", filename, "overwrite", Some("confirmed"));
+            } else {
+                log_action(cfg, "restore", filename, "skipped", Some("live file is newer"));
+                return Ok(report);
+            }
+        }
+
+        // Timed from here, so the confirmation prompt does not count.
+        let started = Instant::now();
+        let RestoreReport { backup, target: path, .. } = &report;
+        if let Some(dir) = &report.created_dir {
+            fs::create_dir_all(dir)?;
+            let rel = dir.strip_prefix(&self.base).unwrap_or(dir);
+            let detail = rel.display().to_string();
+            log_action(cfg, "restore", filename, "created-dirs", Some(&detail));
+        }
+        // Keep what is about to be overwritten, so `undo` can bring it back.
+        let existed = path.is_file();
+        let snapshot = if existed && options.overwrite == OverwritePolicy::Backup {
+            let snapshot = trash_path_for(&self.base, path);
+            fs::create_dir_all(self.base.join(TRASH_DIR))?;
+            copy_atomically(path, &snapshot, cfg.copy_buffer_size, None)?;
+            log_action(cfg, "restore", filename, "snapshot", Some(&file_name_of(&snapshot)));
+            Some(snapshot)
+        } else {
+            None
+        };
+        let written = self.decode_backup(filename, backup, report.format, path)?;
+        debug!("wrote {} bytes to {}", written, path.display());
+        apply_metadata_sidecar(backup, path)?;
+        let undoable = !existed || snapshot.is_some();
+        if undoable {
+            let op = LastOperation::Restore { file: path.clone(), snapshot: snapshot.clone() };
+            record_last_operation(&self.base, &op)?;
+        } else {
+            // Undoing would lose the restored content without bringing the old one back.
+            clear_last_operation(&self.base)?;
+        }
+        // Only a file that reads back as the backup's recorded content counts as restored.
+        let sha256 = match self.recorded_content(&source, backup, report.format)? {
+            Some((algorithm, expected)) => {
+                let actual = digest_file(algorithm, path)?;
+                if actual != expected {
+                    let detail = format!(
+                        "mismatch | {} | {} {} recorded, {} restored",
+                        file_name_of(backup),
+                        algorithm.name(),
+                        &expected[..expected.len().min(12)],
+                        &actual[..actual.len().min(12)]
+                    );
+                    log_action(cfg, "restore", filename, "failure", Some(&detail));
+                    return Err(BackupError::RestoreMismatch { target: path.clone(), undoable });
+                }
+                debug!("restored content verified: {} {}", algorithm.name(), actual);
+                (algorithm == HashAlgorithm::Sha256).then_some(actual)
+            }
+            None => None,
+        };
+        let elapsed = started.elapsed();
+        let mut stats = transfer_stats(report.bytes, elapsed).join(" | ");
+        if existed {
+            stats = format!("policy {} | {}", options.overwrite.name(), stats);
+        }
+        let detail = match (chosen, options.destination) {
+            (_, Some(destination)) => {
+                format!("{} -> {} | {}", file_name_of(backup), destination, stats)
+            }
+            (true, None) => format!("{} | {}", file_name_of(backup), stats),
+            (false, None) => stats,
+        };
+        log_action(cfg, "restore", filename, "success", Some(&detail));
+        report.restored = true;
+        report.sha256 = sha256;
+        report.snapshot = snapshot;
+        report.elapsed = elapsed;
+        Ok(report)
+    }
+
+    /// The digest `file`'s `backup` (in `format`) restores to, as recorded: in the index
+    /// when it is the newest backup, else the sidecar of a plain backup, else the content
+    /// read back from the backup itself. `None` for an encrypted backup outside the index,
+    /// whose decryption already authenticated what was written.
+    fn recorded_content(
+        &self,
+        file: &Path,
+        backup: &Path,
+        format: BackupFormat,
+    ) -> io::Result<Option<(HashAlgorithm, String)>> {
+        if let Some(sha256) = indexed_sha256(&self.config, &self.base, file, backup) {
+            return Ok(Some((HashAlgorithm::Sha256, sha256)));
+        }
+        Ok(match format {
+            BackupFormat::Encrypted => None,
+            BackupFormat::Plain => match read_checksum_sidecar(backup)? {
+                Some(recorded) => Some(recorded),
+                None => Some((HashAlgorithm::Sha256, sha256_file(backup)?)),
+            },
+            _ => Some((HashAlgorithm::Sha256, sha256_backup_content(backup)?)),
+        })
+    }
+
+    /// Write the content of `backup` (in `format`) to `target` the way `restore` does:
+    /// decompressed, decrypted, rebuilt from its base or copied. Returns the bytes written.
+    fn decode_backup(
+        &self,
+        filename: &str,
+        backup: &Path,
+        format: BackupFormat,
+        target: &Path,
+    ) -> Result<u64, BackupError> {
+        let buffer = self.config.copy_buffer_size;
+        Ok(match format {
+            BackupFormat::Gzip => decompress_file(backup, target, self.progress())?,
+            BackupFormat::Encrypted => {
+                let passphrase = self.passphrase(backup, false)?;
+                decrypt_file(backup, target, &passphrase, self.progress())?
+            }
+            BackupFormat::Delta => restore_delta(backup, target, self.progress())?,
+            BackupFormat::Deduplicated => restore_ref(backup, target, buffer, self.progress())?,
+            BackupFormat::Plain => {
+                let copy = || Ok(copy_atomically(backup, target, buffer, self.progress())?);
+                self.with_retries(filename, copy)?
+            }
+        })
+    }
+
+    /// The files labelled with snapshot `name`, relative to the base, for `rollback`.
+    pub fn snapshot_files(&self, name: &str) -> Result<Vec<PathBuf>, BackupError> {
+        Ok(snapshot_backups(&self.base, name)?.into_iter().map(|(file, _)| file).collect())
+    }
+
+    /// The version of `file` labelled with snapshot `name`.
+    fn snapshot_backup(&self, name: &str, file: &Path) -> Result<PathBuf, BackupError> {
+        snapshot_backups(&self.base, name)?
+            .into_iter()
+            .find_map(|(labelled, backup)| (self.base.join(labelled) == file).then_some(backup))
+            .ok_or_else(|| {
+                let file = file.strip_prefix(&self.base).unwrap_or(file);
+                BackupError::NotFound(format!("'{}' is not in snapshot '{}'", file.display(), name))
+            })
+    }
+
+    /// Restore a directory from its "<dir>.bak/" tree: each file goes back to the same path
+    /// under `dir` (or `destination`), subdirectories are created as needed. Files that
+    /// exist are treated as `overwrite` says; those with other content are only
+    /// overwritten with `force` or if `confirm` agrees. `undo` cannot bring back a whole
+    /// tree, so the previous operation is forgotten.
+    fn restore_directory(
+        &self,
+        filename: &str,
+        dir: PathBuf,
+        backup: PathBuf,
+        options: RestoreOptions,
+        confirm: impl FnOnce(&Path) -> io::Result<bool>,
+    ) -> Result<RestoreReport, BackupError> {
+        let cfg = &self.config;
+        let target = match options.destination {
+            Some(destination) => resolve_safe_path(&self.base, destination)?,
+            None => dir,
+        };
+        if target.exists() && !target.is_dir() {
+            return Err(BackupError::InvalidInput(format!(
+                "{} is not a directory; a directory backup restores into one",
+                target.display()
+            )));
+        }
+        let mut files = Vec::new();
+        tree_files(&backup, Path::new(""), &mut files)?;
+        let (mut bytes, mut differing, mut kept) = (0, Vec::new(), Vec::new());
+        for rel in &files {
+            let (from, to) = (backup.join(rel), target.join(rel));
+            if !stays_inside(&self.base, &to)? {
+                return Err(BackupError::PathRejected(format!(
+                    "{} leads out of the base directory through a symlink",
+                    to.display()
+                )));
+            }
+            if to.is_dir() {
+                return Err(BackupError::NotRegularFile(to));
+            }
+            if to.exists() && options.overwrite == OverwritePolicy::Skip {
+                kept.push(rel.clone());
+                continue;
+            }
+            bytes += fs::metadata(&from)?.len();
+            if to.exists() && !same_content(&from, &to)? {
+                differing.push(rel.clone());
+            }
+        }
+        files.retain(|rel| !kept.contains(rel));
+        let mut report = RestoreReport {
+            created_dir: (!target.exists()).then(|| target.clone()),
+            backup,
+            format: BackupFormat::Plain,
+            target,
+            bytes,
+            sha256: None,
+            live_newer: !differing.is_empty(),
+            restored: false,
+            snapshot: None,
+            files: Some(files.len()),
+            kept: kept.len(),
+            elapsed: Duration::ZERO,
+        };
+        let policy = options.overwrite.name();
+        if cfg.dry_run {
+            let detail = format!(
+                "{} files | {} overwritten | {} kept | policy {}",
+                files.len(),
+                differing.len(),
+                kept.len(),
+                policy
+            );
+            log_action(cfg, "restore", filename, "dry-run", Some(&detail));
+            return Ok(report);
+        }
+        if report.live_newer {
+            if options.force {
+                log_action(cfg, "restore", filename, "overwrite", Some("forced"));
+            } else if confirm(&report.target)? {
+                log_action(cfg, "restore", filename, "overwrite", Some("confirmed"));
+            } else {
+                log_action(cfg, "restore", filename, "skipped", Some("files differ"));
+                return Ok(report);
+            }
+        }
+
+        let started = Instant::now();
+        let RestoreReport { backup, target, .. } = &report;
+        let buffer = cfg.copy_buffer_size;
+        for rel in &kept {
+            log_action(cfg, "restore", filename, "skipped", Some(&rel.display().to_string()));
+        }
+        for rel in &files {
+            let (from, to) = (backup.join(rel), target.join(rel));
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if differing.contains(rel) {
+                if options.overwrite == OverwritePolicy::Backup {
+                    fs::create_dir_all(self.base.join(TRASH_DIR))?;
+                    let snapshot = trash_path_for(&self.base, &to);
+                    copy_atomically(&to, &snapshot, buffer, None)?;
+                    let detail = file_name_of(&snapshot);
+                    log_action(cfg, "restore", filename, "snapshot", Some(&detail));
+                } else {
+                    let detail = rel.display().to_string();
+                    log_action(cfg, "restore", filename, "overwritten", Some(&detail));
+                }
+            }
+            let copy = || Ok(copy_atomically(&from, &to, buffer, self.progress())?);
+            self.with_retries(filename, copy)?;
+        }
+        clear_last_operation(&self.base)?;
+        let elapsed = started.elapsed();
+        let stats = transfer_stats(bytes, elapsed).join(" | ");
+        let counts = format!(
+            "{} files | {} overwritten | {} kept | policy {}",
+            files.len(),
+            differing.len(),
+            kept.len(),
+            policy
+        );
+        let detail = match options.destination {
+            Some(destination) => {
+                format!("{} -> {} | {} | {}", file_name_of(backup), destination, counts, stats)
+            }
+            None => format!("{} | {}", counts, stats),
+        };
+        log_action(cfg, "restore", filename, "success", Some(&detail));
+        report.restored = true;
+        report.elapsed = elapsed;
+        Ok(report)
+    }
+
+    /// Verify a backup (or a specific version). If the backup has a checksum sidecar, the
+    /// backup is checked against the digest recorded at creation time; otherwise it falls
+    /// back to comparing its content with the live source (logged as a warning).
+    pub fn verify(
+        &self,
+        filename: impl AsRef<OsStr>,
+        version: Option<&str>,
+    ) -> Result<VerifyReport, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let path = self.resolve_source(input)?;
+        let backup = match version {
+            Some(id) => select_backup(cfg, &self.base, &path, id)?,
+            None => default_backup_for(cfg, &self.base, &path),
+        };
+        if !backup.exists() {
+            return Err(BackupError::NotFound("Backup file not found".to_string()));
+        }
+
+        let report = match read_checksum_sidecar(&backup)? {
+            Some((algorithm, recorded)) => VerifyReport {
+                actual: self.digests.file(algorithm, &backup)?,
+                expected: recorded,
+                basis: VerifyBasis::Sidecar,
+                algorithm,
+                backup,
+                source: path,
+            },
+            None => {
+                log_action(cfg, "verify", filename, "warning", Some("no sidecar"));
+                if !path.is_file() {
+                    return Err(BackupError::NotFound("Source file does not exist".to_string()));
+                }
+                VerifyReport {
+                    expected: self.digests.file(HashAlgorithm::Sha256, &path)?,
+                    actual: self.digests.content(&backup)?,
+                    basis: VerifyBasis::Source,
+                    algorithm: HashAlgorithm::Sha256,
+                    backup,
+                    source: path,
+                }
+            }
+        };
+        let outcome = if report.matches() { "match" } else { "mismatch" };
+        log_action(cfg, "verify", filename, outcome, None);
+        Ok(report)
+    }
+
+    /// Restore a file's backup (or a specific version) into a temporary file in the trash
+    /// and throw it away, to show the backup can be brought back: it is checked against its
+    /// sidecar, read back through the whole restore path (decompressed, decrypted, rebuilt
+    /// from its base, read from the store) and the result checked against the content
+    /// recorded for it, if any. Nothing outside the trash is written.
+    pub fn test_restore(
+        &self,
+        filename: impl AsRef<OsStr>,
+        version: Option<&str>,
+    ) -> Result<TestRestoreReport, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let path = self.resolve_source(input)?;
+        let backup = match version {
+            Some(id) => select_backup(cfg, &self.base, &path, id)?,
+            None => default_backup_for(cfg, &self.base, &path),
+        };
+        if !backup.exists() {
+            return Err(BackupError::NotFound("Backup file not found".to_string()));
+        }
+        self.check_symlink(&backup)?;
+        if backup.is_dir() {
+            return Err(BackupError::NotRegularFile(backup));
+        }
+        let format = backup_format(&backup)?;
+        let stored_matches = match read_checksum_sidecar(&backup)? {
+            Some((algorithm, recorded)) => {
+                Some(self.digests.file(algorithm, &backup)? == recorded)
+            }
+            None => None,
+        };
+
+        let started = Instant::now();
+        let trash = self.base.join(TRASH_DIR);
+        fs::create_dir_all(&trash)?;
+        let scratch = tempfile::Builder::new().prefix(".test-restore.").tempdir_in(&trash)?;
+        let target = scratch.path().join(file_name_of(&path));
+        let bytes = self.decode_backup(filename, &backup, format, &target)?;
+        let sha256 = sha256_file(&target)?;
+        drop(scratch);
+
+        // The live file still has the content backed up if it was not modified since.
+        let unchanged_source = || -> io::Result<Option<String>> {
+            let Some(recorded) = recorded_mtime(&backup)? else {
+                return Ok(None);
+            };
+            match path.is_file() && fs::metadata(&path)?.modified()? == recorded {
+                true => Ok(Some(sha256_file(&path)?)),
+                false => Ok(None),
+            }
+        };
+        let expected = match indexed_sha256(cfg, &self.base, &path, &backup) {
+            Some(sha256) => Some((ContentRecord::Index, sha256)),
+            None => unchanged_source()?.map(|sha256| (ContentRecord::Source, sha256)),
+        };
+        let report = TestRestoreReport {
+            backup,
+            format,
+            bytes,
+            sha256,
+            stored_matches,
+            expected,
+            elapsed: started.elapsed(),
+        };
+        let verdict = |matches| if matches { "match" } else { "mismatch" };
+        let mut checks = Vec::new();
+        if let Some(matches) = report.stored_matches {
+            checks.push(format!("sidecar {}", verdict(matches)));
+        }
+        if let (Some(matches), Some((record, _))) = (report.content_matches(), &report.expected) {
+            checks.push(format!("{} {}", record.name(), verdict(matches)));
+        }
+        if checks.is_empty() {
+            checks.push("nothing recorded".to_string());
+        }
+        let name = file_name_of(&report.backup);
+        let detail = format!("{} | {} | {}", name, format.name(), checks.join(", "));
+        let outcome = if report.passed() { "success" } else { "mismatch" };
+        log_action(cfg, "test-restore", filename, outcome, Some(&detail));
+        Ok(report)
+    }
+
+    /// Compare the live file with its backup (or a specific version): a unified diff when
+    /// both are text, the differing byte ranges otherwise.
+    pub fn diff(
+        &self,
+        filename: impl AsRef<OsStr>,
+        version: Option<&str>,
+    ) -> Result<DiffReport, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let path = self.resolve_source(input)?;
+        let backup = match version {
+            Some(id) => select_backup(cfg, &self.base, &path, id)?,
+            None => default_backup_for(cfg, &self.base, &path),
+        };
+        if !backup.exists() {
+            return Err(BackupError::NotFound(format!(
+                "'{}' has no backup to compare with",
+                filename
+            )));
+        }
+        if !path.is_file() {
+            return Err(BackupError::NotFound("Source file does not exist".to_string()));
+        }
+
+        let old = self.backup_content(&backup)?;
+        let new = Zeroizing::new(fs::read(&path)?);
+        let changes = content_changes(&old, &new, &file_name_of(&backup), &file_name_of(&path));
+        let detail = file_name_of(&backup);
+        let outcome = match &changes {
+            DiffChanges::Identical => "identical",
+            _ => "changed",
+        };
+        log_action(cfg, "diff", filename, outcome, Some(&detail));
+        Ok(DiffReport { source: path, backup, changes })
+    }
+
+    /// Write a file's backup (the default one, or `version` as `select_backup` takes it) to
+    /// `out`, decompressed or decrypted as needed, without creating any file. Returns the
+    /// backup written.
+    pub fn cat(
+        &self,
+        filename: impl AsRef<OsStr>,
+        version: Option<&str>,
+        out: &mut dyn io::Write,
+    ) -> Result<PathBuf, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let path = self.resolve_source(input)?;
+        let backup = match version {
+            Some(id) => select_backup(cfg, &self.base, &path, id)?,
+            None => default_backup_for(cfg, &self.base, &path),
+        };
+        if !backup.exists() {
+            return Err(BackupError::NotFound(format!("'{}' has no backup", filename)));
+        }
+        let content = self.backup_content(&backup)?;
+        out.write_all(&content)?;
+        out.flush()?;
+        log_action(cfg, "cat", filename, "success", Some(&file_name_of(&backup)));
+        Ok(backup)
+    }
+
+    /// Compare two backups of a file with each other, `first` as the old side and `second`
+    /// as the new one. Each is picked by a `select_backup` identifier (an index from `list`
+    /// or a timestamp prefix) or by the backup's path.
+    pub fn compare(
+        &self,
+        filename: impl AsRef<OsStr>,
+        first: &str,
+        second: &str,
+    ) -> Result<CompareReport, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let path = self.resolve_source(input)?;
+        let old = self.pick_backup(&path, first)?;
+        let new = self.pick_backup(&path, second)?;
+
+        let old_content = self.backup_content(&old)?;
+        let new_content = self.backup_content(&new)?;
+        let (old_name, new_name) = (file_name_of(&old), file_name_of(&new));
+        let report = CompareReport {
+            old_sha256: sha256_of(old_content.as_slice())?,
+            new_sha256: sha256_of(new_content.as_slice())?,
+            changes: content_changes(&old_content, &new_content, &old_name, &new_name),
+            old,
+            new,
+        };
+        let outcome = match &report.changes {
+            DiffChanges::Identical => "identical",
+            _ => "changed",
+        };
+        let detail = format!("{} -> {}", old_name, new_name);
+        log_action(cfg, "compare", filename, outcome, Some(&detail));
+        Ok(report)
+    }
+
+    /// One of `path`'s backups, by `select_backup` identifier or, failing that, by its path
+    /// relative to the base.
+    fn pick_backup(&self, path: &Path, id: &str) -> Result<PathBuf, BackupError> {
+        let cfg = &self.config;
+        let selected = select_backup(cfg, &self.base, path, id);
+        if !matches!(selected, Err(BackupError::NotFound(_))) {
+            return selected;
+        }
+        let Ok(named) = resolve_safe_path(&self.base, id) else {
+            return selected;
+        };
+        let backups = find_backups(cfg, &self.base, path)?;
+        match backups.into_iter().find(|b| b.path == named) {
+            Some(backup) => Ok(backup.path),
+            None => selected,
+        }
+    }
+
+    /// A backup's content in memory: decompressed, rebuilt or decrypted as needed.
+    fn backup_content(&self, backup: &Path) -> Result<Zeroizing<Vec<u8>>, BackupError> {
+        if is_enc(backup) {
+            let passphrase = self.passphrase(backup, false)?;
+            return decrypt_to_vec(backup, &passphrase, None);
+        }
+        let content = if is_delta(backup) {
+            read_delta(backup)?
+        } else if is_ref(backup) {
+            fs::read(ref_blob(backup)?)?
+        } else if is_gz(backup) {
+            let mut content = Vec::new();
+            let file = io::BufReader::new(fs::File::open(backup)?);
+            io::Read::read_to_end(&mut flate2::read::GzDecoder::new(file), &mut content)?;
+            content
+        } else {
+            fs::read(backup)?
+        };
+        Ok(Zeroizing::new(content))
+    }
+
+    /// Check every backup under the base (or in the backup directory) against its recorded
+    /// checksum, or its source if it has none. Unreadable backups are reported, not fatal.
+    pub fn verify_all(&self) -> Result<Vec<AuditEntry>, BackupError> {
+        let cfg = &self.config;
+        let target = self.scan_target();
+        let mut entries = Vec::new();
+        for backup in all_backups(cfg, &self.base)? {
+            let source =
+                source_for_any_backup(cfg, &self.base, &backup).filter(|s| s.is_file());
+            let status = match audit_backup(&self.digests, &backup, source.as_deref()) {
+                Ok(status) => status,
+                Err(e) => AuditStatus::Error(e.to_string()),
+            };
+            let outcome = match &status {
+                AuditStatus::Ok => None,
+                AuditStatus::Corrupt => Some("corrupt"),
+                AuditStatus::Stale => Some("stale"),
+                AuditStatus::Orphaned => Some("orphaned"),
+                AuditStatus::Error(_) => Some("error"),
+            };
+            if let Some(outcome) = outcome {
+                let name = file_name_of(&backup);
+                log_action(cfg, "verify-all", &target, outcome, Some(&name));
+            }
+            entries.push(AuditEntry { backup, source, status });
+        }
+        let bad = entries.iter().filter(|e| !matches!(e.status, AuditStatus::Ok)).count();
+        let detail = format!("{} backups, {} not ok", entries.len(), bad);
+        log_action(cfg, "verify-all", &target, "success", Some(&detail));
+        Ok(entries)
+    }
+
+    /// Pair every backup under the base (or in the backup directory) with its live file
+    /// and pass each result to `each` as soon as it is known; see `SyncStatus`. A backup
+    /// with a recorded checksum is checked against it first. Unreadable backups are
+    /// reported, not fatal.
+    pub fn audit(&self, mut each: impl FnMut(&SyncEntry)) -> Result<SyncReport, BackupError> {
+        let cfg = &self.config;
+        let target = self.scan_target();
+        let mut report = SyncReport::default();
+        for backup in all_backups(cfg, &self.base)? {
+            let source =
+                source_for_any_backup(cfg, &self.base, &backup).filter(|s| s.is_file());
+            let status = match self.sync_status(&backup, source.as_deref()) {
+                Ok(status) => status,
+                Err(e) => SyncStatus::Error(e.to_string()),
+            };
+            *match status {
+                SyncStatus::InSync => &mut report.in_sync,
+                SyncStatus::Stale => &mut report.stale,
+                SyncStatus::MissingSource => &mut report.missing_source,
+                SyncStatus::Corrupt => &mut report.corrupt,
+                SyncStatus::Error(_) => &mut report.errors,
+            } += 1;
+            if !matches!(status, SyncStatus::InSync) {
+                let name = file_name_of(&backup);
+                log_action(cfg, "audit", &target, status.name(), Some(&name));
+            }
+            each(&SyncEntry { backup, source, status });
+        }
+        let detail = format!(
+            "{} backups: {} in sync, {} stale, {} missing source, {} corrupt, {} errors",
+            report.total(),
+            report.in_sync,
+            report.stale,
+            report.missing_source,
+            report.corrupt,
+            report.errors
+        );
+        log_action(cfg, "audit", &target, "success", Some(&detail));
+        Ok(report)
+    }
+
+    /// Check one backup for `audit`.
+    fn sync_status(&self, backup: &Path, source: Option<&Path>) -> io::Result<SyncStatus> {
+        if let Some((algorithm, recorded)) = read_checksum_sidecar(backup)? {
+            if self.digests.file(algorithm, backup)? != recorded {
+                return Ok(SyncStatus::Corrupt);
+            }
+        }
+        let Some(source) = source else {
+            return Ok(SyncStatus::MissingSource);
+        };
+        let meta = fs::metadata(backup)?;
+        let (size, modified) = (meta.len(), meta.modified()?);
+        let entry = BackupEntry { path: backup.to_path_buf(), size, modified };
+        Ok(if self.stale(source, &entry)? { SyncStatus::Stale } else { SyncStatus::InSync })
+    }
+
+    /// Every backup whose source file no longer exists, mapped back with the inverse of
+    /// `backup_path_for`. Backups whose name cannot be mapped back are left out.
+    pub fn orphans(&self) -> Result<Vec<BackupEntry>, BackupError> {
+        let cfg = &self.config;
+        let mut orphans = Vec::new();
+        for backup in all_backups(cfg, &self.base)? {
+            let Some(source) = source_for_any_backup(cfg, &self.base, &backup) else {
+                continue;
+            };
+            if source.exists() {
+                continue;
+            }
+            let meta = fs::metadata(&backup)?;
+            let modified = meta.modified()?;
+            orphans.push(BackupEntry { path: backup, size: meta.len(), modified });
+        }
+        let detail = format!("{} orphaned backups", orphans.len());
+        log_action(cfg, "orphans", &self.scan_target(), "success", Some(&detail));
+        Ok(orphans)
+    }
+
+    /// Delete every orphaned backup (see `orphans`) with its sidecars once `confirm` agrees.
+    /// `confirm` is not called when there is nothing to remove or in a dry run.
+    pub fn remove_orphans(
+        &self,
+        confirm: impl FnOnce(&[BackupEntry]) -> io::Result<bool>,
+    ) -> Result<OrphanReport, BackupError> {
+        let cfg = &self.config;
+        let target = self.scan_target();
+        let orphans = self.orphans()?;
+        if orphans.is_empty() {
+            return Ok(OrphanReport { orphans, removed: false });
+        }
+        if cfg.dry_run {
+            log_action(cfg, "remove-orphans", &target, "dry-run", None);
+            return Ok(OrphanReport { orphans, removed: false });
+        }
+        if !confirm(&orphans)? {
+            log_action(cfg, "remove-orphans", &target, "cancelled", None);
+            return Ok(OrphanReport { orphans, removed: false });
+        }
+
+        for orphan in &orphans {
+            remove_backup(&orphan.path)?;
+            let name = file_name_of(&orphan.path);
+            log_action(cfg, "remove-orphans", &target, "removed", Some(&name));
+        }
+        let detail = format!("removed {}", orphans.len());
+        log_action(cfg, "remove-orphans", &target, "success", Some(&detail));
+        Ok(OrphanReport { orphans, removed: true })
+    }
+
+    /// Turn every plain backup ("notes.txt.bak", also compressed, encrypted or deduplicated)
+    /// into a version stamped with its modification time, in the versioned layout in
+    /// effect: one folder per file under `versions_dir` when set, else beside the plain
+    /// backup. Each backup's digest is checked against its sidecar before the move and
+    /// again after it, and the sidecars move with it. Running it again finds nothing to
+    /// do. The plain backups are gone afterwards, so `restore` needs `--version` for them.
+    pub fn migrate(&self) -> Result<MigrateReport, BackupError> {
+        use chrono::{DateTime, Local};
+
+        let cfg = &self.config;
+        let backups = all_backups(cfg, &self.base)?;
+        // A delta is useless without its plain backup, and a snapshot names its backups.
+        let bases: HashSet<PathBuf> =
+            backups.iter().filter(|b| is_delta(b)).filter_map(|b| delta_base(b).ok()).collect();
+        let labelled = labelled_backups(&self.base)?;
+        let mut report = MigrateReport::default();
+        for backup in backups {
+            let Some(source) = source_for_any_backup(cfg, &self.base, &backup) else {
+                continue;
+            };
+            // Versions and deltas have the same source. A version in a `versions_dir` no
+            // longer configured looks plain, but is named by its stamp alone.
+            let plain = backup_path_for(cfg, &self.base, &source);
+            let packed = [gz_path_for(&plain), enc_path_for(&plain), ref_path_for(&plain)];
+            let stamped = is_version_stamp(&cfg.version_stamp_format, &file_name_of(&source));
+            if stamped || (backup != plain && !packed.contains(&backup)) {
+                continue;
+            }
+            let filename = source.strip_prefix(&self.base).unwrap_or(&source).display();
+            let filename = filename.to_string();
+            let skip = if backup.is_dir() {
+                Some("directory")
+            } else if bases.contains(&backup) {
+                Some("delta-base")
+            } else if labelled.contains(&backup) {
+                Some("snapshot")
+            } else {
+                None
+            };
+            if let Some(reason) = skip {
+                log_action(cfg, "migrate", &filename, "skipped", Some(reason));
+                report.skipped.push((backup, reason));
+                continue;
+            }
+            let recorded = read_checksum_sidecar(&backup)?;
+            let algorithm = recorded.as_ref().map_or(cfg.hash, |(algorithm, _)| *algorithm);
+            let before = digest_file(algorithm, &backup)?;
+            if recorded.is_some_and(|(_, digest)| digest != before) {
+                log_action(cfg, "migrate", &filename, "corrupt", Some(&file_name_of(&backup)));
+                report.skipped.push((backup, "corrupt"));
+                continue;
+            }
+            let made: DateTime<Local> = fs::metadata(&backup)?.modified()?.into();
+            let version = versioned_backup_path_at(cfg, &self.base, &source, made);
+            let version = if is_gz(&backup) {
+                gz_path_for(&version)
+            } else if is_enc(&backup) {
+                enc_path_for(&version)
+            } else if is_ref(&backup) {
+                ref_path_for(&version)
+            } else {
+                version
+            };
+            let detail = format!("{} -> {}", file_name_of(&backup), file_name_of(&version));
+            if cfg.dry_run {
+                log_action(cfg, "migrate", &filename, "dry-run", Some(&detail));
+                report.migrated.push((backup, version));
+                continue;
+            }
+            if let Some(folder) = version.parent() {
+                fs::create_dir_all(folder)?;
+            }
+            fs::rename(&backup, &version)?;
+            if meta_path_for(&backup).exists() {
+                fs::rename(meta_path_for(&backup), meta_path_for(&version))?;
+            }
+            if digest_file(algorithm, &version)? != before {
+                return Err(BackupError::Mismatch(version));
+            }
+            // Written anew, as the sidecar names the backup.
+            let sidecar = sidecar_path_for(&backup);
+            if sidecar.exists() {
+                fs::remove_file(&sidecar)?;
+            }
+            write_checksum_sidecar(&version, algorithm)?;
+            self.sync_backup(&version)?;
+            log_action(cfg, "migrate", &filename, "success", Some(&detail));
+            report.migrated.push((backup, version));
+        }
+        let target = self.scan_target();
+        let outcome = if cfg.dry_run { "dry-run" } else { "success" };
+        let detail =
+            format!("migrated {} | skipped {}", report.migrated.len(), report.skipped.len());
+        log_action(cfg, "migrate", &target, outcome, Some(&detail));
+        Ok(report)
+    }
+
+    /// Time the ways of copying on a generated file of `size` bytes where backups are
+    /// written (`backup_dir`, or the base), to pick `copy_buffer_size` by: `fs::copy`, then
+    /// the buffered copy with each of `buffers`. `each` sees each result as it comes in.
+    /// Nothing but the benchmark's own temporary files is written, and those are removed.
+    pub fn bench(
+        &self,
+        size: u64,
+        buffers: &[u64],
+        each: impl FnMut(&CopyBench),
+    ) -> Result<Vec<CopyBench>, BackupError> {
+        let cfg = &self.config;
+        if size == 0 || buffers.contains(&0) {
+            return Err(BackupError::InvalidInput(
+                "The benchmark file and buffers must be larger than 0 bytes".to_string(),
+            ));
+        }
+        let dir = match &cfg.backup_dir {
+            Some(dir) if self.base.join(dir).is_dir() => self.base.join(dir),
+            _ => self.base.clone(),
+        };
+        // The file and one copy of it at a time.
+        self.ensure_space(&dir.join("bench"), size.saturating_mul(2))?;
+        let results = bench_copies(&dir, size, buffers, each)?;
+        let speed = |r: &&CopyBench| r.bytes_per_sec();
+        let fastest = results.iter().max_by(|a, b| speed(a).total_cmp(&speed(b)));
+        let fastest = match fastest.and_then(|f| f.buffer_size) {
+            Some(buffer) => format!("buffer {}", format_size(buffer)),
+            None => "fs::copy".to_string(),
+        };
+        let detail = format!("{} | fastest {}", format_size(size), fastest);
+        log_action(cfg, "bench", &self.scan_target(), "success", Some(&detail));
+        Ok(results)
+    }
+
+    /// Remove the content in the store that no ".ref" backup points to any more, e.g.
+    /// after `prune` or `remove_orphans` deleted the last backup sharing it.
+    pub fn gc(&self) -> Result<GcReport, BackupError> {
+        let cfg = &self.config;
+        let store = store_root(cfg, &self.base);
+        let references: Vec<PathBuf> =
+            all_backups(cfg, &self.base)?.into_iter().filter(|b| is_ref(b)).collect();
+        let removed = unreferenced_blobs(&store, &references)?;
+        let report = GcReport { store, removed, references: references.len() };
+        let target = report.store.display().to_string();
+        if cfg.dry_run {
+            log_action(cfg, "gc", &target, "dry-run", None);
+            return Ok(report);
+        }
+
+        for blob in &report.removed {
+            fs::remove_file(&blob.path)?;
+            if let Some(folder) = blob.path.parent() {
+                // Only succeeds once the folder is empty, which is what we want.
+                let _ = fs::remove_dir(folder);
+            }
+        }
+        let bytes = report.removed.iter().map(|b| b.size).sum();
+        let detail = format!(
+            "removed {} | {} | kept for {} references",
+            report.removed.len(),
+            format_size(bytes),
+            report.references
+        );
+        log_action(cfg, "gc", &target, "success", Some(&detail));
+        Ok(report)
+    }
+
+    /// The entries of the log and its rotated predecessors that `filter` matches, oldest
+    /// first.
+    pub fn search_log(&self, filter: LogFilter) -> Result<Vec<LogEntry>, BackupError> {
+        let cfg = &self.config;
+        let mut entries: Vec<_> =
+            read_entries(cfg)?.into_iter().filter(|e| filter.matches(e, cfg)).collect();
+        if let Some(tail) = filter.tail {
+            entries.drain(..entries.len().saturating_sub(tail));
+        }
+        let log_name = cfg.log_path.display().to_string();
+        let detail = format!("{} matches", entries.len());
+        log_action(cfg, "log-search", &log_name, "success", Some(&detail));
+        Ok(entries)
+    }
+
+    /// Call `each` with every entry `filter` matches as it is appended to the log, until
+    /// `each` returns false; `filter.tail` is not used. Rotation is followed into the new
+    /// log. Blocks between entries, so it never returns on its own.
+    pub fn follow_log(
+        &self,
+        filter: LogFilter,
+        mut each: impl FnMut(&LogEntry) -> bool,
+    ) -> Result<(), BackupError> {
+        let cfg = &self.config;
+        let log_name = cfg.log_path.display().to_string();
+        log_action(cfg, "log-follow", &log_name, "success", None);
+        follow_entries(cfg, |entry| !filter.matches(&entry, cfg) || each(&entry))?;
+        Ok(())
+    }
+
+    /// Count the log's entries per action and outcome, the `top` files backed up most often
+    /// and the bytes backed up.
+    pub fn log_report(&self, top: usize) -> Result<LogReport, BackupError> {
+        let cfg = &self.config;
+        let entries = read_entries(cfg)?;
+        let mut report = LogReport {
+            entries: entries.len(),
+            first: entries.first().map(|e| e.timestamp.clone()),
+            last: entries.last().map(|e| e.timestamp.clone()),
+            ..LogReport::default()
+        };
+        let mut actions: HashMap<&str, ActionStats> = HashMap::new();
+        let mut backups: HashMap<&str, usize> = HashMap::new();
+        for entry in &entries {
+            let stats = actions.entry(&entry.action).or_insert_with(|| ActionStats {
+                action: entry.action.clone(),
+                ..ActionStats::default()
+            });
+            stats.entries += 1;
+            match entry.outcome.as_str() {
+                "success" => stats.succeeded += 1,
+                "failure" => stats.failed += 1,
+                _ => {}
+            }
+            let is_backup = entry.action == "backup" || entry.action.starts_with("backup-");
+            if is_backup && entry.outcome == "success" {
+                *backups.entry(&entry.filename).or_default() += 1;
+                let size = entry.detail.as_deref().and_then(logged_size);
+                report.bytes_backed_up += size.unwrap_or(0);
+            }
+        }
+        report.actions = actions.into_values().collect();
+        report.actions.sort_by(|a, b| b.entries.cmp(&a.entries).then(a.action.cmp(&b.action)));
+        let mut backups: Vec<_> = backups.into_iter().map(|(f, n)| (f.to_string(), n)).collect();
+        backups.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        backups.truncate(top);
+        report.top_backups = backups;
+
+        let log_name = cfg.log_path.display().to_string();
+        let detail = format!("{} entries", report.entries);
+        log_action(cfg, "log-report", &log_name, "success", Some(&detail));
+        Ok(report)
+    }
+
+    /// Check the hash chain, and the numbering if entries are numbered, of the log and of
+    /// each rotated log, oldest first, and that each file carries on from the one before
+    /// it. The result is logged afterwards, so that entry is checked next time.
+    pub fn verify_log(&self) -> Result<Vec<LogCheck>, BackupError> {
+        let cfg = &self.config;
+        let checks = check_log_chains(cfg)?;
+        let log_name = cfg.log_path.display().to_string();
+        let broken = checks.iter().find_map(|c| c.broken_at.map(|line| (c, line, "broken")));
+        let gap = || checks.iter().find_map(|c| c.gap_at.map(|line| (c, line, "gap")));
+        match broken.or_else(gap) {
+            Some((check, line, outcome)) => {
+                let detail = format!("{} line {}", file_name_of(&check.path), line);
+                log_action(cfg, "verify-log", &log_name, outcome, Some(&detail));
+            }
+            None => log_action(cfg, "verify-log", &log_name, "intact", None),
+        }
+        Ok(checks)
+    }
+
+    /// What whole-set operations scan, as shown in the log: the backup directory or ".".
+    fn scan_target(&self) -> String {
+        match &self.config.backup_dir {
+            Some(dir) => dir.display().to_string(),
+            None => ".".to_string(),
+        }
+    }
+
+    /// Every backup of a file, newest first.
+    pub fn list(&self, filename: impl AsRef<OsStr>) -> Result<Vec<BackupEntry>, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let path = self.resolve_source(input)?;
+        let backups = find_backups(cfg, &self.base, &path)?;
+        if backups.is_empty() {
+            log_action(cfg, "list", filename, "empty", None);
+        } else {
+            let detail = format!("{} backups", backups.len());
+            log_action(cfg, "list", filename, "success", Some(&detail));
+        }
+        Ok(backups)
+    }
+
+    /// How a backup `list` found holds its content, checked as `restore` checks it before
+    /// choosing how to restore it.
+    pub fn backup_format(&self, backup: &Path) -> Result<BackupFormat, BackupError> {
+        backup_format(backup)
+    }
+
+    /// Whether a file exists, and whether its newest backup exists, matches it by checksum
+    /// and how old it is. Encrypted backups are not compared, so no passphrase is needed.
+    pub fn status(&self, filename: impl AsRef<OsStr>) -> Result<StatusReport, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let path = self.resolve_source(input)?;
+        let source_exists = path.is_file();
+        let backup = self.newest_backup(&path)?;
+        let matches = match &backup {
+            Some(b) if source_exists && !is_enc(&b.path) => {
+                Some(self.same_as_backup(&path, &b.path)?)
+            }
+            _ => None,
+        };
+        let age = backup.as_ref().and_then(|b| b.modified.elapsed().ok());
+        let stale = match (&backup, matches) {
+            (_, Some(matches)) => !matches,
+            (Some(b), None) => self.stale(&path, b)?,
+            (None, None) => false,
+        };
+        let outcome = match (&backup, matches) {
+            (None, _) => "no-backup",
+            (_, Some(true)) => "current",
+            _ if stale => "stale",
+            _ if !source_exists => "no-source",
+            _ => "unchecked",
+        };
+        log_action(cfg, "status", filename, outcome, None);
+        Ok(StatusReport { source: path, source_exists, backup, matches, age, stale })
+    }
+
+    /// Check a file's newest backup for monitoring: against its recorded checksum, then
+    /// against the live file (see `is_stale`; not if the file is gone), then,
+    /// with `max_age`, that it was written at most that long ago. The first check that
+    /// fails decides the status.
+    pub fn check(
+        &self,
+        filename: impl AsRef<OsStr>,
+        max_age: Option<Duration>,
+    ) -> Result<CheckReport, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let path = self.resolve_source(input)?;
+        let backup = self.newest_backup(&path)?;
+        let age = backup.as_ref().map(|b| b.modified.elapsed().unwrap_or_default());
+        let status = match &backup {
+            None => CheckStatus::Missing,
+            Some(b) => {
+                let corrupt = match read_checksum_sidecar(&b.path)? {
+                    Some((algorithm, recorded)) => {
+                        self.digests.file(algorithm, &b.path)? != recorded
+                    }
+                    None => false,
+                };
+                if corrupt {
+                    CheckStatus::Corrupt
+                } else if self.stale(&path, b)? {
+                    CheckStatus::Stale
+                } else if max_age.is_some_and(|max| age.is_some_and(|age| age > max)) {
+                    CheckStatus::TooOld
+                } else {
+                    CheckStatus::Ok
+                }
+            }
+        };
+        log_action(cfg, "check", filename, status.as_str(), None);
+        Ok(CheckReport { source: path, backup, age, status })
+    }
+
+    /// Delete the backups of a file beyond the `keep` most recent, or with `older_than`
+    /// those beyond them that are older (see `PruneOptions`), except those a kept delta
+    /// needs and those a snapshot labels.
+    pub fn prune(
+        &self,
+        filename: impl AsRef<OsStr>,
+        options: PruneOptions,
+    ) -> Result<PruneReport, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let keep = match options {
+            PruneOptions { keep: Some(keep), .. } => keep,
+            PruneOptions { keep: None, older_than: Some(_) } => 1,
+            PruneOptions { keep: None, older_than: None } => {
+                return Err(BackupError::InvalidInput(
+                    "prune needs the number of backups to keep or an age to remove".to_string(),
+                ));
+            }
+        };
+        let path = self.resolve_source(input)?;
+        let mut kept = find_backups(cfg, &self.base, &path)?;
+        kept.sort_by_key(|b| std::cmp::Reverse(backup_time(cfg, b)));
+        let total = kept.len();
+        let mut old = kept.split_off(keep.min(total));
+        if let Some(age) = options.older_than {
+            let now = SystemTime::now();
+            let recent = |b: &BackupEntry| {
+                now.duration_since(backup_time(cfg, b)).unwrap_or_default() <= age
+            };
+            let (recent, older): (Vec<_>, Vec<_>) = old.into_iter().partition(recent);
+            kept.extend(recent);
+            old = older;
+        }
+        // A kept delta is useless without the full backup it applies to.
+        let bases: Vec<PathBuf> = kept
+            .iter()
+            .filter(|b| is_delta(&b.path))
+            .filter_map(|b| delta_base(&b.path).ok())
+            .collect();
+        let labelled = labelled_backups(&self.base)?;
+        let (retained, removed): (Vec<_>, Vec<_>) = old
+            .into_iter()
+            .partition(|old| bases.contains(&old.path) || labelled.contains(&old.path));
+        kept.extend(retained);
+        if removed.is_empty() {
+            let detail = format!("kept {}, removed 0", kept.len());
+            log_action(cfg, "prune", filename, "success", Some(&detail));
+            return Ok(PruneReport { total, keep, removed, kept });
+        }
+        if cfg.dry_run {
+            let detail = format!("would remove {}, keep {}", removed.len(), kept.len());
+            log_action(cfg, "prune", filename, "dry-run", Some(&detail));
+            return Ok(PruneReport { total, keep, removed, kept });
+        }
+
+        for old in &removed {
+            remove_backup(&old.path)?;
+            log_action(cfg, "prune", filename, "removed", Some(&file_name_of(&old.path)));
+        }
+        if let Some(folder) = versions_dir_for(cfg, &self.base, &path) {
+            // Only succeeds once the folder is empty, which is what we want.
+            let _ = fs::remove_dir(folder);
+        }
+        let detail = format!("kept {}, removed {}", kept.len(), removed.len());
+        log_action(cfg, "prune", filename, "success", Some(&detail));
+        Ok(PruneReport { total, keep, removed, kept })
+    }
+
+    /// Delete a file once `confirm` agrees at the level it is given: by default it is moved
+    /// into ".trash/" (see `undelete`); with `purge` it is removed for good. `confirm` is
+    /// not called in a dry run or with `force`. The level (or "forced") is logged. Runs
+    /// between the `pre_hook` and the `post_hook`.
+    pub fn delete(
+        &self,
+        filename: impl AsRef<OsStr>,
+        options: DeleteOptions,
+        confirm: impl FnOnce(&Path, ConfirmLevel) -> io::Result<Confirmation>,
+    ) -> Result<DeleteReport, BackupError> {
+        let input = filename.as_ref();
+        self.with_hooks("delete", input, || self.run_delete(input, options, confirm))
+    }
+
+    fn run_delete(
+        &self,
+        input: &OsStr,
+        options: DeleteOptions,
+        confirm: impl FnOnce(&Path, ConfirmLevel) -> io::Result<Confirmation>,
+    ) -> Result<DeleteReport, BackupError> {
+        let cfg = &self.config;
+        let filename = &*input.to_string_lossy();
+        let path = resolve_safe_os_path(&self.base, input)?;
+        self.check_symlink(&path)?;
+        if !path.exists() {
+            return Err(BackupError::NotFound("File does not exist".to_string()));
+        }
+        self.check_case(&path)?;
+        if !path.is_file() {
+            return Err(BackupError::NotRegularFile(path));
+        }
+        let bytes = fs::metadata(&path)?.len();
+        let purge = options.purge || options.secure.is_some();
+        let trashed = if purge { None } else { Some(trash_path_for(&self.base, &path)) };
+        let mut report = DeleteReport { path, bytes, trashed, system_trash: false, deleted: false };
+
+        if cfg.dry_run {
+            if options.system_trash && !purge {
+                report.trashed = None;
+                report.system_trash = true;
+            }
+            let detail = format!("{} | {}", report.path.display(), format_size(bytes));
+            log_action(cfg, "delete", filename, "dry-run", Some(&detail));
+            return Ok(report);
+        }
+
+        let large = cfg.confirm_name_size.is_some_and(|size| bytes >= size);
+        let level = match options.confirm_name || large {
+            true => ConfirmLevel::TypedName,
+            false => ConfirmLevel::YesNo,
+        };
+        let answer = match options.force {
+            true => Confirmation::Yes,
+            false => confirm(&report.path, level)?,
+        };
+        report.deleted = match answer {
+            Confirmation::Yes => {
+                let how = if options.force { "forced" } else { level.name() };
+                log_action(cfg, "delete", filename, "confirmed", Some(how));
+                true
+            }
+            Confirmation::No => {
+                log_action(cfg, "delete", filename, "cancelled", None);
+                false
+            }
+            Confirmation::Mismatch => {
+                log_action(cfg, "delete", filename, "confirm-mismatch", None);
+                false
+            }
+        };
+        if !report.deleted {
+            return Ok(report);
+        }
+        let path = &report.path;
+        if options.system_trash && !purge {
+            match move_to_system_trash(path) {
+                Ok(()) => {
+                    debug!("moved {} to the system trash", path.display());
+                    log_action(cfg, "delete", filename, "trashed", Some("system trash"));
+                    let op = LastOperation::Delete {
+                        file: path.clone(),
+                        trashed: None,
+                        system_trash: true,
+                    };
+                    record_last_operation(&self.base, &op)?;
+                    report.trashed = None;
+                    report.system_trash = true;
+                    return Ok(report);
+                }
+                Err(e) => {
+                    let detail = format!("using {} | {}", TRASH_DIR, e);
+                    log_action(cfg, "delete", filename, "no-system-trash", Some(&detail));
+                }
+            }
+        }
+        match &report.trashed {
+            Some(dest) => {
+                fs::create_dir_all(self.base.join(TRASH_DIR))?;
+                fs::rename(path, dest)?;
+                debug!("moved {} to {}", path.display(), dest.display());
+                log_action(cfg, "delete", filename, "trashed", Some(&file_name_of(dest)));
+            }
+            None => match options.secure {
+                Some(pattern) => {
+                    wipe_file(path, pattern)?;
+                    debug!("overwrote {} bytes with {}", bytes, pattern.name());
+                    let detail = format!("success | {}", pattern.name());
+                    log_action(cfg, "delete", filename, "secure", Some(&detail));
+                }
+                None => {
+                    fs::remove_file(path)?;
+                    log_action(cfg, "delete", filename, "success", None);
+                }
+            },
+        }
+        let op = LastOperation::Delete {
+            file: path.clone(),
+            trashed: report.trashed.clone(),
+            system_trash: false,
+        };
+        record_last_operation(&self.base, &op)?;
+        Ok(report)
+    }
+
+    /// Rename a file within the base. An existing target is only replaced with `force`.
+    /// Across filesystems, where a rename is impossible, the file is copied (atomically)
+    /// and the original removed once the copy is complete.
+    pub fn move_file(
+        &self,
+        from: impl AsRef<OsStr>,
+        to: impl AsRef<OsStr>,
+        options: MoveOptions,
+    ) -> Result<MoveReport, BackupError> {
+        let cfg = &self.config;
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let (source, target) = self.transfer_paths(from, to, options.force)?;
+        let label = format!("{} -> {}", from.to_string_lossy(), to.to_string_lossy());
+        let bytes = fs::metadata(&source)?.len();
+        let mut report = MoveReport { source, target, bytes, copied: false };
+        if cfg.dry_run {
+            log_action(cfg, "move", &label, "dry-run", None);
+            return Ok(report);
+        }
+
+        if let Some(parent) = report.target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match fs::rename(&report.source, &report.target) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                debug!("rename across filesystems failed ({}); copying instead", e);
+                let buffer = cfg.copy_buffer_size;
+                copy_atomically(&report.source, &report.target, buffer, self.progress())?;
+                fs::remove_file(&report.source)?;
+                report.copied = true;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let detail = if report.copied { Some("copied across filesystems") } else { None };
+        log_action(cfg, "move", &label, "success", detail);
+        Ok(report)
+    }
+
+    /// Copy a file to another name within the base, atomically like a backup but without
+    /// the backup suffix or sidecars. An existing target is only replaced with `force`.
+    pub fn copy_file(
+        &self,
+        from: impl AsRef<OsStr>,
+        to: impl AsRef<OsStr>,
+        options: CopyOptions,
+    ) -> Result<CopyReport, BackupError> {
+        let cfg = &self.config;
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let (source, target) = self.transfer_paths(from, to, options.force)?;
+        let label = format!("{} -> {}", from.to_string_lossy(), to.to_string_lossy());
+        let bytes = fs::metadata(&source)?.len();
+        let report = CopyReport { source, target, bytes };
+        if cfg.dry_run {
+            log_action(cfg, "copy", &label, "dry-run", None);
+            return Ok(report);
+        }
+
+        if let Some(parent) = report.target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let buffer = cfg.copy_buffer_size;
+        let copied = copy_atomically(&report.source, &report.target, buffer, self.progress())?;
+        debug!("copied {} bytes", copied);
+        log_action(cfg, "copy", &label, "success", None);
+        Ok(report)
+    }
+
+    /// Resolve the source and target of a move or copy: the source must be a regular file,
+    /// the target must differ from it and may only exist (as a file) with `force`.
+    fn transfer_paths(
+        &self,
+        from: &OsStr,
+        to: &OsStr,
+        force: bool,
+    ) -> Result<(PathBuf, PathBuf), BackupError> {
+        let source = resolve_safe_os_path(&self.base, from)?;
+        let target = resolve_safe_os_path(&self.base, to)?;
+        if !source.exists() {
+            return Err(BackupError::NotFound("Source file does not exist".to_string()));
+        }
+        if !source.is_file() {
+            return Err(BackupError::NotRegularFile(source));
+        }
+        if target == source {
+            return Err(BackupError::InvalidInput("Source and target are the same".to_string()));
+        }
+        if target.is_dir() {
+            return Err(BackupError::NotRegularFile(target));
+        }
+        if target.exists() && !force {
+            return Err(BackupError::InvalidInput(format!(
+                "'{}' already exists; use --force to overwrite it",
+                to.to_string_lossy()
+            )));
+        }
+        Ok((source, target))
+    }
+
+    /// Bundle a file, or every regular file under a directory, into a tar archive
+    /// ("<name>.tar", or ".tar.gz" with `compress`) that stores paths relative to the base.
+    /// With `append` and an existing ".tar", only files whose size or modification time
+    /// differ from their last copy in it are added, so it keeps every version stored.
+    pub fn archive(
+        &self,
+        filename: impl AsRef<OsStr>,
+        options: ArchiveOptions,
+    ) -> Result<ArchiveReport, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let path = resolve_safe_os_path(&self.base, input)?;
+        if !path.exists() {
+            return Err(BackupError::NotFound("Source file does not exist".to_string()));
+        }
+        if options.append && options.compress {
+            return Err(BackupError::InvalidInput(
+                "Only a plain .tar can be appended to, not a .tar.gz".to_string(),
+            ));
+        }
+        let mut skipped = Vec::new();
+        let mut files = if path.is_dir() {
+            let mut rel = Vec::new();
+            let filter = DirFilter::default();
+            self.collect_files(filename, &path, Path::new(""), &filter, &mut rel, &mut skipped)?;
+            rel.into_iter().map(|r| path.join(r)).collect()
+        } else if path.is_file() {
+            vec![path.clone()]
+        } else {
+            return Err(BackupError::NotRegularFile(path));
+        };
+        let archive = archive_path_for(cfg, &self.base, &path, options.compress);
+        let appended = options.append && archive.is_file();
+        let mut unchanged = 0;
+        if appended {
+            // A name stored more than once extracts as its last copy.
+            let stored: HashMap<PathBuf, (u64, u64)> = list_tar(&archive)?
+                .into_iter()
+                .map(|member| (member.name, (member.size, member.mtime)))
+                .collect();
+            let mut changed = Vec::new();
+            for file in files {
+                let meta = fs::metadata(&file)?;
+                let mtime = meta.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+                let name = file.strip_prefix(&self.base).unwrap_or(&file);
+                match stored.get(name) {
+                    Some(&stored) if stored == (meta.len(), mtime.as_secs()) => unchanged += 1,
+                    _ => changed.push(file),
+                }
+            }
+            files = changed;
+        }
+        let mut bytes = 0;
+        for file in &files {
+            bytes += fs::metadata(file)?.len();
+        }
+        let report = ArchiveReport {
+            source: path,
+            archive,
+            files: files.len(),
+            unchanged,
+            appended,
+            bytes,
+            skipped,
+        };
+        if cfg.dry_run {
+            log_action(cfg, "archive", filename, "dry-run", None);
+            return Ok(report);
+        }
+
+        let name = file_name_of(&report.archive);
+        let detail = if appended {
+            append_tar(&report.archive, &self.base, &files)?;
+            format!("{} | appended {} files | {} unchanged", name, report.files, unchanged)
+        } else {
+            self.ensure_backup_dir(&report.archive)?;
+            write_tar(&report.archive, &self.base, &files, cfg.compress_level)?;
+            format!("{} | {} files", name, report.files)
+        };
+        log_action(cfg, "archive", filename, "success", Some(&detail));
+        Ok(report)
+    }
+
+    /// The regular files stored in an archive, in the order they were added; a file
+    /// appended more than once is listed once per copy.
+    pub fn archive_members(
+        &self,
+        filename: impl AsRef<OsStr>,
+    ) -> Result<Vec<ArchiveMember>, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let archive = resolve_safe_os_path(&self.base, input)?;
+        if !archive.is_file() {
+            return Err(BackupError::NotFound("Archive not found".to_string()));
+        }
+        let members: Vec<ArchiveMember> = list_tar(&archive)?
+            .into_iter()
+            .map(|member| ArchiveMember {
+                name: member.name,
+                size: member.size,
+                modified: UNIX_EPOCH + Duration::from_secs(member.mtime),
+            })
+            .collect();
+        let detail = format!("{} members", members.len());
+        log_action(cfg, "archive-list", &input.to_string_lossy(), "success", Some(&detail));
+        Ok(members)
+    }
+
+    /// Unpack an archive made by `archive` back under the base, or with `member` just one
+    /// of its files. Every entry is checked with `resolve_safe_path` first, and the whole
+    /// archive is refused if one would land outside the base or is a link. Existing files
+    /// are kept unless `overwrite` is set. A file stored more than once is written as its
+    /// last copy.
+    pub fn extract(
+        &self,
+        filename: impl AsRef<OsStr>,
+        options: ExtractOptions,
+    ) -> Result<ExtractReport, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let archive = resolve_safe_os_path(&self.base, input)?;
+        if !archive.is_file() {
+            return Err(BackupError::NotFound("Archive not found".to_string()));
+        }
+        let mut targets = read_tar_entries(&archive, &self.base)?;
+        let mut seen = HashSet::new();
+        targets.retain(|target| seen.insert(target.clone()));
+        if let Some(member) = options.member {
+            let wanted = resolve_safe_path(&self.base, member)?;
+            targets.retain(|target| *target == wanted);
+            if targets.is_empty() {
+                return Err(BackupError::NotFound(format!("'{}' is not in the archive", member)));
+            }
+        }
+        let (kept, extracted): (Vec<_>, Vec<_>) =
+            targets.into_iter().partition(|target| target.exists() && !options.overwrite);
+        let report = ExtractReport { archive, extracted, kept };
+        if cfg.dry_run {
+            log_action(cfg, "extract", filename, "dry-run", None);
+            return Ok(report);
+        }
+
+        extract_tar(&report.archive, &self.base, |t| report.extracted.iter().any(|e| e == t))?;
+        let detail = format!("{} files, kept {}", report.extracted.len(), report.kept.len());
+        log_action(cfg, "extract", filename, "success", Some(&detail));
+        Ok(report)
+    }
+
+    /// Bring back the most recently trashed copy of a file. Refuses to overwrite a file
+    /// that exists again under that name.
+    pub fn undelete(&self, filename: impl AsRef<OsStr>) -> Result<UndeleteReport, BackupError> {
+        let cfg = &self.config;
+        let input = filename.as_ref();
+        let filename = &*input.to_string_lossy();
+        let path = resolve_safe_os_path(&self.base, input)?;
+        let Some(trashed) = latest_trashed(&self.base, &path)? else {
+            return Err(BackupError::NotFound(format!("'{}' is not in the trash", filename)));
+        };
+        if path.exists() {
+            return Err(BackupError::InvalidInput(format!(
+                "'{}' already exists; move it away before undeleting",
+                filename
+            )));
+        }
+        let report = UndeleteReport { trashed, target: path };
+        if cfg.dry_run {
+            log_action(cfg, "undelete", filename, "dry-run", None);
+            return Ok(report);
+        }
+
+        if let Some(parent) = report.target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&report.trashed, &report.target)?;
+        let detail = file_name_of(&report.trashed);
+        log_action(cfg, "undelete", filename, "success", Some(&detail));
+        Ok(report)
+    }
+
+    /// Reverse the most recent delete or restore, the only one remembered: a trashed file
+    /// is put back, and a restored file gets the content it had before (or, if the restore
+    /// created it, is moved to the trash). A purge cannot be undone.
+    pub fn undo(&self) -> Result<UndoReport, BackupError> {
+        let cfg = &self.config;
+        let Some(op) = last_operation(&self.base)? else {
+            return Err(BackupError::NotFound("Nothing to undo".to_string()));
+        };
+        let name = |file: &Path| {
+            file.strip_prefix(&self.base).unwrap_or(file).to_string_lossy().into_owned()
+        };
+        let (action, file, from, trashed) = match op {
+            LastOperation::Delete { file, system_trash: true, .. } => {
+                return Err(BackupError::InvalidInput(format!(
+                    "'{}' went to the system trash; restore it from there",
+                    name(&file)
+                )));
+            }
+            LastOperation::Delete { file, trashed: None, .. } => {
+                return Err(BackupError::InvalidInput(format!(
+                    "'{}' was purged; that cannot be undone",
+                    name(&file)
+                )));
+            }
+            LastOperation::Delete { file, trashed: Some(_), .. } if file.exists() => {
+                return Err(BackupError::InvalidInput(format!(
+                    "'{}' exists again; move it away before undoing its delete",
+                    name(&file)
+                )));
+            }
+            LastOperation::Delete { file, trashed, .. } => ("delete", file, trashed, None),
+            LastOperation::Restore { file, snapshot: None } => {
+                let trashed = file.exists().then(|| trash_path_for(&self.base, &file));
+                ("restore", file, None, trashed)
+            }
+            LastOperation::Restore { file, snapshot } => ("restore", file, snapshot, None),
+        };
+        if let Some(from) = from.as_ref().filter(|from| !from.exists()) {
+            let msg = format!("{} is no longer in the trash", from.display());
+            return Err(BackupError::NotFound(msg));
+        }
+        let filename = &*name(&file);
+        let report = UndoReport { action, file, from, trashed };
+        if cfg.dry_run {
+            log_action(cfg, "undo", filename, "dry-run", Some(action));
+            return Ok(report);
+        }
+
+        if let Some(from) = &report.from {
+            if let Some(parent) = report.file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(from, &report.file)?;
+        } else if let Some(trashed) = &report.trashed {
+            fs::create_dir_all(self.base.join(TRASH_DIR))?;
+            fs::rename(&report.file, trashed)?;
+        }
+        clear_last_operation(&self.base)?;
+        log_action(cfg, "undo", filename, "success", Some(action));
+        Ok(report)
+    }
+}
+
+impl Drop for BackupManager {
+    /// Remember the digests computed on the way for the next run.
+    fn drop(&mut self) {
+        if let Err(e) = self.digests.save() {
+            debug!("could not save the checksum cache: {}", e);
+        }
+    }
+}
+
+/// Fail if `backup` is `source` itself, as an empty suffix or a misconfigured backup
+/// directory could make it: writing the backup would destroy the file it copies.
+fn refuse_backup_onto_source(source: &Path, backup: &Path) -> Result<(), BackupError> {
+    let same = source == backup
+        || (backup.exists() && fs::canonicalize(backup)? == fs::canonicalize(source)?);
+    if same {
+        return Err(BackupError::InvalidInput(format!(
+            "The backup of {} would be the file itself; check backup_suffix and backup_dir",
+            source.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Check one backup for `verify_all`.
+fn audit_backup(
+    digests: &ChecksumCache,
+    backup: &Path,
+    source: Option<&Path>,
+) -> io::Result<AuditStatus> {
+    if let Some((algorithm, recorded)) = read_checksum_sidecar(backup)? {
+        let ok = digests.file(algorithm, backup)? == recorded;
+        return Ok(if ok { AuditStatus::Ok } else { AuditStatus::Corrupt });
+    }
+    let Some(source) = source else {
+        return Ok(AuditStatus::Orphaned);
+    };
+    let ok = digests.file(HashAlgorithm::Sha256, source)? == digests.content(backup)?;
+    Ok(if ok { AuditStatus::Ok } else { AuditStatus::Stale })
+}
+
+/// How `new` differs from `old`: a unified diff headed by the two names when both are
+/// text, the differing byte ranges otherwise.
+fn content_changes(old: &[u8], new: &[u8], old_name: &str, new_name: &str) -> DiffChanges {
+    if old == new {
+        return DiffChanges::Identical;
+    }
+    match (text_of(old), text_of(new)) {
+        (Some(old_text), Some(new_text)) => {
+            let diff = similar::TextDiff::from_lines(old_text, new_text)
+                .unified_diff()
+                .header(old_name, new_name)
+                .to_string();
+            DiffChanges::Text(diff)
+        }
+        _ => {
+            let ranges = differing_ranges(old, new);
+            let changed = ranges.iter().map(|(start, end)| end - start).sum();
+            DiffChanges::Binary { ranges, changed }
+        }
+    }
+}
+
+/// `content` as text, if it is UTF-8 without NUL bytes.
+fn text_of(content: &[u8]) -> Option<&str> {
+    if content.contains(&0) {
+        return None;
+    }
+    std::str::from_utf8(content).ok()
+}
+
+/// The (start, end) offsets where `old` and `new` differ, adjacent bytes merged.
+fn differing_ranges(old: &[u8], new: &[u8]) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for i in 0..old.len().max(new.len()) {
+        if old.get(i) == new.get(i) {
+            continue;
+        }
+        let i = i as u64;
+        match ranges.last_mut() {
+            Some((_, end)) if *end == i => *end = i + 1,
+            _ => ranges.push((i, i + 1)),
+        }
+    }
+    ranges
+}
+
+/// True if `live` exists, was modified after `backup` was written, and differs from it:
+/// restoring would throw those changes away. Encrypted backups are not compared, so a
+/// later modification alone counts.
+fn live_is_newer(live: &Path, backup: &Path) -> io::Result<bool> {
+    if !live.is_file() {
+        return Ok(false);
+    }
+    if fs::metadata(live)?.modified()? <= fs::metadata(backup)?.modified()? {
+        return Ok(false);
+    }
+    if is_enc(backup) {
+        return Ok(true);
+    }
+    Ok(sha256_file(live)? != sha256_backup_content(backup)?)
+}
+
+/// Clear up after a directory backup that died while swapping in its new copy: with no
+/// `backup`, the previous one set aside in `old` is moved back; next to a `backup`, which
+/// is then the new one, `old` is removed.
+fn recover_old_backup(backup: &Path, old: &Path) -> io::Result<()> {
+    if !old.is_dir() {
+        return Ok(());
+    }
+    if backup.exists() {
+        fs::remove_dir_all(old)
+    } else {
+        fs::rename(old, backup)
+    }
+}
+
+/// Put the previous directory backup's copy of a file into the new one: a hardlink, or a
+/// copy where hardlinks are not supported.
+fn carry_over(previous: &Path, target: &Path, buffer_size: u64) -> io::Result<()> {
+    if let Err(e) = fs::hard_link(previous, target) {
+        debug!("cannot hardlink {}: {}; copying", previous.display(), e);
+        copy_atomically(previous, target, buffer_size, None)?;
+    }
+    Ok(())
+}
+
+/// `time` on the local clock. A time skipped by a DST change is an error; one that occurs
+/// twice means the earlier.
+fn local_time(time: chrono::NaiveDateTime) -> Result<SystemTime, BackupError> {
+    use chrono::TimeZone;
+    match chrono::Local.from_local_datetime(&time).earliest() {
+        Some(local) => Ok(local.into()),
+        None => Err(BackupError::InvalidInput(format!("{} does not exist locally", time))),
+    }
+}
+
+/// True if the files `a` and `b` have the same size and SHA-256.
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    Ok(sha256_file(a)? == sha256_file(b)?)
+}
+
+/// Collect the regular files under `root/rel` as paths relative to `root`, sorted; a
+/// directory backup holds nothing else.
+fn tree_files(root: &Path, rel: &Path, out: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(root.join(rel))?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let child = rel.join(entry.file_name());
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            tree_files(root, &child, out)?;
+        } else if kind.is_file() {
+            out.push(child);
+        }
+    }
+    Ok(())
+}
+
+/// Which entries a directory backup leaves out (see `BackupOptions::exclude`).
+#[derive(Default, Clone)]
+struct DirFilter {
+    exclude: Vec<glob::Pattern>,
+    skip_hidden: bool,
+    /// The rules of the `.backupignore` files from the backed-up directory down to the
+    /// directory being read.
+    ignore: Vec<IgnoreRule>,
+    /// The backup directory, relative to the backed-up directory, when it lies inside it;
+    /// a backup never copies earlier backups or itself.
+    backup_dir: Option<PathBuf>,
+}
+
+impl DirFilter {
+    fn new(options: BackupOptions) -> Result<Self, BackupError> {
+        let exclude = options
+            .exclude
+            .iter()
+            .map(|p| {
+                glob::Pattern::new(p).map_err(|e| {
+                    BackupError::InvalidInput(format!("Invalid exclude pattern '{}': {}", p, e))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(DirFilter { exclude, skip_hidden: options.skip_hidden, ..Default::default() })
+    }
+
+    /// The filter for the entries of `root/rel`: this one plus its `.backupignore`.
+    fn below(&self, root: &Path, rel: &Path) -> Result<Self, BackupError> {
+        let mut filter = self.clone();
+        filter.ignore.extend(read_ignore_rules(root, rel)?);
+        Ok(filter)
+    }
+
+    /// True if the entry at `rel` (relative to the backed-up directory) is left out.
+    /// `--exclude` and `skip_hidden` win over the `.backupignore` rules.
+    fn excludes(&self, rel: &Path, kind: fs::FileType) -> bool {
+        let name = rel.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        self.backup_dir.as_deref() == Some(rel)
+            || (self.skip_hidden && name.starts_with('.'))
+            || self.exclude.iter().any(|p| p.matches_path(rel) || p.matches(&name))
+            || is_ignored(&self.ignore, rel, kind.is_dir())
+    }
+}
+
+/// The size in a backup's log detail ("2.5 MB | 0.3s", "4 files | 10 B | 0.0s"), as a
+/// byte count; `None` if it has none.
+fn logged_size(detail: &str) -> Option<u64> {
+    detail
+        .split(" | ")
+        .filter(|field| field.ends_with('B') && field.contains(' '))
+        .find_map(|field| parse_size(field).ok())
+}
+
+/// Errors worth retrying: the operation may well succeed a moment later. Missing files,
+/// denied permissions and the like are not.
+fn is_transient(e: &io::Error) -> bool {
+    // A process that holds a file exclusively, as a service rotating its log does, often
+    // lets go of it a moment later.
+    let kind = e.kind();
+    matches!(kind, io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock)
+        || is_sharing_violation(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A manager over a fresh temporary base, with its log inside it.
+    fn manager(config: Config) -> (TempDir, BackupManager) {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config { log_path: dir.path().join("logfile.txt"), durable: false, ..config };
+        let mgr = BackupManager::new(dir.path(), config);
+        (dir, mgr)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restore_brings_back_the_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (dir, mgr) = manager(Config::default());
+        let script = dir.path().join("deploy.sh");
+        fs::write(&script, "#!/bin/sh\necho deployed\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        mgr.backup("deploy.sh", BackupOptions::default()).unwrap();
+
+        fs::remove_file(&script).unwrap();
+        mgr.restore("deploy.sh", RestoreOptions::default(), |_| Ok(true)).unwrap();
+        let mode = || fs::metadata(&script).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(), 0o755);
+
+        // Over a live file whose mode was changed since.
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o600)).unwrap();
+        let options = RestoreOptions { force: true, ..RestoreOptions::default() };
+        mgr.restore("deploy.sh", options, |_| Ok(true)).unwrap();
+        assert_eq!(mode(), 0o755);
+    }
+
+    #[test]
+    fn backup_succeeds_when_the_log_cannot_be_written() {
+        let (dir, mut mgr) = manager(Config::default());
+        // A log "inside" a regular file can be neither created nor locked, even as root.
+        fs::write(dir.path().join("blocker"), "").unwrap();
+        mgr.config.log_path = dir.path().join("blocker").join("logfile.txt");
+        fs::write(dir.path().join("notes.txt"), "keep me").unwrap();
+
+        let report = mgr.backup("notes.txt", BackupOptions::default());
+        assert!(matches!(report, Ok(BackupReport::File { .. })), "{:?}", report);
+        let backup = fs::read_to_string(dir.path().join("notes.txt.bak")).unwrap();
+        assert_eq!(backup, "keep me");
+        assert!(crate::log::log_write_failed());
+    }
+
+    #[test]
+    fn backup_dir_inside_the_source_is_left_out() {
+        let backup_dir = Some(PathBuf::from("project/backups"));
+        let (dir, mgr) = manager(Config { backup_dir, ..Config::default() });
+        let project = dir.path().join("project");
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::write(project.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(project.join("README"), "readme").unwrap();
+
+        for _ in 0..3 {
+            match mgr.backup("project", BackupOptions::default()).unwrap() {
+                BackupReport::Directory { files, .. } => assert_eq!(files, 2),
+                report => panic!("not a directory backup: {:?}", report),
+            }
+        }
+        let backups = project.join("backups");
+        let mut found = Vec::new();
+        let mut pending = vec![backups.clone()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(&dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    pending.push(path.clone());
+                }
+                found.push(path.strip_prefix(&backups).unwrap().to_path_buf());
+            }
+        }
+        assert!(!found.iter().any(|path| path.iter().any(|part| part == "backups")), "{:?}", found);
+        assert!(found.iter().all(|path| path.iter().count() <= 3), "{:?}", found);
+
+        // The backup directory itself cannot be the source.
+        let result = mgr.backup("project/backups", BackupOptions::default());
+        assert!(matches!(result, Err(BackupError::InvalidInput(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn empty_suffix_does_not_back_up_onto_the_source() {
+        // `Config::validate` rejects an empty suffix; a manager built around it must still
+        // refuse rather than overwrite the file with itself.
+        let (dir, mgr) = manager(Config { backup_suffix: String::new(), ..Config::default() });
+        let notes = dir.path().join("notes.txt");
+        fs::write(&notes, "the only copy").unwrap();
+
+        let result = mgr.backup("notes.txt", BackupOptions::default());
+        assert!(matches!(result, Err(BackupError::InvalidInput(_))), "{:?}", result);
+        assert_eq!(fs::read_to_string(&notes).unwrap(), "the only copy");
+        let names: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(names, [notes], "nothing else was written");
+    }
+}
@@ -0,0 +1,1075 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use log::debug;
+
+use crate::config::Config;
+use crate::crypto::ENC_MAGIC;
+use crate::delta::DELTA_MAGIC;
+use crate::error::BackupError;
+use crate::snapshot::SNAPSHOT_DIR;
+use crate::store::STORE_DIR;
+use crate::trash::TRASH_DIR;
+
+// ---------- Path helpers (simple & robust on Windows/macOS/Linux) ----------
+
+/// Resolve a user-supplied filename safely under `base` (the CLI passes the CWD).
+/// Rules:
+/// - reject empty names
+/// - treat '\' and '/' alike as separators (see `normalize_separators`)
+/// - reject absolute paths
+/// - reject any parent traversal ("..") anywhere in the input
+/// - reject reserved Windows device names ("CON", "nul.txt", ...) on every platform
+/// - reject names the filesystem could not store (see `MAX_COMPONENT_LEN`, `MAX_PATH_LEN`)
+/// - otherwise, join under `base` (no canonicalization needed → avoids Windows false-positives)
+pub fn resolve_safe_path(base: &Path, input: &str) -> Result<PathBuf, BackupError> {
+    resolve_safe_os_path(base, OsStr::new(input))
+}
+
+/// `resolve_safe_path` for names that need not be UTF-8, as Linux allows. The checks work
+/// on the path's components, so no lossy conversion is involved; surrounding whitespace is
+/// only trimmed from names that are valid UTF-8.
+pub fn resolve_safe_os_path(base: &Path, input: &OsStr) -> Result<PathBuf, BackupError> {
+    resolve_os_path(base, input, false)
+}
+
+/// `resolve_safe_os_path`, but with `allow_absolute` an absolute path is accepted as it
+/// is; every other rule (no "..", no device names, ...) still applies.
+pub(crate) fn resolve_os_path(
+    base: &Path,
+    input: &OsStr,
+    allow_absolute: bool,
+) -> Result<PathBuf, BackupError> {
+    let rejected = |msg: &str| Err(BackupError::PathRejected(msg.to_string()));
+    let trimmed = input.to_str().map_or(input, |s| OsStr::new(s.trim()));
+    if trimmed.is_empty() {
+        return rejected("Empty filename");
+    }
+    if trimmed.as_encoded_bytes().contains(&0) {
+        return rejected("Invalid character in filename");
+    }
+
+    let normalized = normalize_separators(trimmed);
+    if normalized.as_os_str() != trimmed {
+        debug!("normalized '{}' to '{}'", trimmed.to_string_lossy(), normalized.display());
+    }
+    let p = normalized.as_path();
+
+    // 1) No absolute paths (prevents /etc/passwd or C:\Windows\... etc.)
+    if p.is_absolute() && !allow_absolute {
+        return rejected("Absolute paths are not allowed");
+    }
+
+    // 2) No traversal components anywhere (prevents escaping the base directory)
+    for comp in p.components() {
+        if matches!(comp, Component::ParentDir) {
+            return rejected("Parent directory traversal is not allowed");
+        }
+    }
+
+    // 3) No device names, so the same file list works on Windows too
+    for comp in p.components() {
+        let name = comp.as_os_str().to_string_lossy();
+        if is_reserved_name(&name) {
+            return Err(BackupError::ReservedName(name.to_string()));
+        }
+    }
+
+    // 4) Join syntactically under the base directory
+    let resolved = base.join(p);
+
+    // 5) Refuse names too long to exist, rather than fail with an obscure I/O error later
+    if let Some(comp) = p.components().find(|c| os_len(c.as_os_str()) > MAX_COMPONENT_LEN) {
+        return Err(BackupError::PathRejected(format!(
+            "Filename too long: '{}...' is {} {}, the limit is {}",
+            comp.as_os_str().to_string_lossy().chars().take(20).collect::<String>(),
+            os_len(comp.as_os_str()),
+            OS_LEN_UNIT,
+            MAX_COMPONENT_LEN
+        )));
+    }
+    let long_path = resolved.to_string_lossy().starts_with(r"\\?\");
+    if !long_path && os_len(resolved.as_os_str()) > MAX_PATH_LEN {
+        return Err(BackupError::PathRejected(format!(
+            "Filename too long: the full path is {} {}, the limit is {}",
+            os_len(resolved.as_os_str()),
+            OS_LEN_UNIT,
+            MAX_PATH_LEN
+        )));
+    }
+    debug!("resolved '{}' -> {}", input.to_string_lossy(), resolved.display());
+    Ok(resolved)
+}
+
+/// True if the nearest directory of `target` that already exists is really inside `base`,
+/// symlinks resolved, so writing `target` cannot end up outside the base.
+pub(crate) fn stays_inside(base: &Path, target: &Path) -> io::Result<bool> {
+    let root = fs::canonicalize(base)?;
+    let existing = target.ancestors().skip(1).find(|dir| dir.exists()).unwrap_or(base);
+    Ok(fs::canonicalize(existing)?.starts_with(&root))
+}
+
+/// "folder\file.txt", "folder/file.txt" and "folder//file.txt" all become the same
+/// path, built from its components with the platform's separator. Windows already splits
+/// on both; elsewhere a backslash is turned into '/', so a name typed on Windows (and a
+/// "..\" in it) means the same thing on Linux and macOS.
+fn normalize_separators(input: &OsStr) -> PathBuf {
+    #[cfg(unix)]
+    let input = {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+        let bytes = input.as_bytes().iter().map(|&b| if b == b'\\' { b'/' } else { b });
+        OsString::from_vec(bytes.collect())
+    };
+    Path::new(&input).components().collect()
+}
+
+/// Longest file or directory name the common filesystems store: 255 bytes on Linux and
+/// macOS, 255 UTF-16 units on Windows.
+const MAX_COMPONENT_LEN: usize = 255;
+
+/// Longest whole path the platform opens: MAX_PATH on Windows (longer ones need a "\\?\"
+/// base), PATH_MAX on Unix.
+#[cfg(windows)]
+const MAX_PATH_LEN: usize = 260;
+#[cfg(not(windows))]
+const MAX_PATH_LEN: usize = 4096;
+
+/// What `os_len` counts.
+#[cfg(windows)]
+const OS_LEN_UNIT: &str = "characters";
+#[cfg(not(windows))]
+const OS_LEN_UNIT: &str = "bytes";
+
+/// Length of a name in the units its platform limits count.
+#[cfg(windows)]
+fn os_len(name: &OsStr) -> usize {
+    use std::os::windows::ffi::OsStrExt;
+    name.encode_wide().count()
+}
+
+#[cfg(not(windows))]
+fn os_len(name: &OsStr) -> usize {
+    name.len()
+}
+
+/// True for names Windows reserves for devices, case-insensitively and with or without
+/// an extension: CON, PRN, AUX, NUL, COM1-COM9, LPT1-LPT9.
+fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or("").trim_end().to_ascii_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => match stem.strip_prefix("COM").or_else(|| stem.strip_prefix("LPT")) {
+            Some(rest) => {
+                let mut digits = rest.chars();
+                matches!((digits.next(), digits.next()), (Some('1'..='9'), None))
+            }
+            None => false,
+        },
+    }
+}
+
+/// True if `input` contains glob metacharacters ("*", "?", "[").
+pub fn is_glob(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+/// Expand a glob such as "*.log" or "src/**/*.rs" under `base`. The pattern itself must
+/// pass `resolve_safe_path`, and matches are returned relative to `base`, sorted, so each
+/// can be fed back through the normal operations, whatever bytes their names hold.
+pub(crate) fn expand_glob(base: &Path, pattern: &str) -> Result<Vec<PathBuf>, BackupError> {
+    let full = resolve_safe_path(base, pattern)?;
+    let escaped_base = glob::Pattern::escape(&base.to_string_lossy());
+    let rel = full.strip_prefix(base).unwrap_or(&full);
+    let full_pattern = format!("{}/{}", escaped_base, rel.to_string_lossy());
+    let paths = glob::glob(&full_pattern)
+        .map_err(|e| BackupError::InvalidInput(format!("Invalid pattern '{}': {}", pattern, e)))?;
+
+    let mut matches = Vec::new();
+    for entry in paths {
+        let path = entry.map_err(|e| BackupError::Io(e.into()))?;
+        if let Ok(rel) = path.strip_prefix(base) {
+            matches.push(rel.to_path_buf());
+        }
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+/// The files with backups under `base` whose path, relative to `base`, matches a glob
+/// such as "*.conf", whether or not the files themselves still exist; sorted, one entry
+/// per file however many backups it has.
+pub(crate) fn expand_glob_in_backups(
+    cfg: &Config,
+    base: &Path,
+    pattern: &str,
+) -> Result<Vec<PathBuf>, BackupError> {
+    let full = resolve_safe_path(base, pattern)?;
+    let rel = full.strip_prefix(base).unwrap_or(&full);
+    let glob = glob::Pattern::new(&rel.to_string_lossy())
+        .map_err(|e| BackupError::InvalidInput(format!("Invalid pattern '{}': {}", pattern, e)))?;
+    let options = glob::MatchOptions { require_literal_separator: true, ..Default::default() };
+
+    let mut matches = Vec::new();
+    for backup in all_backups(cfg, base)? {
+        let Some(source) = source_for_any_backup(cfg, base, &backup) else {
+            continue;
+        };
+        if let Ok(rel) = source.strip_prefix(base) {
+            if glob.matches_path_with(rel, options) {
+                matches.push(rel.to_path_buf());
+            }
+        }
+    }
+    matches.sort();
+    matches.dedup();
+    Ok(matches)
+}
+
+/// The entry `path` actually names if it exists only under a differently cased name, as
+/// happens on case-insensitive filesystems (macOS, Windows). On a case-sensitive one such a
+/// path does not exist at all, so no probe of the filesystem is needed.
+pub(crate) fn case_variant(path: &Path) -> io::Result<Option<PathBuf>> {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(None);
+    };
+    if !path.exists() {
+        return Ok(None);
+    }
+    let wanted = name.to_string_lossy().to_lowercase();
+    let mut variant = None;
+    for entry in fs::read_dir(parent)? {
+        let entry_name = entry?.file_name();
+        if entry_name == name {
+            return Ok(None);
+        }
+        if variant.is_none() && entry_name.to_string_lossy().to_lowercase() == wanted {
+            variant = Some(parent.join(entry_name));
+        }
+    }
+    Ok(variant)
+}
+
+/// Final component of a path as a (lossy) string, or "" if there is none.
+pub(crate) fn file_name_of(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Where backups of `file` live and the name they are based on.
+/// Without a `backup_dir` that is simply beside the file, under its own name. With one,
+/// the name also encodes the file's subdirectories ("sub/notes.txt" -> "sub%2Fnotes.txt",
+/// with '%' escaped as "%25") so same-named files from different folders never collide.
+/// With `preserve_structure` the subdirectories are mirrored under the backup directory
+/// instead ("sub/notes.txt" -> "<backup_dir>/sub", "notes.txt"); a file outside the base
+/// keeps its flattened name at the top.
+pub(crate) fn backup_location(cfg: &Config, base: &Path, file: &Path) -> (PathBuf, String) {
+    let Some(backup_dir) = &cfg.backup_dir else {
+        let dir = match file.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        return (dir, file_name_of(file));
+    };
+
+    let root = base.join(backup_dir);
+    if cfg.preserve_structure {
+        if let Ok(rel) = file.strip_prefix(base) {
+            let folders = rel.parent().unwrap_or(Path::new(""));
+            return (root.join(folders), file_name_of(file));
+        }
+    }
+    (root, flat_name(base, file))
+}
+
+/// `file`'s path relative to `base` flattened into one name: "sub/notes.txt" ->
+/// "sub%2Fnotes.txt", with '%' escaped as "%25". An absolute path outside the base (see
+/// `allow_absolute`) keeps its root as a leading "%2F": "/etc/hosts" -> "%2Fetc%2Fhosts".
+/// Inverse of `unflatten_name`.
+pub(crate) fn flat_name(base: &Path, file: &Path) -> String {
+    let escape = |c: Component| c.as_os_str().to_string_lossy().replace('%', "%25");
+    match file.strip_prefix(base) {
+        Ok(rel) => rel.components().map(escape).collect::<Vec<_>>().join("%2F"),
+        Err(_) if file.is_absolute() => file
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .map(|c| format!("%2F{}", escape(c)))
+            .collect(),
+        Err(_) => file_name_of(file).replace('%', "%25"),
+    }
+}
+
+/// Undo `flat_name`: "sub%2Fnotes.txt" -> "sub/notes.txt" (relative), "%2Fetc%2Fhosts" ->
+/// "/etc/hosts".
+pub(crate) fn unflatten_name(name: &str) -> PathBuf {
+    let (mut rel, name) = match name.strip_prefix("%2F") {
+        Some(rest) => (PathBuf::from(std::path::MAIN_SEPARATOR_STR), rest),
+        None => (PathBuf::new(), name),
+    };
+    for part in name.split("%2F") {
+        rel.push(part.replace("%25", "%"));
+    }
+    rel
+}
+
+/// Create the backup path by appending the configured suffix to the full file name:
+/// "file.ext" -> "file.ext.bak", "archive.tar.gz" -> "archive.tar.gz.bak",
+/// ".env" -> ".env.bak", "noext" -> "noext.bak" (inside the backup directory if set).
+pub(crate) fn backup_path_for(cfg: &Config, base: &Path, file: &Path) -> PathBuf {
+    let (dir, name) = backup_location(cfg, base, file);
+    dir.join(format!("{}{}", name, cfg.backup_suffix))
+}
+
+/// Inverse of `backup_path_for`: the source a plain backup belongs to. The backup name must
+/// end in exactly the configured suffix (optionally followed by ".gz" or ".enc"); otherwise
+/// `None`.
+pub(crate) fn source_for_backup(cfg: &Config, base: &Path, backup: &Path) -> Option<PathBuf> {
+    let name = file_name_of(backup);
+    let name = strip_backup_ext(&name);
+    let stem = name.strip_suffix(cfg.backup_suffix.as_str()).filter(|s| !s.is_empty())?;
+    let parent = backup.parent().unwrap_or(Path::new(""));
+
+    let folders = cfg
+        .backup_dir
+        .as_ref()
+        .and_then(|d| base.join(parent).strip_prefix(base.join(d)).ok().map(Path::to_path_buf));
+    match folders {
+        Some(rel) if rel.as_os_str().is_empty() => {
+            if cfg.preserve_structure && !stem.starts_with("%2F") {
+                return Some(base.join(stem));
+            }
+            Some(base.join(unflatten_name(stem)))
+        }
+        Some(rel) if cfg.preserve_structure => Some(base.join(rel).join(stem)),
+        _ => Some(parent.join(stem)),
+    }
+}
+
+/// Like `source_for_backup`, but also maps versioned backups
+/// ("notes.txt.2024-06-01T14-30-00.bak", or "notes.txt/2024-06-01T14-30-00.bak" in the
+/// `versions_dir`) back to their source.
+pub(crate) fn source_for_any_backup(cfg: &Config, base: &Path, backup: &Path) -> Option<PathBuf> {
+    let name = file_name_of(backup);
+    strip_backup_ext(&name).strip_suffix(cfg.backup_suffix.as_str())?;
+    let folder = backup.parent().unwrap_or(Path::new(""));
+    let in_versions_dir = cfg
+        .versions_dir
+        .as_ref()
+        .is_some_and(|d| folder.parent().is_some_and(|p| base.join(d) == base.join(p)));
+    if in_versions_dir && version_of(cfg, &name, true, None).is_some() {
+        return Some(base.join(unflatten_name(&file_name_of(folder))));
+    }
+    match version_of(cfg, &name, false, None) {
+        Some(version) => {
+            let plain = format!("{}{}", version.name, cfg.backup_suffix);
+            source_for_backup(cfg, base, &backup.with_file_name(plain))
+        }
+        None => source_for_backup(cfg, base, backup),
+    }
+}
+
+/// Compressed variant of a backup path: "file.ext.bak" -> "file.ext.bak.gz".
+pub(crate) fn gz_path_for(backup: &Path) -> PathBuf {
+    backup.with_file_name(format!("{}.gz", file_name_of(backup)))
+}
+
+pub(crate) fn is_gz(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("gz"))
+}
+
+/// Encrypted variant of a backup path: "file.ext.bak" -> "file.ext.bak.enc".
+pub(crate) fn enc_path_for(backup: &Path) -> PathBuf {
+    backup.with_file_name(format!("{}.enc", file_name_of(backup)))
+}
+
+pub(crate) fn is_enc(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("enc"))
+}
+
+/// Incremental variant of a (versioned) backup path: "file.ext.<stamp>.bak" ->
+/// "file.ext.<stamp>.bak.delta".
+pub(crate) fn delta_path_for(backup: &Path) -> PathBuf {
+    backup.with_file_name(format!("{}.delta", file_name_of(backup)))
+}
+
+pub(crate) fn is_delta(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("delta"))
+}
+
+/// Deduplicated variant of a backup path, a reference into the store:
+/// "file.ext.bak" -> "file.ext.bak.ref".
+pub(crate) fn ref_path_for(backup: &Path) -> PathBuf {
+    backup.with_file_name(format!("{}.ref", file_name_of(backup)))
+}
+
+pub(crate) fn is_ref(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("ref"))
+}
+
+/// How a backup holds its file's content, which decides how it is restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupFormat {
+    /// A copy of the file as it was.
+    Plain,
+    /// Gzip-compressed (".gz").
+    Gzip,
+    /// Encrypted with a passphrase (".enc").
+    Encrypted,
+    /// Only the blocks that changed since a full backup (".delta").
+    Delta,
+    /// A reference to content kept once in the store (".ref").
+    Deduplicated,
+}
+
+impl BackupFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            BackupFormat::Plain => "plain",
+            BackupFormat::Gzip => "gzip",
+            BackupFormat::Encrypted => "encrypted",
+            BackupFormat::Delta => "incremental",
+            BackupFormat::Deduplicated => "deduplicated",
+        }
+    }
+}
+
+/// The format of the backup at `backup`: named by its ending, and for the formats that
+/// have one confirmed by the content's magic bytes, so that a damaged or renamed backup is
+/// refused with what it holds instead of restored as garbage. A plain backup can hold
+/// anything, a gzip file of its own included, so its content is not looked at.
+pub(crate) fn backup_format(backup: &Path) -> Result<BackupFormat, BackupError> {
+    let named = if is_gz(backup) {
+        BackupFormat::Gzip
+    } else if is_enc(backup) {
+        BackupFormat::Encrypted
+    } else if is_delta(backup) {
+        BackupFormat::Delta
+    } else if is_ref(backup) {
+        BackupFormat::Deduplicated
+    } else {
+        return Ok(BackupFormat::Plain);
+    };
+    if named == BackupFormat::Deduplicated {
+        return Ok(named);
+    }
+    let mut head = Vec::with_capacity(8);
+    fs::File::open(backup)?.take(8).read_to_end(&mut head)?;
+    let found = if head.starts_with(ENC_MAGIC) {
+        Some(BackupFormat::Encrypted)
+    } else if head.starts_with(DELTA_MAGIC) {
+        Some(BackupFormat::Delta)
+    } else if head.starts_with(&[0x1f, 0x8b]) {
+        Some(BackupFormat::Gzip)
+    } else {
+        None
+    };
+    if found == Some(named) {
+        return Ok(named);
+    }
+    let content = match found {
+        Some(format) => format!("is {}", format.name()),
+        None => "is not in any backup format".to_string(),
+    };
+    Err(BackupError::InvalidInput(format!(
+        "Cannot restore {}: it is named as a {} backup, but its content {}",
+        backup.display(),
+        named.name(),
+        content
+    )))
+}
+
+/// A backup name without its ".gz", ".enc", ".delta" or ".ref" ending: "notes.txt.bak.gz"
+/// -> "notes.txt.bak".
+pub(crate) fn strip_backup_ext(name: &str) -> &str {
+    [".gz", ".enc", ".delta", ".ref"]
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(name)
+}
+
+/// The backup used when no version is asked for: the plain ".bak", or its ".gz" (then
+/// ".enc", then ".ref") variant if only that one exists.
+pub(crate) fn default_backup_for(cfg: &Config, base: &Path, file: &Path) -> PathBuf {
+    let plain = backup_path_for(cfg, base, file);
+    if plain.exists() {
+        return plain;
+    }
+    [gz_path_for(&plain), enc_path_for(&plain), ref_path_for(&plain)]
+        .into_iter()
+        .find(|p| p.exists())
+        .unwrap_or(plain)
+}
+
+/// Timestamp format used in versioned backup names unless `version_stamp_format` says
+/// otherwise. No colons, so it is safe on Windows.
+pub(crate) const VERSION_STAMP_FORMAT: &str = "%Y-%m-%dT%H-%M-%S";
+
+/// Create a versioned backup path: "file.ext" -> "file.ext.2024-06-01T14-30-00.bak", or
+/// with `versions_dir` "<versions_dir>/file.ext/2024-06-01T14-30-00.bak" (see
+/// `versions_dir_for`). If that name is already taken (two backups in the same second), a
+/// counter is appended: "...2024-06-01T14-30-00-1.bak", "-2", ...
+pub(crate) fn versioned_backup_path_for(cfg: &Config, base: &Path, file: &Path) -> PathBuf {
+    versioned_backup_path_at(cfg, base, file, chrono::Local::now())
+}
+
+/// `versioned_backup_path_for` stamped with `time` instead of the current time.
+pub(crate) fn versioned_backup_path_at(
+    cfg: &Config,
+    base: &Path,
+    file: &Path,
+    time: chrono::DateTime<chrono::Local>,
+) -> PathBuf {
+    match versions_dir_for(cfg, base, file) {
+        Some(dir) => {
+            let template = version_template(cfg, true);
+            stamped_path(cfg, &dir, &template, &file_name_of(file), time)
+        }
+        None => stamped_backup_beside(cfg, base, file, time),
+    }
+}
+
+/// Path of a new incremental backup: a versioned name beside the plain ".bak" it applies
+/// to, with ".delta" appended. Deltas never go into `versions_dir`, as they must sit in the
+/// same directory as their base.
+pub(crate) fn delta_backup_path_for(cfg: &Config, base: &Path, file: &Path) -> PathBuf {
+    delta_path_for(&stamped_backup_beside(cfg, base, file, chrono::Local::now()))
+}
+
+/// "file.ext.<stamp>.bak" (or what `name_template` makes of it) in the file's backup
+/// location.
+fn stamped_backup_beside(
+    cfg: &Config,
+    base: &Path,
+    file: &Path,
+    time: chrono::DateTime<chrono::Local>,
+) -> PathBuf {
+    let (dir, name) = backup_location(cfg, base, file);
+    stamped_path(cfg, &dir, &version_template(cfg, false), &name, time)
+}
+
+/// The first free "<dir>/<version name><suffix>" for `name` at `time`, counting up the
+/// `{counter}` if needed.
+fn stamped_path(
+    cfg: &Config,
+    dir: &Path,
+    template: &[TemplatePart],
+    name: &str,
+    time: chrono::DateTime<chrono::Local>,
+) -> PathBuf {
+    let suffix = &cfg.backup_suffix;
+    let stamp = time.format(&cfg.version_stamp_format).to_string();
+    let at = |counter| {
+        dir.join(format!("{}{}", version_name(template, name, &stamp, counter), suffix))
+    };
+    let mut candidate = at(0);
+    let mut counter = 1;
+    while candidate.exists() || delta_path_for(&candidate).exists() {
+        candidate = at(counter);
+        counter += 1;
+    }
+    candidate
+}
+
+/// One piece of a versioned name template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TemplatePart {
+    Text(String),
+    Name,
+    Ext,
+    Timestamp,
+    Counter,
+}
+
+/// Parse a `name_template` such as "{timestamp}_{name}{ext}". It must hold `{name}`,
+/// `{ext}` and `{timestamp}` once each, so that versions of different files and of
+/// different times never share a name, and `{counter}` at most once; a missing
+/// `{counter}` goes right after the timestamp. The error is the reason, to follow the key.
+pub(crate) fn parse_name_template(template: &str) -> Result<Vec<TemplatePart>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while !rest.is_empty() {
+        let Some(open) = rest.find(['{', '}']) else {
+            parts.push(TemplatePart::Text(rest.to_string()));
+            break;
+        };
+        if open > 0 {
+            parts.push(TemplatePart::Text(rest[..open].to_string()));
+        }
+        let close = match rest[open..].find('}') {
+            Some(close) if rest.as_bytes()[open] == b'{' => open + close,
+            _ => return Err("has an unmatched brace".to_string()),
+        };
+        parts.push(match &rest[open + 1..close] {
+            "name" => TemplatePart::Name,
+            "ext" => TemplatePart::Ext,
+            "timestamp" => TemplatePart::Timestamp,
+            "counter" => TemplatePart::Counter,
+            other => return Err(format!("has an unknown placeholder {{{}}}", other)),
+        });
+        rest = &rest[close + 1..];
+    }
+    let count = |wanted: TemplatePart| parts.iter().filter(|p| **p == wanted).count();
+    for (part, key) in [
+        (TemplatePart::Name, "{name}"),
+        (TemplatePart::Ext, "{ext}"),
+        (TemplatePart::Timestamp, "{timestamp}"),
+    ] {
+        if count(part) != 1 {
+            return Err(format!("must hold {} exactly once", key));
+        }
+    }
+    match count(TemplatePart::Counter) {
+        0 => {
+            let at = parts.iter().position(|p| *p == TemplatePart::Timestamp).unwrap_or(0);
+            parts.insert(at + 1, TemplatePart::Counter);
+        }
+        1 => {}
+        _ => return Err("must hold {counter} at most once".to_string()),
+    }
+    Ok(parts)
+}
+
+/// The template versioned names follow before the suffix: `name_template` if set (checked
+/// when the config was loaded), otherwise "{name}{ext}.{timestamp}{counter}", or just
+/// "{timestamp}{counter}" in a `versions_dir` folder, which is named after the file.
+fn version_template(cfg: &Config, in_versions_dir: bool) -> Vec<TemplatePart> {
+    if let Some(parts) = cfg.name_template.as_deref().and_then(|t| parse_name_template(t).ok())
+    {
+        return parts;
+    }
+    let mut parts = match in_versions_dir {
+        true => Vec::new(),
+        false => vec![TemplatePart::Name, TemplatePart::Ext, TemplatePart::Text(".".into())],
+    };
+    parts.extend([TemplatePart::Timestamp, TemplatePart::Counter]);
+    parts
+}
+
+/// `name` split at its last dot into `{name}` and `{ext}`: "notes.txt" -> ("notes",
+/// ".txt"); a leading dot is part of the name (".env" -> (".env", "")).
+fn split_ext(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    }
+}
+
+/// The version name `template` gives `name` at `stamp`; `counter` 0 leaves `{counter}`
+/// empty.
+fn version_name(template: &[TemplatePart], name: &str, stamp: &str, counter: u32) -> String {
+    let (stem, ext) = split_ext(name);
+    let mut text = String::new();
+    for part in template {
+        match part {
+            TemplatePart::Text(t) => text.push_str(t),
+            TemplatePart::Name => text.push_str(stem),
+            TemplatePart::Ext => text.push_str(ext),
+            TemplatePart::Timestamp => text.push_str(stamp),
+            TemplatePart::Counter if counter > 0 => text.push_str(&format!("-{}", counter)),
+            TemplatePart::Counter => {}
+        }
+    }
+    text
+}
+
+/// What a version name says, as read back by `parse_version_name`.
+#[derive(Debug, Clone, Default)]
+struct VersionName {
+    /// The file name it is a version of, `{name}{ext}` (empty if the template has neither).
+    name: String,
+    stamp: chrono::NaiveDateTime,
+    /// The stamp as written with its counter, "2024-06-01T14-30-00-1", which
+    /// `select_backup` matches.
+    id: String,
+}
+
+/// Read `text` (a backup name without its suffix and extensions) as a name `template`
+/// made, if it is one.
+fn parse_version_name(format: &str, template: &[TemplatePart], text: &str) -> Option<VersionName> {
+    use chrono::TimeZone;
+
+    // The stamp is only tried at the lengths the format gives, not at every split.
+    let samples = [(2024, 1, 1, 0, 0, 0), (2099, 12, 30, 23, 59, 59), (2024, 9, 17, 10, 5, 30)];
+    let stamp_lengths: Vec<usize> = samples
+        .into_iter()
+        .filter_map(|(y, mo, d, h, mi, s)| {
+            chrono::Utc.with_ymd_and_hms(y, mo, d, h, mi, s).single()
+        })
+        .map(|t| t.format(format).to_string().len())
+        .collect();
+    let (min, max) = (stamp_lengths.iter().min()?, stamp_lengths.iter().max()?);
+    let accepts = |part: &TemplatePart, head: &str| match part {
+        TemplatePart::Name => !head.is_empty(),
+        TemplatePart::Ext => head.is_empty() || (head.len() > 1 && head.rfind('.') == Some(0)),
+        TemplatePart::Timestamp => {
+            (*min..=*max).contains(&head.len())
+                && chrono::NaiveDateTime::parse_from_str(head, format).is_ok()
+        }
+        TemplatePart::Counter => {
+            head.is_empty()
+                || head
+                    .strip_prefix('-')
+                    .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+        }
+        TemplatePart::Text(t) => head == t,
+    };
+    fn matches(
+        parts: &[TemplatePart],
+        text: &str,
+        accepts: &dyn Fn(&TemplatePart, &str) -> bool,
+    ) -> Option<Vec<String>> {
+        let Some((part, rest)) = parts.split_first() else {
+            return text.is_empty().then(Vec::new);
+        };
+        if let TemplatePart::Text(t) = part {
+            let mut found = matches(rest, text.strip_prefix(t.as_str())?, accepts)?;
+            found.insert(0, t.clone());
+            return Some(found);
+        }
+        let ends = text.char_indices().map(|(i, _)| i).chain([text.len()]);
+        for end in ends {
+            let (head, tail) = text.split_at(end);
+            if !accepts(part, head) {
+                continue;
+            }
+            if let Some(mut found) = matches(rest, tail, accepts) {
+                found.insert(0, head.to_string());
+                return Some(found);
+            }
+        }
+        None
+    }
+    let found = matches(template, text, &accepts)?;
+    let mut version = VersionName::default();
+    for (part, value) in template.iter().zip(found) {
+        match part {
+            TemplatePart::Name | TemplatePart::Ext => version.name.push_str(&value),
+            TemplatePart::Timestamp => {
+                version.stamp = chrono::NaiveDateTime::parse_from_str(&value, format).ok()?;
+                version.id.push_str(&value);
+            }
+            TemplatePart::Counter => version.id.push_str(&value),
+            TemplatePart::Text(_) => {}
+        }
+    }
+    Some(version)
+}
+
+/// Read the file name `backup_name` as a versioned backup, with the template for its
+/// place (`in_versions_dir`), and if `of` is given only as a version of that file name;
+/// `None` if it is not one (a plain ".bak" is not).
+fn version_of(
+    cfg: &Config,
+    backup_name: &str,
+    in_versions_dir: bool,
+    of: Option<&str>,
+) -> Option<VersionName> {
+    let text = strip_backup_ext(backup_name).strip_suffix(cfg.backup_suffix.as_str())?;
+    let mut template = version_template(cfg, in_versions_dir);
+    if let Some(name) = of {
+        let (stem, ext) = split_ext(name);
+        for part in &mut template {
+            match part {
+                TemplatePart::Name => *part = TemplatePart::Text(stem.to_string()),
+                TemplatePart::Ext => *part = TemplatePart::Text(ext.to_string()),
+                _ => {}
+            }
+        }
+    }
+    let mut version = parse_version_name(&cfg.version_stamp_format, &template, text)?;
+    if let Some(name) = of {
+        version.name = name.to_string();
+    }
+    Some(version)
+}
+
+/// The folder holding `file`'s versioned backups when `versions_dir` is set:
+/// "<versions_dir>/<name>", with the name flattened by `flat_name` ("sub/notes.txt" ->
+/// "sub%2Fnotes.txt") so each source gets a folder of its own, whatever slashes, dots or
+/// percent signs its path holds.
+pub(crate) fn versions_dir_for(cfg: &Config, base: &Path, file: &Path) -> Option<PathBuf> {
+    let dir = cfg.versions_dir.as_ref()?;
+    Some(base.join(dir).join(flat_name(base, file)))
+}
+
+/// True if `rest` (the part between "file.ext." and ".bak") is a version stamp in
+/// `format`, optionally followed by a "-N" collision counter.
+pub(crate) fn is_version_stamp(format: &str, rest: &str) -> bool {
+    let parses = |s: &str| chrono::NaiveDateTime::parse_from_str(s, format).is_ok();
+    if parses(rest) {
+        return true;
+    }
+    match rest.rsplit_once('-') {
+        Some((stamp, counter)) => {
+            !counter.is_empty() && counter.chars().all(|c| c.is_ascii_digit()) && parses(stamp)
+        }
+        None => false,
+    }
+}
+
+/// A backup found on disk for some source file.
+#[derive(Debug, Clone)]
+pub struct BackupEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: std::time::SystemTime,
+}
+
+/// When `backup` was made: the local time in its version stamp, or its modification time
+/// if it has none (the plain ".bak").
+pub(crate) fn backup_time(cfg: &Config, backup: &BackupEntry) -> std::time::SystemTime {
+    use chrono::TimeZone;
+
+    let name = file_name_of(&backup.path);
+    let stamp = version_of(cfg, &name, false, None)
+        .or_else(|| version_of(cfg, &name, true, None))
+        .map(|version| version.stamp);
+    stamp
+        .and_then(|stamp| chrono::Local.from_local_datetime(&stamp).earliest())
+        .map_or(backup.modified, Into::into)
+}
+
+/// Find every backup of `file`: the plain ".bak" plus any versioned backups in its backup
+/// directory and its `versions_dir` folder, compressed (".gz"), encrypted (".enc"),
+/// incremental (".delta"), deduplicated (".ref") or not. Sorted newest-first.
+pub(crate) fn find_backups(cfg: &Config, base: &Path, file: &Path) -> io::Result<Vec<BackupEntry>> {
+    let (dir, name) = backup_location(cfg, base, file);
+    let plain = backup_path_for(cfg, base, file);
+
+    let mut entries = Vec::new();
+    for entry in read_dir_if_exists(&dir)? {
+        let entry = entry?;
+        let entry_path = entry.path();
+        let entry_name = entry.file_name().to_string_lossy().to_string();
+
+        let is_plain = entry_path == plain
+            || entry_path == gz_path_for(&plain)
+            || entry_path == enc_path_for(&plain)
+            || entry_path == ref_path_for(&plain);
+        let is_versioned = version_of(cfg, &entry_name, false, Some(&name)).is_some();
+        if is_plain || is_versioned {
+            push_backup_entry(&mut entries, &entry)?;
+        }
+    }
+    if let Some(folder) = versions_dir_for(cfg, base, file) {
+        let own_name = file_name_of(file);
+        let of = cfg.name_template.is_some().then_some(own_name.as_str());
+        for entry in read_dir_if_exists(&folder)? {
+            let entry = entry?;
+            if version_of(cfg, &entry.file_name().to_string_lossy(), true, of).is_some() {
+                push_backup_entry(&mut entries, &entry)?;
+            }
+        }
+    }
+    // Hardlinked versions share their mtime; the later timestamp in the name goes first.
+    entries.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| b.path.cmp(&a.path)));
+    Ok(entries)
+}
+
+/// The entries of `dir`, or none if it does not exist.
+fn read_dir_if_exists(dir: &Path) -> io::Result<Vec<io::Result<fs::DirEntry>>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_dir(dir)?.collect())
+}
+
+/// Add `entry` to `entries` if it is a regular file.
+fn push_backup_entry(entries: &mut Vec<BackupEntry>, entry: &fs::DirEntry) -> io::Result<()> {
+    let meta = entry.metadata()?;
+    if meta.is_file() {
+        let modified = meta.modified()?;
+        entries.push(BackupEntry { path: entry.path(), size: meta.len(), modified });
+    }
+    Ok(())
+}
+
+/// Every file backup under `base`: the whole backup directory if one is configured (and its
+/// subdirectories under `preserve_structure`), otherwise every ".bak" (versioned, compressed
+/// or not) found beside the files, skipping the trash, the store and directory backups;
+/// then every version in the `versions_dir` folders. Sorted by path.
+pub(crate) fn all_backups(cfg: &Config, base: &Path) -> io::Result<Vec<PathBuf>> {
+    let suffix = cfg.backup_suffix.as_str();
+    let is_backup_name = |name: &str| strip_backup_ext(name).ends_with(suffix);
+    let versions_root = cfg.versions_dir.as_ref().map(|d| base.join(d));
+    let mut found = Vec::new();
+
+    // A mirrored backup directory is walked like the base itself.
+    let (root, recursive) = match &cfg.backup_dir {
+        Some(dir) => (base.join(dir), cfg.preserve_structure),
+        None => (base.to_path_buf(), true),
+    };
+    if !recursive {
+        for entry in read_dir_if_exists(&root)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() && is_backup_name(&entry.file_name().to_string_lossy())
+            {
+                found.push(entry.path());
+            }
+        }
+    } else if root.is_dir() {
+        let mut pending = vec![root];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                let kind = entry.file_type()?;
+                if kind.is_dir() {
+                    let is_versions_root = versions_root.as_ref() == Some(&entry.path());
+                    let skipped = name == TRASH_DIR
+                        || name == STORE_DIR
+                        || name == SNAPSHOT_DIR
+                        || is_versions_root;
+                    if !skipped && !name.ends_with(suffix) {
+                        pending.push(entry.path());
+                    }
+                } else if kind.is_file() && is_backup_name(&name) {
+                    found.push(entry.path());
+                }
+            }
+        }
+    }
+    if let Some(root) = &versions_root {
+        for folder in read_dir_if_exists(root)? {
+            let folder = folder?;
+            if !folder.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(folder.path())? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if entry.file_type()?.is_file() && is_backup_name(&name) {
+                    found.push(entry.path());
+                }
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Pick one backup of `file` by identifier: either a 1-based index as printed
+/// by `list`, or a (prefix of a) version timestamp such as "2024-06-01T14".
+pub(crate) fn select_backup(
+    cfg: &Config,
+    base: &Path,
+    file: &Path,
+    id: &str,
+) -> Result<PathBuf, BackupError> {
+    let backups = find_backups(cfg, base, file)?;
+
+    if let Ok(index) = id.parse::<usize>() {
+        return match index.checked_sub(1).and_then(|i| backups.get(i)) {
+            Some(b) => Ok(b.path.clone()),
+            None => Err(BackupError::NotFound(format!(
+                "No backup #{} (found {})",
+                index,
+                backups.len()
+            ))),
+        };
+    }
+
+    // Matched against the stamp wherever `name_template` puts it in the name.
+    let matches: Vec<&BackupEntry> = backups
+        .iter()
+        .filter(|b| {
+            let name = file_name_of(&b.path);
+            let version = version_of(cfg, &name, false, None)
+                .or_else(|| version_of(cfg, &name, true, None));
+            version.is_some_and(|v| v.id.starts_with(id))
+        })
+        .collect();
+    match matches.as_slice() {
+        [] => Err(BackupError::NotFound(format!("No backup version matches '{}'", id))),
+        [one] => Ok(one.path.clone()),
+        many => Err(BackupError::InvalidInput(format!(
+            "Version '{}' is ambiguous: matches {} backups",
+            id,
+            many.len()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backup_name(file: &str) -> String {
+        let base = Path::new("base");
+        file_name_of(&backup_path_for(&Config::default(), base, &base.join(file)))
+    }
+
+    fn source_name(backup: &str) -> Option<String> {
+        let base = Path::new("base");
+        let source = source_for_backup(&Config::default(), base, &base.join(backup))?;
+        Some(file_name_of(&source))
+    }
+
+    #[test]
+    fn dotfiles_keep_their_whole_name() {
+        assert_eq!(backup_name(".env"), ".env.bak");
+        assert_eq!(backup_name(".bashrc"), ".bashrc.bak");
+        assert_eq!(backup_name("archive.tar.gz"), "archive.tar.gz.bak");
+        assert_eq!(backup_name("noext"), "noext.bak");
+        for file in [".env", ".bashrc", "archive.tar.gz", "noext"] {
+            assert_eq!(source_name(&backup_name(file)).as_deref(), Some(file));
+        }
+    }
+
+    #[test]
+    fn suffix_is_appended_to_multi_extension_names() {
+        assert_eq!(backup_name("archive.tar.gz"), "archive.tar.gz.bak");
+        assert_eq!(backup_name("archive.tar.bz2"), "archive.tar.bz2.bak");
+        assert_eq!(source_name("archive.tar.gz.bak").as_deref(), Some("archive.tar.gz"));
+        assert_eq!(source_name("archive.tar.bz2.bak").as_deref(), Some("archive.tar.bz2"));
+        // A compressed backup of one.
+        assert_eq!(source_name("archive.tar.gz.bak.gz").as_deref(), Some("archive.tar.gz"));
+    }
+
+    #[test]
+    fn dotted_names_without_an_extension_round_trip() {
+        for file in ["release.2024.06", "v1.2.3", ".env.local", "notes.", "a..b"] {
+            let backup = backup_name(file);
+            assert_eq!(backup, format!("{}.bak", file));
+            assert_eq!(source_name(&backup).as_deref(), Some(file));
+        }
+    }
+
+    #[test]
+    fn only_names_ending_in_the_suffix_are_backups() {
+        assert_eq!(source_name("archive.tar.gz"), None);
+        assert_eq!(source_name("notes.bak.txt"), None);
+        assert_eq!(source_name(".bak"), None);
+    }
+
+    fn rejected(base: &Path, input: &str) -> bool {
+        matches!(resolve_safe_path(base, input), Err(BackupError::PathRejected(_)))
+    }
+
+    #[test]
+    fn unsafe_names_are_rejected() {
+        let base = tempfile::tempdir().unwrap();
+        let base = base.path();
+        assert!(rejected(base, "../x"));
+        assert!(rejected(base, "a/../b"));
+        assert!(rejected(base, "a\\..\\b"));
+        assert!(rejected(base, "/etc/passwd"));
+        assert!(rejected(base, ""));
+        assert!(rejected(base, "   "));
+        assert!(rejected(base, "notes\0.txt"));
+        assert!(matches!(resolve_safe_path(base, "CON"), Err(BackupError::ReservedName(_))));
+    }
+
+    #[test]
+    fn safe_names_join_under_the_base() {
+        let base = tempfile::tempdir().unwrap();
+        let base = base.path();
+        assert_eq!(resolve_safe_path(base, "notes.txt").unwrap(), base.join("notes.txt"));
+        assert_eq!(resolve_safe_path(base, " notes.txt ").unwrap(), base.join("notes.txt"));
+        assert_eq!(resolve_safe_path(base, "./a/b").unwrap(), base.join("a").join("b"));
+        assert_eq!(resolve_safe_path(base, "a\\b").unwrap(), base.join("a").join("b"));
+    }
+}
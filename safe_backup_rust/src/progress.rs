@@ -0,0 +1,117 @@
+use std::io;
+use std::time::Duration;
+
+use crate::cancel::check_cancelled;
+
+// ---------- Progress ----------
+
+/// Called with (bytes done, bytes total) while a file is being copied.
+pub type ProgressFn = dyn Fn(u64, u64) + Send + Sync;
+
+/// How far a directory backup has got, reported after each file and while one is copied.
+#[derive(Debug, Clone, Copy)]
+pub struct DirProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    /// Bytes of the files done plus those copied of the current one.
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// Called with a directory backup's overall progress.
+pub type DirProgressFn = dyn Fn(DirProgress) + Send + Sync;
+
+/// Reader that reports how much of `total` has been read so far.
+pub(crate) struct ProgressReader<'a, R> {
+    inner: R,
+    done: u64,
+    total: u64,
+    report: Option<&'a ProgressFn>,
+}
+
+impl<'a, R: io::Read> ProgressReader<'a, R> {
+    pub(crate) fn new(inner: R, total: u64, report: Option<&'a ProgressFn>) -> Self {
+        ProgressReader { inner, done: 0, total, report }
+    }
+}
+
+impl<R: io::Read> io::Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        check_cancelled()?;
+        let n = self.inner.read(buf)?;
+        if let Some(report) = self.report {
+            self.done += n as u64;
+            report(self.done, self.total.max(self.done));
+        }
+        Ok(n)
+    }
+}
+
+// ---------- Transfer stats ----------
+
+/// Transfers at least this big also report their throughput.
+const THROUGHPUT_MIN_BYTES: u64 = 10 * 1024 * 1024;
+
+/// "5.2 MB": a byte count in the largest unit that keeps it at or above 1.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// "0.8s", "45s", "1m 3s", "3h 12m", "2d 5h": a duration in its two largest units, with
+/// tenths of a second below 10 seconds.
+pub fn format_duration(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    let (days, hours, mins) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60);
+    match secs {
+        0..=9 => format!("{:.1}s", elapsed.as_secs_f64()),
+        10..=59 => format!("{}s", secs),
+        60..=3_599 => format!("{}m {}s", mins, secs % 60),
+        3_600..=86_399 => format!("{}h {}m", hours, mins),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+/// Parse a size as a byte count with an optional 1024-based unit: "500", "64K", "1.5M",
+/// "2G", "1TB" (case-insensitive; "KB", "KiB" and "K" all mean the same).
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("'{}' is not a size", text))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let unit = unit.strip_suffix("IB").or_else(|| unit.strip_suffix('B')).unwrap_or(&unit);
+    let shift = match unit {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("unknown size unit in '{}' (use K, M, G or T)", text)),
+    };
+    let bytes = number * (1u64 << shift) as f64;
+    if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+        return Err(format!("'{}' is too large", text));
+    }
+    Ok(bytes as u64)
+}
+
+/// The size and duration of a transfer, plus its throughput for large ones:
+/// ["5.2 MB", "0.8s"] or ["52.0 MB", "1m 3s", "845.2 KB/s"].
+pub fn transfer_stats(bytes: u64, elapsed: Duration) -> Vec<String> {
+    let secs = elapsed.as_secs_f64();
+    let mut stats = vec![format_size(bytes), format_duration(elapsed)];
+    if bytes >= THROUGHPUT_MIN_BYTES && secs > 0.0 {
+        stats.push(format!("{}/s", format_size((bytes as f64 / secs) as u64)));
+    }
+    stats
+}
@@ -0,0 +1,325 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use log::debug;
+use ssh2::{
+    CheckResult, ErrorCode, FileStat, KnownHostFileKind, OpenFlags, OpenType, Session, Sftp,
+};
+use zeroize::Zeroizing;
+
+use crate::error::BackupError;
+
+// ---------- SFTP ----------
+
+/// Asked for the password of "user@host" when the server accepts none of the keys. Errors
+/// (no terminal to ask on) fail the transfer.
+pub type SftpPasswordFn = dyn Fn(&str) -> io::Result<String> + Send + Sync;
+
+/// An SSH server stops answering within this.
+const SFTP_TIMEOUT_MS: u32 = 30_000;
+
+/// Private keys tried in ~/.ssh, after the agent, when no `sftp_identity` is set.
+const DEFAULT_KEYS: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// libssh2's LIBSSH2_FX_NO_SUCH_FILE.
+const FX_NO_SUCH_FILE: i32 = 2;
+
+/// How `SftpDir` logs in: with `identity` if set, else with the ssh agent or a default
+/// key in ~/.ssh, else with the password `password` asks for.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct SftpAuth<'a> {
+    pub(crate) identity: Option<&'a Path>,
+    pub(crate) password: Option<&'a SftpPasswordFn>,
+}
+
+/// A directory on an SFTP server, "sftp://[user@]host[:port]/path". The server's host key
+/// must be in ~/.ssh/known_hosts, as ssh itself checks it; a server not seen before is
+/// refused rather than trusted.
+#[derive(Debug, Clone)]
+pub(crate) struct SftpDir {
+    /// Without one in the URL, the local user name.
+    user: Option<String>,
+    host: String,
+    port: Option<u16>,
+    /// Absolute, or relative to the login's home directory for "sftp://host/~/path".
+    path: String,
+}
+
+/// True if `text` names an SFTP location rather than a local path.
+pub(crate) fn is_sftp_url(text: &str) -> bool {
+    text.starts_with("sftp://")
+}
+
+impl SftpDir {
+    pub(crate) fn parse(url: &str) -> Result<SftpDir, BackupError> {
+        let invalid = |why: &str| {
+            BackupError::InvalidInput(format!("Invalid SFTP location '{}': {}", url, why))
+        };
+        let rest = url.strip_prefix("sftp://").ok_or_else(|| invalid("not sftp://"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let (destination, port) = match authority.rsplit_once(':') {
+            Some((destination, port)) => {
+                let port = port.parse().map_err(|_| invalid("the port is not a number"))?;
+                (destination, Some(port))
+            }
+            None => (authority, None),
+        };
+        let (user, host) = match destination.rsplit_once('@') {
+            Some((user, host)) if !user.is_empty() => (Some(user.to_string()), host),
+            Some(_) => return Err(invalid("no user before '@'")),
+            None => (None, destination),
+        };
+        if host.is_empty() || host.starts_with('-') {
+            return Err(invalid("no host"));
+        }
+        if destination.contains(|c: char| c.is_whitespace() || c.is_control()) {
+            return Err(invalid("spaces in the host"));
+        }
+        let path = match path.strip_prefix("/~/") {
+            Some(home) if !home.is_empty() => home.trim_end_matches('/').to_string(),
+            Some(_) => ".".to_string(),
+            None if path == "/" => path.to_string(),
+            None => path.trim_end_matches('/').to_string(),
+        };
+        Ok(SftpDir { user, host: host.to_string(), port, path })
+    }
+
+    /// Upload each (local file, remote name) into the directory, creating the directory
+    /// (not its parents) if needed. Each file goes to "<name>.tmp" first and only replaces
+    /// `name` once complete; times and permissions are kept.
+    pub(crate) fn upload(&self, files: &[(&Path, String)], auth: SftpAuth) -> io::Result<()> {
+        let run = || -> io::Result<()> {
+            let sftp = self.connect(auth)?;
+            let dir = Path::new(&self.path);
+            if sftp.stat(dir).is_err() {
+                sftp.mkdir(dir, 0o755)?;
+            }
+            for (local, name) in files {
+                let remote = self.remote_path(name);
+                let partial = self.remote_path(&format!("{}.tmp", name));
+                let meta = fs::metadata(local)?;
+                let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+                let mut out = sftp.open_mode(&partial, flags, 0o600, OpenType::File)?;
+                io::copy(&mut fs::File::open(local)?, &mut out)?;
+                out.setstat(stat_of(&meta)?)?;
+                drop(out);
+                replace_remote(&sftp, &partial, &remote)?;
+                debug!("uploaded {} to {}/{}", local.display(), self, name);
+            }
+            Ok(())
+        };
+        run().map_err(|e| self.failed(e))
+    }
+
+    /// Download every file of the directory whose name starts with `prefix` into `dest`,
+    /// keeping times and permissions. No matching file, or no directory, is not an error;
+    /// partial uploads ("*.tmp") are left out.
+    pub(crate) fn download(&self, prefix: &str, dest: &Path, auth: SftpAuth) -> io::Result<()> {
+        let run = || -> io::Result<()> {
+            let sftp = self.connect(auth)?;
+            let entries = match sftp.readdir(Path::new(&self.path)) {
+                Ok(entries) => entries,
+                Err(e) if e.code() == ErrorCode::SFTP(FX_NO_SUCH_FILE) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            for (entry, stat) in entries {
+                let Some(name) = entry.file_name().and_then(|n| n.to_str()) else {
+                    continue;
+                };
+                if !name.starts_with(prefix) || name.ends_with(".tmp") || !stat.is_file() {
+                    continue;
+                }
+                let target = dest.join(name);
+                let mut input = sftp.open(self.remote_path(name))?;
+                let mut out = fs::File::create(&target)?;
+                io::copy(&mut input, &mut out)?;
+                if let Some(mtime) = stat.mtime {
+                    out.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+                }
+                #[cfg(unix)]
+                if let Some(perm) = stat.perm {
+                    use std::os::unix::fs::PermissionsExt;
+                    out.set_permissions(fs::Permissions::from_mode(perm & 0o7777))?;
+                }
+                debug!("downloaded {}/{} to {}", self, name, target.display());
+            }
+            Ok(())
+        };
+        run().map_err(|e| self.failed(e))
+    }
+
+    /// "<path>/<name>" on the server, which always separates with '/'.
+    fn remote_path(&self, name: &str) -> PathBuf {
+        match self.path.as_str() {
+            "/" => PathBuf::from(format!("/{}", name)),
+            dir => PathBuf::from(format!("{}/{}", dir, name)),
+        }
+    }
+
+    /// The user to log in as: the URL's, or the local one's name.
+    fn user(&self) -> io::Result<String> {
+        if let Some(user) = &self.user {
+            return Ok(user.clone());
+        }
+        ["USER", "USERNAME", "LOGNAME"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok().filter(|u| !u.is_empty()))
+            .ok_or_else(|| io::Error::other("no user name; give one as sftp://user@host/..."))
+    }
+
+    /// Connect, check the host key and log in (see `SftpAuth`).
+    fn connect(&self, auth: SftpAuth) -> io::Result<Sftp> {
+        let port = self.port.unwrap_or(22);
+        let tcp = TcpStream::connect((self.host.as_str(), port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.set_timeout(SFTP_TIMEOUT_MS);
+        session.handshake()?;
+        self.check_host_key(&session, port)?;
+        let user = self.user()?;
+        self.authenticate(&session, &user, auth)?;
+        debug!("logged in to {} as {}", self, user);
+        Ok(session.sftp()?)
+    }
+
+    /// Refuse a server whose host key is not the one ~/.ssh/known_hosts has for it.
+    fn check_host_key(&self, session: &Session, port: u16) -> io::Result<()> {
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| io::Error::other("the server sent no host key"))?;
+        let mut known = session.known_hosts()?;
+        if let Some(file) = home_dir().map(|home| home.join(".ssh").join("known_hosts")) {
+            if file.is_file() {
+                known.read_file(&file, KnownHostFileKind::OpenSSH)?;
+            }
+        }
+        match known.check_port(&self.host, port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::NotFound => Err(io::Error::other(format!(
+                "{} is not in ~/.ssh/known_hosts; connect once with ssh to check and accept \
+                 its host key",
+                self.host
+            ))),
+            CheckResult::Mismatch => Err(io::Error::other(format!(
+                "the host key of {} does not match ~/.ssh/known_hosts; it may have been \
+                 replaced, or the connection intercepted",
+                self.host
+            ))),
+            CheckResult::Failure => Err(io::Error::other("cannot check the host key")),
+        }
+    }
+
+    fn authenticate(&self, session: &Session, user: &str, auth: SftpAuth) -> io::Result<()> {
+        if let Some(key) = auth.identity {
+            return session.userauth_pubkey_file(user, None, key, None).map_err(|e| {
+                io::Error::other(format!("the key {} was not accepted: {}", key.display(), e))
+            });
+        }
+        if let Err(e) = session.userauth_agent(user) {
+            debug!("ssh agent: {}", e);
+        }
+        let keys = home_dir().map(|home| home.join(".ssh")).into_iter().flat_map(|ssh| {
+            DEFAULT_KEYS.iter().map(move |key| ssh.join(key)).filter(|key| key.is_file())
+        });
+        for key in keys {
+            if session.authenticated() {
+                break;
+            }
+            if let Err(e) = session.userauth_pubkey_file(user, None, &key, None) {
+                debug!("key {}: {}", key.display(), e);
+            }
+        }
+        if session.authenticated() {
+            return Ok(());
+        }
+        let methods = session.auth_methods(user).unwrap_or_default();
+        let Some(prompt) = auth.password.filter(|_| methods.split(',').any(|m| m == "password"))
+        else {
+            let msg = format!("no key was accepted for {} (the server offers: {})", user, methods);
+            return Err(io::Error::other(msg));
+        };
+        let password = Zeroizing::new(prompt(&format!("{}@{}", user, self.host))?);
+        session
+            .userauth_password(user, &password)
+            .map_err(|e| io::Error::other(format!("the password was not accepted: {}", e)))
+    }
+
+    fn failed(&self, e: io::Error) -> io::Error {
+        io::Error::new(e.kind(), format!("SFTP to {} failed: {}", self, e))
+    }
+}
+
+impl fmt::Display for SftpDir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sftp://")?;
+        if let Some(user) = &self.user {
+            write!(f, "{}@", user)?;
+        }
+        write!(f, "{}", self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        match self.path.starts_with('/') {
+            true => write!(f, "{}", self.path),
+            false => write!(f, "/~/{}", self.path),
+        }
+    }
+}
+
+/// Put the finished upload `partial` in place of `remote`. Servers speaking SFTP version
+/// 3, as OpenSSH does, refuse to rename onto an existing file, so a previous `remote` is
+/// moved aside to "<remote>.old" first and only removed once `partial` took its place; if
+/// that fails, it is moved back.
+fn replace_remote(sftp: &Sftp, partial: &Path, remote: &Path) -> io::Result<()> {
+    if sftp.stat(remote).is_err() {
+        return Ok(sftp.rename(partial, remote, None)?);
+    }
+    let old = PathBuf::from(format!("{}.old", remote.to_string_lossy()));
+    let _ = sftp.unlink(&old);
+    sftp.rename(remote, &old, None)?;
+    if let Err(e) = sftp.rename(partial, remote, None) {
+        let _ = sftp.rename(&old, remote, None);
+        return Err(e.into());
+    }
+    if let Err(e) = sftp.unlink(&old) {
+        debug!("cannot remove {}: {}", old.display(), e);
+    }
+    Ok(())
+}
+
+/// Times and permissions of a local file, to give its upload.
+fn stat_of(meta: &fs::Metadata) -> io::Result<FileStat> {
+    let secs = |time: std::time::SystemTime| {
+        time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+    };
+    #[cfg(unix)]
+    let perm = {
+        use std::os::unix::fs::PermissionsExt;
+        meta.permissions().mode() & 0o7777
+    };
+    #[cfg(not(unix))]
+    let perm = if meta.permissions().readonly() { 0o444 } else { 0o644 };
+    Ok(FileStat {
+        size: None,
+        uid: None,
+        gid: None,
+        perm: Some(perm),
+        atime: Some(secs(meta.accessed()?)),
+        mtime: Some(secs(meta.modified()?)),
+    })
+}
+
+/// The user's home directory, where ssh keeps known_hosts and the default keys.
+fn home_dir() -> Option<PathBuf> {
+    ["HOME", "USERPROFILE"]
+        .iter()
+        .find_map(|var| std::env::var_os(var).filter(|home| !home.is_empty()))
+        .map(PathBuf::from)
+}
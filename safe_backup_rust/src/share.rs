@@ -0,0 +1,60 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use tempfile::NamedTempFile;
+
+// ---------- Files in use (Windows) ----------
+
+/// True if `e` says another process has the file open in a way that keeps us from reading
+/// it: a sharing violation, or a range it has locked. Windows only; on Unix, where opening
+/// a file never conflicts with another process having it open, always false.
+pub(crate) fn is_sharing_violation(e: &io::Error) -> bool {
+    #[cfg(windows)]
+    {
+        const ERROR_SHARING_VIOLATION: i32 = 32;
+        const ERROR_LOCK_VIOLATION: i32 = 33;
+        matches!(e.raw_os_error(), Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION))
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = e;
+        false
+    }
+}
+
+/// Open `path` for reading as leniently as Windows allows: sharing read, write and delete
+/// access with whoever has it open, and with backup semantics, which lets an account
+/// holding the backup privilege past the file's access checks. It still fails when the
+/// owner lets nobody read the file. Elsewhere just `File::open`.
+fn open_shared(path: &Path) -> io::Result<fs::File> {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        const FILE_SHARE_READ: u32 = 0x1;
+        const FILE_SHARE_WRITE: u32 = 0x2;
+        const FILE_SHARE_DELETE: u32 = 0x4;
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+        fs::OpenOptions::new()
+            .read(true)
+            .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)
+    }
+    #[cfg(not(windows))]
+    {
+        fs::File::open(path)
+    }
+}
+
+/// Read `path`, which another process has open, into a temporary file in `dir` through
+/// `open_shared`, so a backup can be made from that copy. As the owner goes on writing,
+/// the copy is only as consistent as the owner's writes make it; for a log that is
+/// appended to, it ends within the last line written. Removed when dropped.
+pub(crate) fn stage_shared(path: &Path, dir: &Path) -> io::Result<NamedTempFile> {
+    let mut input = open_shared(path)?;
+    let mut staged = tempfile::Builder::new().prefix(".in-use.").tempfile_in(dir)?;
+    io::copy(&mut input, staged.as_file_mut())?;
+    Ok(staged)
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::atomic::write_atomically;
+use crate::error::BackupError;
+
+// ---------- Snapshots ----------
+
+/// Directory under the base holding one "<name>.json" per snapshot.
+pub(crate) const SNAPSHOT_DIR: &str = ".snapshots";
+
+/// Held while a snapshot is read and written back, as the files of a glob backup are
+/// labelled from several threads.
+static SNAPSHOT_LOCK: Mutex<()> = Mutex::new(());
+
+/// The versions labelled with one snapshot name: each file, relative to the base, and the
+/// backup to roll it back to.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct Snapshot {
+    files: BTreeMap<PathBuf, PathBuf>,
+}
+
+/// Refuse snapshot names that could not be a file name of their own: only letters,
+/// digits, '.', '-' and '_', not starting with '.'.
+pub(crate) fn check_snapshot_name(name: &str) -> Result<(), BackupError> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_');
+    if name.is_empty() || name.starts_with('.') || !name.chars().all(allowed) {
+        return Err(BackupError::InvalidInput(format!(
+            "Invalid snapshot name '{}': use letters, digits, '.', '-' and '_'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+fn snapshot_path(base: &Path, name: &str) -> PathBuf {
+    base.join(SNAPSHOT_DIR).join(format!("{}.json", name))
+}
+
+fn read_snapshot(base: &Path, name: &str) -> io::Result<Option<Snapshot>> {
+    let path = snapshot_path(base, name);
+    let json = match fs::read(&path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let snapshot = serde_json::from_slice(&json).map_err(|e| {
+        let msg = format!("Damaged snapshot {}: {}", path.display(), e);
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    })?;
+    Ok(Some(snapshot))
+}
+
+/// Label `backup` as the version of `file` in snapshot `name`, creating the snapshot or
+/// replacing the version it had for `file`.
+pub(crate) fn label_backup(base: &Path, name: &str, file: &Path, backup: &Path) -> io::Result<()> {
+    let _lock = SNAPSHOT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut snapshot = read_snapshot(base, name)?.unwrap_or_default();
+    let relative = |path: &Path| path.strip_prefix(base).unwrap_or(path).to_path_buf();
+    snapshot.files.insert(relative(file), relative(backup));
+    fs::create_dir_all(base.join(SNAPSHOT_DIR))?;
+    let json = serde_json::to_vec_pretty(&snapshot).map_err(io::Error::other)?;
+    write_atomically(&snapshot_path(base, name), |out| io::Write::write_all(out, &json))
+}
+
+/// Every (file, backup) pair of snapshot `name`, files relative to the base as labelled
+/// and backups resolved under it.
+pub(crate) fn snapshot_backups(
+    base: &Path,
+    name: &str,
+) -> Result<Vec<(PathBuf, PathBuf)>, BackupError> {
+    check_snapshot_name(name)?;
+    let Some(snapshot) = read_snapshot(base, name)? else {
+        return Err(BackupError::NotFound(format!("No snapshot named '{}'", name)));
+    };
+    Ok(snapshot.files.into_iter().map(|(file, backup)| (file, base.join(backup))).collect())
+}
+
+/// Every backup some snapshot labels, so `prune` keeps them for `rollback`.
+pub(crate) fn labelled_backups(base: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = base.join(SNAPSHOT_DIR);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut labelled = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let name = path.file_stem().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            if let Some(snapshot) = read_snapshot(base, &name)? {
+                labelled.extend(snapshot.files.into_values().map(|backup| base.join(backup)));
+            }
+        }
+    }
+    Ok(labelled)
+}
@@ -0,0 +1,117 @@
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::cancel::check_cancelled;
+use crate::progress::ProgressFn;
+
+// ---------- Sparse copies ----------
+
+/// Blocks of zeros this long are left as holes in a sparse copy instead of written.
+const HOLE_BLOCK: usize = 4096;
+
+/// The (start, end) ranges of `file` that hold data. On Linux these come from
+/// SEEK_DATA/SEEK_HOLE, so holes are skipped without being read; elsewhere, or where the
+/// filesystem cannot tell, the whole file is one range.
+fn data_ranges(file: &fs::File, len: u64) -> io::Result<Vec<(u64, u64)>> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let fd = file.as_raw_fd();
+        let seek = |pos: u64, whence: libc::c_int| -> io::Result<Option<u64>> {
+            // SAFETY: lseek only moves the offset of a descriptor `file` keeps open.
+            match unsafe { libc::lseek(fd, pos as libc::off_t, whence) } {
+                -1 => match io::Error::last_os_error() {
+                    // No data after `pos`.
+                    e if e.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+                    e => Err(e),
+                },
+                found => Ok(Some(found as u64)),
+            }
+        };
+        let mut ranges = Vec::new();
+        let mut pos = 0;
+        while pos < len {
+            let start = match seek(pos, libc::SEEK_DATA) {
+                Ok(Some(start)) => start,
+                Ok(None) => break,
+                Err(e) if e.raw_os_error() == Some(libc::EINVAL) => return Ok(vec![(0, len)]),
+                Err(e) => return Err(e),
+            };
+            let end = seek(start, libc::SEEK_HOLE)?.unwrap_or(len).min(len);
+            ranges.push((start, end));
+            pos = end;
+        }
+        Ok(ranges)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = file;
+        Ok(vec![(0, len)])
+    }
+}
+
+/// Write `buf` at `out`'s position, seeking over whole blocks of zeros instead of writing
+/// them, so they stay holes.
+fn write_sparse(out: &mut fs::File, buf: &[u8]) -> io::Result<()> {
+    for block in buf.chunks(HOLE_BLOCK) {
+        if block.len() == HOLE_BLOCK && block.iter().all(|&b| b == 0) {
+            out.seek(SeekFrom::Current(HOLE_BLOCK as i64))?;
+        } else {
+            out.write_all(block)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy all of `input` to the empty file `out` keeping it sparse: the holes of `input`
+/// are skipped and blocks of zeros in its data are not written either, which the
+/// filesystem then stores as holes where it can. Returns the bytes copied, holes included.
+pub(crate) fn copy_sparse(
+    input: &mut fs::File,
+    out: &mut fs::File,
+    buffer_size: u64,
+    progress: Option<&ProgressFn>,
+) -> io::Result<u64> {
+    let len = input.metadata()?.len();
+    let mut buf = vec![0u8; buffer_size.max(HOLE_BLOCK as u64) as usize];
+    for (start, end) in data_ranges(input, len)? {
+        input.seek(SeekFrom::Start(start))?;
+        out.seek(SeekFrom::Start(start))?;
+        let mut pos = start;
+        while pos < end {
+            check_cancelled()?;
+            let want = buf.len().min((end - pos) as usize);
+            let n = match input.read(&mut buf[..want]) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            write_sparse(out, &buf[..n])?;
+            pos += n as u64;
+            if let Some(report) = progress {
+                report(pos, len);
+            }
+        }
+    }
+    // Holes at the end are only kept by the length.
+    out.set_len(len)?;
+    out.flush()?;
+    Ok(len)
+}
+
+/// The bytes `path` takes on disk, where the platform tells (Unix); holes take none.
+pub(crate) fn allocated_size(path: &Path) -> io::Result<Option<u64>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(Some(fs::metadata(path)?.blocks() * 512))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(None)
+    }
+}
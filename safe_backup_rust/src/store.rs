@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::{copy_atomically, write_atomically};
+use crate::checksum::sha256_file;
+use crate::config::Config;
+use crate::paths::{file_name_of, BackupEntry};
+use crate::progress::ProgressFn;
+
+// ---------- Content-addressed store (deduplicated backups) ----------
+
+/// Directory, in the backup directory if one is configured and under the base otherwise,
+/// that holds the content of deduplicated backups once per SHA-256: ".store/ab/ab12...".
+pub(crate) const STORE_DIR: &str = ".store";
+
+/// Reference files larger than this are not ours.
+const MAX_REF_LEN: u64 = 64 * 1024;
+
+/// What a ".ref" backup holds instead of the content.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RefManifest {
+    sha256: String,
+    size: u64,
+    /// The content in the store, relative to the directory the ".ref" is in, so the
+    /// backups and their store can be moved together.
+    blob: PathBuf,
+}
+
+/// What writing a ".ref" backup did.
+pub(crate) struct RefStats {
+    pub sha256: String,
+    /// False if the store already held the content.
+    pub stored: bool,
+}
+
+/// The store the deduplicated backups under `base` share.
+pub(crate) fn store_root(cfg: &Config, base: &Path) -> PathBuf {
+    match &cfg.backup_dir {
+        Some(dir) => base.join(dir).join(STORE_DIR),
+        None => base.join(STORE_DIR),
+    }
+}
+
+/// "<store>/ab/ab12...": blobs are spread over 256 folders by the first two hex digits.
+fn blob_path(store: &Path, sha256: &str) -> PathBuf {
+    store.join(&sha256[..2]).join(sha256)
+}
+
+/// Put `src`'s content into `store` unless it is already there, then write a reference to
+/// it at `dst` (atomically). A blob that does not hash to what `src` hashed to before the
+/// copy (the file changed meanwhile) is removed again and the backup fails.
+pub(crate) fn write_ref(
+    src: &Path,
+    store: &Path,
+    dst: &Path,
+    buffer_size: u64,
+    progress: Option<&ProgressFn>,
+) -> io::Result<RefStats> {
+    let sha256 = sha256_file(src)?;
+    let blob = blob_path(store, &sha256);
+    let stored = !blob.is_file();
+    if stored {
+        if let Some(dir) = blob.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        copy_atomically(src, &blob, buffer_size, progress)?;
+        if sha256_file(&blob)? != sha256 {
+            fs::remove_file(&blob)?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} changed while it was being stored", src.display()),
+            ));
+        }
+    }
+    let manifest = RefManifest {
+        sha256: sha256.clone(),
+        size: fs::metadata(&blob)?.len(),
+        blob: relative_path(dst.parent().unwrap_or(Path::new("")), &blob),
+    };
+    let json = serde_json::to_vec_pretty(&manifest).map_err(io::Error::other)?;
+    write_atomically(dst, |out| out.write_all(&json))?;
+    Ok(RefStats { sha256, stored })
+}
+
+/// Copy the content a ".ref" backup points to into `dst` (atomically). Returns its size.
+pub(crate) fn restore_ref(
+    reference: &Path,
+    dst: &Path,
+    buffer_size: u64,
+    progress: Option<&ProgressFn>,
+) -> io::Result<u64> {
+    copy_atomically(&ref_blob(reference)?, dst, buffer_size, progress)
+}
+
+/// The blob in the store holding a ".ref" backup's content.
+pub(crate) fn ref_blob(reference: &Path) -> io::Result<PathBuf> {
+    let manifest = read_manifest(reference)?;
+    let blob = reference.parent().unwrap_or(Path::new("")).join(&manifest.blob);
+    if !blob.is_file() {
+        let msg = format!("{}: its content is missing from the store", reference.display());
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+    }
+    Ok(blob)
+}
+
+fn read_manifest(reference: &Path) -> io::Result<RefManifest> {
+    let invalid = || {
+        let msg = format!("{}: not a deduplicated backup", reference.display());
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    };
+    if fs::metadata(reference)?.len() > MAX_REF_LEN {
+        return Err(invalid());
+    }
+    let manifest: RefManifest =
+        serde_json::from_slice(&fs::read(reference)?).map_err(|_| invalid())?;
+    if !is_hash(&manifest.sha256) {
+        return Err(invalid());
+    }
+    Ok(manifest)
+}
+
+fn is_hash(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// The blobs in `store` that none of `references` points to. Fails if a reference cannot
+/// be read, rather than treat its content as garbage. Files not named like a blob (the
+/// temporary file of a backup still being written) are left alone.
+pub(crate) fn unreferenced_blobs(
+    store: &Path,
+    references: &[PathBuf],
+) -> io::Result<Vec<BackupEntry>> {
+    let referenced: HashSet<String> = references
+        .iter()
+        .map(|r| read_manifest(r).map(|m| m.sha256.to_lowercase()))
+        .collect::<io::Result<_>>()?;
+    let mut garbage = Vec::new();
+    if !store.is_dir() {
+        return Ok(garbage);
+    }
+    for folder in fs::read_dir(store)? {
+        let folder = folder?;
+        if !folder.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(folder.path())? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            let name = file_name_of(&entry.path());
+            if meta.is_file() && is_hash(&name) && !referenced.contains(&name) {
+                let modified = meta.modified()?;
+                garbage.push(BackupEntry { path: entry.path(), size: meta.len(), modified });
+            }
+        }
+    }
+    garbage.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(garbage)
+}
+
+/// `to` as seen from the directory `from`: "../../.store/ab/ab12...". Both are absolute;
+/// if they share no root (another drive), `to` itself.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<_> = from.components().collect();
+    let to_parts: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to_parts).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return to.to_path_buf();
+    }
+    let mut rel: PathBuf = from[common..].iter().map(|_| "..").collect();
+    rel.extend(&to_parts[common..]);
+    rel
+}
@@ -0,0 +1,64 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::paths::{flat_name, is_version_stamp, VERSION_STAMP_FORMAT};
+
+// ---------- Trash ----------
+
+/// Directory under the base where `delete` moves files unless `--purge` is given.
+pub(crate) const TRASH_DIR: &str = ".trash";
+
+/// Where a deleted `file` goes: ".trash/sub%2Fnotes.txt.2024-06-01T14-30-00", with a
+/// "-N" counter appended if that name is already taken.
+pub(crate) fn trash_path_for(base: &Path, file: &Path) -> PathBuf {
+    let dir = base.join(TRASH_DIR);
+    let name = flat_name(base, file);
+    let stamp = chrono::Local::now().format(VERSION_STAMP_FORMAT).to_string();
+
+    let mut candidate = dir.join(format!("{}.{}", name, stamp));
+    let mut counter = 1;
+    while candidate.exists() {
+        candidate = dir.join(format!("{}.{}-{}", name, stamp, counter));
+        counter += 1;
+    }
+    candidate
+}
+
+/// The most recently trashed copy of `file`, if any.
+pub(crate) fn latest_trashed(base: &Path, file: &Path) -> io::Result<Option<PathBuf>> {
+    let dir = base.join(TRASH_DIR);
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let prefix = format!("{}.", flat_name(base, file));
+
+    let is_stamp = |rest: &&str| is_version_stamp(VERSION_STAMP_FORMAT, rest);
+    let mut latest: Option<((String, u32), PathBuf)> = None;
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(rest) = name.strip_prefix(&prefix).filter(is_stamp) else {
+            continue;
+        };
+        // "2024-06-01T14-30-00" sorts chronologically as text; the counter does not.
+        let (stamp, counter) = rest.split_at(rest.len().min(19));
+        let key = (stamp.to_string(), counter.trim_start_matches('-').parse().unwrap_or(0));
+        if latest.as_ref().is_none_or(|(k, _)| key > *k) {
+            latest = Some((key, entry.path()));
+        }
+    }
+    Ok(latest.map(|(_, path)| path))
+}
+
+/// Move `path` to the desktop's trash (the freedesktop.org Trash, macOS Trash or the
+/// Windows Recycle Bin), where the file manager shows it. Fails where there is none.
+#[cfg(any(windows, target_os = "macos", target_os = "linux"))]
+pub(crate) fn move_to_system_trash(path: &Path) -> io::Result<()> {
+    trash::delete(path).map_err(io::Error::other)
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+pub(crate) fn move_to_system_trash(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no system trash on this platform"))
+}
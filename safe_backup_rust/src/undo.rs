@@ -0,0 +1,62 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomically;
+use crate::trash::TRASH_DIR;
+
+// ---------- Undo ----------
+
+/// Where the last delete or restore is remembered, inside the trash so that it is never
+/// backed up or listed.
+const UNDO_FILE: &str = "undo.json";
+
+/// The most recent operation `undo` can reverse. Paths are absolute.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub(crate) enum LastOperation {
+    /// `file` was deleted: moved to `trashed`, to the desktop's trash with `system_trash`,
+    /// or purged for good when neither.
+    Delete {
+        file: PathBuf,
+        trashed: Option<PathBuf>,
+        #[serde(default)]
+        system_trash: bool,
+    },
+    /// `file` was overwritten from a backup; its previous content was saved in `snapshot`,
+    /// or it did not exist before when that is `None`.
+    Restore { file: PathBuf, snapshot: Option<PathBuf> },
+}
+
+fn undo_path(base: &Path) -> PathBuf {
+    base.join(TRASH_DIR).join(UNDO_FILE)
+}
+
+/// Remember `op` as the one `undo` reverses, replacing whatever was remembered before.
+pub(crate) fn record_last_operation(base: &Path, op: &LastOperation) -> io::Result<()> {
+    let path = undo_path(base);
+    fs::create_dir_all(base.join(TRASH_DIR))?;
+    let json = serde_json::to_vec(op).map_err(io::Error::other)?;
+    write_atomically(&path, |out| io::Write::write_all(out, &json))
+}
+
+/// The operation `undo` would reverse, if one is remembered.
+pub(crate) fn last_operation(base: &Path) -> io::Result<Option<LastOperation>> {
+    let json = match fs::read(undo_path(base)) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let op = serde_json::from_slice(&json).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Damaged undo record: {}", e))
+    })?;
+    Ok(Some(op))
+}
+
+/// Forget the remembered operation once it has been undone.
+pub(crate) fn clear_last_operation(base: &Path) -> io::Result<()> {
+    match fs::remove_file(undo_path(base)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
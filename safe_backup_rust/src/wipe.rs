@@ -0,0 +1,48 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+
+// ---------- Secure delete ----------
+
+/// What `delete --secure` overwrites a file with before removing it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WipePattern {
+    /// A pass of zero bytes.
+    #[default]
+    Zeros,
+    /// A pass of random bytes.
+    Random,
+}
+
+impl WipePattern {
+    /// Name as logged and accepted on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            WipePattern::Zeros => "zeros",
+            WipePattern::Random => "random",
+        }
+    }
+}
+
+/// Overwrite every byte of `path` in place with one pass of `pattern`, flushed to disk,
+/// then remove it. Best effort only: SSDs and journaling or copy-on-write filesystems may
+/// keep the old data elsewhere.
+pub(crate) fn wipe_file(path: &Path, pattern: WipePattern) -> io::Result<()> {
+    let mut file = fs::File::options().write(true).open(path)?;
+    let mut remaining = file.metadata()?.len();
+    let mut buf = vec![0u8; 64 * 1024];
+    while remaining > 0 {
+        let n = remaining.min(buf.len() as u64) as usize;
+        if pattern == WipePattern::Random {
+            OsRng.fill_bytes(&mut buf[..n]);
+        }
+        file.write_all(&buf[..n])?;
+        remaining -= n as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)
+}